@@ -0,0 +1,20 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Records the commit being built, for `download-organiser version`. Left empty when the source
+/// isn't a git checkout or git isn't installed, as when cross-compiling in a container.
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=GIT_HASH={hash}");
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
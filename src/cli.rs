@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+use std::iter::Peekable;
+
+use crate::commands::bench::Workload;
+use crate::logging::LogFormat;
+use crate::size;
+use crate::Result;
+
+pub const USAGE: &str = "\
+usage: download-organiser [-v|-vv|-q|-qq] [--log-format <format>] [--config <file>] [--profile <name>] [command]
+
+options:
+  -v, -vv / -q, -qq                 log at debug or trace / only warnings or errors
+  --log-format <format>             logfmt (default), json, or pretty for coloured terminal output;
+                                    json is the default under Kubernetes
+  --config <file>                   read the config from this file, rather than CONFIG_YAML,
+                                    CONFIG_FILE or the default locations
+  --profile <name>                  use only this profile of a config with several
+
+commands:
+  run [--force] [--dry-run] [--audit]
+                                    watch the configured directories and organise files (default)
+                                    --force ignores the processed file ledger
+                                    --dry-run logs what would be done without changing anything
+                                    --audit is a dry run that changes nothing at all, only
+                                    reporting what would be done to the outcome sinks
+  inspect <archive> [--rule <n>]    list an archive's entries and where they would be extracted
+  simulate <dir> [--watch <name>]   show where everything under a directory would be organised to
+                                    --watch applies only the rules for that watch directory
+  join <manifest> [<output>] [--check]
+                                    join the chunks the split action wrote back into the original
+                                    file, beside them unless an output is given; --check only
+                                    checks them against the manifest
+  import --from <format> <file>     convert an organize or maid config to this tool's format
+  config dump                       print the effective configuration, with secrets redacted
+  pause [--rule <regex>]            pause processing in the running organiser, of everything or
+                                    of one rule; files are still queued (needs the control API)
+  resume [--rule <regex>]           resume processing paused with pause
+  pending                           list files held for approval by confirm (needs the control API)
+  approve <path> / reject <path>    process a file held for approval, or leave it where it is
+  bench [--files <n>] [--size <size>] [--archives <n>] [--entries <n>] [--workers <n>]
+                                    process a generated workload in a temporary directory and
+                                    report throughput and per-stage latencies; --size takes a
+                                    size or a range such as 4KiB..1MiB (default 1000 files of
+                                    64KiB and 10 archives of 20 entries, with 1 worker)
+  replay <events> [--instant] [--dry-run]
+                                    feed events recorded with recordEvents through the rules
+                                    again, in order and one file at a time; --instant leaves out
+                                    the gaps between them
+  check                             run the example names in each rule's tests
+  version, --version                show the version, commit, and the features, actions and
+                                    integrations built in
+  help                              show this message";
+
+/// Options that apply to every command, and the command itself.
+pub struct Args {
+    pub log_format: LogFormat,
+    /// Net count of `-v` over `-q` flags.
+    pub verbosity: i8,
+    /// Limits the command to one of the config's profiles.
+    pub profile: Option<String>,
+    /// Where the config is read from, ahead of the environment.
+    pub config: Option<PathBuf>,
+    pub command: Command,
+}
+
+pub enum Command {
+    Run { force: bool, dry_run: bool, audit: bool },
+    Inspect { archive: PathBuf, rule: Option<usize> },
+    Simulate { dir: PathBuf, watch: Option<String> },
+    Import { from: String, path: PathBuf },
+    Join { manifest: PathBuf, output: Option<PathBuf>, check: bool },
+    Check,
+    ConfigDump,
+    /// Pauses (`paused`) or resumes the running organiser.
+    Pause { rule: Option<String>, paused: bool },
+    Pending,
+    /// Approves (`approved`) or rejects a file held for approval.
+    Confirm { path: PathBuf, approved: bool },
+    Bench(Workload),
+    Replay { events: PathBuf, instant: bool, dry_run: bool },
+    Version,
+    Help,
+}
+
+/// Parses the process arguments (without the program name).
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut args = args.into_iter().peekable();
+
+    let (log_format, verbosity, profile, config) = global_options(&mut args)?;
+    let command = match args.next().as_deref() {
+        None => Command::Run { force: false, dry_run: false, audit: false },
+        Some("run") => {
+            let (mut force, mut dry_run, mut audit) = (false, false, false);
+            for arg in args.by_ref() {
+                match arg.as_str() {
+                    "--force" => force = true,
+                    "--dry-run" => dry_run = true,
+                    "--audit" => audit = true,
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Run { force, dry_run, audit }
+        },
+        Some("inspect") => {
+            let mut archive = None;
+            let mut rule = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--rule" => rule = Some(value(&mut args, "--rule")?.parse()
+                        .map_err(|_| "--rule expects a rule number")?),
+                    _ if archive.is_none() && !arg.starts_with('-') => archive = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Inspect {
+                archive: archive.ok_or("inspect requires an archive path - see `download-organiser help`")?,
+                rule,
+            }
+        },
+        Some("simulate") => {
+            let mut dir = None;
+            let mut watch = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--watch" => watch = Some(value(&mut args, "--watch")?),
+                    _ if dir.is_none() && !arg.starts_with('-') => dir = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Simulate {
+                dir: dir.ok_or("simulate requires a directory - see `download-organiser help`")?,
+                watch,
+            }
+        },
+        Some("import") => {
+            let mut from = None;
+            let mut path = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--from" => from = Some(value(&mut args, "--from")?),
+                    _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Import {
+                from: from.ok_or("import requires --from organize or --from maid")?,
+                path: path.ok_or("import requires a config file path - see `download-organiser help`")?,
+            }
+        },
+        Some("join") => {
+            let (mut manifest, mut output, mut check) = (None, None, false);
+            for arg in args.by_ref() {
+                match arg.as_str() {
+                    "--check" => check = true,
+                    _ if manifest.is_none() && !arg.starts_with('-') => manifest = Some(PathBuf::from(arg)),
+                    _ if output.is_none() && !arg.starts_with('-') => output = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Join {
+                manifest: manifest.ok_or("join requires the path of a split manifest - see `download-organiser help`")?,
+                output,
+                check,
+            }
+        },
+        Some("bench") => {
+            // without zip support, only files unless archives are asked for
+            let archives = if cfg!(feature="archives") { 10 } else { 0 };
+            let mut workload = Workload { files: 1000, size: (64 << 10, 64 << 10), archives, entries: 20, workers: 1 };
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--files" => workload.files = count(&mut args, "--files")?,
+                    "--archives" => workload.archives = count(&mut args, "--archives")?,
+                    "--entries" => workload.entries = count(&mut args, "--entries")?,
+                    "--workers" => workload.workers = count(&mut args, "--workers")?.max(1),
+                    "--size" => {
+                        let value = value(&mut args, "--size")?;
+                        workload.size = match value.split_once("..") {
+                            Some((min, max)) => (size::parse(min)?, size::parse(max)?),
+                            None => (size::parse(&value)?, size::parse(&value)?),
+                        };
+                        if workload.size.0 > workload.size.1 {
+                            return Err(format!("--size [{value}] has its smallest size above its largest").into())
+                        }
+                    },
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Bench(workload)
+        },
+        Some(command @ ("pause" | "resume")) => {
+            let mut rule = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--rule" => rule = Some(value(&mut args, "--rule")?),
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Pause { rule, paused: command == "pause" }
+        },
+        Some("pending") => Command::Pending,
+        Some(command @ ("approve" | "reject")) => {
+            let path = args.next().filter(|a| !a.starts_with('-'))
+                .ok_or_else(|| format!("{command} requires the path of a file awaiting approval - see `download-organiser help`"))?;
+            Command::Confirm { path: PathBuf::from(path), approved: command == "approve" }
+        },
+        Some("replay") => {
+            let (mut events, mut instant, mut dry_run) = (None, false, false);
+            for arg in args.by_ref() {
+                match arg.as_str() {
+                    "--instant" => instant = true,
+                    "--dry-run" => dry_run = true,
+                    _ if events.is_none() && !arg.starts_with('-') => events = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into()),
+                }
+            }
+            Command::Replay {
+                events: events.ok_or("replay requires the path of an event recording - see `download-organiser help`")?,
+                instant,
+                dry_run,
+            }
+        },
+        Some("check") => Command::Check,
+        Some("config") => match args.next().as_deref() {
+            Some("dump") => Command::ConfigDump,
+            _ => return Err("config expects a subcommand: dump - see `download-organiser help`".into()),
+        },
+        Some("help" | "-h" | "--help") => Command::Help,
+        Some("version" | "-V" | "--version") => Command::Version,
+        Some(other) => return Err(format!("unknown command [{other}] - see `download-organiser help`").into()),
+    };
+
+    if let Some(arg) = args.next() {
+        return Err(format!("unexpected argument [{arg}] - see `download-organiser help`").into())
+    }
+
+    Ok(Args { log_format, verbosity, profile, config, command })
+}
+
+/// Consumes the options given before the command.
+fn global_options(args: &mut Peekable<impl Iterator<Item = String>>) -> Result<(LogFormat, i8, Option<String>, Option<PathBuf>)> {
+    let mut log_format = LogFormat::detect();
+    let mut verbosity = 0i8;
+    let mut profile = None;
+    let mut config = None;
+    while let Some(arg) = args.next_if(|a| a.starts_with('-') && !matches!(a.as_str(), "-h" | "--help" | "-V" | "--version")) {
+        match arg.as_str() {
+            "--log-format" => log_format = value(args, "--log-format")?.parse()?,
+            "--profile" => profile = Some(value(args, "--profile")?),
+            "--config" => config = Some(PathBuf::from(value(args, "--config")?)),
+            "-v" | "-vv" | "-vvv" => verbosity = verbosity.saturating_add(arg.len() as i8 - 1),
+            "-q" | "-qq" => verbosity = verbosity.saturating_sub(arg.len() as i8 - 1),
+            _ => return Err(format!("unknown option [{arg}] - see `download-organiser help`").into()),
+        }
+    }
+    Ok((log_format, verbosity, profile, config))
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next().ok_or_else(|| format!("{flag} requires a value").into())
+}
+
+fn count(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<usize> {
+    value(args, flag)?.parse().map_err(|_| format!("{flag} expects a whole number").into())
+}
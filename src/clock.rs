@@ -0,0 +1,104 @@
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A strftime format for ISO 8601 with the offset, such as `2024-03-31T01:30:00+00:00`, which
+/// sorts and stays unique across a DST change.
+pub const ISO_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// The format of the prefix `rename-date` gives a clashing file.
+const PREFIX_FORMAT: &str = "%Y-%m-%dT%H_%M_%S";
+
+/// The settings in use. Process-wide, as the templates and duplicate names that read them are
+/// worked out everywhere a destination is.
+static DATES: RwLock<DatesConfig> = RwLock::new(DatesConfig { timezone: Zone::Local, prefix: PrefixFormat::Default });
+
+/// How dates in destinations and duplicate names are written.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct DatesConfig {
+    /// The timezone for date tokens and `rename-date` prefixes: `local`, `UTC`, a name such as
+    /// `Europe/London`, or a fixed offset such as `+02:00`.
+    #[serde(default)]
+    pub timezone: Zone,
+    /// Format of the prefix `rename-date` gives a clashing file.
+    #[serde(rename="renameDate", default)]
+    pub prefix: PrefixFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Zone {
+    /// The system's timezone, as set by `TZ` or `/etc/localtime`.
+    #[default]
+    Local,
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum PrefixFormat {
+    /// `2024-03-31T01_30_00`, without the offset, which is safe on any filesystem.
+    #[default]
+    #[serde(rename="default")]
+    Default,
+    /// ISO 8601 with the offset, `2024-03-31T01:30:00+00:00`.
+    #[serde(rename="iso")]
+    Iso,
+}
+
+/// Uses `dates` for every date written from now on.
+pub fn configure(dates: &DatesConfig) {
+    *DATES.write().unwrap() = *dates;
+}
+
+/// Formats `time` with the strftime `format` in the configured timezone.
+pub fn format(time: SystemTime, format: &str) -> String {
+    let time: DateTime<Utc> = time.into();
+    match DATES.read().unwrap().timezone {
+        Zone::Local => time.with_timezone(&Local).format(format).to_string(),
+        Zone::Named(zone) => time.with_timezone(&zone).format(format).to_string(),
+        Zone::Fixed(offset) => time.with_timezone(&offset).format(format).to_string(),
+    }
+}
+
+/// The current time as a prefix for a clashing file's name.
+pub fn prefix() -> String {
+    let prefix = DATES.read().unwrap().prefix;
+    match prefix {
+        PrefixFormat::Default => format(SystemTime::now(), PREFIX_FORMAT),
+        PrefixFormat::Iso => format(SystemTime::now(), ISO_FORMAT),
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(zone: &str) -> Result<Zone, String> {
+        if zone.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local)
+        }
+        if let Ok(offset) = FixedOffset::from_str(zone) {
+            return Ok(Zone::Fixed(offset))
+        }
+        Tz::from_str(zone).map(Zone::Named)
+            .map_err(|_| format!("[{zone}] is not a timezone - expected local, a name such as Europe/London or an offset such as +02:00"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Zone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Zone, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Zone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Zone::Local => serializer.serialize_str("local"),
+            Zone::Named(zone) => serializer.serialize_str(zone.name()),
+            Zone::Fixed(offset) => serializer.serialize_str(&offset.to_string()),
+        }
+    }
+}
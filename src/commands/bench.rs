@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fs;
+#[cfg(feature="archives")]
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::StreamExt;
+#[cfg(feature="archives")]
+use zip::write::FileOptions;
+
+use crate::config::Config;
+use crate::control::Request;
+use crate::logging;
+use crate::outcome::ProcessingOutcome;
+use crate::Result;
+
+/// Extensions the generated files are given, each filed by a rule of its own.
+const EXTENSIONS: [&str; 5] = ["mkv", "pdf", "jpg", "txt", "iso"];
+
+/// The synthetic workload to run.
+pub struct Workload {
+    pub files: usize,
+    /// Smallest and largest file size, in bytes. Sizes are spread evenly between them.
+    pub size: (u64, u64),
+    pub archives: usize,
+    /// Files in each archive.
+    pub entries: usize,
+    pub workers: usize,
+}
+
+/// Generates `workload` in a temporary watch directory, has the organiser process all of it as it
+/// would a rescan, and prints the throughput and how long each stage took.
+pub async fn run(workload: &Workload) -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("download-organiser-bench-{}", std::process::id()));
+    let result = bench(&dir, workload).await;
+    fs::remove_dir_all(&dir)?;
+    result
+}
+
+async fn bench(dir: &Path, workload: &Workload) -> Result<()> {
+    let watch = dir.join("watch");
+    let base = dir.join("base");
+    fs::create_dir_all(&watch)?;
+    for extension in EXTENSIONS {
+        fs::create_dir_all(base.join("files").join(extension))?;
+    }
+
+    let generating = Instant::now();
+    let bytes = generate(&watch, workload)?;
+    println!("generated {} files and {} archives of {} entries ({}) in {:.2}s",
+        workload.files, workload.archives, workload.entries, mebibytes(bytes), generating.elapsed().as_secs_f64());
+
+    let mut rules = String::from("  - regex: '^archive-.*\\.zip$'\n    actions:\n      - unzip: { dest: extracted }\n      - delete\n");
+    for extension in EXTENSIONS {
+        rules.push_str(&format!("  - regex: '\\.{extension}$'\n    actions:\n      - move: {{ dest: files/{extension}, duplicate: skip }}\n"));
+    }
+    let config: Config = serde_yaml::from_str(&format!(
+        "baseDir: '{}'\nwatchDirs: [{{ name: default, path: '{}' }}]\nworkers: {}\nrules:\n{rules}", base.display(), watch.display(), workload.workers))?;
+    // a log line per file would be measured along with everything else
+    logging::configure(Some("warn"), config.log_repeat_window)?;
+
+    let organiser = Arc::new(crate::organiser("bench".to_string(), config, false, false, false, false, None)?);
+    let mut outcomes = organiser.outcomes();
+    let (control, receiver) = mpsc::channel(8);
+    tokio::spawn(Arc::clone(&organiser).run(receiver));
+
+    // the organiser answers once it's watching
+    let (reply, ready) = oneshot::channel();
+    control.send(Request::Ping(reply)).await?;
+    ready.await?;
+
+    let started = Instant::now();
+    let (reply, queued) = oneshot::channel();
+    control.send(Request::Rescan(reply)).await?;
+    let queued = queued.await?;
+    let mut processed = Vec::with_capacity(queued);
+    while processed.len() < queued {
+        processed.push(outcomes.next().await.ok_or("organiser stopped before processing everything")?);
+    }
+    let elapsed = started.elapsed();
+
+    report(&processed, elapsed, bytes, workload.workers);
+    Ok(())
+}
+
+/// Writes the files and archives, returning how many bytes they hold.
+fn generate(watch: &Path, workload: &Workload) -> Result<u64> {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let mut total = 0;
+    for i in 0..workload.files {
+        let contents = random.contents(workload.size);
+        total += contents.len() as u64;
+        fs::write(watch.join(format!("file-{i:06}.{}", EXTENSIONS[i % EXTENSIONS.len()])), contents)?;
+    }
+    for i in 0..workload.archives {
+        total += write_archive(watch, i, workload, &mut random)?;
+    }
+    Ok(total)
+}
+
+/// Writes the `i`th archive, returning how many bytes its entries hold.
+#[cfg(feature="archives")]
+fn write_archive(watch: &Path, i: usize, workload: &Workload, random: &mut Random) -> Result<u64> {
+    let mut archive = zip::ZipWriter::new(fs::File::create(watch.join(format!("archive-{i:04}.zip")))?);
+    let mut total = 0;
+    for entry in 0..workload.entries {
+        let contents = random.contents(workload.size);
+        total += contents.len() as u64;
+        archive.start_file(format!("archive-{i:04}/entry-{entry:04}.bin"), FileOptions::default())?;
+        archive.write_all(&contents)?;
+    }
+    archive.finish()?;
+    Ok(total)
+}
+
+#[cfg(not(feature="archives"))]
+fn write_archive(_watch: &Path, _i: usize, _workload: &Workload, _random: &mut Random) -> Result<u64> {
+    crate::version::require("action", "unzip")?;
+    unreachable!("the archives feature is compiled in")
+}
+
+fn report(processed: &[ProcessingOutcome], elapsed: Duration, bytes: u64, workers: usize) {
+    let seconds = elapsed.as_secs_f64();
+    println!("processed {} entries with {workers} worker(s) in {seconds:.2}s: {:.1} entries/s, {}/s",
+        processed.len(), processed.len() as f64 / seconds, mebibytes((bytes as f64 / seconds) as u64));
+
+    let mut results: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut stages: BTreeMap<String, Vec<Duration>> = BTreeMap::new();
+    for outcome in processed {
+        *results.entry(outcome.result.name()).or_default() += 1;
+        stages.entry("queue wait".to_string()).or_default().push(outcome.queued);
+        stages.entry("rule match".to_string()).or_default().push(outcome.matching);
+        for action in &outcome.actions {
+            stages.entry(format!("action: {}", action.action)).or_default().push(action.duration);
+        }
+        stages.entry("total".to_string()).or_default().push(outcome.duration);
+    }
+    let results: Vec<String> = results.iter().map(|(result, count)| format!("{count} {result}")).collect();
+    println!("results: {}", results.join(", "));
+
+    println!();
+    println!("{:<18} {:>7} {:>10} {:>10} {:>10} {:>10}", "stage", "count", "p50", "p90", "p99", "max");
+    for (stage, mut durations) in stages {
+        durations.sort();
+        println!("{stage:<18} {:>7} {:>10} {:>10} {:>10} {:>10}", durations.len(),
+            millis(percentile(&durations, 50)), millis(percentile(&durations, 90)),
+            millis(percentile(&durations, 99)), millis(durations[durations.len() - 1]));
+    }
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    sorted[(sorted.len() - 1) * percent / 100]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn mebibytes(bytes: u64) -> String {
+    format!("{:.1}MiB", bytes as f64 / (1 << 20) as f64)
+}
+
+/// xorshift64, so every run generates the same workload without a dependency.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn contents(&mut self, (min, max): (u64, u64)) -> Vec<u8> {
+        let size = min + self.next() % (max - min + 1);
+        let mut contents = Vec::with_capacity(size as usize + 8);
+        while (contents.len() as u64) < size {
+            contents.extend_from_slice(&self.next().to_le_bytes());
+        }
+        contents.truncate(size as usize);
+        contents
+    }
+}
@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use crate::config::{Action, Config, Rule, RuleTest};
+use crate::rules::{Candidate, RuleIndex};
+use crate::size;
+use crate::Result;
+
+/// Runs every rule's `tests` against the whole rule list and prints the results.
+///
+/// Each example goes through the rules the same way a download would, so a test fails if an earlier
+/// rule catches the name first. Nothing on disk is touched, and destination conditions are ignored
+/// since they depend on the state of the disk.
+pub fn run(config: &Config) -> Result<()> {
+    let (mut total, mut failed) = (0, 0);
+    let index = RuleIndex::new(&config.rules);
+
+    for (i, rule) in config.rules.iter().enumerate() {
+        for test in &rule.tests {
+            total += 1;
+            let (outcome, matched) = evaluate(config, &index, test)?;
+            if same_outcome(&outcome, &test.expect) {
+                println!("PASS  rule {} [{}]: {} -> {outcome}", i + 1, rule.regex.as_str(), test.name);
+            } else {
+                failed += 1;
+                let by = matched.map(|r| format!(" (matched by [{}])", r.regex.as_str())).unwrap_or_default();
+                println!("FAIL  rule {} [{}]: {} -> expected {}, got {outcome}{by}", i + 1, rule.regex.as_str(), test.name, test.expect);
+            }
+        }
+    }
+
+    println!("{} tests, {} passed, {failed} failed", total, total - failed);
+    if failed > 0 {
+        return Err(format!("{failed} rule tests failed").into())
+    }
+    Ok(())
+}
+
+/// Returns the outcome for the test's name and the rule that produced it.
+fn evaluate<'a>(config: &'a Config, index: &RuleIndex, test: &RuleTest) -> Result<(String, Option<&'a Rule>)> {
+    let size = test.size.as_deref().map(size::parse).transpose()?.unwrap_or(0);
+    let hidden = match &test.watch_dir {
+        Some(name) => config.watch_dirs().iter().find(|w| &w.name == name).map(|w| w.hidden_policy()).unwrap_or_default(),
+        None => config.hidden,
+    };
+    let candidate = Candidate {
+        name: &test.name,
+        source: Path::new(&test.name),
+        is_dir: test.directory,
+        watch_dir: test.watch_dir.as_deref(),
+        size: Some(size),
+        hidden,
+        base_dir: None,
+        read_content: false,
+        from_archive: test.from_archive,
+    };
+
+    let rule = match index.first_match(&config.rules, &candidate)? {
+        Some(rule) => rule,
+        None => return Ok(("none".to_string(), None)),
+    };
+    let outcome = match rule.actions.first() {
+        Some(Action::Move { dest, .. } | Action::LinkIntoLibrary { dest, .. } | Action::Unzip { dest, .. } | Action::Episode { dest, .. } | Action::Split { dest, .. }) => dest.clone(),
+        Some(Action::Photo(photo)) => photo.dest_for(Path::new(&test.name)).to_string(),
+        Some(action) => action.name().to_string(),
+        None => "none".to_string(),
+    };
+    Ok((outcome, Some(rule)))
+}
+
+fn same_outcome(outcome: &str, expect: &str) -> bool {
+    outcome.trim_end_matches('/') == expect.trim_end_matches('/')
+}
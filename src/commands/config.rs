@@ -0,0 +1,15 @@
+use crate::config::Config;
+use crate::Result;
+
+/// Prints the configuration the daemon would run with, after environment overrides and defaults.
+///
+/// `DOWNLOAD_ORGANISER_WATCH_DIR` is shown as the watch directory it becomes, paths are resolved against
+/// `baseDir`, and inline secrets are redacted.
+pub fn dump(mut config: Config) -> Result<()> {
+    config.watch_dirs = config.watch_dirs();
+    config.watch_dir = None;
+    config.state_dir = Some(config.state_dir());
+    config.retry = config.retry.effective();
+    print!("{}", serde_yaml::to_string(&config)?);
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::path::Path;
+use chrono::{Local, TimeZone};
+
+use crate::confirm::Awaiting;
+use crate::control::{self, ControlConfig};
+use crate::Result;
+
+/// Prints the files a running organiser is holding for approval.
+pub async fn list(config: &ControlConfig, profile: Option<&str>) -> Result<()> {
+    let query = profile.map(|p| format!("?profile={p}")).unwrap_or_default();
+    let awaiting: Vec<Awaiting> = serde_json::from_str(&control::call(config, "GET", &format!("/pending{query}"), "").await?)?;
+    if awaiting.is_empty() {
+        println!("no files are awaiting approval");
+    }
+    for file in awaiting {
+        print!("{}\n  rule {}, waiting since {}", file.path.display(), file.rule, time(file.since));
+        match file.expires {
+            Some(expires) => println!(", decided at {}", time(expires)),
+            None => println!(),
+        }
+    }
+    Ok(())
+}
+
+/// Approves (`approved`) or rejects the file at `path`, relative to the current directory unless
+/// it's absolute.
+pub async fn decide(config: &ControlConfig, profile: Option<&str>, path: &Path, approved: bool) -> Result<()> {
+    let query = profile.map(|p| format!("?profile={p}")).unwrap_or_default();
+    let path = std::path::absolute(path)?;
+    let endpoint = if approved { "approve" } else { "reject" };
+    control::call(config, "POST", &format!("/pending/{endpoint}{query}"), &serde_json::json!({ "path": path }).to_string()).await?;
+    match approved {
+        true => println!("approved {} - it has been queued", path.display()),
+        false => println!("rejected {} - it has been left where it is", path.display()),
+    }
+    Ok(())
+}
+
+fn time(secs: u64) -> String {
+    match Local.timestamp_opt(secs as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => secs.to_string(),
+    }
+}
@@ -0,0 +1,22 @@
+use std::fs;
+use std::path::Path;
+
+use crate::import::{maid, organize};
+use crate::Result;
+
+/// Prints `path`, a config for another organiser, converted to this crate's config format.
+///
+/// Anything that couldn't be translated is listed as comments at the top of the output.
+pub fn run(from: &str, path: &Path) -> Result<()> {
+    let source = fs::read_to_string(path).map_err(|e| format!("unable to read [{}]: {e}", path.display()))?;
+    let imported = match from {
+        "organize" => organize::convert(&source)?,
+        "maid" => maid::convert(&source)?,
+        other => return Err(format!("unknown import format [{other}] - expected organize or maid").into()),
+    };
+    print!("{}", imported.to_yaml()?);
+    if !imported.notes.is_empty() {
+        eprintln!("{} items could not be translated exactly - see the comments at the top of the output", imported.notes.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::config::{Action, Config};
+use crate::extract::{self, LinkPolicy, SUSPICIOUS_ENTRY_COUNT, SUSPICIOUS_RATIO, SUSPICIOUS_TOTAL_SIZE};
+use crate::template;
+use crate::Result;
+
+/// Prints what extracting `archive` would do under the selected (or first matching) unzip rule.
+///
+/// `rule` is the 1-based position of a rule in the config. Without it, the first rule whose regex
+/// matches the archive name and that has an unzip action is used.
+pub fn run(config: &Config, archive: &Path, rule: Option<usize>) -> Result<()> {
+    let name = archive.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+
+    let rule = match rule {
+        Some(index) => Some(config.rules.get(index.wrapping_sub(1))
+            .ok_or_else(|| format!("there is no rule number {index} - the config has {} rules", config.rules.len()))?),
+        None => config.rules.iter()
+            .find(|r| r.matches_name(name) && r.actions.iter().any(|a| matches!(a, Action::Unzip { .. }))),
+    };
+
+    let unzip = rule
+        .and_then(|r| r.actions.iter().find_map(|a| match a {
+            Action::Unzip { dest, symlinks, .. } => Some((template::dest(&config.base_dir, dest, archive, r.normalise_names), *symlinks)),
+            _ => None,
+        }));
+    let (dest, links) = match unzip {
+        Some((dest, links)) => (Some(dest), links),
+        None => (None, LinkPolicy::default()),
+    };
+
+    match (rule, &dest) {
+        (Some(rule), Some(_)) => println!("rule: {}", rule.regex.as_str()),
+        (Some(rule), None) => println!("rule: {} (has no unzip action - showing paths relative to the archive)", rule.regex.as_str()),
+        (None, _) => println!("rule: none matched (showing paths relative to the archive)"),
+    }
+
+    let dest = dest.unwrap_or_default();
+    let entries = extract::inspect(archive, &dest)?;
+
+    println!("{:>12} {:>12} {:>8}  {:<40} destination", "size", "compressed", "ratio", "entry");
+    let mut warnings = Vec::new();
+    for entry in &entries {
+        let destination = match &entry.destination {
+            Some(path) => path.display().to_string(),
+            None => {
+                warnings.push(format!("unsafe path [{}] would be skipped", entry.name));
+                "UNSAFE - skipped".to_string()
+            },
+        };
+        if entry.is_suspicious() {
+            warnings.push(format!("[{}] has a compression ratio above {SUSPICIOUS_RATIO}:1", entry.name));
+        }
+        if entry.is_symlink {
+            warnings.push(format!("[{}] is a symlink - it would be {}", entry.name, match links {
+                LinkPolicy::Skip => "skipped",
+                LinkPolicy::Create => "created if it points inside the destination",
+                LinkPolicy::Resolve => "replaced by a copy of its target if that's inside the destination",
+            }));
+        }
+        if entry.is_nested_archive() {
+            warnings.push(format!("[{}] is itself an archive", entry.name));
+        }
+        let name = if entry.is_dir { format!("{}/", entry.name.trim_end_matches('/')) } else { entry.name.clone() };
+        println!("{:>12} {:>12} {:>7.1}x  {:<40} {}", entry.size, entry.compressed_size, entry.ratio(), name, destination);
+    }
+
+    let total: u64 = entries.iter().map(|e| e.size).sum();
+    let compressed: u64 = entries.iter().map(|e| e.compressed_size).sum();
+    println!("{} entries, {total} bytes uncompressed, {compressed} bytes compressed", entries.len());
+
+    if total > SUSPICIOUS_TOTAL_SIZE {
+        warnings.push(format!("archive expands to more than {SUSPICIOUS_TOTAL_SIZE} bytes"));
+    }
+    if entries.len() > SUSPICIOUS_ENTRY_COUNT {
+        warnings.push(format!("archive has more than {SUSPICIOUS_ENTRY_COUNT} entries"));
+    }
+
+    for warning in &warnings {
+        println!("WARNING: {warning}");
+    }
+
+    Ok(())
+}
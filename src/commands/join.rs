@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use crate::split;
+use crate::Result;
+
+/// Joins the chunks listed in a manifest written by the `split` action back into the original
+/// file, or with `check`, only checks that they would.
+pub fn run(manifest: &Path, output: Option<&Path>, check: bool) -> Result<()> {
+    if check {
+        return match split::check(manifest)? {
+            Some(problem) => Err(problem.into()),
+            None => {
+                println!("chunks match {}", manifest.display());
+                Ok(())
+            },
+        }
+    }
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => manifest.with_file_name(split::original_name(manifest)?),
+    };
+    split::join(manifest, &output)?;
+    println!("joined into {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,10 @@
+pub mod bench;
+pub mod check;
+pub mod config;
+pub mod confirm;
+pub mod import;
+pub mod inspect;
+pub mod join;
+pub mod pause;
+pub mod replay;
+pub mod simulate;
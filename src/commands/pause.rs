@@ -0,0 +1,23 @@
+use crate::control::{self, ControlConfig};
+use crate::pause::PauseStatus;
+use crate::Result;
+
+/// Pauses (`paused`) or resumes a running organiser through its control API, of the rule with
+/// `rule` as its regex or of everything, then prints what's paused.
+pub async fn run(config: &ControlConfig, profile: Option<&str>, rule: Option<&str>, paused: bool) -> Result<()> {
+    let query = profile.map(|p| format!("?profile={p}")).unwrap_or_default();
+    let body = match rule {
+        Some(rule) => serde_json::json!({ "rule": rule }).to_string(),
+        None => String::new(),
+    };
+    let endpoint = if paused { "pause" } else { "resume" };
+    control::call(config, "POST", &format!("/{endpoint}{query}"), &body).await?;
+
+    let status: PauseStatus = serde_json::from_str(&control::call(config, "GET", &format!("/pause{query}"), "").await?)?;
+    println!("processing is {}", if status.state.all { "paused" } else { "running" });
+    if !status.state.rules.is_empty() {
+        println!("paused rules: {}", status.state.rules.join(", "));
+    }
+    println!("{} queued, {} held for paused rules", status.queued, status.held);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::StreamExt;
+
+use crate::config::{Config, Watcher};
+use crate::control::Request;
+use crate::outcome::ProcessingOutcome;
+use crate::recording::{self, Recorded};
+use crate::Result;
+
+/// How often the organiser is asked whether it's finished once every event has been sent.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Added to the longest cooldown to give the gap between events after which a longer one changes
+/// nothing, so quiet spells in a recording can be cut short.
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// Feeds the events recorded for profile `name` in `file` through an organiser with `config`, in
+/// order and with the gaps there were between them, and prints what became of each file.
+///
+/// The organiser doesn't watch anything meanwhile, so the only events are the recorded ones, and
+/// it has a single worker, so files are processed in the order their events arrived. It keeps its
+/// state in memory and runs none of the gate, watchdog, maintenance, reconciliation, scheduled
+/// deletions, outcome sinks, notifications, deadline or report, so a running daemon sharing the
+/// config isn't disturbed. Point the config at a copy of the tree to replay against it without
+/// touching the original.
+pub async fn run(name: String, mut config: Config, file: &Path, instant: bool, dry_run: bool) -> Result<()> {
+    let events = recording::read(file, &name)?;
+    if events.is_empty() {
+        return Err(format!("no events are recorded for profile [{name}] in [{}]", file.display()).into())
+    }
+    let longest = config.watch_dirs().iter().filter_map(|d| d.cooldown).chain([config.cooldown]).max().unwrap_or_default() + SETTLE;
+    config.record_events = false;
+    config.watcher = Watcher::Inotify;
+    config.workers = 1;
+    config.gate = None;
+    config.watchdog = None;
+    config.maintenance = None;
+    config.reconcile = None;
+    config.deadline = None;
+    config.report = None;
+    config.outcomes.clear();
+    config.notifications.clear();
+    for rule in &mut config.rules {
+        rule.notify_on.clear();
+    }
+
+    let organiser = Arc::new(crate::organiser(name, config, false, dry_run, false, true, None)?);
+    let mut outcomes = organiser.outcomes();
+    let (control, receiver) = mpsc::channel(8);
+    tokio::spawn(Arc::clone(&organiser).run(receiver));
+
+    // the organiser answers once its event loop is running
+    let (reply, ready) = oneshot::channel();
+    control.send(Request::Ping(reply)).await?;
+    ready.await?;
+
+    let count = events.len();
+    let mut feeding = tokio::spawn(feed(control.clone(), events, if instant { Duration::ZERO } else { longest }));
+    let mut fed = false;
+    let mut poll = tokio::time::interval(POLL);
+    // settled twice running, so a job between leaving the queue and being claimed isn't missed
+    let mut settled = 0;
+    while settled < 2 {
+        tokio::select! {
+            Some(outcome) = outcomes.next() => {
+                print(&outcome);
+                settled = 0;
+            },
+            result = &mut feeding, if !fed => {
+                result??;
+                fed = true;
+            },
+            _ = poll.tick(), if fed => {
+                let (reply, response) = oneshot::channel();
+                control.send(Request::Settled(reply)).await?;
+                settled = if response.await? { settled + 1 } else { 0 };
+            },
+        }
+    }
+    println!("replayed {count} events");
+    Ok(())
+}
+
+/// Sends each event to the organiser, after the gap there was before it, up to `longest`.
+async fn feed(control: mpsc::Sender<Request>, events: Vec<Recorded>, longest: Duration) -> Result<()> {
+    let mut previous: Option<DateTime<FixedOffset>> = None;
+    for event in events {
+        let time = event.time();
+        if let (Some(previous), Some(time)) = (previous, time) {
+            let gap = (time - previous).to_std().unwrap_or_default();
+            tokio::time::sleep(gap.min(longest)).await;
+        }
+        previous = time.or(previous);
+        control.send(Request::Replay(event)).await.map_err(|_| "organiser stopped during the replay")?;
+    }
+    Ok(())
+}
+
+fn print(outcome: &ProcessingOutcome) {
+    let result = outcome.result.name();
+    let file = outcome.file.display();
+    match (&outcome.dest, &outcome.reason) {
+        (Some(dest), _) => println!("{result:<9} {file} -> {}", dest.display()),
+        (None, Some(reason)) => println!("{result:<9} {file} ({reason})"),
+        (None, None) => println!("{result:<9} {file}"),
+    }
+}
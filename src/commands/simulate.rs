@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, HiddenPolicy};
+use crate::duration;
+use crate::extract;
+use crate::plan::{self, MoveTarget, Planned};
+use crate::rules::{self, Candidate, RuleIndex};
+use crate::users;
+use crate::Result;
+
+/// A directory in the simulated destination tree.
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<OsString, Node>,
+    /// Where the entry at this path would come from, empty for intermediate directories.
+    notes: Vec<String>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &Path, note: String) {
+        let node = path.iter().fold(self, |node, part| node.children.entry(part.to_owned()).or_default());
+        node.notes.push(note);
+    }
+
+    fn print(&self, prefix: &str) {
+        let count = self.children.len();
+        for (i, (name, child)) in self.children.iter().enumerate() {
+            let last = i + 1 == count;
+            let branch = if last { "└── " } else { "├── " };
+            let notes = if child.notes.is_empty() { String::new() } else { format!("  ← {}", child.notes.join(", ")) };
+            println!("{prefix}{branch}{}{notes}", name.to_string_lossy());
+            child.print(&format!("{prefix}{}", if last { "    " } else { "│   " }));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    tree: Node,
+    deleted: Vec<String>,
+    skipped: Vec<String>,
+    webhooks: Vec<String>,
+    subtitles: Vec<String>,
+    episodes: Vec<String>,
+    verified: Vec<String>,
+    recompressed: Vec<String>,
+    torrents: Vec<String>,
+    commands: Vec<String>,
+    unmatched: Vec<String>,
+    hidden: Vec<String>,
+}
+
+struct Simulation<'a> {
+    config: &'a Config,
+    base_dir: PathBuf,
+    root: &'a Path,
+    watch: Option<&'a str>,
+    hidden: HiddenPolicy,
+    index: RuleIndex,
+    report: Report,
+}
+
+/// Prints where every file under `dir` would end up if it arrived in a watch directory, without
+/// changing anything.
+///
+/// With `watch` set, rules are filtered by `appliesTo` as if the files arrived in that watch
+/// directory; otherwise every rule is considered. Each file is planned against the tree as it is
+/// now, so two files that would land on the same path are both shown there.
+pub fn run(config: &Config, dir: &Path, watch: Option<&str>) -> Result<()> {
+    let hidden = match watch {
+        Some(name) => config.watch_dirs().iter().find(|w| w.name == name)
+            .ok_or_else(|| format!("there is no watch directory named [{name}]"))?
+            .hidden_policy(),
+        None => config.hidden,
+    };
+    // finds the users of per-user watch directories, for `{user}`
+    users::expand(config.watch_dirs());
+
+    let mut simulation = Simulation {
+        config,
+        base_dir: PathBuf::from(&config.base_dir),
+        root: dir,
+        watch,
+        hidden,
+        index: RuleIndex::new(&config.rules),
+        report: Report::default(),
+    };
+    simulation.walk(dir)?;
+
+    let report = simulation.report;
+    println!("{}", simulation.base_dir.display());
+    report.tree.print("");
+    for (heading, lines) in [
+        ("deleted", &report.deleted),
+        ("skipped", &report.skipped),
+        ("webhooks", &report.webhooks),
+        ("subtitles downloaded", &report.subtitles),
+        ("episodes looked up", &report.episodes),
+        ("verified", &report.verified),
+        ("recompressed", &report.recompressed),
+        ("torrents submitted", &report.torrents),
+        ("commands run", &report.commands),
+        ("unmatched", &report.unmatched),
+        ("ignored as hidden", &report.hidden),
+    ] {
+        if !lines.is_empty() {
+            println!();
+            println!("{heading}:");
+            for line in lines {
+                println!("  {line}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Simulation<'_> {
+    fn walk(&mut self, dir: &Path) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let source = entry.path();
+            let is_dir = entry.file_type()?.is_dir();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if rules::is_hidden(&name) && self.hidden == HiddenPolicy::Ignore {
+                self.report.hidden.push(source.strip_prefix(self.root).unwrap_or(&source).display().to_string());
+                continue
+            }
+            // hidden directories are only searched when hidden entries are processed like any other
+            let descend = is_dir && (!rules::is_hidden(&name) || self.hidden == HiddenPolicy::Process);
+            if !self.simulate(&name, &source, is_dir)? && descend {
+                self.walk(&source)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Plans the first matching rule for one entry. Returns whether a rule matched.
+    fn simulate(&mut self, name: &str, source: &Path, is_dir: bool) -> Result<bool> {
+        let shown = source.strip_prefix(self.root).unwrap_or(source).display().to_string();
+        let candidate = Candidate {
+            name,
+            source,
+            is_dir,
+            watch_dir: self.watch,
+            size: None,
+            hidden: self.hidden,
+            base_dir: Some(&self.base_dir),
+            read_content: true,
+            from_archive: false,
+        };
+        let rule = match self.index.first_match(&self.config.rules, &candidate)? {
+            Some(rule) => rule,
+            None => {
+                // directories are only interesting if a rule handles them whole
+                if !is_dir {
+                    self.report.unmatched.push(shown);
+                }
+                return Ok(false)
+            },
+        };
+
+        for action in &rule.actions {
+            let planned = plan::plan_action(&self.base_dir, rule, action, name, source);
+            match &planned {
+                Planned::Move(MoveTarget::Move(to)) => self.place(to, shown.clone()),
+                Planned::Move(MoveTarget::Replace(to)) => self.place(to, format!("{shown} (replacing existing)")),
+                Planned::Move(MoveTarget::Merge(into)) => self.place(into, format!("{shown} (merged)")),
+                Planned::Move(MoveTarget::Skip(existing)) | Planned::Link(MoveTarget::Skip(existing)) => {
+                    self.report.skipped.push(format!("{shown} - {} already exists", existing.display()))
+                },
+                Planned::Move(MoveTarget::Already(at)) | Planned::Link(MoveTarget::Already(at)) => {
+                    self.report.skipped.push(format!("{shown} - already linked at {}", at.display()))
+                },
+                Planned::Link(MoveTarget::Move(to)) => self.place(to, format!("{shown} (linked)")),
+                Planned::Link(MoveTarget::Replace(to)) => self.place(to, format!("{shown} (linked, replacing existing)")),
+                Planned::Link(MoveTarget::Merge(into)) => self.place(into, format!("{shown} (linked, merged)")),
+                Planned::Extract(into) => self.extract(source, into, &shown),
+                Planned::Delete => self.report.deleted.push(shown.clone()),
+                Planned::DeleteAfter(after) => self.report.deleted.push(format!("{shown} (in {})", duration::format(after))),
+                Planned::Webhook(method) => self.report.webhooks.push(format!("{shown} - {method}")),
+                Planned::Subtitles(languages) => self.report.subtitles.push(format!("{shown} - {}", languages.join(", "))),
+                Planned::Episode(Some(episode), into) => self.report.episodes.push(format!("{shown} - {episode} under {}", into.display())),
+                Planned::Episode(None, _) => self.report.episodes.push(format!("{shown} - no season and episode in the name")),
+                Planned::Torrent(client, category) => match category {
+                    Some(category) => self.report.torrents.push(format!("{shown} - {client} as {category}")),
+                    None => self.report.torrents.push(format!("{shown} - {client}")),
+                },
+                Planned::Verify => self.report.verified.push(shown.clone()),
+                Planned::Exec(program) => self.report.commands.push(format!("{shown} - {program}")),
+                Planned::Photo(targets) => match targets.first() {
+                    Some(MoveTarget::Skip(existing)) => self.report.skipped.push(format!("{shown} - {} already exists", existing.display())),
+                    _ => for target in targets {
+                        self.place(target.path(), shown.clone());
+                    },
+                },
+                Planned::Recompress(to) => self.report.recompressed.push(format!("{shown} - into {}", to.file_name().unwrap_or_default().to_string_lossy())),
+                Planned::Split(to, 1) => self.place(to, shown.clone()),
+                Planned::Split(manifest, chunks) => self.place(manifest, format!("{shown} (split into {chunks} chunks)")),
+                Planned::LinkFarm(targets) => for target in targets.iter().filter(|t| matches!(t, MoveTarget::Move(_))) {
+                    self.place(target.path(), format!("{shown} (symlinked)"));
+                },
+            }
+            if planned.stops() {
+                break
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn extract(&mut self, archive: &Path, into: &Path, shown: &str) {
+        match extract::inspect(archive, into) {
+            Ok(entries) => {
+                for entry in entries.iter().filter(|e| !e.is_dir) {
+                    match &entry.destination {
+                        Some(to) => self.place(to, format!("{shown}: {}", entry.name)),
+                        None => self.report.skipped.push(format!("{shown}: {} - unsafe path", entry.name)),
+                    }
+                }
+            },
+            Err(err) => self.place(into, format!("extracted from {shown} (unreadable archive: {err})")),
+        }
+    }
+
+    fn place(&mut self, to: &Path, note: String) {
+        let relative = to.strip_prefix(&self.base_dir).unwrap_or(to);
+        self.report.tree.insert(relative, note);
+    }
+}
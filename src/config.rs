@@ -0,0 +1,1234 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use inotify::{EventMask, WatchMask};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use regex::Regex;
+
+use crate::clock::{self, DatesConfig};
+use crate::confirm::ConfirmConfig;
+use crate::control::ControlConfig;
+use crate::dbus::DbusConfig;
+use crate::deadline::DeadlineConfig;
+use crate::diskimage::ImageCondition;
+use crate::duplicate::Compare;
+use crate::duration;
+use crate::episodes::Provider;
+use crate::extract::{self, Cleanup, LinkPolicy};
+use crate::fsutil;
+use crate::glob::Glob;
+use crate::group::{self, RuleGroup};
+use crate::header::{self, HeaderCondition};
+use crate::index::DuplicateIndexConfig;
+use crate::gate::GateConfig;
+use crate::privilege::RunAs;
+use crate::sandbox::SandboxConfig;
+use crate::ledger::LedgerConfig;
+use crate::linkfarm::LinkFarmConfig;
+use crate::lint;
+use crate::maintenance::MaintenanceConfig;
+use crate::migrate;
+use crate::mode::{self, Modes};
+use crate::notify::{Channel, NotifyEvent};
+use crate::normalise::Normalisation;
+use crate::pattern::{Engine, Pattern};
+use crate::ocr::ContentCondition;
+use crate::pdf::PdfCondition;
+use crate::photos::PhotoConfig;
+use crate::recompress::RecompressConfig;
+use crate::logging;
+use crate::outcome::Sink;
+use crate::queue::QueueOrder;
+use crate::removable::Volume;
+use crate::reconcile::ReconcileConfig;
+use crate::report::ReportConfig;
+use crate::retry::{ActionClass, RetryPolicies};
+use crate::secret::SecretRef;
+use crate::selinux::Relabel;
+use crate::size::{self, SizeRange};
+use crate::snapshot::SnapshotConfig;
+use crate::template;
+use crate::uploads::UploadConfig;
+use crate::users;
+use crate::torrent::{Client as TorrentClient, TorrentConfig};
+use crate::watchdog::WatchdogConfig;
+use crate::verify::VerifyConfig;
+use crate::version;
+use crate::Result;
+
+/// Prefix for all environment variables that override configuration values.
+const ENV_PREFIX: &str = "DOWNLOAD_ORGANISER_";
+
+/// Environment variable holding a complete YAML configuration document.
+const CONFIG_YAML_ENV: &str = "CONFIG_YAML";
+
+/// Environment variable naming a YAML configuration file, read again on reload.
+const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+
+/// Where a config file is looked for, under `$XDG_CONFIG_HOME` (or `~/.config`) and `/etc`, when
+/// neither `--config` nor the environment says.
+const DEFAULT_FILE: &str = "download-organiser/config.yml";
+
+/// The file given with `--config`, which takes precedence over the environment.
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Version of the config format, raised when a change to it isn't backwards compatible.
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Config {
+    /// Version of the config format. Always the current one once loaded, as older configs are
+    /// migrated first.
+    #[serde(default="default_version")]
+    pub version: u32,
+    #[serde(rename="baseDir")]
+    pub base_dir: PathBuf,
+    /// Single watch directory set by `DOWNLOAD_ORGANISER_WATCH_DIR`. It is named `default`.
+    #[serde(skip)]
+    pub watch_dir: Option<String>,
+    #[serde(rename="watchDirs", default)]
+    pub watch_dirs: Vec<WatchDir>,
+    /// Removable volumes with a directory that's organised while they're mounted, each named like
+    /// a watch directory.
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub removable: Vec<Volume>,
+    /// Where persistent state is kept, relative to `baseDir`. Defaults to `.download-organiser`.
+    #[serde(rename="stateDir")]
+    pub state_dir: Option<PathBuf>,
+    #[serde(rename="duplicateIndex")]
+    pub duplicate_index: Option<DuplicateIndexConfig>,
+    /// How long a file must go without further events before it is processed, so repeated
+    /// events for the same file coalesce into one run. Zero (the default) processes immediately.
+    #[serde(default, deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+    pub cooldown: Duration,
+    /// Number of files processed concurrently.
+    #[serde(default="default_workers")]
+    pub workers: usize,
+    /// Queue length at which intake pauses, resuming once it has drained to half this. Unbounded
+    /// when unset.
+    #[serde(rename="maxQueueDepth")]
+    pub max_queue_depth: Option<usize>,
+    /// Order in which queued files are processed when workers are busy.
+    #[serde(default)]
+    pub order: QueueOrder,
+    /// Where files that can't be processed are moved, relative to `baseDir`. They are left in
+    /// place when unset.
+    #[serde(rename="failedDir")]
+    pub failed_dir: Option<PathBuf>,
+    /// Where intermediate files, such as entries being extracted, are written until they're
+    /// complete, relative to `baseDir`. Defaults to `.work` on each destination's filesystem.
+    #[serde(rename="workDir")]
+    pub work_dir: Option<PathBuf>,
+    /// Whether dot-files and dot-directories are processed. Can be overridden per watch directory.
+    #[serde(default)]
+    pub hidden: HiddenPolicy,
+    /// How symlinks that appear in a watch directory are handled.
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    /// How FIFOs, sockets and device nodes that appear in a watch directory are handled.
+    #[serde(rename="specialFiles", default)]
+    pub special_files: SpecialFilePolicy,
+    /// Unicode form for destination names, for rules that don't set their own.
+    #[serde(rename="normaliseNames", skip_serializing_if="Option::is_none")]
+    pub normalise_names: Option<Normalisation>,
+    /// Timezone and format of dates in destinations and duplicate names. The same for every
+    /// profile.
+    #[serde(default)]
+    pub dates: DatesConfig,
+    /// How moved and extracted files are given the SELinux label of their destination, for rules
+    /// that don't set their own.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub relabel: Option<Relabel>,
+    /// Holds files matched by rules that delete or overwrite until they're approved.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub confirm: Option<ConfirmConfig>,
+    /// Log what every rule would do without touching any files.
+    #[serde(rename="dryRun", default)]
+    pub dry_run: bool,
+    /// A dry run that makes no other changes either, so the organiser can watch a shared server and
+    /// only report what it would do.
+    #[serde(default)]
+    pub audit: bool,
+    /// Log filter directives such as `warn,extract=debug`. `DOWNLOAD_ORGANISER_LOG` and `-v`/`-q`
+    /// take precedence.
+    #[serde(rename="logFilter")]
+    pub log_filter: Option<String>,
+    /// Identical warnings within this window are logged once, then summarised with a count. Zero
+    /// logs every occurrence.
+    #[serde(rename="logRepeatWindow", default="default_log_repeat_window",
+        deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+    pub log_repeat_window: Duration,
+    /// Remembers processed files so repeated events for them are skipped.
+    pub ledger: Option<LedgerConfig>,
+    /// Holds back other processes' opens of files in the watch directories until they've been
+    /// organised.
+    pub gate: Option<GateConfig>,
+    /// How the watch directories are watched.
+    #[serde(default)]
+    pub watcher: Watcher,
+    /// Reports the event loop or a worker making no progress, e.g. on a dead mount.
+    pub watchdog: Option<WatchdogConfig>,
+    /// How soon files should be organised after arriving, with a warning, metric and optional
+    /// notification for each file that isn't.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub deadline: Option<DeadlineConfig>,
+    /// Housekeeping run on a schedule, such as deleting old entries from destinations.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub maintenance: Option<MaintenanceConfig>,
+    /// A daily summary of files organised, failures and unmatched files.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub report: Option<ReportConfig>,
+    /// Checks the outcome history and saved state against the filesystem on start.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub reconcile: Option<ReconcileConfig>,
+    /// Appends every event the watcher reads to `events.jsonl` in `stateDir`, so the sequence can
+    /// be replayed with `replay`.
+    #[serde(rename="recordEvents", default)]
+    pub record_events: bool,
+    /// Takes a filesystem snapshot before a rescan queues many files.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub snapshot: Option<SnapshotConfig>,
+    /// Where the outcome of every file is reported, besides D-Bus.
+    #[serde(default)]
+    pub outcomes: Vec<Sink>,
+    /// Channels, by name, that rules with `notifyOn` send notifications to.
+    #[serde(default)]
+    pub notifications: BTreeMap<String, Channel>,
+    /// Where the control API listens. Only read from the top level of the config.
+    pub control: Option<ControlConfig>,
+    /// Which bus to offer the D-Bus service on. Only read from the top level of the config.
+    pub dbus: Option<DbusConfig>,
+    /// User to switch to once the watches are set up, when started as root.
+    #[serde(rename="runAs")]
+    pub run_as: Option<RunAs>,
+    #[serde(default)]
+    pub retry: RetryPolicies,
+    /// Rules, and `group`s of rules sharing conditions.
+    #[serde(deserialize_with="group::deserialize_rules", serialize_with="group::serialize_rules")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+pub enum SymlinkPolicy {
+    /// Leave symlinks alone.
+    #[default]
+    #[serde(rename="ignore")]
+    Ignore,
+    /// Apply rules to the file or directory the link points at, removing the link if its target
+    /// is moved or deleted.
+    #[serde(rename="follow")]
+    Follow,
+    /// Apply rules to the link itself. Relative links are rewritten so they still resolve.
+    #[serde(rename="move-link")]
+    MoveLink,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+pub enum SpecialFilePolicy {
+    /// Leave them alone.
+    #[default]
+    #[serde(rename="ignore")]
+    Ignore,
+    /// Remove them from the watch directory.
+    #[serde(rename="delete")]
+    Delete,
+    /// Leave them alone, but log a warning and report them as failed.
+    #[serde(rename="report")]
+    Report,
+}
+
+/// How entries whose names start with a dot are treated.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum HiddenPolicy {
+    /// Leave them alone - they are usually application state rather than downloads.
+    #[default]
+    #[serde(rename="ignore")]
+    Ignore,
+    /// Treat them like any other entry.
+    #[serde(rename="process")]
+    Process,
+    /// Only rules with `matchHidden: true` consider them.
+    #[serde(rename="opt-in")]
+    OptIn,
+}
+
+/// A directory to watch for new files, named so rules can be scoped to it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WatchDir {
+    pub name: String,
+    /// Relative paths are resolved against `baseDir`. A `*` component, as in `/home/*/Downloads`,
+    /// watches the directory of every user it matches, each under this name.
+    pub path: PathBuf,
+    /// Filesystem events that cause a file to be processed.
+    #[serde(default="default_events")]
+    pub events: Vec<TriggerEvent>,
+    /// Overrides the global `cooldown` for this directory.
+    #[serde(default, deserialize_with="duration::deserialize_option", serialize_with="duration::serialize_option")]
+    pub cooldown: Option<Duration>,
+    /// Overrides the global `hidden` policy for this directory.
+    pub hidden: Option<HiddenPolicy>,
+    /// Also watch subdirectories, including ones created later.
+    #[serde(default)]
+    pub recursive: bool,
+    /// How many levels of subdirectories are watched when `recursive`. Unlimited when unset.
+    #[serde(rename="maxDepth")]
+    pub max_depth: Option<usize>,
+    /// Globs for paths, relative to the watch directory, that are never watched or processed.
+    #[serde(rename="excludePaths", default)]
+    pub exclude_paths: Vec<Glob>,
+    /// Files from directories with a higher priority are always processed before queued files from
+    /// lower ones, whatever the queue `order`.
+    #[serde(default)]
+    pub priority: i32,
+    /// Treats the directory as an upload area, only processing files once their uploader has
+    /// renamed them from a name that marks them as in progress.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub uploads: Option<UploadConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Watcher {
+    /// A watch on each directory, and on each subdirectory of recursive watch directories.
+    #[default]
+    #[serde(rename="inotify")]
+    Inotify,
+    /// One fanotify mark on each filesystem the watch directories are on, with events outside them
+    /// passed over. Cheaper for very large trees, but needs `CAP_SYS_ADMIN`.
+    #[serde(rename="fanotify")]
+    Fanotify,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum TriggerEvent {
+    /// A file opened for writing was closed - the usual signal that a download is complete.
+    #[serde(rename="close-write")]
+    CloseWrite,
+    /// A file or directory was moved (or renamed) into the directory.
+    #[serde(rename="moved-to")]
+    MovedTo,
+    /// A file or directory was created, including hardlinks. Files may still be being written.
+    #[serde(rename="create")]
+    Create,
+}
+
+fn default_log_repeat_window() -> Duration {
+    logging::DEFAULT_REPEAT_WINDOW
+}
+
+fn default_version() -> u32 {
+    SCHEMA_VERSION
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_events() -> Vec<TriggerEvent> {
+    vec![TriggerEvent::CloseWrite, TriggerEvent::MovedTo]
+}
+
+impl TriggerEvent {
+    fn watch_mask(&self) -> WatchMask {
+        match self {
+            TriggerEvent::CloseWrite => WatchMask::CLOSE_WRITE,
+            TriggerEvent::MovedTo => WatchMask::MOVED_TO,
+            TriggerEvent::Create => WatchMask::CREATE,
+        }
+    }
+
+    fn event_mask(&self) -> EventMask {
+        match self {
+            TriggerEvent::CloseWrite => EventMask::CLOSE_WRITE,
+            TriggerEvent::MovedTo => EventMask::MOVED_TO,
+            TriggerEvent::Create => EventMask::CREATE,
+        }
+    }
+}
+
+impl WatchDir {
+    /// The effective dot-file policy, once resolved by `Config::watch_dirs`.
+    pub fn hidden_policy(&self) -> HiddenPolicy {
+        self.hidden.unwrap_or_default()
+    }
+
+    pub fn watch_mask(&self) -> WatchMask {
+        let mask = self.events.iter().fold(WatchMask::ONLYDIR, |mask, e| mask | e.watch_mask());
+        if self.recursive {
+            // new subdirectories need watches of their own
+            mask | WatchMask::CREATE | WatchMask::MOVED_TO
+        } else {
+            mask
+        }
+    }
+
+    /// This directory with its path resolved against `base_dir` and its hidden policy filled in.
+    pub fn resolve(self, base_dir: &Path, hidden: HiddenPolicy) -> WatchDir {
+        WatchDir { path: base_dir.join(&self.path), hidden: Some(self.hidden.unwrap_or(hidden)), ..self }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.events.is_empty() {
+            return Err(format!("watch directory [{}] has no events configured", self.name).into())
+        }
+        if self.max_depth.is_some() && !self.recursive {
+            return Err(format!("watch directory [{}] sets maxDepth without recursive", self.name).into())
+        }
+        if self.uploads.is_some() && !self.events.contains(&TriggerEvent::MovedTo) {
+            return Err(format!("watch directory [{}] sets uploads without the moved-to event, so finished uploads wouldn't be seen", self.name).into())
+        }
+        users::validate(&self.name, &self.path)?;
+        Ok(())
+    }
+
+    /// Whether `name` is a file an uploader is still writing, in an upload area.
+    pub fn is_uploading(&self, name: &str) -> bool {
+        self.uploads.as_ref().is_some_and(|uploads| uploads.in_progress(name))
+    }
+
+    /// Whether a path relative to this directory is excluded by `excludePaths`.
+    pub fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude_paths.iter().any(|glob| glob.matches_path(relative))
+    }
+
+    /// Whether a subdirectory at this path, relative to the watch directory, should be watched.
+    pub fn watches_subdir(&self, relative: &Path) -> bool {
+        let depth = relative.components().count();
+        self.recursive && self.max_depth.map(|max| depth <= max).unwrap_or(true) && !self.is_excluded(relative)
+    }
+
+    /// Whether an event with this mask should cause processing.
+    pub fn is_triggered_by(&self, mask: EventMask) -> bool {
+        self.events.iter().any(|e| mask.contains(e.event_mask()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Rule {
+    pub regex: Pattern,
+    /// Names matching this are turned away even though `regex` matches them, for "X but not Y"
+    /// rules the regex syntax has no lookaround for.
+    #[serde(rename="excludeRegex", default, skip_serializing_if="Option::is_none")]
+    pub exclude_regex: Option<Pattern>,
+    /// Engine `regex` and `excludeRegex` run on. `fancy` allows lookaround and backreferences.
+    #[serde(default)]
+    pub engine: Engine,
+    /// Names of the watch directories this rule is limited to. All directories when unset.
+    #[serde(rename="appliesTo")]
+    pub applies_to: Option<Vec<String>>,
+    /// Used by the `priority` queue order; higher values are processed first.
+    #[serde(default)]
+    pub priority: i32,
+    /// Whether the rule matches files, directories, or both. Defaults to files.
+    #[serde(default)]
+    pub kind: EntryKind,
+    /// Minimum size of the file, or of everything inside the directory, such as `700MB`. Also
+    /// accepts comparisons (`> 1.5GiB`) and ranges (`700MB..4GB`).
+    #[serde(rename="minSize", default, deserialize_with="size::deserialize_min")]
+    pub min_size: Option<SizeRange>,
+    /// Maximum size, like `minSize` but with a bare size as the upper bound.
+    #[serde(rename="maxSize", default, deserialize_with="size::deserialize_max")]
+    pub max_size: Option<SizeRange>,
+    /// Free space required on the volume of the rule's destination, e.g. `>= 50GB`. When it isn't
+    /// met the rule is skipped, so a later rule can send the file to an overflow destination.
+    #[serde(rename="destFreeSpace", default, deserialize_with="size::deserialize_min")]
+    pub dest_free_space: Option<SizeRange>,
+    /// Number of entries allowed in the rule's destination directory before the rule is skipped,
+    /// e.g. `< 10000`.
+    #[serde(rename="destFileCount", default, deserialize_with="size::deserialize_max_count")]
+    pub dest_file_count: Option<SizeRange>,
+    /// Only match if the would-be destination does (`true`) or doesn't (`false`) exist already.
+    /// For a move that's the file's path in the destination; for unzip, the destination directory.
+    #[serde(rename="destExists", skip_serializing_if="Option::is_none")]
+    pub dest_exists: Option<bool>,
+    /// Only match if the would-be destination exists and was modified more recently than the
+    /// source (`true`), or if it doesn't (`false`).
+    #[serde(rename="destNewerThanSource", skip_serializing_if="Option::is_none")]
+    pub dest_newer_than_source: Option<bool>,
+    /// Only match files that do (`true`) or don't (`false`) have other hard links, such as a file
+    /// a torrent client seeds from that's also linked into the library. A directory counts if any
+    /// file inside it does.
+    #[serde(rename="isHardlinkedElsewhere", skip_serializing_if="Option::is_none")]
+    pub is_hardlinked_elsewhere: Option<bool>,
+    /// Only match files with a hard link already somewhere under this directory, relative to
+    /// `baseDir`. A directory matches if any file inside it does.
+    #[serde(rename="hardlinkedInto", skip_serializing_if="Option::is_none")]
+    pub hardlinked_into: Option<PathBuf>,
+    /// Only match PDFs and images whose text matches, found with OCR when they have no text layer.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub content: Option<ContentCondition>,
+    /// Only match PDFs whose document information matches.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub pdf: Option<PdfCondition>,
+    /// Only match ISO and raw disk images, recognised by their contents, whose format and volume
+    /// label match.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub image: Option<ImageCondition>,
+    /// Only match files whose first `headerBytes` bytes, read as text, match this regex.
+    #[serde(rename="headerRegex", with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub header_regex: Option<Regex>,
+    /// Only match files that start with these bytes, written in hex such as `25 50 44 46`.
+    #[serde(rename="headerHex", default, deserialize_with="header::deserialize_hex", serialize_with="header::serialize_hex", skip_serializing_if="Option::is_none")]
+    pub header_hex: Option<Vec<u8>>,
+    /// How many bytes from the start of the file `headerRegex` looks at. Defaults to 512.
+    #[serde(rename="headerBytes", skip_serializing_if="Option::is_none")]
+    pub header_bytes: Option<usize>,
+    /// Overrides the global retry policies for this rule's actions.
+    #[serde(default)]
+    pub retry: RetryPolicies,
+    /// Only match files extracted by an earlier rule's `reprocess` unzip (`true`), or only files
+    /// that arrived in a watch directory (`false`).
+    #[serde(rename="fromArchive", skip_serializing_if="Option::is_none")]
+    pub from_archive: Option<bool>,
+    /// Lets the rule match dot-files when the `hidden` policy is `opt-in`.
+    #[serde(rename="matchHidden", default)]
+    pub match_hidden: bool,
+    /// When to send a notification about a file this rule handles: on `match`, `success` or
+    /// `failure`.
+    #[serde(rename="notifyOn", default, skip_serializing_if="Vec::is_empty")]
+    pub notify_on: Vec<NotifyEvent>,
+    /// Names of the `notifications` channels to send to. Every channel when unset.
+    #[serde(rename="notifyVia", skip_serializing_if="Option::is_none")]
+    pub notify_via: Option<Vec<String>>,
+    /// Let the rule's destinations be outside `baseDir`, such as another mount. Otherwise a
+    /// destination that ends up outside it, e.g. through `..`, is refused.
+    #[serde(rename="allowOutsideBase", default)]
+    pub allow_outside_base: bool,
+    /// Unicode form the names of directories and files the rule creates are written in, and in
+    /// which existing names are compared to spot the same name written differently. Defaults to
+    /// the top-level setting.
+    #[serde(rename="normaliseNames", skip_serializing_if="Option::is_none")]
+    pub normalise_names: Option<Normalisation>,
+    /// Octal mode, such as `0644`, for the files the rule puts in its destination. They keep the
+    /// mode they had, or the one stored in the archive, when unset.
+    #[serde(rename="fileMode", default, deserialize_with="mode::deserialize_option", serialize_with="mode::serialize_option", skip_serializing_if="Option::is_none")]
+    pub file_mode: Option<u32>,
+    /// Octal mode, such as `0755`, for the directories the rule creates. They get the process
+    /// umask's when unset.
+    #[serde(rename="dirMode", default, deserialize_with="mode::deserialize_option", serialize_with="mode::serialize_option", skip_serializing_if="Option::is_none")]
+    pub dir_mode: Option<u32>,
+    /// How the files the rule moves or extracts are given the SELinux label of their destination.
+    /// Defaults to the top-level setting, and to leaving them as they are.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub relabel: Option<Relabel>,
+    /// Whether files the rule matches wait for approval when `confirm` is set. Defaults to whether
+    /// the rule deletes, or overwrites what's at its destination.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub confirm: Option<bool>,
+    /// Log what this rule would do without touching any files, for trialling new rules.
+    #[serde(rename="dryRun", default)]
+    pub dry_run: bool,
+    /// User the rule's filesystem actions and commands run as. Needs the daemon to keep root.
+    #[serde(rename="runAs", skip_serializing_if="Option::is_none")]
+    pub run_as: Option<RunAs>,
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub actions: Vec<Action>,
+    /// Example names and where they should end up, run by the `check` command.
+    #[serde(default)]
+    pub tests: Vec<RuleTest>,
+    /// The group the rule is in, whose conditions it has to meet too.
+    #[serde(skip)]
+    pub group: Option<Arc<RuleGroup>>,
+}
+
+/// An example run through all the rules by `download-organiser check`.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RuleTest {
+    pub name: String,
+    /// The destination of the first move or unzip action, `delete`, `webhook` or `none` if no rule
+    /// should match.
+    pub expect: String,
+    /// Size to check `minSize` and `maxSize` against. Zero when unset.
+    pub size: Option<String>,
+    /// Watch directory the name arrives in, for `appliesTo`. All rules are considered when unset.
+    #[serde(rename="watchDir", skip_serializing_if="Option::is_none")]
+    pub watch_dir: Option<String>,
+    /// Treat the name as a directory rather than a file.
+    #[serde(default)]
+    pub directory: bool,
+    /// Treat the name as extracted from an archive, for `fromArchive`.
+    #[serde(rename="fromArchive", default)]
+    pub from_archive: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    #[default]
+    #[serde(rename="file")]
+    File,
+    #[serde(rename="directory")]
+    Directory,
+    #[serde(rename="any")]
+    Any,
+}
+
+impl EntryKind {
+    pub fn matches(&self, is_dir: bool) -> bool {
+        match self {
+            EntryKind::File => !is_dir,
+            EntryKind::Directory => is_dir,
+            EntryKind::Any => true,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Action {
+    #[serde(rename="move")]
+    Move{
+        dest: String,
+        duplicate: DuplicateAction,
+        /// How a file already at the destination is compared with this one.
+        #[serde(default)]
+        compare: Compare,
+        /// New name for the file, which can use the same tokens as `dest`. Keeps its name when unset.
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        rename: Option<String>,
+    },
+    /// Hard links the file into `dest` and leaves it where it is, so a torrent client can keep
+    /// seeding it while the library has it straight away. Directories are linked file by file.
+    #[serde(rename="linkIntoLibrary")]
+    LinkIntoLibrary{
+        dest: String,
+        duplicate: DuplicateAction,
+        #[serde(default)]
+        compare: Compare,
+        /// Name for the link, which can use the same tokens as `dest`. Keeps the file's name when unset.
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        rename: Option<String>,
+    },
+    #[serde(rename="unzip")]
+    Unzip{
+        dest: String,
+        /// Test every entry's CRC before extracting anything, so a corrupt archive leaves no partial
+        /// output. Corrupt archives are moved to `failedDir` when it is set.
+        #[serde(default)]
+        verify: bool,
+        /// Junk deleted from the output once extracted: `true` for the defaults, or the patterns to use.
+        #[serde(default, deserialize_with="extract::deserialize_cleanup", skip_serializing_if="Option::is_none")]
+        cleanup: Option<Cleanup>,
+        /// Run the extracted files through the rules as if they had arrived, with `fromArchive`
+        /// set, rather than leaving them where they were extracted to.
+        #[serde(default)]
+        reprocess: bool,
+        /// What to do with entries that are symlinks.
+        #[serde(default)]
+        symlinks: LinkPolicy,
+    },
+    #[serde(rename="delete")]
+    Delete,
+    /// Deletes the file once `duration` has passed, e.g. when a torrent client is done seeding it
+    /// after `linkIntoLibrary`. The schedule is kept in `stateDir`, so it outlasts a restart.
+    #[serde(rename="deleteAfter")]
+    DeleteAfter{
+        #[serde(deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+        duration: Duration,
+    },
+    #[serde(rename="webhook")]
+    Webhook{
+        url: SecretRef,
+        #[serde(default="default_webhook_method")]
+        method: String,
+        #[serde(default)]
+        headers: BTreeMap<String, SecretRef>,
+    },
+    /// Downloads subtitles for a video from OpenSubtitles, saving them beside it. Put it after the
+    /// move, so they end up next to the moved file.
+    #[serde(rename="subtitles")]
+    Subtitles{
+        /// ISO 639 codes such as `en` or `pt-BR`.
+        languages: Vec<String>,
+        #[serde(rename="apiKey")]
+        api_key: SecretRef,
+        /// OpenSubtitles account to log in with, for a larger download quota.
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        username: Option<SecretRef>,
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        password: Option<SecretRef>,
+    },
+    /// Looks up the episode a release is of and moves it under `dest` as
+    /// `Show (Year)/Season 01/Show - S01E02 - Title.ext`.
+    #[serde(rename="episode")]
+    Episode{
+        dest: String,
+        #[serde(default)]
+        provider: Provider,
+        #[serde(rename="apiKey")]
+        api_key: SecretRef,
+        duplicate: DuplicateAction,
+        #[serde(default)]
+        compare: Compare,
+    },
+    /// Submits a `.torrent` or `.magnet` file to a torrent client, then removes it.
+    #[serde(rename="torrent")]
+    Torrent(TorrentConfig),
+    /// Checks the file against the checksum and signature published beside it. A file that fails
+    /// is moved to `failedDir` when it is set, and the rule's remaining actions don't run.
+    #[serde(rename="verify")]
+    Verify(VerifyConfig),
+    /// Moves a photo along with the other files of its shot, with RAW files and the rest going to
+    /// parallel directories.
+    #[serde(rename="photo")]
+    Photo(PhotoConfig),
+    /// Re-compresses a zip more tightly, as a zstd-compressed tar or a zip deflated at a higher
+    /// level, in place of the original. Later actions see the new archive.
+    #[serde(rename="recompress")]
+    Recompress(RecompressConfig),
+    /// Splits a file larger than `chunkSize` into numbered chunks in `dest`, with a manifest to
+    /// check and join them by, for destinations that limit how large a file can be. Smaller files
+    /// are moved there whole.
+    #[serde(rename="split")]
+    Split{
+        #[serde(rename="chunkSize", deserialize_with="size::deserialize")]
+        chunk_size: u64,
+        dest: String,
+    },
+    /// Symlinks the file into several directories, pointing at wherever the earlier actions put it.
+    #[serde(rename="linkFarm")]
+    LinkFarm(LinkFarmConfig),
+    /// Runs a program. `{path}` and `{name}` in its arguments are replaced with the file's path and
+    /// name, and `{dest}` with the first of `dests`.
+    #[serde(rename="exec")]
+    Exec{
+        command: Vec<String>,
+        /// Directories the command writes to, relative to `baseDir`.
+        #[serde(default)]
+        dests: Vec<String>,
+        #[serde(default)]
+        sandbox: SandboxConfig,
+    },
+}
+
+impl Rule {
+    /// Whether the rule has any size condition, which means the entry has to be measured.
+    pub fn has_size_condition(&self) -> bool {
+        self.min_size.is_some() || self.max_size.is_some()
+    }
+
+    /// Directory of the first move, link, unzip or episode action, relative to `baseDir`, which
+    /// destination conditions are checked against.
+    pub fn destination(&self) -> Option<&str> {
+        self.actions.iter().find_map(|a| match a {
+            Action::Move { dest, .. } | Action::LinkIntoLibrary { dest, .. } | Action::Unzip { dest, .. } | Action::Episode { dest, .. } | Action::Split { dest, .. } => Some(dest.as_str()),
+            Action::Photo(photo) => Some(photo.raw.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn header(&self) -> Option<HeaderCondition<'_>> {
+        HeaderCondition::of(self.header_regex.as_ref(), self.header_hex.as_ref(), self.header_bytes)
+    }
+
+    pub fn has_link_condition(&self) -> bool {
+        self.is_hardlinked_elsewhere.is_some() || self.hardlinked_into.is_some()
+    }
+
+    pub fn has_destination_condition(&self) -> bool {
+        self.dest_free_space.is_some() || self.dest_file_count.is_some()
+            || self.dest_exists.is_some() || self.dest_newer_than_source.is_some()
+    }
+
+    pub fn modes(&self) -> Modes {
+        Modes { file: self.file_mode, dir: self.dir_mode }
+    }
+
+    /// Whether the rule deletes the file, or can overwrite an existing one at its destination.
+    pub fn is_destructive(&self) -> bool {
+        self.actions.iter().any(|a| match a {
+            Action::Delete | Action::DeleteAfter { .. } => true,
+            Action::Move { duplicate, compare, .. } | Action::LinkIntoLibrary { duplicate, compare, .. } | Action::Episode { duplicate, compare, .. } =>
+                *duplicate == DuplicateAction::Overwrite || *compare == Compare::Version,
+            Action::Photo(photo) => photo.duplicate == DuplicateAction::Overwrite,
+            _ => false,
+        })
+    }
+
+    /// Whether files the rule matches wait for approval when confirmation is configured.
+    pub fn needs_confirmation(&self) -> bool {
+        self.confirm.unwrap_or_else(|| self.is_destructive())
+    }
+
+    /// Whether `name` matches the rule's regex and not its `excludeRegex`.
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.regex.is_match(name) && !self.excludes(name)
+    }
+
+    /// Whether the rule's `excludeRegex` turns `name` away.
+    pub fn excludes(&self, name: &str) -> bool {
+        self.exclude_regex.as_ref().is_some_and(|r| r.is_match(name))
+    }
+
+    pub fn applies_to(&self, watch_dir: &str) -> bool {
+        self.applies_to.as_ref().map(|names| names.iter().any(|n| n == watch_dir)).unwrap_or(true)
+            && self.group.as_ref().is_none_or(|g| g.conditions.applies_to(watch_dir))
+    }
+
+    /// Whether the rule, and its group, handle files or directories like this one.
+    pub fn kind_matches(&self, is_dir: bool) -> bool {
+        self.kind.matches(is_dir) && self.group.as_ref().and_then(|g| g.conditions.kind).is_none_or(|k| k.matches(is_dir))
+    }
+}
+
+impl Action {
+    /// The name of every action, as in the config.
+    pub const NAMES: &'static [&'static str] = &["move", "linkIntoLibrary", "unzip", "delete", "deleteAfter", "webhook",
+        "subtitles", "episode", "torrent", "verify", "photo", "recompress", "split", "linkFarm", "exec"];
+
+    /// The action's name in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Move { .. } => "move",
+            Action::LinkIntoLibrary { .. } => "linkIntoLibrary",
+            Action::Unzip { .. } => "unzip",
+            Action::Delete => "delete",
+            Action::DeleteAfter { .. } => "deleteAfter",
+            Action::Webhook { .. } => "webhook",
+            Action::Subtitles { .. } => "subtitles",
+            Action::Episode { .. } => "episode",
+            Action::Torrent(_) => "torrent",
+            Action::Verify(_) => "verify",
+            Action::Photo(_) => "photo",
+            Action::Recompress(_) => "recompress",
+            Action::Split { .. } => "split",
+            Action::LinkFarm(_) => "linkFarm",
+            Action::Exec { .. } => "exec",
+        }
+    }
+
+    pub fn class(&self) -> ActionClass {
+        match self {
+            Action::Move { .. } | Action::LinkIntoLibrary { .. } | Action::Unzip { .. } | Action::Delete | Action::DeleteAfter { .. } | Action::Verify(_) | Action::Photo(_) | Action::Recompress(_) | Action::Split { .. } | Action::LinkFarm(_) | Action::Exec { .. } => ActionClass::Local,
+            Action::Webhook { .. } | Action::Subtitles { .. } | Action::Episode { .. } => ActionClass::Network,
+            Action::Torrent(config) => match config.client {
+                TorrentClient::Blackhole { .. } => ActionClass::Local,
+                _ => ActionClass::Network,
+            },
+        }
+    }
+}
+
+fn default_webhook_method() -> String {
+    "POST".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub enum DuplicateAction {
+    #[serde(rename="rename-date")]
+    RenameDate,
+    #[serde(rename="skip")]
+    Skip,
+    #[serde(rename="overwrite")]
+    Overwrite,
+    /// Moves a directory's contents into the existing one. Behaves like `rename-date` for files.
+    #[serde(rename="merge")]
+    Merge,
+}
+
+/// A named pipeline with its own watch directories, rules and workers, run alongside the others.
+pub struct Profile {
+    pub name: String,
+    pub config: Config,
+}
+
+/// Reads the config from `file` from now on, whatever the environment says. Set once, from
+/// `--config`.
+pub fn use_file(file: PathBuf) {
+    let _ = CONFIG_PATH.set(file);
+}
+
+impl Config {
+    /// Loads the configuration from a file or the environment.
+    ///
+    /// The config is the file given with `--config`, a complete document in `CONFIG_YAML`, the
+    /// file named by `CONFIG_FILE`, or a file in one of the default locations - the first there
+    /// is - and it's an error when there's none of them. Individual `DOWNLOAD_ORGANISER_*`
+    /// variables are then applied on top.
+    ///
+    /// A config without `profiles` is a single profile named `default`. Otherwise each profile is
+    /// the top-level settings with the profile's own keys replacing them. `only` selects a single
+    /// profile by name.
+    pub fn load(only: Option<&str>) -> Result<Vec<Profile>> {
+        let yaml = env::var(CONFIG_YAML_ENV).ok().filter(|_| CONFIG_PATH.get().is_none());
+        let mut document: serde_yaml::Value = match (yaml, Config::file()) {
+            (Some(yaml), _) => serde_yaml::from_str(&yaml)
+                .map_err(|e| format!("unable to parse config from [{CONFIG_YAML_ENV}]: {e}"))?,
+            (None, Some(file)) => {
+                let yaml = std::fs::read_to_string(&file).map_err(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => format!("config file [{}] does not exist", file.display()),
+                    _ => format!("unable to read config file [{}]: {e}", file.display()),
+                })?;
+                serde_yaml::from_str(&yaml).map_err(|e| format!("unable to parse config file [{}]: {e}", file.display()))?
+            },
+            (None, None) => {
+                let searched: Vec<String> = default_files().iter().map(|f| format!("[{}]", f.display())).collect();
+                return Err(format!("no config found - pass --config, set {CONFIG_YAML_ENV} or {CONFIG_FILE_ENV}, or create one of {}", searched.join(", ")).into())
+            },
+        };
+        migrate::migrate(&mut document)?;
+
+        let profiles = match document.as_mapping_mut().and_then(|m| m.remove("profiles")) {
+            Some(profiles) => Config::profiles(document, profiles)?,
+            None => {
+                hoist_dir_rules(&mut document)?;
+                let mut config: Config = serde_yaml::from_value(document)?;
+                // before inherit, so rules from the environment get the top-level settings too
+                config.apply_env()?;
+                config.inherit();
+                config.validate()?;
+                vec![Profile { name: "default".to_string(), config }]
+            },
+        };
+
+        if let Some(profile) = profiles.first() {
+            clock::configure(&profile.config.dates);
+        }
+        match only {
+            Some(name) => {
+                let profile = profiles.into_iter().find(|p| p.name == name)
+                    .ok_or_else(|| format!("config has no profile named [{name}]"))?;
+                Ok(vec![profile])
+            },
+            None => Ok(profiles),
+        }
+    }
+
+    /// The config file, if the config is read from one rather than `CONFIG_YAML`.
+    pub fn file() -> Option<PathBuf> {
+        if let Some(file) = CONFIG_PATH.get() {
+            return Some(file.clone())
+        }
+        if env::var_os(CONFIG_YAML_ENV).is_some() {
+            return None
+        }
+        if let Some(file) = env::var_os(CONFIG_FILE_ENV) {
+            return Some(PathBuf::from(file))
+        }
+        default_files().into_iter().find(|file| file.is_file())
+    }
+
+    fn profiles(shared: serde_yaml::Value, profiles: serde_yaml::Value) -> Result<Vec<Profile>> {
+        if ["BASE_DIR", "WATCH_DIR", "STATE_DIR", "RULES"].into_iter().any(|name| env_var(name).is_some()) {
+            return Err(format!("{ENV_PREFIX}* overrides can't be used with profiles - set the values in each profile").into())
+        }
+
+        let mut loaded: Vec<Profile> = Vec::new();
+        for mut overrides in serde_yaml::from_value::<Vec<serde_yaml::Mapping>>(profiles)? {
+            let name = overrides.remove("name").and_then(|n| n.as_str().map(str::to_string))
+                .ok_or("every profile needs a name")?;
+            if loaded.iter().any(|p| p.name == name) {
+                return Err(format!("profile name [{name}] is used more than once").into())
+            }
+            if overrides.contains_key("dates") {
+                return Err(format!("profile [{name}] sets dates, which can only be set for every profile").into())
+            }
+
+            let mut merged = shared.clone();
+            let settings = merged.as_mapping_mut().ok_or("config must be a mapping")?;
+            settings.extend(overrides);
+            hoist_dir_rules(&mut merged).map_err(|e| format!("profile [{name}]: {e}"))?;
+            let mut config: Config = serde_yaml::from_value(merged).map_err(|e| format!("profile [{name}]: {e}"))?;
+            config.inherit();
+            // the daemon has to stay root to serve every profile, so the user applies to the rules
+            if let Some(run_as) = config.run_as.take() {
+                for rule in &mut config.rules {
+                    rule.run_as.get_or_insert_with(|| run_as.clone());
+                }
+            }
+            config.validate().map_err(|e| format!("profile [{name}]: {e}"))?;
+
+            for other in &loaded {
+                if let Some(dir) = config.watch_dirs().iter().find(|d| other.config.watch_dirs().iter().any(|o| o.path == d.path)) {
+                    return Err(format!("profiles [{}] and [{name}] both watch [{}]", other.name, dir.path.display()).into())
+                }
+                // each profile rewrites its state files from what it holds, so a shared one would
+                // end up with whichever saved last, and be loaded by both on start
+                if fsutil::same_path(&config.state_dir(), &other.config.state_dir()) {
+                    return Err(format!("profiles [{}] and [{name}] share the state directory [{}] - give one its own stateDir",
+                        other.name, config.state_dir().display()).into())
+                }
+            }
+            loaded.push(Profile { name, config });
+        }
+
+        if loaded.is_empty() {
+            return Err("profiles is empty".into())
+        }
+        Ok(loaded)
+    }
+
+    /// Gives rules the top-level settings they don't override.
+    fn inherit(&mut self) {
+        if let Some(form) = self.normalise_names {
+            for rule in &mut self.rules {
+                rule.normalise_names.get_or_insert(form);
+            }
+        }
+        if let Some(relabel) = self.relabel {
+            for rule in &mut self.rules {
+                rule.relabel.get_or_insert(relabel);
+            }
+        }
+    }
+
+    /// All watch directories, with paths resolved against `baseDir`.
+    pub fn watch_dirs(&self) -> Vec<WatchDir> {
+        let legacy = self.watch_dir.iter().map(|path| WatchDir {
+            name: "default".to_string(),
+            path: PathBuf::from(path),
+            events: default_events(),
+            cooldown: None,
+            hidden: None,
+            recursive: false,
+            max_depth: None,
+            exclude_paths: Vec::new(),
+            priority: 0,
+            uploads: None,
+        });
+        legacy.chain(self.watch_dirs.iter().cloned())
+            .map(|dir| dir.resolve(&self.base_dir, self.hidden))
+            .collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        let watch_dirs = self.watch_dirs();
+        if watch_dirs.is_empty() && self.removable.is_empty() {
+            return Err("config must set watchDirs or removable".into())
+        }
+        if self.watchdog.as_ref().is_some_and(|w| w.timeout.is_zero()) {
+            return Err("watchdog timeout must be more than zero".into())
+        }
+        if self.watcher == Watcher::Fanotify && self.run_as.is_some() {
+            return Err("watcher fanotify can't be used with runAs, as working out which file an event is for needs root".into())
+        }
+        if let Some(deadline) = &self.deadline {
+            if deadline.after.is_zero() {
+                return Err("deadline after must be more than zero".into())
+            }
+            if deadline.notify && self.notifications.is_empty() {
+                return Err("deadline sets notify, but no notification channels are configured".into())
+            }
+            for name in deadline.notify_via.iter().flatten() {
+                if !self.notifications.contains_key(name) {
+                    return Err(format!("deadline notifies unknown channel [{name}]").into())
+                }
+            }
+        }
+        if let Some(maintenance) = &self.maintenance {
+            maintenance.validate(&self.base_dir, &watch_dirs)?;
+        }
+        if let Some(report) = &self.report {
+            report.validate()?;
+            if report.notify && self.notifications.is_empty() {
+                return Err("report sets notify, but no notification channels are configured".into())
+            }
+            for name in report.notify_via.iter().flatten() {
+                if !self.notifications.contains_key(name) {
+                    return Err(format!("report notifies unknown channel [{name}]").into())
+                }
+            }
+        }
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.validate()?;
+        }
+        if self.dbus.is_some() {
+            version::require("setting", "dbus")?;
+        }
+        if let Some(control) = &self.control {
+            control.validate()?;
+        }
+        self.retry.validate()?;
+        for (name, channel) in &self.notifications {
+            version::require("notification channel", channel.name()).map_err(|e| format!("notification channel [{name}]: {e}"))?;
+        }
+        for (i, dir) in watch_dirs.iter().enumerate() {
+            dir.validate()?;
+            if watch_dirs[..i].iter().any(|d| d.name == dir.name) {
+                return Err(format!("watch directory name [{}] is used more than once", dir.name).into())
+            }
+        }
+        for (i, volume) in self.removable.iter().enumerate() {
+            volume.validate()?;
+            let name = &volume.dir.name;
+            if watch_dirs.iter().any(|d| &d.name == name) || self.removable[..i].iter().any(|v| &v.dir.name == name) {
+                return Err(format!("watch directory name [{name}] is used more than once").into())
+            }
+        }
+        let known = |name: &String| watch_dirs.iter().any(|d| &d.name == name) || self.removable.iter().any(|v| &v.dir.name == name);
+        for rule in &self.rules {
+            if rule.engine != Engine::Fancy {
+                let refused = rule.regex.needs_fancy().or_else(|| rule.exclude_regex.as_ref().and_then(|r| r.needs_fancy()));
+                if let Some(reason) = refused {
+                    return Err(format!("rule [{}] needs `engine: fancy` for lookaround or backreferences: {reason}", rule.regex.as_str()).into())
+                }
+            }
+            let group = rule.group.as_ref().and_then(|g| g.conditions.applies_to.as_ref());
+            for name in rule.applies_to.iter().flatten().chain(group.into_iter().flatten()) {
+                if !known(name) {
+                    return Err(format!("rule [{}] applies to unknown watch directory [{name}]", rule.regex.as_str()).into())
+                }
+            }
+            rule.retry.validate().map_err(|e| format!("rule [{}]: {e}", rule.regex.as_str()))?;
+            for action in &rule.actions {
+                version::require("action", action.name()).map_err(|e| format!("rule [{}]: {e}", rule.regex.as_str()))?;
+                let dests = match action {
+                    Action::Move { dest, .. } | Action::LinkIntoLibrary { dest, .. } | Action::Unzip { dest, .. } | Action::Episode { dest, .. } | Action::Split { dest, .. } => vec![dest],
+                    Action::Photo(photo) => vec![&photo.raw, &photo.jpeg],
+                    Action::LinkFarm(farm) => farm.dests.iter().collect(),
+                    _ => Vec::new(),
+                };
+                for dest in dests {
+                    template::validate(dest)?;
+                    // the part before any token has to stay inside baseDir; the rest is checked once expanded
+                    let fixed = &dest[..dest.find('{').unwrap_or(dest.len())];
+                    let fixed = if template::has_tokens(dest) { fixed.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("") } else { fixed };
+                    if !rule.allow_outside_base && !fsutil::is_within(&self.base_dir, &self.base_dir.join(fixed)) {
+                        return Err(format!("rule [{}] has destination [{dest}] outside baseDir - set allowOutsideBase to allow it", rule.regex.as_str()).into())
+                    }
+                }
+                if let Action::Exec { dests, .. } = action {
+                    if let Some(dest) = dests.iter().find(|d| !rule.allow_outside_base && !fsutil::is_within(&self.base_dir, &self.base_dir.join(d))) {
+                        return Err(format!("rule [{}] has destination [{}] outside baseDir - set allowOutsideBase to allow it", rule.regex.as_str(), dest).into())
+                    }
+                }
+                if let Action::Recompress(recompress) = action {
+                    recompress.validate().map_err(|e| format!("rule [{}]: {e}", rule.regex.as_str()))?;
+                }
+                if let Action::LinkFarm(LinkFarmConfig { dests, .. }) = action {
+                    if dests.is_empty() {
+                        return Err(format!("rule [{}] has a linkFarm with no dests", rule.regex.as_str()).into())
+                    }
+                }
+                if let Action::Split { chunk_size: 0, .. } = action {
+                    return Err(format!("rule [{}] splits into chunks of 0 bytes", rule.regex.as_str()).into())
+                }
+                if let Action::Subtitles { username, password, languages, .. } = action {
+                    if username.is_some() != password.is_some() {
+                        return Err(format!("rule [{}] sets only one of username and password for subtitles", rule.regex.as_str()).into())
+                    }
+                    if languages.is_empty() {
+                        return Err(format!("rule [{}] downloads subtitles in no languages", rule.regex.as_str()).into())
+                    }
+                }
+                if let Action::Torrent(TorrentConfig { client: TorrentClient::Qbittorrent { username, password, .. } | TorrentClient::Transmission { username, password, .. }, .. }) = action {
+                    if username.is_some() != password.is_some() {
+                        return Err(format!("rule [{}] sets only one of username and password for its torrent client", rule.regex.as_str()).into())
+                    }
+                }
+                if let Action::Move { rename: Some(rename), .. } | Action::LinkIntoLibrary { rename: Some(rename), .. } = action {
+                    template::validate_name(rename)?;
+                    if rename.contains('/') || rename.is_empty() {
+                        return Err(format!("rule [{}] renames to [{rename}], which isn't a file name - put directories in dest", rule.regex.as_str()).into())
+                    }
+                }
+            }
+            if rule.has_destination_condition() && rule.destination().is_none() {
+                return Err(format!("rule [{}] has destination conditions but no move or unzip action", rule.regex.as_str()).into())
+            }
+            if (rule.content.is_some() || rule.pdf.is_some() || rule.image.is_some() || rule.header().is_some()) && rule.kind != EntryKind::File {
+                return Err(format!("rule [{}] has a content, PDF, header or disk image condition, so it can only match files", rule.regex.as_str()).into())
+            }
+            let group = rule.group.as_ref().map(|g| &g.conditions);
+            for (bytes, header) in [(rule.header_bytes, rule.header()), (group.and_then(|g| g.header_bytes), group.and_then(|g| g.header()))] {
+                match (bytes, header) {
+                    (Some(_), None) => return Err(format!("rule [{}] sets headerBytes without headerRegex or headerHex", rule.regex.as_str()).into()),
+                    (Some(bytes), _) if bytes == 0 || bytes > header::MAX_BYTES => {
+                        return Err(format!("rule [{}] headerBytes must be between 1 and {}", rule.regex.as_str(), header::MAX_BYTES).into())
+                    },
+                    _ => {},
+                }
+            }
+            if !rule.notify_on.is_empty() && self.notifications.is_empty() {
+                return Err(format!("rule [{}] sets notifyOn, but no notification channels are configured", rule.regex.as_str()).into())
+            }
+            for name in rule.notify_via.iter().flatten() {
+                if !self.notifications.contains_key(name) {
+                    return Err(format!("rule [{}] notifies unknown channel [{name}]", rule.regex.as_str()).into())
+                }
+            }
+            if rule.run_as.is_some() && self.run_as.is_some() {
+                return Err(format!("rule [{}] sets runAs, which needs root, but the daemon drops privileges with a global runAs", rule.regex.as_str()).into())
+            }
+            for name in rule.tests.iter().filter_map(|t| t.watch_dir.as_ref()) {
+                if !known(name) {
+                    return Err(format!("test for rule [{}] uses unknown watch directory [{name}]", rule.regex.as_str()).into())
+                }
+            }
+        }
+        for warning in lint::lint(self) {
+            warn!(warning=warning; "possible problem with rules");
+        }
+        Ok(())
+    }
+
+    pub fn state_dir(&self) -> PathBuf {
+        self.base_dir.join(self.state_dir.as_deref().unwrap_or(".download-organiser".as_ref()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(base_dir) = env_var("BASE_DIR") {
+            self.base_dir = PathBuf::from(base_dir);
+        }
+        if let Some(watch_dir) = env_var("WATCH_DIR") {
+            self.watch_dir = Some(watch_dir);
+        }
+        if let Some(state_dir) = env_var("STATE_DIR") {
+            self.state_dir = Some(PathBuf::from(state_dir));
+        }
+        if let Some(rules) = env_var("RULES") {
+            self.rules = group::deserialize_rules(serde_yaml::Deserializer::from_str(&rules))
+                .map_err(|e| format!("unable to parse rules from [{ENV_PREFIX}RULES]: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// The default locations of the config file, in the order they're tried.
+fn default_files() -> Vec<PathBuf> {
+    let xdg = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    xdg.into_iter().chain([PathBuf::from("/etc")]).map(|dir| dir.join(DEFAULT_FILE)).collect()
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(format!("{ENV_PREFIX}{name}")).ok().filter(|v| !v.is_empty())
+}
+
+/// Moves the `rules` of each watch directory ahead of the top-level ones, limited to that directory
+/// with `appliesTo`, so they're matched like any other rule and a directory's own rules are tried
+/// before the shared ones.
+fn hoist_dir_rules(document: &mut serde_yaml::Value) -> Result<()> {
+    use serde_yaml::Value;
+
+    let Some(config) = document.as_mapping_mut() else { return Ok(()) };
+    let mut hoisted = Vec::new();
+    for dir in config.get_mut("watchDirs").and_then(Value::as_sequence_mut).into_iter().flatten() {
+        let Some(rules) = dir.as_mapping_mut().and_then(|d| d.remove("rules")) else { continue };
+        let name = dir.get("name").and_then(Value::as_str).ok_or("a watch directory with rules needs a name")?.to_string();
+        let Value::Sequence(rules) = rules else {
+            return Err(format!("rules of watch directory [{name}] must be a list").into())
+        };
+        for mut entry in rules {
+            // a group is limited as a whole, leaving its rules to narrow it further
+            let limited = match entry.get("group").is_some() {
+                true => entry.get_mut("group").unwrap(),
+                false => &mut entry,
+            };
+            let limited = limited.as_mapping_mut().ok_or_else(|| format!("rules of watch directory [{name}] must be mappings"))?;
+            if limited.contains_key("appliesTo") {
+                return Err(format!("rules of watch directory [{name}] can't set appliesTo").into())
+            }
+            limited.insert("appliesTo".into(), Value::Sequence(vec![name.clone().into()]));
+            hoisted.push(entry);
+        }
+    }
+    if hoisted.is_empty() {
+        return Ok(())
+    }
+    let rules = config.entry("rules".into()).or_insert(Value::Sequence(Vec::new()));
+    let shared = rules.as_sequence_mut().ok_or("rules must be a list")?;
+    hoisted.append(shared);
+    *shared = hoisted;
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{as_error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::duration;
+use crate::Result;
+
+/// Holds files matched by rules that delete or overwrite until they're approved through the
+/// control API.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ConfirmConfig {
+    /// How long a file waits for a decision before `onTimeout` makes it. Forever when unset.
+    #[serde(default, deserialize_with="duration::deserialize_option", serialize_with="duration::serialize_option")]
+    pub timeout: Option<Duration>,
+    #[serde(rename="onTimeout", default)]
+    pub on_timeout: OnTimeout,
+}
+
+/// What happens to a file nobody decided on within the timeout.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum OnTimeout {
+    /// Leave it where it is, as if it had been rejected.
+    #[default]
+    #[serde(rename="skip")]
+    Skip,
+    /// Run the rule's actions, as if it had been approved.
+    #[serde(rename="run")]
+    Run,
+}
+
+/// A file waiting for approval, as listed by `GET /pending`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Awaiting {
+    pub path: PathBuf,
+    /// Regex of the rule it matched.
+    pub rule: String,
+    /// Seconds since the epoch.
+    pub since: u64,
+    /// When `onTimeout` decides for it, in seconds since the epoch.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub expires: Option<u64>,
+}
+
+/// Files waiting for approval, saved so they're still listed after a restart.
+pub struct Confirmations {
+    awaiting: Mutex<Vec<Awaiting>>,
+    /// Files approved and queued again, which are let through the next time they match.
+    approved: Mutex<HashSet<PathBuf>>,
+    file: Option<PathBuf>,
+    /// Woken when a file starts waiting, in case it expires before whatever was next.
+    added: Notify,
+}
+
+impl Confirmations {
+    /// Loads the saved list from `file`, if there is one.
+    pub fn open(file: Option<PathBuf>) -> Result<Confirmations> {
+        let awaiting = match file.as_ref().filter(|f| f.exists()) {
+            Some(file) => serde_yaml::from_str(&fs::read_to_string(file)?)
+                .map_err(|e| format!("unable to read [{}]: {e}", file.display()))?,
+            None => Vec::new(),
+        };
+        Ok(Confirmations { awaiting: Mutex::new(awaiting), approved: Mutex::new(HashSet::new()), file, added: Notify::new() })
+    }
+
+    /// Keeps `path` waiting for approval, for `timeout` when set. A file that's already waiting
+    /// keeps its place.
+    pub fn hold(&self, path: &Path, rule: &str, timeout: Option<Duration>) {
+        let now = now();
+        let mut awaiting = self.awaiting.lock().unwrap();
+        if awaiting.iter().any(|a| a.path == path) {
+            return
+        }
+        let expires = timeout.map(|t| now + t.as_secs());
+        awaiting.push(Awaiting { path: path.to_path_buf(), rule: rule.to_string(), since: now, expires });
+        self.save(&awaiting);
+        drop(awaiting);
+        self.added.notify_one();
+    }
+
+    /// Whether `path` was approved, letting it through this once.
+    pub fn take_approval(&self, path: &Path) -> bool {
+        self.approved.lock().unwrap().remove(path)
+    }
+
+    pub fn list(&self) -> Vec<Awaiting> {
+        self.awaiting.lock().unwrap().clone()
+    }
+
+    /// Stops `path` waiting, letting it through the next time it matches if `approved`. Returns
+    /// what it was waiting for, or `None` if it wasn't.
+    pub fn decide(&self, path: &Path, approved: bool) -> Option<Awaiting> {
+        let mut awaiting = self.awaiting.lock().unwrap();
+        let index = awaiting.iter().position(|a| a.path == path)?;
+        let decided = awaiting.remove(index);
+        self.save(&awaiting);
+        if approved {
+            self.approve(path);
+        }
+        Some(decided)
+    }
+
+    /// Lets `path` through the next time it matches.
+    pub fn approve(&self, path: &Path) {
+        self.approved.lock().unwrap().insert(path.to_path_buf());
+    }
+
+    fn save(&self, awaiting: &[Awaiting]) {
+        if let Some(file) = &self.file {
+            if let Err(err) = write(file, awaiting) {
+                warn!(error=as_error!(*err); "unable to save files awaiting approval - they will be lost on restart");
+            }
+        }
+    }
+
+    /// Waits until files have waited out their timeout, and takes them off the list. Ones that
+    /// expired while the organiser wasn't running are returned straight away.
+    pub async fn expired(&self) -> Vec<Awaiting> {
+        loop {
+            let added = self.added.notified();
+            let now = now();
+            let next = {
+                let mut awaiting = self.awaiting.lock().unwrap();
+                let (expired, waiting): (Vec<Awaiting>, Vec<Awaiting>) = awaiting.drain(..).partition(|a| a.expires.is_some_and(|e| e <= now));
+                *awaiting = waiting;
+                if !expired.is_empty() {
+                    self.save(&awaiting);
+                    return expired
+                }
+                awaiting.iter().filter_map(|a| a.expires).min()
+            };
+            match next {
+                Some(next) => {
+                    let wait = Duration::from_secs(next.saturating_sub(now).max(1));
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {},
+                        _ = added => {},
+                    }
+                },
+                None => added.await,
+            }
+        }
+    }
+}
+
+fn write(file: &Path, awaiting: &[Awaiting]) -> Result<()> {
+    Ok(fs::write(file, serde_yaml::to_string(awaiting)?)?)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
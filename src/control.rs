@@ -0,0 +1,579 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{as_debug, debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::WatchDir;
+use crate::confirm::Awaiting;
+use crate::metrics;
+use crate::version;
+use crate::pause::PauseStatus;
+use crate::reconcile::Reconciliation;
+use crate::recording::Recorded;
+use crate::removable::VolumeMode;
+use crate::secret::SecretRef;
+use crate::tls::{Acceptor, TlsConfig};
+use crate::watchdog;
+use crate::Result;
+
+/// Largest request body accepted, to keep a misbehaving client from exhausting memory.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// How long each profile gets to answer a readiness check.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ControlConfig {
+    /// Unix socket to listen on. Access is controlled by the socket file's permissions.
+    pub socket: Option<PathBuf>,
+    /// TCP address to listen on, such as `127.0.0.1:7070`. Without `tokens` there is no
+    /// authentication, so keep it on loopback or behind a proxy.
+    pub listen: Option<SocketAddr>,
+    /// Bearer tokens requests to `listen` must carry, each allowed some scopes. Requests on
+    /// `socket` need none.
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub tokens: Vec<ApiToken>,
+    /// Serves `listen` over TLS, and can require client certificates.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ApiToken {
+    /// Logged with each request made with the token.
+    pub name: String,
+    pub token: SecretRef,
+    pub scopes: Vec<Scope>,
+}
+
+/// What a token allows. The health checks need none, so probes can make them without a token.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Every `GET`: watch directories, pauses, files pending approval, reconciliation, the
+    /// version and metrics.
+    #[serde(rename="read")]
+    Read,
+    /// Processing: rescans, queuing a file, pausing and resuming, approving and rejecting.
+    #[serde(rename="trigger")]
+    Trigger,
+    /// Changing what runs: adding and removing watch directories, and reloading the rules.
+    #[serde(rename="config")]
+    Config,
+}
+
+impl Scope {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Trigger => "trigger",
+            Scope::Config => "config",
+        }
+    }
+}
+
+impl ControlConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.listen.is_none() && (!self.tokens.is_empty() || self.tls.is_some()) {
+            return Err("control tokens and tls only apply to listen, which isn't set".into())
+        }
+        for (i, token) in self.tokens.iter().enumerate() {
+            if token.scopes.is_empty() {
+                return Err(format!("control token [{}] has no scopes", token.name).into())
+            }
+            if self.tokens[..i].iter().any(|t| t.name == token.name) {
+                return Err(format!("control token [{}] is defined more than once", token.name).into())
+            }
+        }
+        if self.tls.is_some() {
+            version::require("setting", "control.tls")?;
+        }
+        Ok(())
+    }
+}
+
+/// A failed request, with the HTTP status to report it with.
+pub struct Rejection(pub u16, pub String);
+
+impl Rejection {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Rejection(400, message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Rejection(404, message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Rejection(409, message.into())
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Rejection(401, message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Rejection(403, message.into())
+    }
+}
+
+/// A request for a running organiser, answered from its event loop.
+pub enum Request {
+    ListWatchDirs(oneshot::Sender<Vec<WatchDir>>),
+    AddWatchDir(WatchDir, oneshot::Sender<std::result::Result<(), Rejection>>),
+    RemoveWatchDir(String, oneshot::Sender<std::result::Result<(), Rejection>>),
+    /// Queues everything already in the watch directories, replying with how many entries were
+    /// queued.
+    Rescan(oneshot::Sender<usize>),
+    /// Queues a single file, which must be inside a watch directory.
+    ProcessFile(PathBuf, oneshot::Sender<std::result::Result<(), Rejection>>),
+    /// Reads the config again and replaces the rules.
+    Reload(oneshot::Sender<std::result::Result<(), Rejection>>),
+    /// Pauses (`true`) or resumes processing, of the rule with the given regex or of everything.
+    SetPaused(Option<String>, bool, oneshot::Sender<std::result::Result<(), Rejection>>),
+    Paused(oneshot::Sender<PauseStatus>),
+    /// Lists the files waiting for approval.
+    Pending(oneshot::Sender<Vec<Awaiting>>),
+    /// Approves (`true`) or rejects a file waiting for approval.
+    Confirm(PathBuf, bool, oneshot::Sender<std::result::Result<(), Rejection>>),
+    /// Organises the directory of a removable volume that has been mounted, watching it too unless
+    /// it's only organised once, and replies with how many entries were queued.
+    Mounted(WatchDir, VolumeMode, oneshot::Sender<std::result::Result<usize, Rejection>>),
+    /// Stops watching the directory of a removable volume that has been unmounted.
+    Unmounted(String),
+    /// What the check against the filesystem on start found, if it's configured.
+    Reconciliation(oneshot::Sender<Option<Reconciliation>>),
+    /// Answered once the organiser is watching and its event loop is running.
+    Ping(oneshot::Sender<()>),
+    /// Handles a recorded event as though the watcher had just read it.
+    Replay(Recorded),
+    /// Whether nothing is waiting out a cooldown, queued or being processed.
+    Settled(oneshot::Sender<bool>),
+}
+
+/// Channels to each profile's organiser.
+pub type Profiles = Vec<(String, mpsc::Sender<Request>)>;
+
+/// Serves the HTTP control API on the configured socket and/or address until the process exits.
+pub async fn serve(config: &ControlConfig, profiles: Profiles) -> Result<()> {
+    let profiles = Arc::new(profiles);
+
+    if let Some(path) = &config.socket {
+        // a socket left behind by a previous run would make bind fail
+        if fs::symlink_metadata(path).map(|m| m.file_type().is_socket()).unwrap_or(false) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path).map_err(|e| format!("unable to listen on [{}]: {e}", path.display()))?;
+        info!(socket=path.to_str(); "control API listening");
+        let profiles = Arc::clone(&profiles);
+        // the socket's permissions already decide who can connect
+        let tokens = Arc::new(Vec::new());
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => { tokio::spawn(handle_connection(stream, Arc::clone(&profiles), Arc::clone(&tokens))); },
+                    Err(err) => warn!(error=as_debug!(err); "unable to accept control connection"),
+                }
+            }
+        });
+    }
+    if let Some(address) = config.listen {
+        let acceptor = config.tls.as_ref().map(Acceptor::new).transpose()?.map(Arc::new);
+        let tokens = Arc::new(config.tokens.clone());
+        let listener = TcpListener::bind(address).await.map_err(|e| format!("unable to listen on [{address}]: {e}"))?;
+        info!(address=address.to_string(), tls=acceptor.is_some(), tokens=tokens.len(); "control API listening");
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        warn!(error=as_debug!(err); "unable to accept control connection");
+                        continue
+                    },
+                };
+                let (profiles, tokens) = (Arc::clone(&profiles), Arc::clone(&tokens));
+                match &acceptor {
+                    Some(acceptor) => {
+                        let acceptor = Arc::clone(acceptor);
+                        tokio::spawn(async move {
+                            match acceptor.accept(stream).await {
+                                Ok(stream) => handle_connection(stream, profiles, tokens).await,
+                                Err(err) => debug!(error=as_debug!(err); "control TLS handshake failed"),
+                            }
+                        });
+                    },
+                    None => { tokio::spawn(handle_connection(stream, profiles, tokens)); },
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answers a single request, which must carry a bearer token with the scope it needs when
+/// `tokens` isn't empty.
+async fn handle_connection(stream: impl AsyncRead + AsyncWrite + Unpin, profiles: Arc<Profiles>, tokens: Arc<Vec<ApiToken>>) {
+    let mut stream = BufReader::new(stream);
+    let mut content_type = "application/json";
+    let (status, body) = match read_request(&mut stream).await {
+        Ok((method, target, _, bearer)) if method == "GET" && target.split('?').next() == Some("/metrics") => {
+            match authorise(&tokens, &method, &target, bearer.as_deref()) {
+                Ok(_) => {
+                    content_type = metrics::CONTENT_TYPE;
+                    (200, metrics::render())
+                },
+                Err(Rejection(status, message)) => (status, serde_json::json!({ "error": message }).to_string()),
+            }
+        },
+        Ok((method, target, body, bearer)) => {
+            let routed = match authorise(&tokens, &method, &target, bearer.as_deref()) {
+                Ok(token) => {
+                    debug!(method=method, target=target, token=token; "control request");
+                    route(&method, &target, &body, &profiles).await
+                },
+                Err(rejection) => {
+                    warn!(method=method, target=target, error=rejection.1; "control request refused");
+                    Err(rejection)
+                },
+            };
+            match routed {
+                Ok((status, body)) => (status, body),
+                Err(Rejection(status, message)) => (status, serde_json::json!({ "error": message }).to_string()),
+            }
+        },
+        Err(err) => (400, serde_json::json!({ "error": err.to_string() }).to_string()),
+    };
+
+    let challenge = match status {
+        401 => "WWW-Authenticate: Bearer\r\n",
+        _ => "",
+    };
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n{challenge}Connection: close\r\n\r\n{body}",
+        reason(status), body.len());
+    if let Err(err) = stream.get_mut().write_all(response.as_bytes()).await {
+        debug!(error=as_debug!(err); "unable to write control response");
+    }
+}
+
+/// Reads the request line, headers and body of a single HTTP/1.1 request, with the bearer token in
+/// its `Authorization` header.
+async fn read_request(stream: &mut (impl AsyncBufReadExt + Unpin)) -> Result<(String, String, Vec<u8>, Option<String>)> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err("malformed request line".into()),
+    };
+
+    let mut length = 0;
+    let mut bearer = None;
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| "invalid Content-Length")?;
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                bearer = value.trim().strip_prefix("Bearer ").map(|token| token.trim().to_string());
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err("request body too large".into())
+    }
+
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+    Ok((method, target, body, bearer))
+}
+
+/// The scope a request needs, or none for the health checks.
+fn scope(method: &str, target: &str) -> Option<Scope> {
+    let path = target.split('?').next().unwrap_or(target);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["healthz" | "readyz"]) => None,
+        ("POST", ["watch-dirs"] | ["reload"]) | ("DELETE", ["watch-dirs", _]) => Some(Scope::Config),
+        ("GET", _) => Some(Scope::Read),
+        _ => Some(Scope::Trigger),
+    }
+}
+
+/// Checks `bearer` is one of `tokens` and allows the request, returning the token's name. Anything
+/// is allowed when there are no tokens.
+fn authorise<'a>(tokens: &'a [ApiToken], method: &str, target: &str, bearer: Option<&str>) -> std::result::Result<Option<&'a str>, Rejection> {
+    let Some(scope) = scope(method, target).filter(|_| !tokens.is_empty()) else { return Ok(None) };
+    let bearer = bearer.ok_or_else(|| Rejection::unauthorized("a bearer token is needed"))?;
+    let token = tokens.iter()
+        .find(|token| match token.token.resolve() {
+            Ok(secret) => same(secret.expose().as_bytes(), bearer.as_bytes()),
+            Err(err) => {
+                warn!(token=token.name, error=as_debug!(err); "unable to resolve control token");
+                false
+            },
+        })
+        .ok_or_else(|| Rejection::unauthorized("unknown bearer token"))?;
+    if !token.scopes.contains(&scope) {
+        return Err(Rejection::forbidden(format!("token [{}] doesn't have the {} scope", token.name, scope.name())))
+    }
+    Ok(Some(&token.name))
+}
+
+/// Compares in the same time however much of the two matches, so a token can't be guessed a byte
+/// at a time.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn route(method: &str, target: &str, body: &[u8], profiles: &Profiles) -> std::result::Result<(u16, String), Rejection> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let profile = query.split('&').find_map(|pair| pair.strip_prefix("profile="));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        // the process is up, serving requests, and no profile's watchdog has found it stuck
+        ("GET", ["healthz"]) => match watchdog::stalled().as_slice() {
+            [] => Ok((200, "{}".to_string())),
+            stalled => Err(Rejection(503, format!("profile [{}] is stuck", stalled.join(", ")))),
+        },
+        ("GET", ["readyz"]) => {
+            for (name, sender) in profiles {
+                let (reply, response) = oneshot::channel();
+                let ping = async {
+                    sender.send(Request::Ping(reply)).await.ok()?;
+                    response.await.ok()
+                };
+                if tokio::time::timeout(READY_TIMEOUT, ping).await.ok().flatten().is_none() {
+                    return Err(Rejection(503, format!("profile [{name}] is not ready")))
+                }
+            }
+            Ok((200, "{}".to_string()))
+        },
+        ("GET", ["version"]) => Ok((200, serde_json::to_string(&version::info()).map_err(|e| Rejection(500, e.to_string()))?)),
+        ("GET", ["watch-dirs"]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::ListWatchDirs(reply)).await?;
+            let dirs = response.await.map_err(|_| stopped())?;
+            Ok((200, serde_json::to_string(&dirs).map_err(|e| Rejection(500, e.to_string()))?))
+        },
+        ("POST", ["watch-dirs"]) => {
+            let dir: WatchDir = serde_json::from_slice(body)
+                .map_err(|e| Rejection::bad_request(format!("invalid watch directory: {e}")))?;
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::AddWatchDir(dir, reply)).await?;
+            response.await.map_err(|_| stopped())??;
+            Ok((201, "{}".to_string()))
+        },
+        ("DELETE", ["watch-dirs", name]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::RemoveWatchDir(name.to_string(), reply)).await?;
+            response.await.map_err(|_| stopped())??;
+            Ok((200, "{}".to_string()))
+        },
+        ("POST", ["rescan"]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::Rescan(reply)).await?;
+            let queued = response.await.map_err(|_| stopped())?;
+            Ok((200, serde_json::json!({ "queued": queued }).to_string()))
+        },
+        ("POST", ["process"]) => {
+            #[derive(Deserialize)]
+            struct Body { path: PathBuf }
+            let Body { path } = serde_json::from_slice(body)
+                .map_err(|e| Rejection::bad_request(format!("invalid request: {e}")))?;
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::ProcessFile(path, reply)).await?;
+            response.await.map_err(|_| stopped())??;
+            Ok((202, "{}".to_string()))
+        },
+        ("POST", ["reload"]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::Reload(reply)).await?;
+            response.await.map_err(|_| stopped())??;
+            Ok((200, "{}".to_string()))
+        },
+        ("GET", ["pause"]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::Paused(reply)).await?;
+            let status = response.await.map_err(|_| stopped())?;
+            Ok((200, serde_json::to_string(&status).map_err(|e| Rejection(500, e.to_string()))?))
+        },
+        ("POST", [action @ ("pause" | "resume")]) => {
+            #[derive(Deserialize)]
+            struct Body { rule: Option<String> }
+            let Body { rule } = match body.is_empty() {
+                true => Body { rule: None },
+                false => serde_json::from_slice(body).map_err(|e| Rejection::bad_request(format!("invalid request: {e}")))?,
+            };
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::SetPaused(rule, *action == "pause", reply)).await?;
+            response.await.map_err(|_| stopped())??;
+            Ok((200, "{}".to_string()))
+        },
+        ("GET", ["reconciliation"]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::Reconciliation(reply)).await?;
+            let found = response.await.map_err(|_| stopped())?.ok_or_else(|| Rejection::not_found("reconcile isn't set".to_string()))?;
+            Ok((200, serde_json::to_string(&found).map_err(|e| Rejection(500, e.to_string()))?))
+        },
+        ("GET", ["pending"]) => {
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::Pending(reply)).await?;
+            let awaiting = response.await.map_err(|_| stopped())?;
+            Ok((200, serde_json::to_string(&awaiting).map_err(|e| Rejection(500, e.to_string()))?))
+        },
+        ("POST", ["pending", decision @ ("approve" | "reject")]) => {
+            #[derive(Deserialize)]
+            struct Body { path: PathBuf }
+            let Body { path } = serde_json::from_slice(body)
+                .map_err(|e| Rejection::bad_request(format!("invalid request: {e}")))?;
+            let (reply, response) = oneshot::channel();
+            send(profiles, profile, Request::Confirm(path, *decision == "approve", reply)).await?;
+            response.await.map_err(|_| stopped())??;
+            Ok((200, "{}".to_string()))
+        },
+        (_, ["watch-dirs"] | ["watch-dirs", _] | ["rescan"] | ["process"] | ["reload"] | ["pause"] | ["resume"] | ["pending"] | ["pending", "approve" | "reject"]) => Err(Rejection(405, format!("method {method} not allowed"))),
+        _ => Err(Rejection::not_found(format!("no such endpoint [{path}]"))),
+    }
+}
+
+/// Makes a request of a running organiser's control API, as the CLI does, returning the response
+/// body. An error status is returned as an error with the API's message.
+pub async fn call(config: &ControlConfig, method: &str, target: &str, body: &str) -> Result<String> {
+    if config.socket.is_none() && config.tls.is_some() {
+        return Err("the control API's address uses TLS - set control.socket too to use it from the command line".into())
+    }
+    // over TCP, with the first token allowed to make the request
+    let authorization = match (&config.socket, scope(method, target)) {
+        (None, Some(scope)) => match config.tokens.iter().find(|token| token.scopes.contains(&scope)) {
+            Some(token) => format!("Authorization: Bearer {}\r\n", token.token.resolve()?.expose()),
+            None if config.tokens.is_empty() => String::new(),
+            None => return Err(format!("no control token has the {} scope", scope.name()).into()),
+        },
+        _ => String::new(),
+    };
+    let request = format!(
+        "{method} {target} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{authorization}Connection: close\r\n\r\n{body}",
+        body.len());
+    let mut response = Vec::new();
+    match (&config.socket, config.listen) {
+        (Some(path), _) => {
+            let mut stream = UnixStream::connect(path).await
+                .map_err(|e| format!("unable to connect to the control socket [{}] - is the organiser running? {e}", path.display()))?;
+            stream.write_all(request.as_bytes()).await?;
+            stream.read_to_end(&mut response).await?;
+        },
+        (None, Some(address)) => {
+            let mut stream = TcpStream::connect(address).await
+                .map_err(|e| format!("unable to connect to the control API on [{address}] - is the organiser running? {e}"))?;
+            stream.write_all(request.as_bytes()).await?;
+            stream.read_to_end(&mut response).await?;
+        },
+        (None, None) => return Err("control has neither socket nor listen set".into()),
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").ok_or("malformed control API response")?;
+    let status: u16 = head.split_whitespace().nth(1).and_then(|s| s.parse().ok()).ok_or("malformed control API response")?;
+    if status >= 400 {
+        let message = serde_json::from_str::<serde_json::Value>(body).ok()
+            .and_then(|v| v["error"].as_str().map(str::to_string))
+            .unwrap_or_else(|| body.to_string());
+        return Err(message.into())
+    }
+    Ok(body.to_string())
+}
+
+/// Sends `request` to the profile named in the query, which can be left out when there's only one.
+pub async fn send(profiles: &Profiles, name: Option<&str>, request: Request) -> std::result::Result<(), Rejection> {
+    let sender = match name {
+        Some(name) => profiles.iter().find(|(n, _)| n == name)
+            .ok_or_else(|| Rejection::not_found(format!("no profile named [{name}]")))?,
+        None if profiles.len() == 1 => &profiles[0],
+        None => return Err(Rejection::bad_request("several profiles are running - choose one with ?profile=<name>")),
+    };
+    sender.1.send(request).await.map_err(|_| stopped())
+}
+
+fn stopped() -> Rejection {
+    Rejection(503, "organiser is not running".to_string())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Watch directories added and removed through the control API, saved so the changes survive a
+/// restart.
+#[derive(Deserialize, Serialize, Default)]
+pub struct RuntimeWatchDirs {
+    #[serde(default)]
+    added: Vec<WatchDir>,
+    /// Names of configured directories that have been removed.
+    #[serde(default)]
+    removed: Vec<String>,
+    #[serde(skip)]
+    file: Option<PathBuf>,
+}
+
+impl RuntimeWatchDirs {
+    pub fn open(file: Option<PathBuf>) -> Result<Self> {
+        let mut runtime = match file.as_ref().filter(|f| f.exists()) {
+            Some(file) => serde_yaml::from_str(&fs::read_to_string(file)?)
+                .map_err(|e| format!("unable to read [{}]: {e}", file.display()))?,
+            None => RuntimeWatchDirs::default(),
+        };
+        runtime.file = file;
+        Ok(runtime)
+    }
+
+    /// The configured directories with the runtime changes applied.
+    pub fn apply(&self, mut configured: Vec<WatchDir>) -> Vec<WatchDir> {
+        configured.retain(|d| !self.removed.contains(&d.name) && !self.added.iter().any(|a| a.name == d.name));
+        configured.extend(self.added.iter().cloned());
+        configured
+    }
+
+    pub fn add(&mut self, dir: WatchDir) -> Result<()> {
+        self.removed.retain(|name| name != &dir.name);
+        self.added.push(dir);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let before = self.added.len();
+        self.added.retain(|d| d.name != name);
+        if self.added.len() == before {
+            self.removed.push(name.to_string());
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        match &self.file {
+            Some(file) => Ok(fs::write(file, serde_yaml::to_string(self)?)?),
+            None => Ok(()),
+        }
+    }
+}
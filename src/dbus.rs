@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature="dbus")]
+pub use service::{serve, Signals};
+#[cfg(not(feature="dbus"))]
+pub use unsupported::{serve, Signals};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DbusConfig {
+    #[serde(default)]
+    pub bus: Bus,
+    /// Well-known name to claim, so that two daemons on the same bus can be told apart.
+    #[serde(default="default_name")]
+    pub name: String,
+}
+
+fn default_name() -> String {
+    "io.github.ipsi.DownloadOrganiser".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+pub enum Bus {
+    /// The desktop session's bus, for a daemon running as the logged-in user.
+    #[default]
+    #[serde(rename="session")]
+    Session,
+    /// The system bus, which needs a policy file allowing the name to be owned.
+    #[serde(rename="system")]
+    System,
+}
+
+/// The service itself, which needs the `dbus` feature.
+#[cfg(feature="dbus")]
+mod service {
+    use std::path::{Path, PathBuf};
+    use log::{as_debug, info, warn};
+    use serde::Serialize;
+    use tokio::sync::oneshot;
+    use zbus::{connection, fdo, interface, Connection};
+
+    use crate::control::{self, Profiles, Rejection, Request};
+    use crate::Result;
+    use super::{Bus, DbusConfig};
+
+    const OBJECT_PATH: &str = "/io/github/ipsi/DownloadOrganiser";
+    const INTERFACE: &str = "io.github.ipsi.DownloadOrganiser1";
+
+    /// Methods offered on the bus, each forwarded to a profile's organiser like a control API request.
+    struct Service {
+        profiles: Profiles,
+    }
+
+    #[interface(name="io.github.ipsi.DownloadOrganiser1")]
+    impl Service {
+        /// Queues everything already in the watch directories, returning how many entries were queued.
+        async fn rescan(&self, profile: &str) -> fdo::Result<u32> {
+            let (reply, response) = oneshot::channel();
+            self.send(profile, Request::Rescan(reply)).await?;
+            let queued = response.await.map_err(|_| fdo::Error::Failed("organiser is not running".to_string()))?;
+            Ok(queued as u32)
+        }
+
+        async fn process_file(&self, profile: &str, path: &str) -> fdo::Result<()> {
+            let (reply, response) = oneshot::channel();
+            self.send(profile, Request::ProcessFile(PathBuf::from(path), reply)).await?;
+            answer(response).await
+        }
+
+        async fn reload(&self, profile: &str) -> fdo::Result<()> {
+            let (reply, response) = oneshot::channel();
+            self.send(profile, Request::Reload(reply)).await?;
+            answer(response).await
+        }
+    }
+
+    impl Service {
+        /// Sends `request` to the named profile. An empty name means the only profile.
+        async fn send(&self, profile: &str, request: Request) -> fdo::Result<()> {
+            let profile = Some(profile).filter(|p| !p.is_empty());
+            control::send(&self.profiles, profile, request).await.map_err(to_fdo)
+        }
+    }
+
+    async fn answer(response: oneshot::Receiver<std::result::Result<(), Rejection>>) -> fdo::Result<()> {
+        match response.await {
+            Ok(result) => result.map_err(to_fdo),
+            Err(_) => Err(fdo::Error::Failed("organiser is not running".to_string())),
+        }
+    }
+
+    fn to_fdo(Rejection(status, message): Rejection) -> fdo::Error {
+        match status {
+            400 | 404 => fdo::Error::InvalidArgs(message),
+            _ => fdo::Error::Failed(message),
+        }
+    }
+
+    /// Claims the configured name and serves the methods, returning the signals for the organisers
+    /// to emit.
+    pub async fn serve(config: &DbusConfig, profiles: Profiles) -> Result<Signals> {
+        let builder = match config.bus {
+            Bus::Session => connection::Builder::session()?,
+            Bus::System => connection::Builder::system()?,
+        };
+        let connection = builder
+            .name(config.name.as_str())?
+            .serve_at(OBJECT_PATH, Service { profiles })?
+            .build().await
+            .map_err(|e| format!("unable to offer [{}] on the {:?} bus: {e}", config.name, config.bus))?;
+        info!(name=config.name, bus=as_debug!(config.bus); "D-Bus service registered");
+        Ok(Signals { connection })
+    }
+
+    /// Emits the service's signals. Failures are logged, never fatal to the file being processed.
+    #[derive(Clone)]
+    pub struct Signals {
+        connection: Connection,
+    }
+
+    impl Signals {
+        /// A file was handled by `rule`.
+        pub async fn file_organised(&self, path: &Path, rule: &str) {
+            self.emit("FileOrganised", &(path.to_string_lossy(), rule)).await
+        }
+
+        /// A file couldn't be processed.
+        pub async fn action_failed(&self, path: &Path, error: &str) {
+            self.emit("ActionFailed", &(path.to_string_lossy(), error)).await
+        }
+
+        async fn emit(&self, signal: &str, body: &(impl Serialize + zbus::zvariant::DynamicType)) {
+            if let Err(err) = self.connection.emit_signal(None::<&str>, OBJECT_PATH, INTERFACE, signal, body).await {
+                warn!(signal=signal, error=as_debug!(err); "unable to emit D-Bus signal");
+            }
+        }
+    }
+}
+
+/// Stand-ins for when D-Bus support isn't compiled in. A `dbus` config is refused when it's
+/// loaded then, so there are never any signals to emit.
+#[cfg(not(feature="dbus"))]
+mod unsupported {
+    use std::path::Path;
+
+    use crate::control::Profiles;
+    use crate::version;
+    use crate::Result;
+    use super::DbusConfig;
+
+    #[derive(Clone)]
+    pub enum Signals {}
+
+    impl Signals {
+        pub async fn file_organised(&self, _path: &Path, _rule: &str) {
+            match *self {}
+        }
+
+        pub async fn action_failed(&self, _path: &Path, _error: &str) {
+            match *self {}
+        }
+    }
+
+    pub async fn serve(_config: &DbusConfig, _profiles: Profiles) -> Result<Signals> {
+        version::require("setting", "dbus")?;
+        unreachable!("the dbus feature is compiled in")
+    }
+}
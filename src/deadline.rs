@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::duration;
+
+/// Longest between checks for overdue files, so a long deadline is still noticed soon after it passes.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How soon files should be organised after they arrive, so a rule that silently never matches or
+/// a stuck queue is noticed.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeadlineConfig {
+    /// Time from a file arriving to it being organised, such as `5m`.
+    #[serde(deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+    pub after: Duration,
+    /// Send a notification about each overdue file.
+    #[serde(default)]
+    pub notify: bool,
+    /// Names of the `notifications` channels to send to. Every channel when unset.
+    #[serde(rename="notifyVia", skip_serializing_if="Option::is_none")]
+    pub notify_via: Option<Vec<String>>,
+}
+
+impl DeadlineConfig {
+    /// How often to look for overdue files.
+    pub fn interval(&self) -> Duration {
+        (self.after / 4).clamp(Duration::from_secs(1), MAX_CHECK_INTERVAL)
+    }
+}
+
+/// Files that have arrived but not been organised yet.
+#[derive(Default)]
+pub struct Arrivals {
+    files: Mutex<HashMap<PathBuf, Arrival>>,
+}
+
+struct Arrival {
+    at: Instant,
+    /// Whether it has been reported as overdue, so it's only reported once.
+    reported: bool,
+}
+
+impl Arrivals {
+    /// Notes that `path` has arrived, unless it's already waiting, when it keeps its first arrival.
+    pub fn arrived(&self, path: &Path) {
+        self.files.lock().unwrap().entry(path.to_path_buf()).or_insert_with(|| Arrival { at: Instant::now(), reported: false });
+    }
+
+    /// Notes that `path` has been dealt with.
+    pub fn done(&self, path: &Path) {
+        self.files.lock().unwrap().remove(path);
+    }
+
+    /// Files waiting longer than `after` that haven't been reported yet, with how long they've
+    /// waited, and how many are overdue altogether. Files that have gone, such as ones moved away
+    /// by hand, are forgotten.
+    pub fn overdue(&self, after: Duration) -> (Vec<(PathBuf, Duration)>, usize) {
+        let mut files = self.files.lock().unwrap();
+        files.retain(|path, _| path.symlink_metadata().is_ok());
+        let mut newly = Vec::new();
+        let mut count = 0;
+        for (path, arrival) in files.iter_mut() {
+            let waited = arrival.at.elapsed();
+            if waited < after {
+                continue
+            }
+            count += 1;
+            if !arrival.reported {
+                arrival.reported = true;
+                newly.push((path.clone(), waited));
+            }
+        }
+        (newly, count)
+    }
+}
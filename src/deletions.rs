@@ -0,0 +1,153 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{as_debug, as_error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::Result;
+
+/// A file to delete once it's due.
+#[derive(Deserialize, Serialize, Clone)]
+struct Scheduled {
+    path: PathBuf,
+    /// Seconds since the epoch.
+    due: u64,
+    /// Inode the file had when it was scheduled, so a different file that has since taken its
+    /// name is left alone.
+    inode: u64,
+}
+
+/// How the schedule compares with the files it's for.
+pub struct ScheduleCheck {
+    pub pending: usize,
+    /// Already due, so deleted as soon as the schedule runs.
+    pub overdue: usize,
+    /// For files that are gone, or have been replaced by another with the same name.
+    pub orphaned: usize,
+}
+
+/// Files scheduled by `deleteAfter`, saved so the schedule outlasts a restart. The file is
+/// rewritten whole from this profile's schedule, so no two profiles may share a `stateDir`.
+pub struct Deletions {
+    scheduled: Mutex<Vec<Scheduled>>,
+    file: Option<PathBuf>,
+    /// Woken when something is scheduled, in case it's due before whatever was next.
+    added: Notify,
+}
+
+impl Deletions {
+    /// Loads the saved schedule from `file`, if there is one.
+    pub fn open(file: Option<PathBuf>) -> Result<Deletions> {
+        let scheduled = match file.as_ref().filter(|f| f.exists()) {
+            Some(file) => serde_yaml::from_str(&fs::read_to_string(file)?)
+                .map_err(|e| format!("unable to read [{}]: {e}", file.display()))?,
+            None => Vec::new(),
+        };
+        Ok(Deletions { scheduled: Mutex::new(scheduled), file, added: Notify::new() })
+    }
+
+    /// Deletes `path` once `after` has passed. Scheduling a path again replaces its deadline.
+    pub fn schedule(&self, path: &Path, after: Duration) -> Result<()> {
+        let inode = fs::symlink_metadata(path)?.ino();
+        let due = (SystemTime::now() + after).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut scheduled = self.scheduled.lock().unwrap();
+        scheduled.retain(|s| s.path != path);
+        scheduled.push(Scheduled { path: path.to_path_buf(), due, inode });
+        self.save(&scheduled);
+        drop(scheduled);
+        self.added.notify_one();
+        Ok(())
+    }
+
+    /// Checks the scheduled files still exist, dropping those that don't when `clean_up` is set.
+    pub fn reconcile(&self, clean_up: bool) -> ScheduleCheck {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut scheduled = self.scheduled.lock().unwrap();
+        let orphaned = |s: &Scheduled| fs::symlink_metadata(&s.path).map(|m| m.ino() != s.inode).unwrap_or(true);
+        let check = ScheduleCheck {
+            pending: scheduled.len(),
+            overdue: scheduled.iter().filter(|s| s.due <= now).count(),
+            orphaned: scheduled.iter().filter(|s| orphaned(s)).count(),
+        };
+        if clean_up && check.orphaned > 0 {
+            scheduled.retain(|s| !orphaned(s));
+            self.save(&scheduled);
+        }
+        check
+    }
+
+    fn save(&self, scheduled: &[Scheduled]) {
+        if let Some(file) = &self.file {
+            if let Err(err) = write(file, scheduled) {
+                warn!(error=as_error!(*err); "unable to save scheduled deletions - they will be lost on restart");
+            }
+        }
+    }
+
+    /// Deletes files as they fall due, forever. Ones that were due while the organiser wasn't
+    /// running are deleted straight away. With `dry_run` they are only logged, and the saved
+    /// schedule is left as it was.
+    pub async fn run(&self, dry_run: bool) {
+        loop {
+            let added = self.added.notified();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let due: Vec<Scheduled> = {
+                let mut scheduled = self.scheduled.lock().unwrap();
+                let (due, waiting) = scheduled.drain(..).partition(|s| s.due <= now);
+                *scheduled = waiting;
+                if !due.is_empty() && !dry_run {
+                    self.save(&scheduled);
+                }
+                due
+            };
+            for scheduled in due {
+                delete(&scheduled, dry_run);
+            }
+            let next = self.scheduled.lock().unwrap().iter().map(|s| s.due).min();
+            match next {
+                Some(next) => {
+                    let wait = Duration::from_secs(next.saturating_sub(now).max(1));
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {},
+                        _ = added => {},
+                    }
+                },
+                None => added.await,
+            }
+        }
+    }
+}
+
+fn write(file: &Path, scheduled: &[Scheduled]) -> Result<()> {
+    Ok(fs::write(file, serde_yaml::to_string(scheduled)?)?)
+}
+
+fn delete(scheduled: &Scheduled, dry_run: bool) {
+    let path = scheduled.path.to_str();
+    let metadata = match fs::symlink_metadata(&scheduled.path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            info!(path=path; "file scheduled for deletion is already gone");
+            return
+        },
+    };
+    if metadata.ino() != scheduled.inode {
+        warn!(path=path; "file scheduled for deletion has been replaced by another - leaving it");
+        return
+    }
+    if dry_run {
+        info!(path=path; "dry run - would delete file whose deleteAfter delay has passed");
+        return
+    }
+    let deleted = match metadata.is_dir() {
+        true => fs::remove_dir_all(&scheduled.path),
+        false => fs::remove_file(&scheduled.path),
+    };
+    match deleted {
+        Ok(()) => info!(path=path; "deleted file whose deleteAfter delay has passed"),
+        Err(err) => warn!(path=path, error=as_debug!(err); "unable to delete file scheduled for deletion"),
+    }
+}
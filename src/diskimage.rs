@@ -0,0 +1,171 @@
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+const SECTOR: u64 = 512;
+
+/// Where an ISO 9660 filesystem's primary volume descriptor is.
+const ISO_DESCRIPTOR: u64 = 16 * 2048;
+
+/// Where an ext2/3/4 superblock starts, relative to the filesystem.
+const EXT_SUPERBLOCK: u64 = 1024;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    /// An ISO 9660 optical disc image, including hybrid images that also boot from USB.
+    #[serde(rename="iso")]
+    Iso,
+    /// A raw disk or filesystem image, such as a Raspberry Pi `.img` or a `dd` backup.
+    #[serde(rename="img")]
+    Img,
+}
+
+impl ImageFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Iso => "iso",
+            ImageFormat::Img => "img",
+        }
+    }
+}
+
+/// A rule condition on a disk image. Every field that is set has to match.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ImageCondition {
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub format: Option<ImageFormat>,
+    /// Matched against the volume label, e.g. `^Ubuntu 24\.04`.
+    #[serde(with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub label: Option<Regex>,
+}
+
+impl ImageCondition {
+    pub fn matches(&self, image: &DiskImage, filename: &str) -> bool {
+        if let Some(format) = self.format {
+            if image.format != format {
+                info!(filename=filename, format=image.format.name(), required=format.name(); "disk image format doesn't match - skipping rule");
+                return false
+            }
+        }
+        if let Some(regex) = &self.label {
+            if !image.label.as_deref().is_some_and(|l| regex.is_match(l)) {
+                info!(filename=filename, label=image.label.as_deref(), regex=regex.as_str(); "volume label doesn't match - skipping rule");
+                return false
+            }
+        }
+        true
+    }
+}
+
+/// What a disk image's contents say it is.
+pub struct DiskImage {
+    pub format: ImageFormat,
+    /// The volume label, if the image (or its first partition) has one this can read: ISO 9660,
+    /// FAT or ext2/3/4.
+    pub label: Option<String>,
+}
+
+impl DiskImage {
+    /// A field by the name used in `{image:...}` template tokens.
+    pub fn field(&self, name: &str) -> Option<String> {
+        match name {
+            "label" => self.label.clone(),
+            "format" => Some(self.format.name().to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Reads `path` as a disk image, going by its contents rather than its extension. Returns `None`
+/// for anything that isn't one.
+pub fn read(path: &Path) -> Result<Option<DiskImage>> {
+    let mut file = fs::File::open(path)?;
+    if let Some(descriptor) = read_at(&mut file, ISO_DESCRIPTOR, 2048)? {
+        if descriptor[0] == 1 && &descriptor[1..6] == b"CD001" {
+            return Ok(Some(DiskImage { format: ImageFormat::Iso, label: text(&descriptor[40..72]) }))
+        }
+    }
+
+    // a filesystem image without a partition table
+    if let Some(label) = filesystem_label(&mut file, 0)? {
+        return Ok(Some(DiskImage { format: ImageFormat::Img, label }))
+    }
+    let boot = match read_at(&mut file, 0, SECTOR as usize)? {
+        Some(boot) if boot[510..512] == [0x55, 0xaa] => boot,
+        _ => return Ok(None),
+    };
+    let label = match first_partition(&mut file, &boot)? {
+        Some(start) => filesystem_label(&mut file, start)?.flatten(),
+        None => None,
+    };
+    Ok(Some(DiskImage { format: ImageFormat::Img, label }))
+}
+
+/// The label of a FAT or ext filesystem starting at `offset`: `None` when there isn't one of
+/// those there, `Some(None)` when there is but it has no label.
+fn filesystem_label(file: &mut fs::File, offset: u64) -> Result<Option<Option<String>>> {
+    if let Some(boot) = read_at(file, offset, SECTOR as usize)? {
+        if boot[510..512] == [0x55, 0xaa] {
+            if &boot[0x52..0x57] == b"FAT32" {
+                return Ok(Some(text(&boot[0x47..0x52])))
+            }
+            if &boot[0x36..0x39] == b"FAT" {
+                return Ok(Some(text(&boot[0x2b..0x36])))
+            }
+        }
+    }
+    let Some(superblock_offset) = offset.checked_add(EXT_SUPERBLOCK) else { return Ok(None) };
+    if let Some(superblock) = read_at(file, superblock_offset, 1024)? {
+        if superblock[56..58] == [0x53, 0xef] {
+            return Ok(Some(text(&superblock[120..136])))
+        }
+    }
+    Ok(None)
+}
+
+/// Byte offset of the first partition in an MBR or GPT partition table.
+fn first_partition(file: &mut fs::File, boot: &[u8]) -> Result<Option<u64>> {
+    let entry = &boot[446..462];
+    if entry[4] == 0xee {
+        // protective MBR - the real table is GPT
+        let header = match read_at(file, SECTOR, SECTOR as usize)? {
+            Some(header) if &header[0..8] == b"EFI PART" => header,
+            _ => return Ok(None),
+        };
+        // LBAs come from the image, so a crafted one mustn't overflow
+        let entries = u64::from_le_bytes(header[72..80].try_into().unwrap());
+        let Some(entries) = entries.checked_mul(SECTOR) else { return Ok(None) };
+        return Ok(read_at(file, entries, 128)?
+            .map(|entry| u64::from_le_bytes(entry[32..40].try_into().unwrap()))
+            .filter(|lba| *lba != 0)
+            .and_then(|lba| lba.checked_mul(SECTOR)))
+    }
+    Ok(boot[446..510].chunks_exact(16)
+        .find(|entry| entry[4] != 0)
+        .map(|entry| u64::from(u32::from_le_bytes(entry[8..12].try_into().unwrap())) * SECTOR))
+}
+
+/// Reads `len` bytes at `offset`, or `None` if the file is too short.
+fn read_at(file: &mut fs::File, offset: u64, len: usize) -> Result<Option<Vec<u8>>> {
+    if offset > i64::MAX as u64 {
+        return Ok(None)
+    }
+    let mut buffer = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset))?;
+    match file.read_exact(&mut buffer) {
+        Ok(()) => Ok(Some(buffer)),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// A space or NUL padded label, which FAT sets to `NO NAME` when there isn't one.
+fn text(bytes: &[u8]) -> Option<String> {
+    let label = String::from_utf8_lossy(bytes).trim_matches(|c: char| c == ' ' || c == '\0').to_string();
+    Some(label).filter(|l| !l.is_empty() && l != "NO NAME")
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{as_error, debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::DuplicateAction;
+use crate::fsutil;
+use crate::hashcache;
+use crate::plan::MoveTarget;
+
+/// How a file already at the destination is compared with the one arriving, to tell a real
+/// duplicate from a different file that happens to have the same name.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Compare {
+    /// Anything already there is a clash, dealt with by `duplicate`.
+    #[default]
+    #[serde(rename="exists")]
+    Exists,
+    /// A file with the same content is the one already organised, so the arriving file is
+    /// skipped. Anything else is a clash.
+    #[serde(rename="hash")]
+    Hash,
+    /// Like `hash`, but comparing size and modification time, which is cheaper for large files.
+    #[serde(rename="size-mtime")]
+    SizeMtime,
+    /// Keeps whichever is newer: a newer file replaces the one there, an older one is skipped.
+    #[serde(rename="version")]
+    Version,
+}
+
+/// What to do with a file whose destination already exists. Each way of deciding is its own
+/// strategy, so the actions that place files share them.
+pub trait Strategy {
+    /// Where `source` goes given that `dest` already exists.
+    fn resolve(&self, source: &Path, dest: PathBuf) -> MoveTarget;
+}
+
+/// The strategy for an action's `duplicate` and `compare`.
+pub fn strategy(duplicate: &DuplicateAction, compare: Compare) -> Box<dyn Strategy + '_> {
+    let clash = ExistsCheck(duplicate);
+    match compare {
+        Compare::Exists => Box::new(clash),
+        Compare::Hash => Box::new(HashCompare(clash)),
+        Compare::SizeMtime => Box::new(SizeMtimeCompare(clash)),
+        Compare::Version => Box::new(VersionKeep),
+    }
+}
+
+/// Treats any existing destination as a clash, settled by the `duplicate` setting.
+pub struct ExistsCheck<'a>(pub &'a DuplicateAction);
+
+impl Strategy for ExistsCheck<'_> {
+    fn resolve(&self, source: &Path, dest: PathBuf) -> MoveTarget {
+        match self.0 {
+            DuplicateAction::Skip => MoveTarget::Skip(dest),
+            DuplicateAction::Overwrite => MoveTarget::Replace(dest),
+            DuplicateAction::Merge if source.is_dir() && dest.is_dir() => MoveTarget::Merge(dest),
+            DuplicateAction::RenameDate | DuplicateAction::Merge => {
+                let name = dest.file_name().unwrap_or_default().to_string_lossy().to_string();
+                MoveTarget::Move(fsutil::fit(&dest.with_file_name(fsutil::date_prefixed(&name))))
+            },
+        }
+    }
+}
+
+/// Skips a file whose content is the same as the destination's, leaving anything else to the
+/// strategy it wraps.
+pub struct HashCompare<S>(pub S);
+
+impl<S: Strategy> Strategy for HashCompare<S> {
+    fn resolve(&self, source: &Path, dest: PathBuf) -> MoveTarget {
+        if !(source.is_file() && dest.is_file()) {
+            return self.0.resolve(source, dest)
+        }
+        match (hashcache::hash_file(source), hashcache::hash_file(&dest)) {
+            (Ok(a), Ok(b)) if a == b => {
+                debug!(source=source.to_str(), dest=dest.to_str(); "destination has the same content");
+                MoveTarget::Skip(dest)
+            },
+            (Err(err), _) | (_, Err(err)) => {
+                warn!(source=source.to_str(), dest=dest.to_str(), error=as_error!(*err); "unable to compare file with its destination - treating it as a clash");
+                self.0.resolve(source, dest)
+            },
+            _ => self.0.resolve(source, dest),
+        }
+    }
+}
+
+/// Skips a file with the same size and modification time as the destination, leaving anything
+/// else to the strategy it wraps.
+pub struct SizeMtimeCompare<S>(pub S);
+
+impl<S: Strategy> Strategy for SizeMtimeCompare<S> {
+    fn resolve(&self, source: &Path, dest: PathBuf) -> MoveTarget {
+        let same = match (fs::metadata(source), fs::metadata(&dest)) {
+            (Ok(a), Ok(b)) => a.is_file() && b.is_file() && a.len() == b.len() && fsutil::mtime(&a) == fsutil::mtime(&b),
+            _ => false,
+        };
+        match same {
+            true => MoveTarget::Skip(dest),
+            false => self.0.resolve(source, dest),
+        }
+    }
+}
+
+/// Keeps the newer of the two by modification time, the destination winning a tie.
+pub struct VersionKeep;
+
+impl Strategy for VersionKeep {
+    fn resolve(&self, source: &Path, dest: PathBuf) -> MoveTarget {
+        let modified = |path: &Path| fs::metadata(path).map(|m| fsutil::mtime(&m)).unwrap_or(0);
+        match modified(source) > modified(&dest) {
+            true => MoveTarget::Replace(dest),
+            false => MoveTarget::Skip(dest),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::Result;
+
+/// Parses a human duration such as `500ms`, `30s`, `5m`, `12h`, `90d` or `1w`. A bare number is
+/// taken as seconds.
+pub fn parse(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount.parse()
+        .map_err(|_| format!("duration [{value}] does not start with a number"))?;
+
+    let seconds = match unit.trim() {
+        "ms" => amount / 1000.0,
+        "" | "s" | "sec" | "secs" => amount,
+        "m" | "min" | "mins" => amount * 60.0,
+        "h" | "hr" | "hrs" => amount * 60.0 * 60.0,
+        "d" | "day" | "days" => amount * 60.0 * 60.0 * 24.0,
+        "w" | "week" | "weeks" => amount * 60.0 * 60.0 * 24.0 * 7.0,
+        v => return Err(format!("unknown duration unit [{v}] in [{value}]").into()),
+    };
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Formats a duration in the largest unit that represents it exactly, e.g. `90s` as `90s` and
+/// `7d` as `1w`.
+pub fn format(duration: &Duration) -> String {
+    let millis = duration.as_millis();
+    if millis == 0 {
+        return "0s".to_string()
+    }
+    const UNITS: [(&str, u128); 5] = [("w", 604_800_000), ("d", 86_400_000), ("h", 3_600_000), ("m", 60_000), ("s", 1000)];
+    UNITS.iter()
+        .find(|(_, size)| millis.is_multiple_of(*size))
+        .map(|(unit, size)| format!("{}{unit}", millis / size))
+        .unwrap_or_else(|| format!("{millis}ms"))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Seconds(f64),
+    Text(String),
+}
+
+/// Serde helper for `#[serde(deserialize_with = "duration::deserialize")]`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Duration, D::Error> {
+    from_raw(RawDuration::deserialize(deserializer)?)
+}
+
+/// Serde helper for optional durations, used with `#[serde(default)]`. Accepts `null`, which is
+/// how an unset duration is serialised.
+pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error> {
+    Option::<RawDuration>::deserialize(deserializer)?.map(from_raw).transpose()
+}
+
+fn from_raw<E: serde::de::Error>(raw: RawDuration) -> std::result::Result<Duration, E> {
+    match raw {
+        RawDuration::Seconds(secs) if secs >= 0.0 => Ok(Duration::from_secs_f64(secs)),
+        RawDuration::Seconds(secs) => Err(E::custom(format!("duration [{secs}] is negative"))),
+        RawDuration::Text(raw) => parse(&raw).map_err(E::custom),
+    }
+}
+
+/// Serde helper for `#[serde(serialize_with = "duration::serialize")]`.
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(duration))
+}
+
+/// Serde helper for optional durations.
+pub fn serialize_option<S: Serializer>(duration: &Option<Duration>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use log::{as_debug, debug, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::http;
+use crate::retry::HttpStatusError;
+use crate::secret::SecretRef;
+use crate::Result;
+
+const TMDB_API: &str = "https://api.themoviedb.org/3";
+const TVDB_API: &str = "https://api4.thetvdb.com/v4";
+
+/// Where episode titles are looked up.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+pub enum Provider {
+    /// The Movie Database, with an API read access token.
+    #[default]
+    #[serde(rename="tmdb")]
+    Tmdb,
+    /// TheTVDB, with a v4 API key.
+    #[serde(rename="tvdb")]
+    Tvdb,
+}
+
+impl Provider {
+    fn key(&self) -> &'static str {
+        match self {
+            Provider::Tmdb => "tmdb",
+            Provider::Tvdb => "tvdb",
+        }
+    }
+}
+
+/// What a release's name says about the episode in it.
+pub struct Episode {
+    pub show: String,
+    pub year: Option<u16>,
+    pub season: u32,
+    pub episode: u32,
+}
+
+/// Finds the show, season and episode in a release name such as `Show.Name.2019.S01E02.1080p.mkv`
+/// or `Show Name - 1x02.avi`.
+pub fn parse(name: &str) -> Option<Episode> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(concat!(
+        r"(?i)^(?P<show>.+?)[ ._-]+(?:\(?(?P<year>(?:19|20)\d{2})\)?[ ._-]+)?",
+        r"(?:s(?P<season>\d{1,2})[ ._-]?e(?P<episode>\d{1,3})|(?P<x_season>\d{1,2})x(?P<x_episode>\d{2,3}))\b",
+    )).unwrap());
+
+    let captures = pattern.captures(name)?;
+    let number = |a: &str, b: &str| captures.name(a).or_else(|| captures.name(b)).and_then(|m| m.as_str().parse().ok());
+    let show = captures["show"].replace(['.', '_'], " ");
+    Some(Episode {
+        show: show.split_whitespace().collect::<Vec<_>>().join(" "),
+        year: captures.name("year").and_then(|m| m.as_str().parse().ok()),
+        season: number("season", "x_season")?,
+        episode: number("episode", "x_episode")?,
+    })
+}
+
+impl Episode {
+    pub fn code(&self) -> String {
+        format!("S{:02}E{:02}", self.season, self.episode)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct Series {
+    id: String,
+    name: String,
+    year: Option<u16>,
+}
+
+/// An episode as the provider names it.
+pub struct Named {
+    show: String,
+    year: Option<u16>,
+    season: u32,
+    episode: u32,
+    title: String,
+}
+
+impl Named {
+    /// Where the episode goes, relative to the action's `dest`:
+    /// `Show (Year)/Season 01/Show - S01E02 - Title.ext`.
+    pub fn path(&self, extension: Option<&str>) -> PathBuf {
+        let show = component(&self.show);
+        let folder = match self.year {
+            Some(year) => format!("{show} ({year})"),
+            None => show.clone(),
+        };
+        let mut file = format!("{show} - S{:02}E{:02} - {}", self.season, self.episode, component(&self.title));
+        if let Some(extension) = extension {
+            file.push('.');
+            file.push_str(extension);
+        }
+        PathBuf::from(folder).join(format!("Season {:02}", self.season)).join(file)
+    }
+}
+
+/// Makes a show or episode title safe to use as a file name on any filesystem the files might end
+/// up shared from.
+fn component(value: &str) -> String {
+    let value = value.replace(": ", " - ").replace(['/', '\\', ':'], "-").replace(['?', '*', '"', '<', '>', '|', '\0'], "");
+    value.trim().trim_end_matches('.').to_string()
+}
+
+/// Shows and episode titles already looked up, kept in the state directory so that a season's worth
+/// of episodes costs one search, and a restart none.
+pub struct EpisodeCache {
+    file: Option<PathBuf>,
+    entries: CacheEntries,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct CacheEntries {
+    #[serde(default)]
+    shows: BTreeMap<String, Series>,
+    #[serde(default)]
+    titles: BTreeMap<String, String>,
+}
+
+impl EpisodeCache {
+    pub fn open(file: Option<PathBuf>) -> Result<EpisodeCache> {
+        let entries = match &file {
+            Some(file) if file.exists() => match serde_json::from_slice(&fs::read(file)?) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    warn!(file=file.to_str(), error=as_debug!(err); "episode cache is unreadable - starting afresh");
+                    CacheEntries::default()
+                },
+            },
+            _ => CacheEntries::default(),
+        };
+        Ok(EpisodeCache { file, entries })
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let tmp = file.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(&self.entries)?)?;
+        fs::rename(&tmp, file)?;
+        Ok(())
+    }
+}
+
+/// Looks up the show and episode title, from the cache when they've been looked up before.
+pub async fn lookup(cache: &Mutex<EpisodeCache>, provider: Provider, api_key: &SecretRef, episode: &Episode) -> Result<Named> {
+    let show_key = format!("{}:{}:{}", provider.key(), episode.show.to_lowercase(), episode.year.map(|y| y.to_string()).unwrap_or_default());
+    let cached = cache.lock().unwrap().entries.shows.get(&show_key).cloned();
+    let mut client = Client { provider, api_key, token: None };
+    let series = match cached {
+        Some(series) => series,
+        None => {
+            let series = client.search(&episode.show, episode.year).await?
+                .ok_or_else(|| format!("no show called [{}] on {}", episode.show, provider.key()))?;
+            debug!(show=episode.show, found=series.name, id=series.id; "show looked up");
+            let mut cache = cache.lock().unwrap();
+            cache.entries.shows.insert(show_key, series.clone());
+            cache.save()?;
+            series
+        },
+    };
+
+    let title_key = format!("{}:{}:{}:{}", provider.key(), series.id, episode.season, episode.episode);
+    let cached = cache.lock().unwrap().entries.titles.get(&title_key).cloned();
+    let title = match cached {
+        Some(title) => title,
+        None => {
+            let title = client.title(&series.id, episode.season, episode.episode).await?
+                .ok_or_else(|| format!("[{}] has no episode {}", series.name, episode.code()))?;
+            let mut cache = cache.lock().unwrap();
+            cache.entries.titles.insert(title_key, title.clone());
+            cache.save()?;
+            title
+        },
+    };
+
+    Ok(Named { show: series.name, year: series.year, season: episode.season, episode: episode.episode, title })
+}
+
+struct Client<'a> {
+    provider: Provider,
+    api_key: &'a SecretRef,
+    /// TheTVDB's bearer token, which has to be logged in for with the API key.
+    token: Option<String>,
+}
+
+impl Client<'_> {
+    async fn search(&mut self, show: &str, year: Option<u16>) -> Result<Option<Series>> {
+        #[derive(Deserialize)]
+        struct TmdbResults { results: Vec<TmdbShow> }
+        #[derive(Deserialize)]
+        struct TmdbShow { id: u64, name: String, first_air_date: Option<String> }
+        #[derive(Deserialize)]
+        struct TvdbResults { data: Vec<TvdbShow> }
+        #[derive(Deserialize)]
+        struct TvdbShow { tvdb_id: String, name: String, year: Option<String> }
+
+        let year_of = |date: Option<String>| date.and_then(|d| d.get(..4).and_then(|y| y.parse().ok()));
+        match self.provider {
+            Provider::Tmdb => {
+                let mut url = format!("{TMDB_API}/search/tv?query={}", http::encode(show));
+                if let Some(year) = year {
+                    url.push_str(&format!("&first_air_date_year={year}"));
+                }
+                let results: TmdbResults = self.get(&url).await?;
+                Ok(results.results.into_iter().next()
+                    .map(|s| Series { id: s.id.to_string(), name: s.name, year: year_of(s.first_air_date) }))
+            },
+            Provider::Tvdb => {
+                let mut url = format!("{TVDB_API}/search?type=series&query={}", http::encode(show));
+                if let Some(year) = year {
+                    url.push_str(&format!("&year={year}"));
+                }
+                let results: TvdbResults = self.get(&url).await?;
+                Ok(results.data.into_iter().next()
+                    .map(|s| Series { id: s.tvdb_id, name: s.name, year: year_of(s.year) }))
+            },
+        }
+    }
+
+    async fn title(&mut self, id: &str, season: u32, episode: u32) -> Result<Option<String>> {
+        #[derive(Deserialize)]
+        struct TmdbEpisode { name: String }
+        #[derive(Deserialize)]
+        struct TvdbEpisodes { data: TvdbSeries }
+        #[derive(Deserialize)]
+        struct TvdbSeries { episodes: Vec<TvdbEpisode> }
+        #[derive(Deserialize)]
+        struct TvdbEpisode { name: Option<String> }
+
+        match self.provider {
+            Provider::Tmdb => {
+                let url = format!("{TMDB_API}/tv/{id}/season/{season}/episode/{episode}");
+                match self.get::<TmdbEpisode>(&url).await {
+                    Ok(found) => Ok(Some(found.name)),
+                    Err(err) if is_not_found(err.as_ref()) => Ok(None),
+                    Err(err) => Err(err),
+                }
+            },
+            Provider::Tvdb => {
+                let url = format!("{TVDB_API}/series/{id}/episodes/default?season={season}&episodeNumber={episode}");
+                let found: TvdbEpisodes = self.get(&url).await?;
+                Ok(found.data.episodes.into_iter().find_map(|e| e.name))
+            },
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&mut self, url: &str) -> Result<T> {
+        let headers = vec![format!("Authorization: Bearer {}", self.token().await?), "Accept: application/json".to_string()];
+        let (_, body) = http::request("GET", url, &headers, None).await?;
+        serde_json::from_slice(&body).map_err(|e| format!("unexpected {} response: {e}", self.provider.key()).into())
+    }
+
+    async fn token(&mut self) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Login { data: LoginData }
+        #[derive(Deserialize)]
+        struct LoginData { token: String }
+
+        if let Some(token) = &self.token {
+            return Ok(token.clone())
+        }
+        let api_key = self.api_key.resolve()?.expose().to_string();
+        let token = match self.provider {
+            Provider::Tmdb => api_key,
+            Provider::Tvdb => {
+                let body = serde_json::json!({ "apikey": api_key });
+                let headers = ["Content-Type: application/json".to_string(), "Accept: application/json".to_string()];
+                let (_, response) = http::request("POST", &format!("{TVDB_API}/login"), &headers, Some(&body.to_string())).await?;
+                let login: Login = serde_json::from_slice(&response).map_err(|e| format!("unexpected tvdb login response: {e}"))?;
+                login.data.token
+            },
+        };
+        self.token = Some(token.clone());
+        Ok(token)
+    }
+}
+
+fn is_not_found(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    err.downcast_ref::<HttpStatusError>().is_some_and(|e| e.0 == 404)
+}
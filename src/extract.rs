@@ -0,0 +1,353 @@
+use std::fs;
+#[cfg(feature="archives")]
+use std::io::{self, Read};
+#[cfg(feature="archives")]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use log::debug;
+#[cfg(feature="archives")]
+use log::{as_debug, info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[cfg(feature="archives")]
+use crate::fsutil;
+use crate::glob::Glob;
+use crate::size::{self, Bare, SizeRange};
+use crate::Result;
+
+/// Compression ratio above which a single entry looks like a zip bomb.
+pub const SUSPICIOUS_RATIO: f64 = 100.0;
+
+/// Total uncompressed size above which an archive is flagged for review.
+pub const SUSPICIOUS_TOTAL_SIZE: u64 = 10 * 2u64.pow(30);
+
+/// Entry count above which an archive is flagged for review.
+pub const SUSPICIOUS_ENTRY_COUNT: usize = 10_000;
+
+/// What `unzip` does with archive entries that are symlinks.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum LinkPolicy {
+    /// Leaves them out.
+    #[default]
+    #[serde(rename="skip")]
+    Skip,
+    /// Creates them, if they point inside the extracted tree.
+    #[serde(rename="create")]
+    Create,
+    /// Writes a copy of the file they point to in their place, if it's inside the extracted tree.
+    #[serde(rename="resolve")]
+    Resolve,
+}
+
+#[cfg(feature="archives")]
+/// Extracts every entry of the zip archive at `source` into `dest`, skipping entries whose
+/// paths would escape it and handling symlink entries according to `links`. Returns the paths of
+/// the extracted files.
+///
+/// Symlinks are dealt with after everything else, so no entry is written through one, and every
+/// directory written to is checked against the real path of `dest` in case one was there already.
+pub fn unzip(source: &Path, dest: &Path, links: LinkPolicy) -> Result<Vec<PathBuf>> {
+    let file = fs::File::open(source)?;
+
+    let mut archive = zip::ZipArchive::new(file)?;
+    fs::create_dir_all(dest)?;
+    let root = fs::canonicalize(dest)?;
+
+    let mut extracted = Vec::new();
+    let mut symlinks = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let relative = match file.enclosed_name() {
+            Some(path) => path.to_path_buf(),
+            None => {
+                warn!(file_index=i, entry=file.name(); "skipping entry whose path leaves the destination");
+                continue
+            },
+        };
+        let outpath = dest.join(&relative);
+
+        {
+            let comment = file.comment();
+            if !comment.is_empty() {
+                info!(file_index=i, comment=comment; "File comment");
+            }
+        }
+
+        if is_symlink(file.unix_mode()) {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            symlinks.push((outpath, PathBuf::from(target)));
+            continue
+        }
+        if (file.name()).ends_with('/') {
+            info!(file_index=i, destination=outpath.to_str(); "File extracted");
+            create_dir_inside(&root, &outpath)?;
+        } else {
+            info!(
+                file_index=i,
+                destination=outpath.to_str(),
+                file_size=file.size();
+                "File extracted",
+            );
+            if let Some(p) = outpath.parent() {
+                create_dir_inside(&root, p)?;
+            }
+            // a symlink already at the path is refused rather than followed
+            let mut outfile = fs::OpenOptions::new().write(true).create(true).truncate(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&outpath)
+                .map_err(|e| format!("unable to write [{}]: {e}", outpath.display()))?;
+            io::copy(&mut file, &mut outfile)?;
+            extracted.push(outpath.clone());
+        }
+
+        // Get and Set permissions
+        if let Some(mode) = file.unix_mode() {
+            fs::set_permissions(&outpath, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    for (link, target) in symlinks {
+        // where the link points, worked out from the paths alone as nothing it names may exist yet
+        let resolved = link.parent().map(|dir| dir.join(&target)).unwrap_or_else(|| target.clone());
+        if links == LinkPolicy::Skip || target.is_absolute() || !fsutil::is_within(dest, &resolved) {
+            warn!(link=link.to_str(), target=target.to_str(), policy=as_debug!(links); "skipping symlink entry");
+            continue
+        }
+        if let Some(parent) = link.parent() {
+            create_dir_inside(&root, parent)?;
+        }
+        if fs::symlink_metadata(&link).is_ok() {
+            warn!(link=link.to_str(); "skipping symlink entry whose path is already taken");
+            continue
+        }
+        match links {
+            LinkPolicy::Create => std::os::unix::fs::symlink(&target, &link)?,
+            _ => {
+                let real = match fs::canonicalize(&resolved) {
+                    Ok(real) if real.starts_with(&root) && real.is_file() => real,
+                    _ => {
+                        warn!(link=link.to_str(), target=target.to_str(); "symlink entry doesn't point to an extracted file - skipping");
+                        continue
+                    },
+                };
+                fs::copy(real, &link)?;
+            },
+        }
+        debug!(link=link.to_str(), target=target.to_str(), policy=as_debug!(links); "extracted symlink entry");
+        extracted.push(link);
+    }
+
+    Ok(extracted)
+}
+
+#[cfg(feature="archives")]
+/// Whether a zip entry's Unix mode marks it as a symlink.
+pub fn is_symlink(mode: Option<u32>) -> bool {
+    mode.is_some_and(|mode| mode & libc::S_IFMT == libc::S_IFLNK)
+}
+
+#[cfg(feature="archives")]
+/// Creates `dir`, failing if it turns out to be outside `root` (a real path) once any symlinks on
+/// the way are followed.
+fn create_dir_inside(root: &Path, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    if !fs::canonicalize(dir)?.starts_with(root) {
+        return Err(format!("[{}] leads outside the destination through a symlink", dir.display()).into())
+    }
+    Ok(())
+}
+
+/// Junk removed from an archive's output after extraction.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Cleanup {
+    /// Entries deleted whatever their size. Globs without `/` match a name at any depth.
+    #[serde(default="default_junk")]
+    pub junk: Vec<Glob>,
+    /// Sample videos, deleted only when within `sampleMaxSize` so that a film with "sample" in its
+    /// title survives.
+    #[serde(default="default_samples")]
+    pub samples: Vec<Glob>,
+    #[serde(rename="sampleMaxSize", default="default_sample_max_size", deserialize_with="size::deserialize_max")]
+    pub sample_max_size: Option<SizeRange>,
+    /// Remove directories the cleanup leaves empty.
+    #[serde(rename="emptyDirs", default="default_empty_dirs")]
+    pub empty_dirs: bool,
+}
+
+impl Default for Cleanup {
+    fn default() -> Self {
+        Cleanup {
+            junk: default_junk(),
+            samples: default_samples(),
+            sample_max_size: default_sample_max_size(),
+            empty_dirs: default_empty_dirs(),
+        }
+    }
+}
+
+fn default_junk() -> Vec<Glob> {
+    ["*.url", "Thumbs.db", "desktop.ini", ".DS_Store", "__MACOSX"].iter().map(|g| Glob::new(g).unwrap()).collect()
+}
+
+fn default_samples() -> Vec<Glob> {
+    ["*[Ss]ample*.{mkv,mp4,avi,m4v,wmv,mov}", "[Ss]ample"].iter().map(|g| Glob::new(g).unwrap()).collect()
+}
+
+fn default_sample_max_size() -> Option<SizeRange> {
+    SizeRange::parse("300MB", Bare::AtMost).ok()
+}
+
+fn default_empty_dirs() -> bool {
+    true
+}
+
+/// Serde helper for `cleanup`, which is either `true` for the defaults or the settings to use.
+pub fn deserialize_cleanup<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Cleanup>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Enabled(bool),
+        Custom(Cleanup),
+    }
+    Ok(match Raw::deserialize(deserializer)? {
+        Raw::Enabled(true) => Some(Cleanup::default()),
+        Raw::Enabled(false) => None,
+        Raw::Custom(cleanup) => Some(cleanup),
+    })
+}
+
+/// Deletes the junk among the files extracted into `dest`, returning the files that are left.
+pub fn clean(dest: &Path, extracted: Vec<PathBuf>, cleanup: &Cleanup) -> Result<Vec<PathBuf>> {
+    let mut kept = Vec::new();
+    let mut emptied = Vec::new();
+    for path in extracted {
+        let relative = path.strip_prefix(dest).unwrap_or(&path);
+        let junk = cleanup.junk.iter().any(|g| g.matches_path(relative));
+        let sample = !junk && cleanup.samples.iter().any(|g| g.matches_path(relative))
+            && cleanup.sample_max_size.as_ref().map(|max| max.contains(fs::metadata(&path).map(|m| m.len()).unwrap_or(0))).unwrap_or(true);
+        if !junk && !sample {
+            kept.push(path);
+            continue
+        }
+        debug!(path=path.to_str(), sample=sample; "deleting junk from extraction");
+        fs::remove_file(&path)?;
+        emptied.extend(path.ancestors().skip(1).take_while(|dir| dir.starts_with(dest) && *dir != dest).map(Path::to_path_buf));
+    }
+
+    if cleanup.empty_dirs {
+        // deepest first, so a directory whose only content was an empty directory goes too
+        emptied.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        emptied.dedup();
+        for dir in emptied {
+            if fs::read_dir(&dir).map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+                fs::remove_dir(&dir)?;
+            }
+        }
+    }
+    Ok(kept)
+}
+
+#[cfg(feature="archives")]
+/// Reads every entry of the zip archive at `source` to the end, which checks each entry's CRC.
+/// Returns why the archive is unusable, or `None` if it is intact.
+pub fn verify(source: &Path) -> Result<Option<String>> {
+    let file = fs::File::open(source)?;
+    let mut archive = match zip::ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(err) => return Ok(Some(format!("not a readable zip archive: {err}"))),
+    };
+
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(err) => return Ok(Some(format!("entry {i} is unreadable: {err}"))),
+        };
+        if let Err(err) = io::copy(&mut entry, &mut io::sink()) {
+            return Ok(Some(format!("entry [{}] is corrupt: {err}", entry.name())))
+        }
+    }
+
+    Ok(None)
+}
+
+/// What extracting a single archive entry would do.
+pub struct EntryReport {
+    pub name: String,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// Where the entry would be written, or `None` if its path is unsafe and would be skipped.
+    pub destination: Option<PathBuf>,
+}
+
+impl EntryReport {
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_size == 0 {
+            if self.size == 0 { 1.0 } else { f64::INFINITY }
+        } else {
+            self.size as f64 / self.compressed_size as f64
+        }
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        self.ratio() > SUSPICIOUS_RATIO
+    }
+
+    pub fn is_nested_archive(&self) -> bool {
+        let lower = self.name.to_lowercase();
+        [".zip", ".rar", ".7z", ".tar", ".gz", ".tgz", ".xz", ".zst"].iter().any(|ext| lower.ends_with(ext))
+    }
+}
+
+#[cfg(feature="archives")]
+/// Reads the central directory of an archive without extracting anything.
+pub fn inspect(source: &Path, dest: &Path) -> Result<Vec<EntryReport>> {
+    let file = fs::File::open(source)?;
+    let mut archive = zip::ZipArchive::new(file)?;
+
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        entries.push(EntryReport {
+            name: file.name().to_string(),
+            size: file.size(),
+            compressed_size: file.compressed_size(),
+            is_dir: file.is_dir(),
+            is_symlink: is_symlink(file.unix_mode()),
+            destination: file.enclosed_name().map(|path| dest.join(path)),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Stand-ins for when zip support isn't compiled in. Rules that unzip are refused when the config
+/// is loaded then, which leaves `inspect` and `verify`.
+#[cfg(not(feature="archives"))]
+mod unsupported {
+    use std::path::{Path, PathBuf};
+
+    use crate::version;
+    use crate::Result;
+    use super::{EntryReport, LinkPolicy};
+
+    pub fn unzip(_source: &Path, _dest: &Path, _links: LinkPolicy) -> Result<Vec<PathBuf>> {
+        version::require("action", "unzip")?;
+        unreachable!("the archives feature is compiled in")
+    }
+
+    pub fn verify(_source: &Path) -> Result<Option<String>> {
+        version::require("action", "unzip")?;
+        unreachable!("the archives feature is compiled in")
+    }
+
+    pub fn inspect(_source: &Path, _dest: &Path) -> Result<Vec<EntryReport>> {
+        version::require("action", "unzip")?;
+        unreachable!("the archives feature is compiled in")
+    }
+}
+
+#[cfg(not(feature="archives"))]
+pub use unsupported::{inspect, unzip, verify};
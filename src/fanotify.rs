@@ -0,0 +1,207 @@
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem::{self, MaybeUninit};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use inotify::EventMask;
+use log::{as_debug, debug, error, warn};
+use tokio::sync::mpsc;
+
+use crate::Result;
+
+/// Filesystem events reported, the same ones watch directories can be triggered by.
+const MARK_MASK: u64 = libc::FAN_CLOSE_WRITE | libc::FAN_MOVED_TO | libc::FAN_CREATE | libc::FAN_ONDIR;
+
+/// Events waiting for the event loop before the reader stops taking more from the kernel.
+const BACKLOG: usize = 1024;
+
+/// Size of a `fanotify_event_info_fid` before its file handle: the header and the filesystem ID.
+const INFO_FID_LEN: usize = 12;
+
+/// A file or directory event somewhere in a watch directory.
+pub struct MountEvent {
+    /// Under the watch directory's path as configured, rather than as the kernel resolves it.
+    pub path: PathBuf,
+    /// The event as inotify would have reported it, so watch directories' `events` apply as usual.
+    pub mask: EventMask,
+}
+
+/// A watched directory, with a descriptor its filesystem's file handles are opened through.
+struct Marked {
+    path: PathBuf,
+    canonical: PathBuf,
+    fsid: [u8; 8],
+    fs: File,
+}
+
+/// Watches whole filesystems with one fanotify mark each, rather than an inotify watch per
+/// directory, and picks out events under the watch directories by their path. Needs
+/// `CAP_SYS_ADMIN`, and Linux 5.9 for events to say which directory and name they're for.
+///
+/// The mark is on the filesystem rather than the mount, as the kernel doesn't report creates and
+/// renames for mount marks.
+pub struct MountWatch {
+    fanotify: File,
+    dirs: Mutex<Vec<Marked>>,
+}
+
+impl MountWatch {
+    pub fn start() -> Result<Arc<MountWatch>> {
+        // SAFETY: plain syscall, the returned descriptor is owned by `fanotify` below
+        let fd = unsafe {
+            libc::fanotify_init(libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_REPORT_DFID_NAME, (libc::O_RDONLY | libc::O_LARGEFILE) as u32)
+        };
+        if fd < 0 {
+            return Err(format!("unable to start fanotify (it needs CAP_SYS_ADMIN and Linux 5.9): {}", io::Error::last_os_error()).into())
+        }
+        // SAFETY: fd is a freshly created descriptor nothing else owns
+        let fanotify = unsafe { File::from_raw_fd(fd) };
+        Ok(Arc::new(MountWatch { fanotify, dirs: Mutex::default() }))
+    }
+
+    /// Reports events under `dir`, marking its filesystem if no other watched directory is on it.
+    pub fn watch(&self, dir: &Path) -> Result<()> {
+        let canonical = fs::canonicalize(dir)?;
+        let fsid = fsid(&canonical)?;
+        let mut dirs = self.dirs.lock().unwrap();
+        if !dirs.iter().any(|d| d.fsid == fsid) {
+            let c_path = CString::new(canonical.as_os_str().as_bytes())?;
+            // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+            let marked = unsafe {
+                libc::fanotify_mark(self.fanotify.as_raw_fd(), libc::FAN_MARK_ADD | libc::FAN_MARK_FILESYSTEM, MARK_MASK, libc::AT_FDCWD, c_path.as_ptr())
+            };
+            if marked != 0 {
+                return Err(format!("unable to mark the filesystem of [{}]: {}", dir.display(), io::Error::last_os_error()).into())
+            }
+            debug!(path=canonical.to_str(); "marked filesystem");
+        }
+        dirs.push(Marked { path: dir.to_path_buf(), fs: File::open(&canonical)?, canonical, fsid });
+        Ok(())
+    }
+
+    /// Stops reporting events under `dir`. Its filesystem stays marked, with events on it passed over.
+    pub fn unwatch(&self, dir: &Path) {
+        self.dirs.lock().unwrap().retain(|d| d.path != dir);
+    }
+
+    /// Starts reading events on a thread, sending those under a watched directory.
+    pub fn events(self: &Arc<Self>) -> Result<mpsc::Receiver<MountEvent>> {
+        let (sender, receiver) = mpsc::channel(BACKLOG);
+        let reader = Arc::clone(self);
+        std::thread::Builder::new()
+            .name("fanotify".into())
+            .spawn(move || reader.serve(sender))?;
+        Ok(receiver)
+    }
+
+    fn serve(&self, sender: mpsc::Sender<MountEvent>) {
+        let mut buffer = [0u8; 8192];
+        loop {
+            match (&self.fanotify).read(&mut buffer) {
+                Ok(len) => if !self.handle_events(&buffer[..len], &sender) {
+                    return
+                },
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => {
+                    error!(error=as_debug!(err); "unable to read fanotify events - no more files will be seen");
+                    return
+                },
+            }
+        }
+    }
+
+    /// Sends the events in `buffer` that are under a watched directory, returning false once the
+    /// event loop has gone.
+    fn handle_events(&self, mut buffer: &[u8], sender: &mpsc::Sender<MountEvent>) -> bool {
+        let metadata_len = mem::size_of::<libc::fanotify_event_metadata>();
+        while buffer.len() >= metadata_len {
+            // SAFETY: the kernel writes whole fanotify_event_metadata records, and the length was checked above
+            let event = unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::fanotify_event_metadata) };
+            let event_len = (event.event_len as usize).min(buffer.len());
+            if event.vers != libc::FANOTIFY_METADATA_VERSION || event_len < metadata_len {
+                error!(version=event.vers; "unexpected fanotify event format");
+                return true
+            }
+            let (record, rest) = buffer.split_at(event_len);
+            buffer = rest;
+            if event.mask & libc::FAN_Q_OVERFLOW != 0 {
+                warn!("fanotify event queue overflowed - some files may need to be reprocessed manually");
+                continue
+            }
+            let Some(path) = self.path(&record[event.metadata_len as usize..]) else { continue };
+            let mut mask = EventMask::empty();
+            for (fan, inotify) in [(libc::FAN_CLOSE_WRITE, EventMask::CLOSE_WRITE), (libc::FAN_MOVED_TO, EventMask::MOVED_TO), (libc::FAN_CREATE, EventMask::CREATE), (libc::FAN_ONDIR, EventMask::ISDIR)] {
+                if event.mask & fan != 0 {
+                    mask |= inotify;
+                }
+            }
+            if sender.blocking_send(MountEvent { path, mask }).is_err() {
+                return false
+            }
+        }
+        true
+    }
+
+    /// The path an event's directory-and-name record is for, under the watched directory it's in.
+    fn path(&self, mut info: &[u8]) -> Option<PathBuf> {
+        while info.len() >= INFO_FID_LEN {
+            let (kind, len) = (info[0], u16::from_ne_bytes([info[2], info[3]]) as usize);
+            if len < INFO_FID_LEN || len > info.len() {
+                return None
+            }
+            let (record, rest) = info.split_at(len);
+            info = rest;
+            if kind == libc::FAN_EVENT_INFO_TYPE_DFID_NAME {
+                return self.resolve(record)
+            }
+        }
+        None
+    }
+
+    fn resolve(&self, record: &[u8]) -> Option<PathBuf> {
+        let fsid: [u8; 8] = record[4..INFO_FID_LEN].try_into().ok()?;
+        let handle = &record[INFO_FID_LEN..];
+        let handle_len = 8 + u32::from_ne_bytes(handle.get(..4)?.try_into().ok()?) as usize;
+        let name = handle.get(handle_len..)?;
+        let name = OsStr::from_bytes(name.split(|b| *b == 0).next()?);
+        if name.is_empty() || name == "." {
+            return None
+        }
+
+        let dirs = self.dirs.lock().unwrap();
+        let fs = &dirs.iter().find(|d| d.fsid == fsid)?.fs;
+        // a copy with the alignment the kernel's struct file_handle needs
+        let mut aligned = vec![0u32; handle_len.div_ceil(4)];
+        // SAFETY: aligned has room for handle_len bytes, and handle has at least that many
+        unsafe { std::ptr::copy_nonoverlapping(handle.as_ptr(), aligned.as_mut_ptr() as *mut u8, handle_len) };
+        // SAFETY: aligned holds a whole file handle as the kernel reported it
+        let fd = unsafe { libc::open_by_handle_at(fs.as_raw_fd(), aligned.as_mut_ptr() as *mut libc::file_handle, libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            // the directory may have gone since the event
+            return None
+        }
+        // SAFETY: fd is a freshly opened descriptor nothing else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let dir = fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())).ok()?;
+        let path = dir.join(name);
+        dirs.iter()
+            .filter_map(|d| path.strip_prefix(&d.canonical).ok().map(|relative| (d, relative)))
+            .max_by_key(|(d, _)| d.canonical.as_os_str().len())
+            .map(|(d, relative)| d.path.join(relative))
+    }
+}
+
+/// The ID of the filesystem `path` is on, as fanotify reports it.
+fn fsid(path: &Path) -> Result<[u8; 8]> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: c_path is a valid NUL-terminated string and stat has room for the result
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(format!("unable to stat the filesystem of [{}]: {}", path.display(), io::Error::last_os_error()).into())
+    }
+    // SAFETY: statfs succeeded, so stat is initialised; fsid_t is two ints, the same bytes fanotify reports
+    Ok(unsafe { mem::transmute::<libc::fsid_t, [u8; 8]>(stat.assume_init().f_fsid) })
+}
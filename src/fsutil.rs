@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::clock;
+use crate::mode::Modes;
+use crate::Result;
+
+/// Prefixes `name` with the current date and time, used to keep both copies on a name clash.
+pub fn date_prefixed(name: &str) -> String {
+    format!("{}__{name}", clock::prefix())
+}
+
+/// Longest file name, in bytes, that most filesystems allow.
+pub const NAME_MAX: usize = 255;
+
+/// Longest path, in bytes, that the kernel accepts, less its terminating NUL.
+pub const PATH_MAX: usize = 4095;
+
+/// Characters of the content hash appended to a shortened name.
+const SHORTENED_HASH_LEN: usize = 8;
+
+/// `path` with every component longer than `NAME_MAX` shortened, and its file name shortened
+/// further if the whole path is longer than `PATH_MAX`. A shortened name keeps its extension and
+/// ends in a hash of the full name, so names that only differ past the cut don't collide.
+pub fn fit(path: &Path) -> PathBuf {
+    let mut fitted: PathBuf = path.components().map(|component| match component {
+        Component::Normal(name) if name.len() > NAME_MAX => shorten(name, NAME_MAX),
+        component => component.as_os_str().to_os_string(),
+    }).collect();
+    let excess = fitted.as_os_str().len().saturating_sub(PATH_MAX);
+    if let (true, Some(name)) = (excess > 0, fitted.file_name().map(OsStr::to_os_string)) {
+        // too little of the name would be left to tell files apart, so the path is left to fail
+        if name.len() > excess + SHORTENED_HASH_LEN * 2 {
+            fitted.set_file_name(shorten(&name, name.len() - excess));
+        }
+    }
+    if fitted != path {
+        debug!(path=path.to_str(), shortened=fitted.to_str(); "destination is too long for the filesystem - shortened it");
+    }
+    fitted
+}
+
+/// `name` cut down to at most `max` bytes, ending in `~` and a hash of the whole name, before the
+/// extension if it has a short one.
+fn shorten(name: &OsStr, max: usize) -> OsString {
+    let bytes = name.as_bytes();
+    let hash: String = Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).take(SHORTENED_HASH_LEN / 2).collect();
+    let extension = match bytes.iter().rposition(|b| *b == b'.') {
+        Some(dot) if dot > 0 && bytes.len() - dot <= 16 => &bytes[dot..],
+        _ => &[][..],
+    };
+    let mut keep = max.saturating_sub(extension.len() + hash.len() + 1).min(bytes.len() - extension.len());
+    // cut on a character boundary, so a UTF-8 name stays valid
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        while !text.is_char_boundary(keep) {
+            keep -= 1;
+        }
+    }
+    let mut shortened = bytes[..keep].to_vec();
+    shortened.push(b'~');
+    shortened.extend_from_slice(hash.as_bytes());
+    shortened.extend_from_slice(extension);
+    OsString::from_vec(shortened)
+}
+
+/// Whether `path` is `base` or inside it, going by the paths alone: `..` takes away the component
+/// before it, and symlinks aren't followed.
+pub fn is_within(base: &Path, path: &Path) -> bool {
+    normalise(path).starts_with(normalise(base))
+}
+
+/// Whether `a` and `b` are the same path, going by the paths alone, as for [`is_within`].
+pub fn same_path(a: &Path, b: &Path) -> bool {
+    normalise(a) == normalise(b)
+}
+
+fn normalise(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => { normalised.pop(); },
+            Component::CurDir => {},
+            component => normalised.push(component),
+        }
+    }
+    normalised
+}
+
+/// What kind of special file `file_type` is - a FIFO, socket or device node - or `None` for
+/// regular files, directories and symlinks.
+pub fn special_kind(file_type: fs::FileType) -> Option<&'static str> {
+    if file_type.is_fifo() {
+        Some("FIFO")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else if file_type.is_char_device() {
+        Some("character device")
+    } else {
+        None
+    }
+}
+
+/// Modification time in nanoseconds since the epoch, or zero if unavailable.
+pub fn mtime(metadata: &fs::Metadata) -> u128 {
+    metadata.modified().ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+/// Whether two paths are the same file on disk.
+pub fn same_inode(a: &Path, b: &Path) -> Result<bool> {
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+/// Renames `source` to `dest`. A relative symlink is recreated with an absolute target so that it
+/// still resolves from its new location. Across filesystems, such as from a removable drive, it's
+/// copied beside `dest` under a hidden name, renamed into place and only then removed.
+pub fn rename(source: &Path, dest: &Path) -> Result<()> {
+    if fs::symlink_metadata(source)?.file_type().is_symlink() {
+        let target = fs::read_link(source)?;
+        if target.is_relative() {
+            let absolute = source.parent().unwrap_or(Path::new("/")).join(target);
+            if fs::symlink_metadata(dest).map(|m| !m.is_dir()).unwrap_or(false) {
+                fs::remove_file(dest)?;
+            }
+            std::os::unix::fs::symlink(absolute, dest)?;
+            fs::remove_file(source)?;
+            return Ok(())
+        }
+    }
+    match fs::rename(source, dest) {
+        Ok(()) => Ok(()),
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => move_across(source, dest),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn move_across(source: &Path, dest: &Path) -> Result<()> {
+    let name = dest.file_name().ok_or_else(|| format!("[{}] has no file name", dest.display()))?;
+    let mut partial = OsString::from(".");
+    partial.push(name);
+    partial.push(".partial");
+    let partial = dest.with_file_name(partial);
+    if let Err(err) = copy_tree(source, &partial) {
+        let _ = match fs::symlink_metadata(&partial).map(|m| m.is_dir()) {
+            Ok(true) => fs::remove_dir_all(&partial),
+            _ => fs::remove_file(&partial),
+        };
+        return Err(format!("unable to copy [{}] to [{}]: {err}", source.display(), dest.display()).into())
+    }
+    fs::rename(&partial, dest)?;
+    match fs::symlink_metadata(source)?.is_dir() {
+        true => fs::remove_dir_all(source)?,
+        false => fs::remove_file(source)?,
+    }
+    Ok(())
+}
+
+/// Copies a file, symlink or directory tree, keeping modes and modification times.
+fn copy_tree(source: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+    if metadata.file_type().is_symlink() {
+        return std::os::unix::fs::symlink(fs::read_link(source)?, dest)
+    }
+    if metadata.is_dir() {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dest, metadata.permissions())?;
+    } else {
+        fs::copy(source, dest)?;
+    }
+    fs::File::open(dest)?.set_modified(metadata.modified()?)
+}
+
+/// Size of a file, or the total size of every file below a directory.
+pub fn tree_size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len())
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += tree_size(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Whether the file at `path`, or any file inside the directory at `path`, has other hard links.
+pub fn has_other_links(path: &Path) -> Result<bool> {
+    Ok(!linked_inodes(path)?.is_empty())
+}
+
+/// Whether the file at `path`, or any file inside the directory at `path`, also has a hard link
+/// somewhere under `dir`.
+pub fn is_linked_into(path: &Path, dir: &Path) -> Result<bool> {
+    let inodes = linked_inodes(path)?;
+    if inodes.is_empty() || !dir.is_dir() {
+        return Ok(false)
+    }
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if inodes.contains(&(metadata.dev(), metadata.ino())) && !entry.path().starts_with(path) {
+                return Ok(true)
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Device and inode of every file at or under `path` that has more than one link.
+fn linked_inodes(path: &Path) -> Result<HashSet<(u64, u64)>> {
+    let mut inodes = HashSet::new();
+    let mut paths = vec![path.to_path_buf()];
+    while let Some(path) = paths.pop() {
+        let metadata = fs::symlink_metadata(&path)?;
+        if metadata.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                paths.push(entry?.path());
+            }
+        } else if metadata.is_file() && metadata.nlink() > 1 {
+            inodes.insert((metadata.dev(), metadata.ino()));
+        }
+    }
+    Ok(inodes)
+}
+
+/// Hard links `source` at `dest`, or reflinks it (a copy-on-write clone) when a hard link can't
+/// cross to where `dest` is, such as another btrfs subvolume. A directory is recreated at `dest`,
+/// or merged into one already there, with each file inside linked; files already there are left
+/// alone. Directories it creates get the `dir` mode of `modes`.
+pub fn link(source: &Path, dest: &Path, modes: Modes) -> Result<()> {
+    if !fs::symlink_metadata(source)?.is_dir() {
+        return link_file(source, dest)
+    }
+    modes.create_dir_all(dest)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let to = dest.join(entry.file_name());
+        if !entry.file_type()?.is_dir() && fs::symlink_metadata(&to).is_ok() {
+            continue
+        }
+        link(&entry.path(), &to, modes)?;
+    }
+    Ok(())
+}
+
+fn link_file(source: &Path, dest: &Path) -> Result<()> {
+    match fs::hard_link(source, dest) {
+        Ok(()) => Ok(()),
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => reflink(source, dest)
+            .map_err(|e| format!("[{}] is on another filesystem from [{}] and can't be reflinked there either: {e}", dest.display(), source.display()).into()),
+        Err(err) => Err(format!("unable to link [{}] to [{}]: {err}", source.display(), dest.display()).into()),
+    }
+}
+
+fn reflink(source: &Path, dest: &Path) -> io::Result<()> {
+    let from = fs::File::open(source)?;
+    let to = fs::OpenOptions::new().write(true).create_new(true).open(dest)?;
+    // SAFETY: both descriptors stay open for the duration of the call
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } != 0 {
+        let err = io::Error::last_os_error();
+        drop(to);
+        let _ = fs::remove_file(dest);
+        return Err(err)
+    }
+    to.set_permissions(from.metadata()?.permissions())
+}
+
+/// Moves the contents of `source` into the existing directory `dest`, recursing into
+/// directories present in both. Files that clash keep both copies, the incoming one being
+/// date-prefixed. `source` is removed once empty.
+pub fn merge_dirs(source: &Path, dest: &Path) -> Result<()> {
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() && to.is_dir() {
+            merge_dirs(&from, &to)?;
+        } else if to.exists() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            fs::rename(&from, dest.join(date_prefixed(&name)))?;
+        } else {
+            fs::rename(&from, &to)?;
+        }
+    }
+    fs::remove_dir(source)?;
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`, or its nearest existing
+/// ancestor if `path` doesn't exist yet.
+pub fn free_space(path: &Path) -> Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(Path::new("/"));
+    let c_path = CString::new(existing.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path is a valid NUL-terminated string and stat is only read after statvfs succeeds
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(format!("unable to read free space for [{}]: {}", existing.display(), std::io::Error::last_os_error()).into())
+        }
+        stat.assume_init()
+    };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Number of entries directly inside `dir`, or zero if it doesn't exist.
+pub fn entry_count(dir: &Path) -> Result<u64> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.count() as u64),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{as_debug, debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::duration;
+use crate::Result;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GateConfig {
+    /// Longest an open is held back before it is let through anyway, so a stuck action can't hang
+    /// a consumer forever.
+    #[serde(deserialize_with="duration::deserialize", serialize_with="duration::serialize", default="default_timeout")]
+    pub timeout: Duration,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+/// An open by another process, waiting on its file to be organised.
+struct Held {
+    /// The file descriptor fanotify opened for the event, used to answer it.
+    fd: OwnedFd,
+    path: PathBuf,
+    pid: i32,
+    deadline: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// Files the organiser has seen an event for but not finished with.
+    pending: HashSet<PathBuf>,
+    held: Vec<Held>,
+}
+
+/// Holds back other processes' opens of files in the watch directories until they have been
+/// organised, using fanotify permission events. Needs `CAP_SYS_ADMIN`.
+pub struct Gate {
+    fanotify: File,
+    timeout: Duration,
+    state: Mutex<State>,
+}
+
+impl Gate {
+    /// Sets up the fanotify group and starts answering its permission events on a thread.
+    pub fn start(config: &GateConfig) -> Result<Arc<Gate>> {
+        // SAFETY: plain syscall, the returned descriptor is owned by `fanotify` below
+        let fd = unsafe {
+            libc::fanotify_init(libc::FAN_CLASS_CONTENT | libc::FAN_CLOEXEC, (libc::O_RDONLY | libc::O_LARGEFILE) as u32)
+        };
+        if fd < 0 {
+            return Err(format!("unable to start the open gate (fanotify needs CAP_SYS_ADMIN): {}", io::Error::last_os_error()).into())
+        }
+        // SAFETY: fd is a freshly created descriptor nothing else owns
+        let fanotify = unsafe { File::from_raw_fd(fd) };
+
+        let gate = Arc::new(Gate { fanotify, timeout: config.timeout, state: Mutex::default() });
+        let server = Arc::clone(&gate);
+        std::thread::Builder::new()
+            .name("open-gate".into())
+            .spawn(move || server.serve())?;
+        Ok(gate)
+    }
+
+    /// Gates opens of `dir` and the entries directly inside it.
+    pub fn watch(&self, dir: &Path) -> Result<()> {
+        let c_path = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+        let marked = unsafe {
+            libc::fanotify_mark(self.fanotify.as_raw_fd(), libc::FAN_MARK_ADD,
+                libc::FAN_OPEN_PERM | libc::FAN_EVENT_ON_CHILD, libc::AT_FDCWD, c_path.as_ptr())
+        };
+        if marked != 0 {
+            return Err(format!("unable to gate opens in [{}]: {}", dir.display(), io::Error::last_os_error()).into())
+        }
+        debug!(path=dir.to_str(); "gating opens");
+        Ok(())
+    }
+
+    /// Stops gating opens in `dir`. Errors are ignored, since the directory may be gone already.
+    pub fn unwatch(&self, dir: &Path) {
+        if let Ok(c_path) = CString::new(dir.as_os_str().as_bytes()) {
+            // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+            unsafe {
+                libc::fanotify_mark(self.fanotify.as_raw_fd(), libc::FAN_MARK_REMOVE,
+                    libc::FAN_OPEN_PERM | libc::FAN_EVENT_ON_CHILD, libc::AT_FDCWD, c_path.as_ptr());
+            }
+        }
+    }
+
+    /// Starts holding back opens of `path` until it is released.
+    pub fn hold(&self, path: &Path) {
+        self.state.lock().unwrap().pending.insert(key(path));
+    }
+
+    /// Lets through any opens of `path` waiting on it, and any later ones.
+    pub fn release(&self, path: &Path) {
+        let path = key(path);
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&path);
+        let (released, held) = state.held.drain(..).partition(|h| h.path == path);
+        state.held = held;
+        drop(state);
+
+        for held in released {
+            debug!(path=held.path.to_str(), pid=held.pid; "releasing held open");
+            self.respond(held.fd, libc::FAN_ALLOW);
+        }
+    }
+
+    fn serve(&self) {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let mut poll = libc::pollfd { fd: self.fanotify.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            // SAFETY: poll is a single valid pollfd; the one second timeout lets held opens expire
+            let ready = unsafe { libc::poll(&mut poll, 1, 1000) };
+            if ready > 0 {
+                match (&self.fanotify).read(&mut buffer) {
+                    Ok(len) => self.handle_events(&buffer[..len]),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                    Err(err) => {
+                        error!(error=as_debug!(err); "unable to read fanotify events - opens in watch directories may hang");
+                        return
+                    },
+                }
+            }
+            self.expire();
+        }
+    }
+
+    fn handle_events(&self, mut buffer: &[u8]) {
+        let metadata_len = std::mem::size_of::<libc::fanotify_event_metadata>();
+        while buffer.len() >= metadata_len {
+            // SAFETY: the kernel writes whole fanotify_event_metadata records, and the length was checked above
+            let event = unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::fanotify_event_metadata) };
+            if event.vers != libc::FANOTIFY_METADATA_VERSION || (event.event_len as usize) < metadata_len {
+                error!(version=event.vers; "unexpected fanotify event format");
+                return
+            }
+            buffer = &buffer[(event.event_len as usize).min(buffer.len())..];
+            if event.fd == libc::FAN_NOFD {
+                warn!("fanotify event queue overflowed");
+                continue
+            }
+            // SAFETY: the kernel opened this descriptor for us, and we close it once answered
+            let fd = unsafe { OwnedFd::from_raw_fd(event.fd) };
+            self.handle_open(fd, event.pid);
+        }
+    }
+
+    fn handle_open(&self, fd: OwnedFd, pid: i32) {
+        // the organiser's own opens (hashing, extracting) must never wait on itself
+        if pid as u32 == std::process::id() {
+            return self.respond(fd, libc::FAN_ALLOW)
+        }
+        let path = match fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())) {
+            Ok(path) => path,
+            Err(_) => return self.respond(fd, libc::FAN_ALLOW),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if !state.pending.contains(&path) {
+            drop(state);
+            return self.respond(fd, libc::FAN_ALLOW)
+        }
+        // nor must the tools it runs on the file (exec actions, pdftotext, gpgv and the like)
+        if is_descendant(pid) {
+            drop(state);
+            return self.respond(fd, libc::FAN_ALLOW)
+        }
+        debug!(path=path.to_str(), pid=pid; "holding open of file that hasn't been organised yet");
+        state.held.push(Held { fd, path, pid, deadline: Instant::now() + self.timeout });
+    }
+
+    /// Lets through opens that have been held for longer than the timeout.
+    fn expire(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.held.is_empty() {
+            return
+        }
+        let (expired, held): (Vec<_>, _) = state.held.drain(..).partition(|h| h.deadline <= now);
+        state.held = held;
+        drop(state);
+
+        for held in expired {
+            warn!(path=held.path.to_str(), pid=held.pid; "file still not organised after gate timeout - letting open through");
+            self.respond(held.fd, libc::FAN_ALLOW);
+        }
+    }
+
+    fn respond(&self, fd: OwnedFd, response: u32) {
+        let response = libc::fanotify_response { fd: fd.as_raw_fd(), response };
+        // SAFETY: fanotify_response is plain old data
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&response as *const _ as *const u8, std::mem::size_of::<libc::fanotify_response>())
+        };
+        if let Err(err) = (&self.fanotify).write_all(bytes) {
+            error!(error=as_debug!(err); "unable to answer fanotify permission event");
+        }
+    }
+}
+
+/// Whether `pid` was started by the organiser, directly or through its children, going by the
+/// parent pids in `/proc`.
+fn is_descendant(mut pid: i32) -> bool {
+    let own = std::process::id() as i32;
+    while pid > 1 {
+        pid = match parent(pid) {
+            Some(parent) if parent == own => return true,
+            Some(parent) => parent,
+            None => return false,
+        };
+    }
+    false
+}
+
+fn parent(pid: i32) -> Option<i32> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    status.lines().find_map(|line| line.strip_prefix("PPid:")).and_then(|ppid| ppid.trim().parse().ok())
+}
+
+/// The form fanotify reports paths in, with the parent directory resolved.
+fn key(path: &Path) -> PathBuf {
+    match (path.parent().and_then(|p| fs::canonicalize(p).ok()), path.file_name()) {
+        (Some(parent), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::path::Path;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Result;
+
+/// A shell-style glob such as `node_modules`, `*.part` or `keep/**`.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    raw: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(raw: &str) -> Result<Glob> {
+        Ok(Glob { raw: raw.to_string(), regex: Regex::new(&to_regex(raw))? })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Matches a relative path. A glob containing `/` is matched against the whole path, otherwise
+    /// against each component, so `node_modules` matches at any depth. A directory also matches
+    /// globs for its contents, so `keep/**` matches `keep`.
+    pub fn matches_path(&self, path: &Path) -> bool {
+        if self.raw.contains('/') {
+            let path = path.to_string_lossy();
+            return self.is_match(&path) || self.is_match(&format!("{path}/"))
+        }
+        path.iter().any(|part| self.is_match(&part.to_string_lossy()))
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Glob::new(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Glob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+/// Converts a glob (`*`, `**`, `?`, `[...]` and `{a,b}`) into an anchored regex. `*` and `?` don't
+/// match `/`; `**` does.
+pub fn to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut in_braces = false;
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.next_if_eq(&'*').is_some() => regex.push_str(".*"),
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '{' => { in_braces = true; regex.push_str("(?:") },
+            '}' if in_braces => { in_braces = false; regex.push(')') },
+            ',' if in_braces => regex.push('|'),
+            '[' => {
+                regex.push('[');
+                for c in chars.by_ref() {
+                    match c {
+                        '!' if regex.ends_with('[') => regex.push('^'),
+                        ']' => break,
+                        '\\' => regex.push_str("\\\\"),
+                        c => regex.push(c),
+                    }
+                }
+                regex.push(']');
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
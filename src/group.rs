@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::{EntryKind, Rule};
+use crate::diskimage::ImageCondition;
+use crate::header::{self, HeaderCondition};
+use crate::ocr::ContentCondition;
+use crate::pdf::PdfCondition;
+use crate::size::{self, SizeRange};
+
+/// Conditions shared by the rules of a `group`, which a file has to meet as well as a rule's own.
+/// They're checked once per file for the whole group, however many of its rules are tried.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConditions {
+    /// Regex the name has to match as well as the rule's own.
+    #[serde(with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub regex: Option<Regex>,
+    #[serde(rename="appliesTo", skip_serializing_if="Option::is_none")]
+    pub applies_to: Option<Vec<String>>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub kind: Option<EntryKind>,
+    #[serde(rename="minSize", default, deserialize_with="size::deserialize_min", skip_serializing_if="Option::is_none")]
+    pub min_size: Option<SizeRange>,
+    #[serde(rename="maxSize", default, deserialize_with="size::deserialize_max", skip_serializing_if="Option::is_none")]
+    pub max_size: Option<SizeRange>,
+    #[serde(rename="isHardlinkedElsewhere", skip_serializing_if="Option::is_none")]
+    pub is_hardlinked_elsewhere: Option<bool>,
+    #[serde(rename="hardlinkedInto", skip_serializing_if="Option::is_none")]
+    pub hardlinked_into: Option<PathBuf>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub content: Option<ContentCondition>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub pdf: Option<PdfCondition>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub image: Option<ImageCondition>,
+    /// Only match files whose first `headerBytes` bytes, read as text, match this regex.
+    #[serde(rename="headerRegex", with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub header_regex: Option<Regex>,
+    /// Only match files that start with these bytes, written in hex such as `25 50 44 46`.
+    #[serde(rename="headerHex", default, deserialize_with="header::deserialize_hex", serialize_with="header::serialize_hex", skip_serializing_if="Option::is_none")]
+    pub header_hex: Option<Vec<u8>>,
+    /// How many bytes from the start of the file `headerRegex` looks at. Defaults to 512.
+    #[serde(rename="headerBytes", skip_serializing_if="Option::is_none")]
+    pub header_bytes: Option<usize>,
+    #[serde(rename="fromArchive", skip_serializing_if="Option::is_none")]
+    pub from_archive: Option<bool>,
+}
+
+/// A group the rules inside a `group` entry of `rules` share.
+#[derive(Debug)]
+pub struct RuleGroup {
+    /// Position of the group among the config's groups, which results are kept by.
+    pub id: usize,
+    pub conditions: GroupConditions,
+}
+
+impl GroupConditions {
+    pub fn applies_to(&self, watch_dir: &str) -> bool {
+        self.applies_to.as_ref().is_none_or(|names| names.iter().any(|n| n == watch_dir))
+    }
+
+    pub fn header(&self) -> Option<HeaderCondition<'_>> {
+        HeaderCondition::of(self.header_regex.as_ref(), self.header_hex.as_ref(), self.header_bytes)
+    }
+}
+
+/// A `group` entry of `rules`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    group: GroupConditions,
+    rules: Vec<Rule>,
+}
+
+/// Serde helper for `rules`, where an entry is either a rule or a `group` of them. The rules of a
+/// group are listed in its place, each pointing at the group.
+pub fn deserialize_rules<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<Rule>, D::Error> {
+    use serde::de::Error;
+    let entries = Vec::<serde_yaml::Value>::deserialize(deserializer)?;
+    let mut rules = Vec::new();
+    let mut groups = 0;
+    for (i, entry) in entries.into_iter().enumerate() {
+        if entry.get("group").is_none() {
+            rules.push(serde_yaml::from_value(entry).map_err(|e| D::Error::custom(format!("rules[{i}]: {e}")))?);
+            continue
+        }
+        let entry: Entry = serde_yaml::from_value(entry).map_err(|e| D::Error::custom(format!("rules[{i}] group: {e}")))?;
+        if entry.rules.is_empty() {
+            return Err(D::Error::custom(format!("rules[{i}] is a group without rules")))
+        }
+        let group = Arc::new(RuleGroup { id: groups, conditions: entry.group });
+        groups += 1;
+        for mut rule in entry.rules {
+            rule.group = Some(Arc::clone(&group));
+            rules.push(rule);
+        }
+    }
+    Ok(rules)
+}
+
+/// Serde helper for `rules`, writing the rules of each group back inside it.
+pub fn serialize_rules<S: Serializer>(rules: &[Rule], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Out<'a> {
+        Rule(&'a Rule),
+        Group { group: &'a GroupConditions, rules: Vec<&'a Rule> },
+    }
+    let mut entries: Vec<Out> = Vec::new();
+    for rule in rules {
+        match (&rule.group, entries.last_mut()) {
+            (Some(group), Some(Out::Group { group: last, rules })) if std::ptr::eq(*last, &group.conditions) => rules.push(rule),
+            (Some(group), _) => entries.push(Out::Group { group: &group.conditions, rules: vec![rule] }),
+            (None, _) => entries.push(Out::Rule(rule)),
+        }
+    }
+    serializer.collect_seq(entries)
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{as_debug, debug, info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::fsutil;
+use crate::Result;
+
+/// How long a hash is kept without being looked up, so hashes of files long since deleted don't
+/// pile up.
+const MAX_AGE_SECS: u64 = 90 * 24 * 60 * 60;
+
+/// How often a hash that's looked up has that saved, which keeps it from expiring.
+const TOUCH_SECS: u64 = 24 * 60 * 60;
+
+/// The cache every profile shares. Keys are inodes, so it doesn't matter whose state directory it
+/// lives in.
+static CACHE: OnceLock<Mutex<HashCache>> = OnceLock::new();
+
+/// Identifies a file's contents without reading them: the same inode, with the same size and
+/// modification time, is assumed to hold the same bytes. Survives renames, so a file is hashed once
+/// however many times it's moved around `baseDir`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+struct Key {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: u128,
+}
+
+impl Key {
+    fn of(metadata: &fs::Metadata) -> Key {
+        Key { dev: metadata.dev(), ino: metadata.ino(), size: metadata.len(), mtime: fsutil::mtime(metadata) }
+    }
+}
+
+struct Cached {
+    hash: String,
+    /// When the hash was last looked up, in seconds since the epoch.
+    used: u64,
+}
+
+/// SHA-256 hashes of files by inode, persisted to the state directory. New hashes are appended to
+/// the file as they're made, and it's rewritten without stale entries on start.
+struct HashCache {
+    /// Where the cache is saved - `None` keeps it in memory only.
+    file: Option<PathBuf>,
+    entries: HashMap<Key, Cached>,
+}
+
+/// Loads the cache that [`hash_file`] uses from `file`. Only the first call does anything, so
+/// each profile can offer its own state directory.
+pub fn open(file: Option<PathBuf>) -> Result<()> {
+    if CACHE.get().is_some() {
+        return Ok(())
+    }
+    let mut cache = HashCache { file, entries: HashMap::new() };
+    cache.load()?;
+    let _ = CACHE.set(Mutex::new(cache));
+    Ok(())
+}
+
+/// SHA-256 of a file's contents, hex encoded, from the cache when the file hasn't changed since it
+/// was last hashed.
+pub fn hash_file(path: &Path) -> Result<String> {
+    let cache = match CACHE.get() {
+        Some(cache) => cache,
+        None => return hash_contents(path),
+    };
+    let key = Key::of(&fs::metadata(path)?);
+    if let Some(hash) = cache.lock().unwrap().get(&key) {
+        debug!(path=path.to_str(); "using cached hash");
+        return Ok(hash)
+    }
+
+    let hash = hash_contents(path)?;
+    // a file still being written would otherwise be cached with the hash of half its contents
+    if Key::of(&fs::metadata(path)?) == key {
+        cache.lock().unwrap().insert(key, hash.clone());
+    }
+    Ok(hash)
+}
+
+fn hash_contents(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file = fs::File::open(path)?;
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+impl HashCache {
+    fn get(&mut self, key: &Key) -> Option<String> {
+        let cached = self.entries.get_mut(key)?;
+        let hash = cached.hash.clone();
+        let now = now();
+        if now.saturating_sub(cached.used) > TOUCH_SECS {
+            cached.used = now;
+            self.append(key, &hash);
+        }
+        Some(hash)
+    }
+
+    fn insert(&mut self, key: Key, hash: String) {
+        self.append(&key, &hash);
+        self.entries.insert(key, Cached { hash, used: now() });
+    }
+
+    fn append(&self, key: &Key, hash: &str) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let appended = fs::OpenOptions::new().create(true).append(true).open(file)
+            .and_then(|mut out| writeln!(out, "{}", line(key, hash, now())));
+        if let Err(err) = appended {
+            warn!(file=file.to_str(), error=as_debug!(err); "unable to save hash to the cache");
+        }
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) if file.exists() => file.clone(),
+            _ => return Ok(()),
+        };
+
+        let now = now();
+        let mut lines = 0;
+        for line in io::BufReader::new(fs::File::open(&file)?).lines() {
+            let line = line?;
+            lines += 1;
+            let mut fields = line.split('\t');
+            let parsed = (|| {
+                let used: u64 = fields.next()?.parse().ok()?;
+                let key = Key {
+                    dev: fields.next()?.parse().ok()?,
+                    ino: fields.next()?.parse().ok()?,
+                    size: fields.next()?.parse().ok()?,
+                    mtime: fields.next()?.parse().ok()?,
+                };
+                Some((key, Cached { hash: fields.next()?.to_string(), used }))
+            })();
+            match parsed {
+                Some((_, cached)) if now.saturating_sub(cached.used) > MAX_AGE_SECS => {},
+                // later lines are newer
+                Some((key, cached)) => { self.entries.insert(key, cached); },
+                None => warn!(file=file.to_str(), line=line; "ignoring malformed hash cache line"),
+            }
+        }
+        info!(file=file.to_str(), hashes=self.entries.len(); "hash cache loaded");
+
+        if lines != self.entries.len() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file with just the current entries.
+    fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let tmp = file.with_extension("tmp");
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        for (key, cached) in &self.entries {
+            writeln!(out, "{}", line(key, &cached.hash, cached.used))?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, file)?;
+        Ok(())
+    }
+}
+
+fn line(key: &Key, hash: &str, used: u64) -> String {
+    format!("{used}\t{}\t{}\t{}\t{}\t{hash}", key.dev, key.ino, key.size, key.mtime)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::Result;
+
+/// Bytes read from the start of a file for `headerRegex` and `headerHex` when `headerBytes` isn't set.
+pub const DEFAULT_BYTES: usize = 512;
+
+/// Most `headerBytes` may be, so a header condition stays cheap.
+pub const MAX_BYTES: usize = 1 << 20;
+
+/// Conditions on the first bytes of a file, for telling formats and text markers apart without a
+/// MIME database.
+pub struct HeaderCondition<'r> {
+    /// Regex on the bytes read, decoded as UTF-8 with anything invalid replaced.
+    pub regex: Option<&'r Regex>,
+    /// Bytes the file has to start with.
+    pub hex: Option<&'r [u8]>,
+    /// How many bytes are read for `regex`.
+    pub bytes: usize,
+}
+
+impl<'r> HeaderCondition<'r> {
+    /// The condition made of a rule's or group's `headerRegex`, `headerHex` and `headerBytes`,
+    /// if it has one.
+    pub fn of(regex: Option<&'r Regex>, hex: Option<&'r Vec<u8>>, bytes: Option<usize>) -> Option<HeaderCondition<'r>> {
+        if regex.is_none() && hex.is_none() {
+            return None
+        }
+        Some(HeaderCondition { regex, hex: hex.map(|h| h.as_slice()), bytes: bytes.unwrap_or(DEFAULT_BYTES) })
+    }
+
+    /// How many bytes have to be read to check the condition.
+    pub fn needs(&self) -> usize {
+        self.bytes.max(self.hex.map(|h| h.len()).unwrap_or(0))
+    }
+
+    /// Whether `header`, the first bytes of the file named `name`, meets the condition, logging
+    /// why it doesn't as the rule labelled `label`.
+    pub fn matches(&self, header: &[u8], label: &str, name: &str) -> bool {
+        if let Some(hex) = self.hex {
+            if !header.starts_with(hex) {
+                info!(regex=label, filename=name; "file doesn't start with headerHex - skipping rule");
+                return false
+            }
+        }
+        if let Some(regex) = self.regex {
+            let text = String::from_utf8_lossy(&header[..header.len().min(self.bytes)]);
+            if !regex.is_match(&text) {
+                info!(regex=label, filename=name, header=regex.as_str(); "file header doesn't match headerRegex - skipping rule");
+                return false
+            }
+        }
+        true
+    }
+}
+
+/// Up to the first `bytes` bytes of the file at `path`.
+pub fn read(path: &Path, bytes: usize) -> Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(bytes);
+    File::open(path)?.take(bytes as u64).read_to_end(&mut header)
+        .map_err(|e| format!("unable to read the header of [{}]: {e}", path.display()))?;
+    Ok(header)
+}
+
+/// Parses hex such as `89504e47` or `89 50 4E 47`.
+pub fn parse_hex(value: &str) -> Result<Vec<u8>> {
+    let digits: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("[{value}] is not whole bytes of hex").into())
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2).unwrap_or_default(), 16)
+            .map_err(|_| format!("[{value}] is not hex").into()))
+        .collect()
+}
+
+/// Serde helper for an optional `headerHex`.
+pub fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Vec<u8>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => parse_hex(&value).map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Serde helper writing an optional `headerHex` as spaced hex.
+pub fn serialize_hex<S: Serializer>(hex: &Option<Vec<u8>>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match hex {
+        Some(hex) => serializer.serialize_str(&hex.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")),
+        None => serializer.serialize_none(),
+    }
+}
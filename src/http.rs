@@ -0,0 +1,158 @@
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::retry::{HttpStatusError, NetworkError};
+use crate::Result;
+
+/// What's sent as a request's body.
+pub enum Body<'a> {
+    Empty,
+    Data(&'a str),
+    /// `multipart/form-data` fields.
+    Form(&'a [(&'a str, Field<'a>)]),
+}
+
+pub enum Field<'a> {
+    Text(&'a str),
+    /// A file upload, read by curl.
+    File(&'a Path),
+}
+
+pub struct Response {
+    pub status: u16,
+    /// Headers of the final response, after any `100 Continue`.
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Makes an HTTP request with `curl`, returning the status and body. An error status is returned
+/// as an [`HttpStatusError`] so the retry policies can tell it apart.
+pub async fn request(method: &str, url: &str, headers: &[String], body: Option<&str>) -> Result<(u16, Vec<u8>)> {
+    let response = exchange(method, url, headers, body.map(Body::Data).unwrap_or(Body::Empty)).await?;
+    if response.status >= 400 {
+        return Err(HttpStatusError(response.status).into())
+    }
+    Ok((response.status, response.body))
+}
+
+/// Makes an HTTP request with `curl`, returning the whole response whatever its status, for
+/// protocols that answer with an error status and expect it to be handled. Failing to reach the
+/// server is returned as a [`NetworkError`].
+///
+/// curl is configured over stdin so that URLs and headers (which may carry credentials) never
+/// appear in the process list.
+pub async fn exchange(method: &str, url: &str, headers: &[String], body: Body<'_>) -> Result<Response> {
+    let mut curl_config = String::new();
+    curl_config.push_str(&format!("url = {}\n", quote(url)));
+    curl_config.push_str(&format!("request = {}\n", quote(method)));
+    for header in headers {
+        curl_config.push_str(&format!("header = {}\n", quote(header)));
+    }
+    match body {
+        Body::Empty => {},
+        Body::Data(data) => curl_config.push_str(&format!("data-binary = {}\n", quote(data))),
+        Body::Form(fields) => for (name, field) in fields {
+            match field {
+                Field::Text(value) => curl_config.push_str(&format!("form-string = {}\n", quote(&format!("{name}={value}")))),
+                Field::File(path) => {
+                    let path = path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\"");
+                    curl_config.push_str(&format!("form = {}\n", quote(&format!("{name}=@\"{path}\""))));
+                },
+            }
+        },
+    }
+
+    let mut child = Command::new("curl")
+        // headers come before the body, and the status goes on a line of its own after it
+        .args(["--silent", "--show-error", "--include", "--write-out", "\n%{http_code}", "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run curl: {e}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(curl_config.as_bytes()).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        let message = format!("HTTP request failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        // curl's exit codes for failing to resolve, connect, complete TLS or get a whole reply,
+        // and for timing out
+        return Err(match output.status.code() {
+            Some(5 | 6 | 7 | 28 | 35 | 52 | 55 | 56) => NetworkError(message).into(),
+            _ => message.into(),
+        })
+    }
+
+    let mut body = output.stdout;
+    let split = body.iter().rposition(|b| *b == b'\n').ok_or("curl didn't report an HTTP status")?;
+    let status = String::from_utf8_lossy(&body[split + 1..]).trim().parse::<u16>()?;
+    body.truncate(split);
+
+    let mut headers = Vec::new();
+    while body.starts_with(b"HTTP/") {
+        let end = match body.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None => break,
+        };
+        let block = String::from_utf8_lossy(&body[..end]).into_owned();
+        body.drain(..end + 4);
+        let mut lines = block.lines();
+        let interim = lines.next().and_then(|l| l.split_whitespace().nth(1)).is_some_and(|code| code.starts_with('1'));
+        headers = lines.filter_map(|l| l.split_once(':')).map(|(n, v)| (n.trim().to_string(), v.trim().to_string())).collect();
+        if !interim {
+            break
+        }
+    }
+    Ok(Response { status, headers, body })
+}
+
+/// Quotes a value for a curl config file.
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{escaped}\"")
+}
+
+/// Percent-encodes a query string value.
+pub fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Standard base64 with padding.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,66 @@
+//! Rules from [Maid](https://github.com/maid/maid), a Ruby file management tool.
+//!
+//! Maid rules are Ruby, so only the common one-line `move`, `trash` and `remove` calls on a
+//! `dir('...')` glob are understood. Every other line is listed as not translated.
+
+use regex::Regex;
+
+use super::{delete_action, leaves_base, move_action, Imported, ImportedRule};
+use crate::glob;
+use crate::Result;
+
+/// Converts a Maid `rules.rb`.
+pub fn convert(source: &str) -> Result<Imported> {
+    let rule_start = Regex::new(r#"^rule\s+['"](?P<name>[^'"]*)['"]\s+do$"#)?;
+    let call = Regex::new(concat!(
+        r#"^(?P<action>move|trash|remove)\s*\(?\s*dir\(\s*['"](?P<glob>[^'"]+)['"]\s*\)"#,
+        r#"(?:\s*,\s*['"](?P<dest>[^'"]+)['"])?\s*\)?$"#,
+    ))?;
+
+    let mut imported = Imported::new();
+    let mut rule = String::from("(top level)");
+    for (n, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == "end" || line.starts_with("Maid.rules") {
+            continue
+        }
+        if let Some(captures) = rule_start.captures(line) {
+            rule = format!("rule [{}]", &captures["name"]);
+            continue
+        }
+        let Some(captures) = call.captures(line) else {
+            imported.note(format!("{rule} line {}: `{line}`", n + 1));
+            continue
+        };
+
+        let glob = &captures["glob"];
+        let (location, pattern) = glob.rsplit_once('/').unwrap_or((".", glob));
+        if location.contains(['*', '?', '[', '{']) {
+            imported.note(format!("{rule} line {}: glob [{glob}] matches several directories - skipped", n + 1));
+            continue
+        }
+        let action = match (&captures["action"], captures.name("dest")) {
+            ("move", Some(dest)) => move_action(imported.dest(dest.as_str()), "rename-date", None),
+            ("move", None) => {
+                imported.note(format!("{rule} line {}: move without a destination - skipped", n + 1));
+                continue
+            },
+            ("trash", _) => {
+                imported.note(format!("{rule} line {}: trash is translated to delete", n + 1));
+                delete_action()
+            },
+            _ => delete_action(),
+        };
+        let watch_dir = imported.watch_dir(location);
+        imported.rules.push(ImportedRule {
+            regex: glob::to_regex(pattern),
+            applies_to: Some(vec![watch_dir]),
+            kind: None,
+            min_size: None,
+            max_size: None,
+            allow_outside_base: leaves_base(std::slice::from_ref(&action)),
+            actions: vec![action],
+        });
+    }
+    Ok(imported)
+}
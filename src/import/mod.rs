@@ -0,0 +1,146 @@
+//! Converts rules written for other file organisers into this crate's config format.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::config::SCHEMA_VERSION;
+use crate::Result;
+
+pub mod maid;
+pub mod organize;
+
+/// A config assembled from a foreign format, along with everything that had no equivalent.
+#[derive(Default)]
+pub struct Imported {
+    base_dir: PathBuf,
+    watch_dirs: BTreeMap<PathBuf, String>,
+    rules: Vec<ImportedRule>,
+    /// Things that were dropped or only approximated, in the order they were found.
+    pub notes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ImportedRule {
+    regex: String,
+    #[serde(rename="appliesTo", skip_serializing_if="Option::is_none")]
+    applies_to: Option<Vec<String>>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    kind: Option<&'static str>,
+    #[serde(rename="minSize", skip_serializing_if="Option::is_none")]
+    min_size: Option<String>,
+    #[serde(rename="maxSize", skip_serializing_if="Option::is_none")]
+    max_size: Option<String>,
+    /// Set when a destination is outside the home directory used as `baseDir`.
+    #[serde(rename="allowOutsideBase", skip_serializing_if="std::ops::Not::not")]
+    allow_outside_base: bool,
+    actions: Vec<Value>,
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    version: u32,
+    #[serde(rename="baseDir")]
+    base_dir: &'a Path,
+    #[serde(rename="watchDirs")]
+    watch_dirs: Vec<WatchDirOutput<'a>>,
+    rules: &'a [ImportedRule],
+}
+
+#[derive(Serialize)]
+struct WatchDirOutput<'a> {
+    name: &'a str,
+    path: &'a Path,
+}
+
+impl Imported {
+    /// Starts an import with paths made relative to the user's home directory where possible.
+    pub fn new() -> Self {
+        Imported {
+            base_dir: home(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the name of the watch directory for `location`, adding it if it is new.
+    fn watch_dir(&mut self, location: &str) -> String {
+        let path = expand(location);
+        if let Some(name) = self.watch_dirs.get(&path) {
+            return name.clone()
+        }
+        let stem = path.file_name()
+            .map(|n| n.to_string_lossy().to_lowercase().replace(|c: char| !c.is_alphanumeric(), "-"))
+            .unwrap_or_else(|| "root".to_string());
+        let mut name = stem.clone();
+        let mut n = 1;
+        while self.watch_dirs.values().any(|v| v == &name) {
+            n += 1;
+            name = format!("{stem}-{n}");
+        }
+        self.watch_dirs.insert(path, name.clone());
+        name
+    }
+
+    /// Destination path as it should appear in a `move` or `unzip` action.
+    fn dest(&self, dest: &str) -> String {
+        let path = expand(dest);
+        path.strip_prefix(&self.base_dir).unwrap_or(&path).display().to_string()
+    }
+
+    fn note(&mut self, note: impl Into<String>) {
+        self.notes.push(note.into());
+    }
+
+    /// Renders the config as YAML, with the notes as comments at the top.
+    pub fn to_yaml(&self) -> Result<String> {
+        let watch_dirs = self.watch_dirs.iter()
+            .map(|(path, name)| WatchDirOutput { name, path: path.strip_prefix(&self.base_dir).unwrap_or(path) })
+            .collect();
+        let output = Output { version: SCHEMA_VERSION, base_dir: &self.base_dir, watch_dirs, rules: &self.rules };
+
+        let mut yaml = String::new();
+        if !self.notes.is_empty() {
+            yaml.push_str("# not translated:\n");
+            for note in &self.notes {
+                yaml.push_str(&format!("#   - {note}\n"));
+            }
+        }
+        yaml.push_str(&serde_yaml::to_string(&output)?);
+        Ok(yaml)
+    }
+}
+
+fn move_action(dest: String, duplicate: &str, compare: Option<&str>) -> Value {
+    let mut options = serde_yaml::Mapping::new();
+    options.insert("dest".into(), dest.into());
+    options.insert("duplicate".into(), duplicate.into());
+    if let Some(compare) = compare {
+        options.insert("compare".into(), compare.into());
+    }
+    let mut action = serde_yaml::Mapping::new();
+    action.insert("move".into(), options.into());
+    action.into()
+}
+
+/// Whether any of `actions` moves files outside `baseDir`, which [`Imported::dest`] leaves as an
+/// absolute path.
+fn leaves_base(actions: &[Value]) -> bool {
+    actions.iter().any(|action| action.get("move").and_then(|m| m.get("dest")).and_then(Value::as_str).is_some_and(|d| d.starts_with('/')))
+}
+
+fn delete_action() -> Value {
+    "delete".into()
+}
+
+fn home() -> PathBuf {
+    std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("/"))
+}
+
+/// Expands a leading `~` to the home directory.
+fn expand(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) => home().join(rest.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,223 @@
+//! Rules from [organize](https://github.com/tfeldmann/organize), a Python file management tool.
+
+use serde_yaml::Value;
+
+use super::{delete_action, leaves_base, move_action, Imported, ImportedRule};
+use crate::glob;
+use crate::Result;
+
+/// Converts an organize `config.yaml`.
+pub fn convert(source: &str) -> Result<Imported> {
+    let document: Value = serde_yaml::from_str(source)?;
+    let rules = document.get("rules").and_then(Value::as_sequence)
+        .ok_or("organize config has no rules list")?;
+
+    let mut imported = Imported::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let label = match rule.get("name").and_then(Value::as_str) {
+            Some(name) => format!("rule [{name}]"),
+            None => format!("rule {}", i + 1),
+        };
+        if let Some(rule) = convert_rule(&mut imported, rule, &label) {
+            imported.rules.push(rule);
+        }
+    }
+    Ok(imported)
+}
+
+fn convert_rule(imported: &mut Imported, rule: &Value, label: &str) -> Option<ImportedRule> {
+    if rule.get("enabled").and_then(Value::as_bool) == Some(false) {
+        imported.note(format!("{label} is disabled - skipped"));
+        return None
+    }
+    if let Some(mode) = rule.get("filter_mode").and_then(Value::as_str).filter(|m| *m != "all") {
+        imported.note(format!("{label} uses filter_mode {mode} - skipped"));
+        return None
+    }
+    if rule.get("subfolders").and_then(Value::as_bool) == Some(true) {
+        imported.note(format!("{label}: subfolders - only files directly in each location are organised"));
+    }
+
+    let locations = strings(rule.get("locations"), "path");
+    let applies_to = if locations.is_empty() {
+        imported.note(format!("{label} has no locations - it applies to every watch directory"));
+        None
+    } else {
+        Some(locations.iter().map(|l| imported.watch_dir(l)).collect())
+    };
+    let kind = match rule.get("targets").and_then(Value::as_str) {
+        Some("dirs") => Some("directory"),
+        _ => None,
+    };
+
+    let mut filters = Filters::default();
+    for filter in rule.get("filters").and_then(Value::as_sequence).into_iter().flatten() {
+        let (name, options) = single(filter);
+        match name.as_str() {
+            "extension" => filters.extensions = Some(strings(options, "")),
+            "name" => filters.name = Some(name_pattern(imported, label, options)),
+            "regex" => filters.regex = options.and_then(|o| o.as_str().or_else(|| o.get("expr")?.as_str())).map(String::from),
+            "size" => size_filter(imported, label, options, &mut filters),
+            other => imported.note(format!("{label}: filter [{other}] has no equivalent - ignored")),
+        }
+    }
+
+    let mut actions = Vec::new();
+    for action in rule.get("actions").and_then(Value::as_sequence).into_iter().flatten() {
+        let (name, options) = single(action);
+        match name.as_str() {
+            "move" => {
+                let dest = options.and_then(|o| o.as_str().or_else(|| o.get("dest")?.as_str()));
+                let Some(dest) = dest else {
+                    imported.note(format!("{label}: move without a destination - ignored"));
+                    continue
+                };
+                if dest.contains('{') {
+                    imported.note(format!("{label}: move destination [{dest}] uses placeholders - ignored"));
+                    continue
+                }
+                if !dest.ends_with('/') {
+                    imported.note(format!("{label}: move destination [{dest}] is treated as a directory"));
+                }
+                let conflict = options.and_then(|o| o.get("on_conflict")).and_then(Value::as_str).unwrap_or("rename_new");
+                let (duplicate, compare) = match conflict {
+                    "skip" => ("skip", None),
+                    "overwrite" => ("overwrite", None),
+                    "rename_new" | "rename_existing" => ("rename-date", None),
+                    // organize deletes the new file when it's identical, where this leaves it in place
+                    "deduplicate" => ("rename-date", Some("hash")),
+                    other => {
+                        imported.note(format!("{label}: on_conflict {other} - using rename-date"));
+                        ("rename-date", None)
+                    },
+                };
+                actions.push(move_action(imported.dest(dest), duplicate, compare));
+            },
+            "delete" => actions.push(delete_action()),
+            "trash" => {
+                imported.note(format!("{label}: trash is translated to delete"));
+                actions.push(delete_action());
+            },
+            other => imported.note(format!("{label}: action [{other}] has no equivalent - ignored")),
+        }
+    }
+    if actions.is_empty() {
+        imported.note(format!("{label} has no actions that could be translated - skipped"));
+        return None
+    }
+
+    Some(ImportedRule {
+        regex: filters.regex(imported, label),
+        applies_to,
+        kind,
+        min_size: filters.min_size,
+        max_size: filters.max_size,
+        allow_outside_base: leaves_base(&actions),
+        actions,
+    })
+}
+
+#[derive(Default)]
+struct Filters {
+    extensions: Option<Vec<String>>,
+    /// Regex for the name without its extension, and whether it is case sensitive.
+    name: Option<(String, bool)>,
+    regex: Option<String>,
+    min_size: Option<String>,
+    max_size: Option<String>,
+}
+
+impl Filters {
+    fn regex(&self, imported: &mut Imported, label: &str) -> String {
+        if let Some(regex) = &self.regex {
+            if self.name.is_some() || self.extensions.is_some() {
+                imported.note(format!("{label}: regex combined with name or extension filters - only the regex is used"));
+            }
+            return regex.clone()
+        }
+        let extension = self.extensions.as_ref().filter(|e| !e.is_empty()).map(|extensions| {
+            let extensions = extensions.iter().map(|e| regex::escape(e.trim_start_matches('.'))).collect::<Vec<_>>();
+            format!(r"\.(?i:{})$", extensions.join("|"))
+        });
+        match (&self.name, extension) {
+            (Some((stem, sensitive)), Some(extension)) => format!("{}^{stem}{extension}", flags(*sensitive)),
+            (Some((stem, sensitive)), None) => format!(r"{}^{stem}(?:\.[^.]*)?$", flags(*sensitive)),
+            (None, Some(extension)) => extension,
+            (None, None) => ".*".to_string(),
+        }
+    }
+}
+
+fn flags(case_sensitive: bool) -> &'static str {
+    if case_sensitive { "" } else { "(?i)" }
+}
+
+/// Regex for organize's `name` filter, which applies to the name without its extension.
+fn name_pattern(imported: &mut Imported, label: &str, options: Option<&Value>) -> (String, bool) {
+    if let Some(glob) = options.and_then(Value::as_str) {
+        return (unanchored(glob::to_regex(glob)), true)
+    }
+    let option = |key| options.and_then(|o| o.get(key));
+    let text = |key| option(key).and_then(Value::as_str).map(regex::escape).unwrap_or_default();
+    for key in ["startswith", "contains", "endswith"] {
+        if option(key).map(Value::is_sequence).unwrap_or(false) {
+            imported.note(format!("{label}: name {key} with several values - ignored"));
+        }
+    }
+    let sensitive = option("case_sensitive").and_then(Value::as_bool).unwrap_or(true);
+    let stem = match option("match").and_then(Value::as_str) {
+        Some(glob) => unanchored(glob::to_regex(glob)),
+        None => match text("contains") {
+            contains if contains.is_empty() => format!("{}.*{}", text("startswith"), text("endswith")),
+            contains => format!("{}.*{contains}.*{}", text("startswith"), text("endswith")),
+        },
+    };
+    (stem, sensitive)
+}
+
+fn unanchored(regex: String) -> String {
+    regex.trim_start_matches('^').trim_end_matches('$').to_string()
+}
+
+/// Translates size conditions into `minSize` and `maxSize`. organize's single-letter units are SI.
+fn size_filter(imported: &mut Imported, label: &str, options: Option<&Value>, filters: &mut Filters) {
+    let condition = regex::Regex::new(r"^\s*(>=|>|<=|<)\s*([\d.]+)\s*([a-zA-Z]*)\s*$").unwrap();
+    for c in strings(options, "") {
+        let Some(captures) = condition.captures(&c) else {
+            imported.note(format!("{label}: size condition [{c}] - ignored"));
+            continue
+        };
+        let unit = match &captures[3] {
+            unit if unit.len() == 1 && !unit.eq_ignore_ascii_case("b") => format!("{unit}B"),
+            unit => unit.to_string(),
+        };
+        let translated = format!("{} {}{unit}", &captures[1], &captures[2]);
+        let bound = if captures[1].starts_with('>') { &mut filters.min_size } else { &mut filters.max_size };
+        if bound.is_some() {
+            imported.note(format!("{label}: size condition [{c}] - only one lower and one upper bound are kept"));
+            continue
+        }
+        *bound = Some(translated);
+    }
+}
+
+/// A filter or action written either as a bare name or as a single-key mapping.
+fn single(value: &Value) -> (String, Option<&Value>) {
+    match value {
+        Value::String(name) => (name.clone(), None),
+        Value::Mapping(map) => map.iter().next()
+            .map(|(k, v)| (k.as_str().unwrap_or_default().to_string(), Some(v)))
+            .unwrap_or_default(),
+        _ => (String::new(), None),
+    }
+}
+
+/// A value given as a string or a list, where list items may be mappings holding the string under `key`.
+fn strings(value: Option<&Value>, key: &str) -> Vec<String> {
+    let item = |v: &Value| v.as_str().or_else(|| v.get(key)?.as_str()).map(String::from);
+    match value {
+        Some(Value::Sequence(items)) => items.iter().filter_map(item).collect(),
+        Some(value) => item(value).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
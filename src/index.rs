@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil;
+use crate::hashcache;
+use crate::Result;
+
+/// How often a changed index is saved while files are being processed.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What to do with a new download whose content already exists somewhere under `baseDir`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub enum OnDuplicate {
+    /// Leave the download where it is and don't apply any rules.
+    #[serde(rename="skip")]
+    Skip,
+    /// Replace the download with a hardlink to the existing copy, then apply the rules as normal.
+    #[serde(rename="hardlink")]
+    Hardlink,
+    /// Log the duplicate and apply the rules as normal.
+    #[serde(rename="report")]
+    Report,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DuplicateIndexConfig {
+    #[serde(rename="onDuplicate")]
+    pub on_duplicate: OnDuplicate,
+}
+
+struct Entry {
+    size: u64,
+    mtime: u128,
+    hash: Option<String>,
+}
+
+/// Content index of every file under `baseDir`, persisted to the state directory.
+///
+/// Files are only hashed when a new download has the same size as an indexed file, so keeping
+/// the index up to date costs a `stat` per file rather than reading the whole tree.
+pub struct DuplicateIndex {
+    pub on_duplicate: OnDuplicate,
+    root: PathBuf,
+    excludes: Vec<PathBuf>,
+    /// Where the index is saved - `None` keeps it in memory only.
+    file: Option<PathBuf>,
+    entries: HashMap<PathBuf, Entry>,
+    dirty: bool,
+    last_saved: Instant,
+}
+
+impl DuplicateIndex {
+    /// Loads the persisted index (if any) and reconciles it with the files currently on disk.
+    pub fn open(config: &DuplicateIndexConfig, root: &Path, file: Option<PathBuf>, excludes: Vec<PathBuf>) -> Result<Self> {
+        let mut index = DuplicateIndex {
+            on_duplicate: config.on_duplicate,
+            root: root.to_path_buf(),
+            excludes,
+            file,
+            entries: HashMap::new(),
+            dirty: false,
+            last_saved: Instant::now(),
+        };
+
+        let persisted = index.load()?;
+        index.scan(&index.root.clone(), &persisted)?;
+        index.dirty = true;
+        info!(root=index.root.to_str(), files=index.entries.len(); "duplicate index ready");
+
+        Ok(index)
+    }
+
+    /// Returns an indexed file with the same content as `candidate`, if there is one.
+    pub fn find_duplicate(&mut self, candidate: &Path) -> Result<Option<PathBuf>> {
+        let size = fs::metadata(candidate)?.len();
+        let same_size: Vec<PathBuf> = self.entries.iter()
+            .filter(|(_, e)| e.size == size)
+            .map(|(p, _)| p.clone())
+            .collect();
+        if same_size.is_empty() {
+            return Ok(None)
+        }
+
+        let hash = hashcache::hash_file(candidate)?;
+        for relative in same_size {
+            let path = self.root.join(&relative);
+            let entry = match self.refresh(&relative)? {
+                Some(entry) if entry.size == size => entry,
+                _ => continue,
+            };
+            let newly_hashed = entry.hash.is_none();
+            if newly_hashed {
+                entry.hash = Some(hashcache::hash_file(&path)?);
+            }
+            let matched = entry.hash.as_deref() == Some(hash.as_str());
+            self.dirty |= newly_hashed;
+            if matched {
+                return Ok(Some(path))
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Adds (or refreshes) a file, or every file in a directory, placed under the root.
+    pub fn record(&mut self, path: &Path) -> Result<()> {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => return Ok(()),
+        };
+        let metadata = fs::metadata(path)?;
+        if metadata.is_dir() {
+            self.scan(path, &HashMap::new())?;
+            self.dirty = true;
+            return Ok(())
+        }
+        self.entries.insert(relative, Entry { size: metadata.len(), mtime: fsutil::mtime(&metadata), hash: None });
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Saves the index if it has changed and hasn't been saved for a while, so that processing a
+    /// batch of files doesn't rewrite it after every one. What's lost if the organiser stops first
+    /// is found again by the scan on start.
+    pub fn save_if_due(&mut self) -> Result<()> {
+        if self.last_saved.elapsed() < SAVE_INTERVAL {
+            return Ok(())
+        }
+        self.save()
+    }
+
+    /// Writes the index to disk if it has changed since the last save.
+    pub fn save(&mut self) -> Result<()> {
+        let file = match (&self.file, self.dirty) {
+            (Some(file), true) => file,
+            _ => return Ok(()),
+        };
+
+        let tmp = file.with_extension("tmp");
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        for (path, entry) in &self.entries {
+            let path = match path.to_str() {
+                Some(p) if !p.contains('\n') => p,
+                _ => continue,
+            };
+            writeln!(out, "{}\t{}\t{}\t{}", entry.size, entry.mtime, entry.hash.as_deref().unwrap_or("-"), path)?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, file)?;
+
+        self.dirty = false;
+        self.last_saved = Instant::now();
+        debug!(file=file.to_str(), files=self.entries.len(); "saved duplicate index");
+        Ok(())
+    }
+
+    fn load(&self) -> Result<HashMap<PathBuf, Entry>> {
+        let mut entries = HashMap::new();
+        let file = match &self.file {
+            Some(file) if file.exists() => file,
+            _ => return Ok(entries),
+        };
+
+        for line in io::BufReader::new(fs::File::open(file)?).lines() {
+            let line = line?;
+            let mut fields = line.splitn(4, '\t');
+            let parsed = (|| {
+                let size = fields.next()?.parse().ok()?;
+                let mtime = fields.next()?.parse().ok()?;
+                let hash = fields.next().filter(|h| *h != "-").map(str::to_string);
+                let path = PathBuf::from(fields.next()?);
+                Some((path, Entry { size, mtime, hash }))
+            })();
+            match parsed {
+                Some((path, entry)) => { entries.insert(path, entry); },
+                None => warn!(file=file.to_str(), line=line; "ignoring malformed duplicate index line"),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Walks `dir`, keeping persisted hashes for files whose size and mtime are unchanged.
+    fn scan(&mut self, dir: &Path, persisted: &HashMap<PathBuf, Entry>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if self.excludes.iter().any(|e| e == &path) {
+                continue
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.scan(&path, persisted)?;
+            } else if file_type.is_file() {
+                let metadata = entry.metadata()?;
+                let relative = path.strip_prefix(&self.root)?.to_path_buf();
+                let (size, mtime) = (metadata.len(), fsutil::mtime(&metadata));
+                let hash = persisted.get(&relative)
+                    .filter(|e| e.size == size && e.mtime == mtime)
+                    .and_then(|e| e.hash.clone());
+                self.entries.insert(relative, Entry { size, mtime, hash });
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings an entry in line with the file on disk, dropping it if the file has gone and
+    /// forgetting its hash if the file has changed.
+    fn refresh(&mut self, relative: &Path) -> Result<Option<&mut Entry>> {
+        let metadata = match fs::metadata(self.root.join(relative)) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.entries.remove(relative);
+                self.dirty = true;
+                return Ok(None)
+            },
+            Err(err) => return Err(err.into()),
+        };
+        let entry = match self.entries.get_mut(relative) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if entry.size != metadata.len() || entry.mtime != fsutil::mtime(&metadata) {
+            *entry = Entry { size: metadata.len(), mtime: fsutil::mtime(&metadata), hash: None };
+            self.dirty = true;
+        }
+        Ok(Some(entry))
+    }
+}
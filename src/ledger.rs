@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{duration, fsutil, hashcache};
+use crate::Result;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LedgerConfig {
+    /// How long a processed file is remembered.
+    #[serde(deserialize_with="duration::deserialize", serialize_with="duration::serialize", default="default_ttl")]
+    pub ttl: Duration,
+    /// Whether to include a content hash in the fingerprint. Hashing reads every file in full
+    /// before it is processed; without it, size and mtime are compared.
+    #[serde(default="default_hash")]
+    pub hash: bool,
+}
+
+fn default_ttl() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+fn default_hash() -> bool {
+    true
+}
+
+/// What a file looked like when it was processed.
+#[derive(PartialEq)]
+pub struct Fingerprint {
+    size: u64,
+    mtime: u128,
+    hash: Option<String>,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path, hash: bool) -> Result<Fingerprint> {
+        let metadata = fs::metadata(path)?;
+        let hash = if hash && metadata.is_file() { Some(hashcache::hash_file(path)?) } else { None };
+        Ok(Fingerprint { size: metadata.len(), mtime: fsutil::mtime(&metadata), hash })
+    }
+}
+
+struct Entry {
+    fingerprint: Fingerprint,
+    recorded: u64,
+}
+
+/// Record of files that have already been processed, so replayed or repeated events for an
+/// unchanged file don't process it twice.
+pub struct Ledger {
+    ttl: Duration,
+    pub hash: bool,
+    /// Where the ledger is saved - `None` keeps it in memory only.
+    file: Option<PathBuf>,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl Ledger {
+    pub fn open(config: &LedgerConfig, file: Option<PathBuf>) -> Result<Self> {
+        let mut ledger = Ledger {
+            ttl: config.ttl,
+            hash: config.hash,
+            file,
+            entries: HashMap::new(),
+        };
+        ledger.load()?;
+        Ok(ledger)
+    }
+
+    /// Whether `path` was processed within the TTL and hasn't changed since.
+    pub fn is_processed(&self, path: &Path, fingerprint: &Fingerprint) -> bool {
+        match self.entries.get(path) {
+            Some(entry) => entry.fingerprint == *fingerprint && !self.is_expired(entry, now()),
+            None => false,
+        }
+    }
+
+    /// Remembers that `path` was processed. The entry is appended to the file rather than the
+    /// whole ledger being rewritten, which would make a rescan of many files quadratic.
+    pub fn record(&mut self, path: &Path, fingerprint: Fingerprint) -> Result<()> {
+        let entry = Entry { fingerprint, recorded: now() };
+        if let (Some(file), Some(line)) = (&self.file, line(path, &entry)) {
+            let mut out = fs::OpenOptions::new().create(true).append(true).open(file)?;
+            writeln!(out, "{line}")?;
+        }
+        self.entries.insert(path.to_path_buf(), entry);
+        Ok(())
+    }
+
+    fn is_expired(&self, entry: &Entry, now: u64) -> bool {
+        now.saturating_sub(entry.recorded) > self.ttl.as_secs()
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) if file.exists() => file.clone(),
+            _ => return Ok(()),
+        };
+
+        let now = now();
+        let mut lines = 0;
+        for line in io::BufReader::new(fs::File::open(&file)?).lines() {
+            let line = line?;
+            lines += 1;
+            let mut fields = line.splitn(5, '\t');
+            let parsed = (|| {
+                let recorded = fields.next()?.parse().ok()?;
+                let size = fields.next()?.parse().ok()?;
+                let mtime = fields.next()?.parse().ok()?;
+                let hash = fields.next().filter(|h| *h != "-").map(str::to_string);
+                let path = PathBuf::from(fields.next()?);
+                Some((path, Entry { fingerprint: Fingerprint { size, mtime, hash }, recorded }))
+            })();
+            match parsed {
+                Some((_, entry)) if self.is_expired(&entry, now) => {},
+                // later lines are newer
+                Some((path, entry)) => { self.entries.insert(path, entry); },
+                None => warn!(file=file.to_str(), line=line; "ignoring malformed ledger line"),
+            }
+        }
+
+        debug!(file=file.to_str(), entries=self.entries.len(); "loaded processed file ledger");
+        // drop expired and superseded lines
+        if lines != self.entries.len() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let now = now();
+        let ttl = self.ttl.as_secs();
+        self.entries.retain(|_, e| now.saturating_sub(e.recorded) <= ttl);
+
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let tmp = file.with_extension("tmp");
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        for line in self.entries.iter().filter_map(|(path, entry)| line(path, entry)) {
+            writeln!(out, "{line}")?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, file)?;
+        Ok(())
+    }
+}
+
+/// An entry as a line of the ledger file. Paths that aren't UTF-8, or that contain a newline,
+/// aren't saved.
+fn line(path: &Path, entry: &Entry) -> Option<String> {
+    let path = path.to_str().filter(|p| !p.contains('\n'))?;
+    let fp = &entry.fingerprint;
+    Some(format!("{}\t{}\t{}\t{}\t{}", entry.recorded, fp.size, fp.mtime, fp.hash.as_deref().unwrap_or("-"), path))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Component, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil;
+use crate::mode::Modes;
+use crate::normalise::Normalisation;
+use crate::plan::MoveTarget;
+use crate::template;
+use crate::Result;
+
+/// Symlinks to the file in several directories, so it can be found under more than one scheme
+/// (by date, by type, by rule) while only being stored once, where the earlier actions put it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LinkFarmConfig {
+    /// Directories the links go in, relative to `baseDir`, with the same tokens as `dest`.
+    pub dests: Vec<String>,
+    /// Point the links at the file by a relative path, so the tree still works when it's mounted
+    /// somewhere else, e.g. over a network share.
+    #[serde(default)]
+    pub relative: bool,
+}
+
+impl LinkFarmConfig {
+    /// Where each link to `source`, named `name`, would go. A link that's already there and points
+    /// at `source` is `Already`; any other entry with the name gets the new link a date prefix.
+    pub fn targets(&self, base_dir: &Path, name: &str, source: &Path, form: Option<Normalisation>) -> Vec<MoveTarget> {
+        self.dests.iter().map(|dest| {
+            let link = fsutil::fit(&template::dest(base_dir, dest, source, form).join(name));
+            if fs::symlink_metadata(&link).is_err() {
+                return MoveTarget::Move(link)
+            }
+            if points_at(&link, source) {
+                return MoveTarget::Already(link)
+            }
+            MoveTarget::Move(fsutil::fit(&link.with_file_name(fsutil::date_prefixed(name))))
+        }).collect()
+    }
+
+    /// Makes the link at `link` to `source`.
+    pub fn link(&self, source: &Path, link: &Path, modes: Modes) -> Result<()> {
+        let dir = link.parent().unwrap_or(Path::new("/"));
+        modes.create_dir_all(dir)?;
+        let target = match self.relative {
+            true => relative_to(source, dir),
+            false => source.to_path_buf(),
+        };
+        symlink(&target, link).map_err(|e| format!("unable to link [{}] to [{}]: {e}", link.display(), target.display()))?;
+        Ok(())
+    }
+}
+
+/// Whether the symlink at `link` resolves to `source`.
+fn points_at(link: &Path, source: &Path) -> bool {
+    let Ok(target) = fs::read_link(link) else { return false };
+    let target = link.parent().map(|dir| dir.join(&target)).unwrap_or(target);
+    match (target.canonicalize(), source.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The path from `dir` to `path`, both absolute, such as `../../Store/file.pdf`.
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let dir: Vec<Component> = dir.components().collect();
+    let common = path.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+    let mut relative: PathBuf = dir[common..].iter().map(|_| Component::ParentDir).collect();
+    relative.extend(&path[common..]);
+    relative
+}
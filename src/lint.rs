@@ -0,0 +1,154 @@
+use std::sync::Arc;
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+use crate::config::{Action, Config, DuplicateAction, EntryKind, Rule};
+use crate::fsutil;
+use crate::pattern::Pattern;
+use crate::template;
+
+/// Upper bound on the example names generated for one regex.
+const MAX_SAMPLES: usize = 64;
+
+/// Names that a rule matching "everything" should match.
+const PROBES: &[&str] = &[
+    "a", "12345", "download.tmp", "Some File (1).tar.gz", ".hidden", "UPPER.PDF", "with space", "ünïcödé.mkv",
+];
+
+/// Looks for rules that can never run or that are likely mistakes, returning a warning for each.
+///
+/// Regex overlap is judged from example names generated from each regex, so a warning means the
+/// rules very probably overlap rather than that they provably do.
+pub fn lint(config: &Config) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let rules = &config.rules;
+
+    for (j, later) in rules.iter().enumerate() {
+        let samples = samples(&later.regex);
+        if samples.is_empty() {
+            continue
+        }
+        if let Some((i, earlier)) = rules[..j].iter().enumerate()
+            .find(|(_, earlier)| covers(earlier, later) && samples.iter().all(|s| earlier.matches_name(s)))
+        {
+            warnings.push(format!("rule {} [{}] looks unreachable - names it matches are caught first by rule {} [{}]",
+                j + 1, later.regex.as_str(), i + 1, earlier.regex.as_str()));
+        }
+    }
+
+    let moves = rules.iter().enumerate().flat_map(|(i, rule)| rule.actions.iter().filter_map(move |a| match a {
+        Action::Move { dest, duplicate, .. } => Some((i, rule, dest.trim_end_matches('/'), duplicate)),
+        _ => None,
+    })).collect::<Vec<_>>();
+    for (n, (j, later, dest, duplicate)) in moves.iter().enumerate() {
+        if let Some((i, earlier, _, other)) = moves[..n].iter().find(|(_, _, d, other)| d == dest && other != duplicate) {
+            warnings.push(format!("rules {} [{}] and {} [{}] both move to [{dest}] but handle duplicates differently ({} and {})",
+                i + 1, earlier.regex.as_str(), j + 1, later.regex.as_str(), strategy(other), strategy(duplicate)));
+        }
+    }
+
+    for (i, rule) in rules.iter().enumerate() {
+        let deletes = rule.actions.iter().any(|a| matches!(a, Action::Delete));
+        if deletes && !rule.has_size_condition() && PROBES.iter().all(|p| rule.matches_name(p)) {
+            warnings.push(format!("rule {} [{}] deletes everything it sees", i + 1, rule.regex.as_str()));
+        }
+    }
+
+    for (i, rule) in rules.iter().enumerate() {
+        let dests = rule.actions.iter().flat_map(|action| match action {
+            Action::Move { dest, rename, .. } | Action::LinkIntoLibrary { dest, rename, .. } => vec![(dest, rename.as_deref())],
+            Action::Unzip { dest, .. } | Action::Episode { dest, .. } | Action::Split { dest, .. } => vec![(dest, None)],
+            Action::Photo(photo) => vec![(&photo.raw, None), (&photo.jpeg, None)],
+            Action::LinkFarm(farm) => farm.dests.iter().map(|dest| (dest, None)).collect(),
+            _ => Vec::new(),
+        });
+        for (dest, rename) in dests {
+            let mut path = config.base_dir.join(template::estimate(dest));
+            if let Some(rename) = rename {
+                path.push(template::estimate(rename));
+            }
+            let described = match rename {
+                Some(rename) => format!("{dest}/{rename}"),
+                None => dest.clone(),
+            };
+            if path.iter().any(|component| component.len() > fsutil::NAME_MAX) {
+                warnings.push(format!("rule {} [{}] destination [{described}] can expand to a name longer than {} bytes - it will be shortened",
+                    i + 1, rule.regex.as_str(), fsutil::NAME_MAX));
+            } else if path.as_os_str().len() + rename.map(|_| 0).unwrap_or(fsutil::NAME_MAX) > fsutil::PATH_MAX {
+                warnings.push(format!("rule {} [{}] destination [{described}] can expand to a path longer than {} bytes with a long file name - the name will be shortened",
+                    i + 1, rule.regex.as_str(), fsutil::PATH_MAX));
+            }
+        }
+    }
+
+    warnings
+}
+
+/// Whether every entry `later` could handle would also be considered by `earlier`, ignoring regexes.
+fn covers(earlier: &Rule, later: &Rule) -> bool {
+    let kind = matches!(earlier.kind, EntryKind::Any) || earlier.kind == later.kind;
+    let dirs = match (&earlier.applies_to, &later.applies_to) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(earlier), Some(later)) => later.iter().all(|d| earlier.contains(d)),
+    };
+    // a group's conditions can turn a file away from its rules, but not from another rule of the group
+    let group = match (&earlier.group, &later.group) {
+        (None, _) => true,
+        (Some(earlier), Some(later)) => Arc::ptr_eq(earlier, later),
+        (Some(_), None) => false,
+    };
+    kind && dirs && group && !earlier.has_size_condition() && !earlier.has_destination_condition() && !earlier.has_link_condition() && earlier.content.is_none() && earlier.header().is_none() && earlier.pdf.is_none() && earlier.image.is_none() && earlier.from_archive.is_none()
+}
+
+fn strategy(duplicate: &DuplicateAction) -> &'static str {
+    match duplicate {
+        DuplicateAction::RenameDate => "rename-date",
+        DuplicateAction::Skip => "skip",
+        DuplicateAction::Overwrite => "overwrite",
+        DuplicateAction::Merge => "merge",
+    }
+}
+
+/// Example names matched by `regex`, including ones with text around an unanchored pattern.
+fn samples(regex: &Pattern) -> Vec<String> {
+    let hir = match regex_syntax::parse(regex.as_str()) {
+        Ok(hir) => hir,
+        Err(_) => return Vec::new(),
+    };
+    generate(&hir).into_iter()
+        .flat_map(|s| [format!("Ab1 {s}"), format!("{s}.x"), format!("a{s}"), s])
+        .filter(|s| regex.is_match(s))
+        .collect()
+}
+
+fn generate(hir: &Hir) -> Vec<String> {
+    let mut out = match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => vec![String::new()],
+        HirKind::Literal(literal) => vec![String::from_utf8_lossy(&literal.0).into_owned()],
+        HirKind::Class(Class::Unicode(class)) => {
+            let mut chars = ['a', 'Z', '0', ' ', '.', '-'].into_iter()
+                .filter(|c| class.ranges().iter().any(|r| (r.start()..=r.end()).contains(c)))
+                .collect::<Vec<_>>();
+            chars.extend(class.ranges().first().map(|r| r.start()));
+            chars.dedup();
+            chars.into_iter().take(3).map(String::from).collect()
+        },
+        HirKind::Class(Class::Bytes(class)) => {
+            class.ranges().first().map(|r| char::from(r.start()).to_string()).into_iter().collect()
+        },
+        HirKind::Repetition(repetition) => {
+            let sub = generate(&repetition.sub);
+            let counts = [repetition.min, repetition.min + 1, repetition.min + 3].into_iter()
+                .filter(|n| repetition.max.map(|max| *n <= max).unwrap_or(true));
+            counts.flat_map(|n| sub.iter().map(move |s| s.repeat(n as usize))).collect()
+        },
+        HirKind::Capture(capture) => generate(&capture.sub),
+        HirKind::Concat(parts) => parts.iter().fold(vec![String::new()], |acc, part| {
+            let next = generate(part);
+            acc.iter().flat_map(|a| next.iter().map(move |n| format!("{a}{n}"))).take(MAX_SAMPLES).collect()
+        }),
+        HirKind::Alternation(branches) => branches.iter().flat_map(generate).collect(),
+    };
+    out.truncate(MAX_SAMPLES);
+    out
+}
@@ -0,0 +1,373 @@
+use std::env;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use log::kv::{self, Key, Value};
+use log::{error, Level, LevelFilter, Log, Metadata, Record};
+
+use crate::webhook;
+
+/// Environment variable holding filter directives, e.g. `warn,extract=debug`.
+const FILTER_ENV: &str = "DOWNLOAD_ORGANISER_LOG";
+
+/// Module prefix added to directives that name one of this crate's modules without it.
+const CRATE_PREFIX: &str = "download_organiser";
+
+/// How log records are written to stderr.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// `key="value"` pairs, one record per line. Suited to running as a daemon.
+    #[default]
+    Logfmt,
+    /// One JSON object per line.
+    Json,
+    /// Coloured, aligned output for watching in a terminal.
+    Pretty,
+}
+
+impl LogFormat {
+    /// The format used without `--log-format`: JSON when running in Kubernetes, where log
+    /// collectors expect it, and logfmt elsewhere.
+    pub fn detect() -> LogFormat {
+        if env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+            LogFormat::Json
+        } else {
+            LogFormat::default()
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "logfmt" => Ok(LogFormat::Logfmt),
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            other => Err(format!("unknown log format [{other}] - expected logfmt, json or pretty")),
+        }
+    }
+}
+
+/// Log levels by module. The most specific matching directive wins.
+#[derive(Debug, Clone)]
+struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Starts from `LOG_LEVEL`, `TRACE` or `DEBUG`, which were honoured before directives existed.
+    fn from_legacy_env() -> Filter {
+        let level = ["LOG", "LOG_LEVEL"].iter().find_map(|v| env::var(v).ok()?.parse().ok());
+        let default = match level {
+            Some(level) => level,
+            None if env::var_os("TRACE").is_some() => LevelFilter::Trace,
+            None if env::var_os("DEBUG").is_some() => LevelFilter::Debug,
+            None => LevelFilter::Info,
+        };
+        Filter { default, modules: Vec::new() }
+    }
+
+    /// Applies comma-separated directives: a bare level sets the default, `module=level` sets one
+    /// module and everything below it. Modules of this crate can be named without the crate prefix.
+    fn apply(&mut self, directives: &str) -> Result<(), String> {
+        for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse = |level: &str| level.parse::<LevelFilter>()
+                .map_err(|_| format!("invalid log level [{level}] in log filter [{directives}]"));
+            match directive.split_once('=') {
+                None => self.default = parse(directive)?,
+                Some((module, level)) => {
+                    let module = if module == CRATE_PREFIX || module.contains("::") {
+                        module.to_string()
+                    } else {
+                        format!("{CRATE_PREFIX}::{module}")
+                    };
+                    let level = parse(level)?;
+                    self.modules.retain(|(m, _)| m != &module);
+                    self.modules.push((module, level));
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `-v`/`-q` flags: positive for more output, negative for less.
+    fn verbosity(&mut self, verbosity: i8) {
+        self.default = match verbosity {
+            i8::MIN..=-2 => LevelFilter::Error,
+            -1 => LevelFilter::Warn,
+            0 => return,
+            1 => LevelFilter::Debug,
+            2..=i8::MAX => LevelFilter::Trace,
+        };
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules.iter()
+            .filter(|(module, _)| target == module || target.strip_prefix(module.as_str()).map(|r| r.starts_with("::")).unwrap_or(false))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+struct Logger {
+    format: LogFormat,
+    start: Instant,
+    colour: bool,
+    verbosity: i8,
+    filter: RwLock<Filter>,
+    repeats: Repeats,
+}
+
+/// Collapses identical warnings and errors, such as the same failure for the same file on every
+/// rescan, into one line per window followed by a count of how often it was repeated.
+struct Repeats {
+    window: RwLock<Duration>,
+    seen: Mutex<HashMap<String, Repeat>>,
+}
+
+struct Repeat {
+    since: Instant,
+    suppressed: u64,
+    line: Line,
+}
+
+/// Default for `logRepeatWindow`.
+pub const DEFAULT_REPEAT_WINDOW: Duration = Duration::from_secs(60);
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Installs the global logger, filtered by the environment and the `-v`/`-q` count.
+pub fn init(format: LogFormat, verbosity: i8) -> crate::Result<()> {
+    let filter = build_filter(None, verbosity)?;
+    log::set_max_level(filter.max());
+    let logger = LOGGER.get_or_init(|| Logger {
+        format,
+        start: Instant::now(),
+        colour: std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        verbosity,
+        filter: RwLock::new(filter),
+        repeats: Repeats { window: RwLock::new(DEFAULT_REPEAT_WINDOW), seen: Mutex::new(HashMap::new()) },
+    });
+    log::set_logger(logger).map_err(|_| "logger is already initialised")?;
+    std::thread::Builder::new()
+        .name("log-repeats".to_string())
+        .spawn(|| loop {
+            std::thread::sleep(Duration::from_secs(1));
+            logger.summarise_repeats(false);
+        })?;
+    std::panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        error!(target: "panic", "thread '{}' {info}", thread.name().unwrap_or("unnamed"));
+    }));
+    Ok(())
+}
+
+/// Adds the config's `logFilter` and `logRepeatWindow`. The environment and command line flags
+/// still take precedence over the filter.
+pub fn configure(directives: Option<&str>, repeat_window: Duration) -> crate::Result<()> {
+    let Some(logger) = LOGGER.get() else {
+        return Ok(())
+    };
+    let filter = build_filter(directives, logger.verbosity)?;
+    log::set_max_level(filter.max());
+    *logger.filter.write().unwrap() = filter;
+    *logger.repeats.window.write().unwrap() = repeat_window;
+    Ok(())
+}
+
+fn build_filter(config: Option<&str>, verbosity: i8) -> crate::Result<Filter> {
+    let mut filter = Filter::from_legacy_env();
+    if let Some(directives) = config {
+        filter.apply(directives)?;
+    }
+    if let Ok(directives) = env::var(FILTER_ENV) {
+        filter.apply(&directives).map_err(|e| format!("{e} from [{FILTER_ENV}]"))?;
+    }
+    filter.verbosity(verbosity);
+    Ok(filter)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.read().unwrap().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+
+        let mut fields = Fields::default();
+        // formatting a field can't fail in a way worth reporting from inside the logger
+        let _ = record.key_values().visit(&mut fields);
+        let line = Line {
+            level: record.level(),
+            target: record.target().to_string(),
+            module: record.module_path().unwrap_or_default().to_string(),
+            message: record.args().to_string(),
+            fields,
+        };
+
+        if line.level <= Level::Warn && self.is_repeat(&line) {
+            return
+        }
+        self.write(&line);
+    }
+
+    fn flush(&self) {
+        self.summarise_repeats(true);
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// A record captured so it can be written later as a repeat summary.
+#[derive(Clone)]
+struct Line {
+    level: Level,
+    target: String,
+    module: String,
+    message: String,
+    fields: Fields,
+}
+
+/// Width the message is padded to so that fields line up in the pretty format.
+const MESSAGE_WIDTH: usize = 44;
+
+impl Logger {
+    /// Records an occurrence of `line`, returning whether it repeats one already logged in the
+    /// current window and so should be counted rather than written.
+    fn is_repeat(&self, line: &Line) -> bool {
+        let window = *self.repeats.window.read().unwrap();
+        if window.is_zero() {
+            return false
+        }
+        let key = format!("{}|{}|{:?}", line.target, line.message, line.fields.0);
+        let mut seen = self.repeats.seen.lock().unwrap();
+        match seen.get_mut(&key) {
+            Some(repeat) if repeat.since.elapsed() < window => {
+                repeat.suppressed += 1;
+                true
+            },
+            _ => {
+                if let Some(repeat) = seen.insert(key, Repeat { since: Instant::now(), suppressed: 0, line: line.clone() }) {
+                    self.write_summary(&repeat);
+                }
+                false
+            },
+        }
+    }
+
+    /// Writes a summary for every repeat whose window has ended, or for all of them when `all`.
+    fn summarise_repeats(&self, all: bool) {
+        let window = *self.repeats.window.read().unwrap();
+        let mut seen = self.repeats.seen.lock().unwrap();
+        seen.retain(|_, repeat| {
+            if !all && repeat.since.elapsed() < window {
+                return true
+            }
+            self.write_summary(repeat);
+            repeat.since = Instant::now();
+            let active = repeat.suppressed > 0;
+            repeat.suppressed = 0;
+            // a warning that stopped recurring is forgotten after a quiet window
+            active
+        });
+    }
+
+    fn write_summary(&self, repeat: &Repeat) {
+        if repeat.suppressed == 0 {
+            return
+        }
+        let mut line = repeat.line.clone();
+        line.message = format!("{} (repeated {} times)", line.message, repeat.suppressed);
+        line.fields.0.push(("repeated".to_string(), repeat.suppressed.to_string()));
+        self.write(&line);
+    }
+
+    fn write(&self, line: &Line) {
+        let text = match self.format {
+            LogFormat::Logfmt => self.logfmt(line),
+            LogFormat::Json => self.json(line),
+            LogFormat::Pretty => self.pretty(line),
+        };
+        let _ = writeln!(std::io::stderr().lock(), "{text}");
+    }
+
+    fn logfmt(&self, line: &Line) -> String {
+        let mut text = format!("ts=\"{}\" lvl=\"{}\" msg={:?} target={:?} module={:?}",
+            timestamp(), line.level, line.message, line.target, line.module);
+        for (key, value) in &line.fields.0 {
+            let _ = write!(text, " {key}={value:?}");
+        }
+        text
+    }
+
+    fn json(&self, line: &Line) -> String {
+        let (timestamp, level) = (timestamp(), line.level.to_string());
+        let mut pairs = vec![
+            ("timestamp", timestamp.as_str()),
+            ("level", level.as_str()),
+            ("message", line.message.as_str()),
+            ("target", line.target.as_str()),
+            ("module", line.module.as_str()),
+        ];
+        pairs.extend(line.fields.0.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        webhook::json_object(&pairs)
+    }
+
+    fn pretty(&self, line: &Line) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let level = format!("{:<5}", line.level);
+        let level = match (self.colour, line.level) {
+            (false, _) => level,
+            (true, Level::Error) => format!("\x1b[1;31m{level}\x1b[0m"),
+            (true, Level::Warn) => format!("\x1b[33m{level}\x1b[0m"),
+            (true, Level::Info) => format!("\x1b[32m{level}\x1b[0m"),
+            (true, Level::Debug) => format!("\x1b[34m{level}\x1b[0m"),
+            (true, Level::Trace) => format!("\x1b[2m{level}\x1b[0m"),
+        };
+
+        // the file a record is about leads the line, so a run reads as one summary per file
+        let filename = line.fields.0.iter().find(|(k, _)| k == "filename").map(|(_, v)| v);
+        let message = match filename {
+            Some(filename) => format!("{filename}: {}", line.message),
+            None => line.message.clone(),
+        };
+        let mut text = format!("{elapsed:>9.3}s {level} {message:<MESSAGE_WIDTH$}");
+        for (key, value) in line.fields.0.iter().filter(|(k, _)| k != "filename") {
+            let value = if value.contains(' ') { format!("{value:?}") } else { value.clone() };
+            if self.colour {
+                let _ = write!(text, " \x1b[2m{key}=\x1b[0m{value}");
+            } else {
+                let _ = write!(text, " {key}={value}");
+            }
+        }
+        text.trim_end().to_string()
+    }
+}
+
+fn timestamp() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()
+}
+
+#[derive(Default, Clone)]
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> kv::Visitor<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
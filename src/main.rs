@@ -1,119 +1,430 @@
-use std::{path::PathBuf, ffi::OsString};
-use inotify::{Inotify, Event, WatchMask, EventMask};
-use tokio_stream::StreamExt;
-use serde::Deserialize;
-use regex::Regex;
-use chrono::prelude::*;
+use std::path::{Path, PathBuf};
+use inotify::{Inotify, EventMask, WatchDescriptor, Watches};
+use tokio_stream::{Stream, StreamExt};
+use std::ffi::OsString;
+use std::collections::HashMap;
 use std::fs;
-use std::io;
-use log::{info, warn, error, debug, as_debug};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use log::{info, warn, error, debug, as_debug, as_display, as_error};
 
+mod cli;
+mod clock;
+mod commands;
+mod config;
+mod confirm;
+mod control;
+mod dbus;
+mod deadline;
+mod deletions;
+mod diskimage;
+mod duplicate;
+mod duration;
+mod episodes;
+mod fanotify;
+mod extract;
+mod fsutil;
+mod gate;
+mod glob;
+mod group;
+mod header;
+mod hashcache;
+mod http;
+mod import;
+mod index;
+mod ledger;
+mod linkfarm;
+mod lint;
+mod logging;
+mod maintenance;
+mod metrics;
+mod migrate;
+mod mode;
+mod mqtt;
+mod normalise;
+mod notify;
+mod ocr;
+mod outcome;
+mod pattern;
+mod pause;
+mod pdf;
+mod photos;
+mod plan;
+mod privilege;
+mod queue;
+mod recompress;
+mod reload;
+mod reconcile;
+mod recording;
+mod removable;
+mod report;
+mod retry;
+mod rules;
+mod sandbox;
+mod secret;
+mod selinux;
+mod size;
+mod snapshot;
+mod split;
+mod state;
+mod subtitles;
+mod template;
+mod tls;
+mod torrent;
+mod uploads;
+mod users;
+mod verify;
+mod version;
+mod watchdog;
+mod webhook;
+mod workdir;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use cli::Command;
+use confirm::{Awaiting, ConfirmConfig, Confirmations, OnTimeout};
+use control::{Rejection, RuntimeWatchDirs};
+use config::{Config, Profile, Rule, Action, HiddenPolicy, SpecialFilePolicy, SymlinkPolicy, WatchDir, Watcher};
+use episodes::EpisodeCache;
+use fanotify::MountWatch;
+use gate::Gate;
+use index::{DuplicateIndex, OnDuplicate};
+use ledger::{Fingerprint, Ledger};
+use maintenance::MaintenanceConfig;
+use mode::Modes;
+use notify::{NotifyEvent, Notifier, Subscription};
+use outcome::{Outcomes, ProcessingOutcome, Status};
+use deadline::{Arrivals, DeadlineConfig};
+use deletions::Deletions;
+use pause::{PauseStatus, Pauses};
+use plan::MoveTarget;
+use privilege::{Credentials, FsIdentity, RunAs};
+use queue::{InFlight, Job, Origin, SortKey, WorkQueue};
+use retry::{ActionClass, RetryPolicies};
+use removable::VolumeMode;
+use reconcile::{ReconcileConfig, Reconciliation};
+use recording::{Recorded, Recorder};
+use report::{ReportConfig, Tally};
+use rules::{Candidate, RuleSet};
+use snapshot::SnapshotConfig;
+use watchdog::{Busy, Watchdog};
+use workdir::WorkDirs;
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    #[serde(rename="baseDir")]
-    base_dir: PathBuf,
-    #[serde(rename="watchDir")]
-    watch_dir: String,
-    rules: Vec<Rule>,
-}
+/// The watch directory, and subdirectory within it, each inotify watch is for.
+type WatchMap = HashMap<WatchDescriptor, (Arc<WatchDir>, PathBuf)>;
 
-#[derive(Deserialize, Debug)]
-struct Rule {
-    #[serde(with = "serde_regex")]
-    regex: Regex,
-    #[serde(rename = "minSize")]
-    min_size: Option<String>,
-    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
-    actions: Vec<Action>,
-}
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
-#[derive(Deserialize, Debug)]
-enum Action {
-    #[serde(rename="move")]
-    Move{dest: String, duplicate: DuplicateAction},
-    #[serde(rename="unzip")]
-    Unzip{dest: String},
-    #[serde(rename="delete")]
-    Delete,
-}
+/// How many files a rescan finds before queueing them.
+const RESCAN_BATCH: usize = 512;
 
-#[derive(Deserialize, Debug)]
-enum DuplicateAction {
-    #[serde(rename="rename-date")]
-    RenameDate,
-    #[serde(rename="skip")]
-    Skip,
-    #[serde(rename="overwrite")]
-    Overwrite,
-}
+/// Threads a rescan stats files with. Statting waits on the disk (or network), not the CPU.
+const STAT_THREADS: usize = 8;
 
-struct SizeMatcher {
-    matcher: Regex,
-}
+/// How many archives deep extracted files are run through the rules, so an archive that contains
+/// itself can't extract forever.
+const MAX_DERIVED_DEPTH: u32 = 4;
 
-impl SizeMatcher {
-    fn new() -> Result<Self> {
-        Ok(SizeMatcher {
-            matcher: Regex::new("^(?P<size>\\d+)(?P<units>\\w{0,2}$)")?,
-        })
-    }
+/// Whether the remaining actions of a rule should run after an action completes.
+enum Flow {
+    Continue,
+    /// Continue, with the remaining actions working on the file at its new path.
+    Moved(PathBuf),
+    /// Continue on the file where it is, it also being at this path now.
+    Linked(PathBuf),
+    Stop,
+    /// Stop, the file having been given up on for this reason.
+    Failed(String),
+    /// Continue, having produced these files to be run through the rules.
+    Derived(Vec<PathBuf>),
+}
 
-    fn is_gteq(&self, file_size: u64, comparison: &str) -> Result<bool> {
-        let (size, units) = if let Some(captures) = self.matcher.captures(comparison) {
-            let raw_size = if let Some(raw_size) = captures.name("size") {
-                raw_size
-            } else {
-                return Err("unable to find capture group [size]".into())
-            };
+struct Organiser {
+    /// Name of the profile, used to find its settings again on reload.
+    profile: String,
+    base_dir: PathBuf,
+    watch_dirs: RwLock<Vec<Arc<WatchDir>>>,
+    /// Watch directories added or removed through the control API.
+    runtime_dirs: Mutex<RuntimeWatchDirs>,
+    /// Policy for watch directories added at runtime that don't set their own.
+    hidden: HiddenPolicy,
+    retry: RetryPolicies,
+    /// Replaced as a whole on reload, so a file already being processed keeps the rules it started with.
+    rules: RwLock<Arc<RuleSet>>,
+    duplicate_index: Option<Mutex<DuplicateIndex>>,
+    symlinks: SymlinkPolicy,
+    special_files: SpecialFilePolicy,
+    failed_dir: Option<PathBuf>,
+    /// Scratch space for files still being written, such as an archive's entries.
+    work_dirs: WorkDirs,
+    /// Holds back opens of files until they've been processed.
+    gate: Option<Arc<Gate>>,
+    /// Watches whole filesystems rather than each directory, when configured.
+    fanotify: Option<Arc<MountWatch>>,
+    /// Who to switch to once watching.
+    run_as: Option<Credentials>,
+    cooldown: Duration,
+    queue: WorkQueue,
+    /// Files being processed, so each is only processed by one worker at a time.
+    in_flight: InFlight,
+    workers: usize,
+    max_queue_depth: Option<usize>,
+    ledger: Option<Mutex<Ledger>>,
+    /// Shows and episode titles looked up by episode actions.
+    episode_cache: Mutex<EpisodeCache>,
+    /// Where the outcome of each file is reported, including D-Bus signals.
+    outcomes: Outcomes,
+    /// Sends the notifications rules ask for with `notifyOn`.
+    notifier: Notifier,
+    /// Processing paused through the control API.
+    pauses: Pauses,
+    /// Files waiting out a `deleteAfter` delay.
+    deletions: Deletions,
+    /// Holds files matched by destructive rules for approval, when set.
+    confirm: Option<ConfirmConfig>,
+    /// Files waiting for approval.
+    confirmations: Confirmations,
+    /// Housekeeping run in the background, when configured.
+    maintenance: Option<MaintenanceConfig>,
+    /// Taken before a rescan queues many files, when configured.
+    snapshot: Option<SnapshotConfig>,
+    /// Told about the event loop's and workers' progress, when configured.
+    watchdog: Option<Arc<Watchdog>>,
+    /// How soon files should be organised, when configured.
+    deadline: Option<DeadlineConfig>,
+    /// Files waiting to be organised, which the deadline is checked against.
+    arrivals: Arrivals,
+    /// The daily report, when configured.
+    report: Option<ReportConfig>,
+    /// What's happened since the last report.
+    tally: Tally,
+    /// Checked against the filesystem on start, when configured.
+    reconcile: Option<ReconcileConfig>,
+    /// What that found, for the control API.
+    reconciliation: Mutex<Option<Reconciliation>>,
+    /// Where watcher events are recorded, when configured.
+    recorder: Option<Recorder>,
+    /// Events come from a recording sent through the control channel rather than from watches,
+    /// and scheduled deletions aren't made.
+    replaying: bool,
+    /// Process files even if the ledger says they have already been processed.
+    force: bool,
+    /// Log what every rule would do instead of doing it.
+    dry_run: bool,
+    /// Change nothing at all, only reporting what would be done. Implies `dry_run`.
+    audit: bool,
+}
 
-            let units = if let Some(units) = captures.name("units") {
-                units
-            } else {
-                return Err("unable to find capture group [units]".into())
-            };
+impl Organiser {
+    /// Makes sure the directories the organiser depends on are usable before watching.
+    ///
+    /// A missing watch directory is created if possible. A read-only base directory (e.g. a
+    /// container with a read-only mount) is reported but not fatal, so the daemon keeps running
+    /// and logging instead of crash-looping. An audit only checks the watch directories exist.
+    fn prepare_dirs(&self) -> Result<()> {
+        for watch_dir in self.watch_dirs.read().unwrap().iter() {
+            if !watch_dir.path.is_dir() && self.audit {
+                return Err(format!("watch directory [{}] does not exist", watch_dir.path.display()).into())
+            }
+            if !watch_dir.path.is_dir() {
+                fs::create_dir_all(&watch_dir.path)
+                    .map_err(|e| format!("watch directory [{}] does not exist and could not be created: {e}", watch_dir.path.display()))?;
+                info!(watch_dir=watch_dir.path.to_str(); "created missing watch directory");
+            }
+        }
 
-            (raw_size.as_str().parse::<u64>()?, units.as_str())
-        } else {
-            return Err(format!("size comparison string [{}] is not valid for regex [{}]", comparison, self.matcher.as_str()).into())
-        };
+        if self.audit {
+            return Ok(())
+        }
+        let probe = self.base_dir.join(format!(".download-organiser-probe-{}", std::process::id()));
+        match fs::File::create(&probe) {
+            Ok(_) => fs::remove_file(&probe)?,
+            Err(err) => {
+                warn!(base_dir=self.base_dir.to_str(), error=as_debug!(err); "base directory is not writable - actions will fail until it is")
+            },
+        }
 
-        let size = match units {
-            "" | "b" | "B" => size,
-            "k" | "kb" | "Kb" | "KB" => size * 2u64.pow(10),
-            "m" | "mb" | "Mb" | "MB" => size * 2u64.pow(20),
-            "g" | "gb" | "Gb" | "GB" => size * 2u64.pow(20),
-            "t" | "tb" | "Tb" | "TB" => size * 2u64.pow(20),
-            v @ _ => return Err(format!("unknown unit specification {v}").into()),
-        };
+        Ok(())
+    }
 
-        Ok(file_size > size)
+    /// The outcome of every file processed from now on, for code running the organiser that reacts
+    /// to files being organised, such as a GUI or a chat bot, rather than reading the logs or a sink.
+    /// The stream ends if the organiser is dropped.
+    pub fn outcomes(&self) -> impl Stream<Item = ProcessingOutcome> {
+        self.outcomes.subscribe()
     }
-}
 
-struct Organiser {
-    base_dir: PathBuf,
-    watch_dir: PathBuf,
-    rules: Vec<Rule>,
-    size_matcher: SizeMatcher,
-}
+    async fn run(self: Arc<Self>, mut control: mpsc::Receiver<control::Request>) -> Result<()> {
+        self.prepare_dirs()?;
 
-impl Organiser {
-    async fn run(&self) -> Result<()> {
         let inotify = Inotify::init()?;
-        inotify.watches().add(self.watch_dir.to_str().unwrap(), WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::ONLYDIR)?;
+        let mut watch_handle = inotify.watches();
+        let mut watches = HashMap::new();
+        for watch_dir in self.watch_dirs.read().unwrap().iter().filter(|_| !self.replaying) {
+            self.add_watches(&mut watch_handle, &mut watches, watch_dir, PathBuf::new())?;
+            info!(watch_dir=watch_dir.path.to_str(), name=watch_dir.name, recursive=watch_dir.recursive; "watching directory for file events");
+        }
+        if let Some(credentials) = self.run_as {
+            privilege::drop_to(credentials)?;
+            info!(uid=credentials.uid, gid=credentials.gid; "dropped privileges");
+        }
+        // before the workers, so no partial file it finds can be one that's being written
+        if let Some(reconcile) = &self.reconcile {
+            let found = reconcile.run(&self.outcomes.sinks, &self.base_dir, &self.deletions, self.dry_run);
+            *self.reconciliation.lock().unwrap() = Some(found);
+        }
+
+        for worker in 0..self.workers.max(1) {
+            let organiser = Arc::clone(&self);
+            tokio::spawn(async move {
+                debug!(worker=worker; "worker started");
+                loop {
+                    let (job, waited) = organiser.queue.pop().await;
+                    // a job taken just as processing is paused waits with the worker
+                    organiser.pauses.running().await;
+                    organiser.handle_job(job, waited).await;
+                }
+            });
+        }
+
+        // a replay only schedules deleteAfter deletions, so it finishes without deleting anything
+        if !self.replaying {
+            let organiser = Arc::clone(&self);
+            tokio::spawn(async move { organiser.deletions.run(organiser.dry_run).await });
+        }
+        if self.maintenance.is_some() {
+            let organiser = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Some(maintenance) = &organiser.maintenance {
+                    maintenance.run(&organiser.base_dir, organiser.dry_run).await
+                }
+            });
+        }
+        if let Some(deadline) = &self.deadline {
+            let organiser = Arc::clone(&self);
+            let mut interval = tokio::time::interval(deadline.interval());
+            tokio::spawn(async move {
+                loop {
+                    interval.tick().await;
+                    organiser.check_deadline().await;
+                }
+            });
+        }
+        if let Some(report) = &self.report {
+            let organiser = Arc::clone(&self);
+            let until_next = report.until_next();
+            info!(next_in=as_display!(duration::format(&Duration::from_secs(until_next.as_secs()))); "writing a daily report");
+            tokio::spawn(async move {
+                loop {
+                    let Some(report) = &organiser.report else { return };
+                    tokio::time::sleep(report.until_next()).await;
+                    organiser.send_report(report).await;
+                    // past the minute, so it isn't sent twice
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+            });
+        }
+        let organiser = Arc::clone(&self);
+        tokio::spawn(async move {
+            loop {
+                for awaiting in organiser.confirmations.expired().await {
+                    organiser.timed_out(awaiting);
+                }
+            }
+        });
+
         let mut buffer = [0; 1024];
         let mut stream = inotify.into_event_stream(&mut buffer)?;
+        let mut mount_events = self.fanotify.as_ref().map(|mount| mount.events()).transpose()?;
 
-        info!(watch_dir=self.watch_dir.to_str(); "watching directory for file events");
+        // Jobs waiting out their cooldown, keyed by path.
+        let mut pending: HashMap<PathBuf, (Instant, Job)> = HashMap::new();
 
-        while let Some(event) = stream.next().await {
-            match self.process_event(event).await {
-                Ok(_) => { /* NO OP */ },
-                Err(err) => {
-                    error!(error=err; "encountered error processing event")
+        let mut paused = false;
+        let resume_depth = self.max_queue_depth.map(|max| max / 2).unwrap_or(usize::MAX);
+        // wakes an idle loop so the watchdog can tell idle from stuck
+        let mut heartbeat = tokio::time::interval(self.watchdog.as_ref().map(|w| w.interval()).unwrap_or(Duration::MAX));
+
+        loop {
+            if let Some(watchdog) = &self.watchdog {
+                watchdog.beat();
+            }
+            let next_due = pending.values().map(|(due, _)| *due).min();
+            tokio::select! {
+                Some(request) = control.recv() => {
+                    self.handle_control(request, &mut watch_handle, &mut watches, &mut pending);
+                },
+                _ = heartbeat.tick(), if self.watchdog.is_some() => {},
+                _ = self.queue.drained_to(resume_depth), if paused => {
+                    paused = false;
+                    info!(queue_depth=self.queue.len(); "queue has drained - resuming intake");
+                },
+                event = stream.next(), if !paused => {
+                    let event = match event {
+                        Some(Ok(event)) => event,
+                        Some(Err(err)) => {
+                            error!(error=as_debug!(err); "encountered error reading filesystem events");
+                            continue
+                        },
+                        None => break,
+                    };
+                    if event.mask.contains(EventMask::Q_OVERFLOW) {
+                        warn!("inotify event queue overflowed - some files may need to be reprocessed manually");
+                        continue
+                    }
+                    if event.mask.contains(EventMask::IGNORED) {
+                        watches.remove(&event.wd);
+                        continue
+                    }
+                    let (watch_dir, subdir) = match watches.get(&event.wd) {
+                        Some((watch_dir, subdir)) => (Arc::clone(watch_dir), subdir.clone()),
+                        None => continue,
+                    };
+
+                    debug!(event_type=as_debug!(event.mask), filename=as_debug!(event.name), watch_dir=watch_dir.name; "received filesystem event");
+                    if let (Some(recorder), Some(name)) = (&self.recorder, &event.name) {
+                        recorder.record(&watch_dir.name, &watch_dir.path, &subdir.join(name), event.mask);
+                    }
+                    if let Some(name) = &event.name {
+                        let relative = subdir.join(name);
+                        let new_dir = event.mask.contains(EventMask::ISDIR) && event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO);
+                        let hidden = watch_dir.hidden_policy() == HiddenPolicy::Ignore && rules::is_hidden(&name.to_string_lossy());
+                        if new_dir && !hidden && watch_dir.watches_subdir(&relative) {
+                            match self.add_watches(&mut watch_handle, &mut watches, &watch_dir, relative.clone()) {
+                                Ok(()) => paused = self.queue_moved_dir(&watch_dir, &relative, event.mask, &mut pending),
+                                Err(err) => warn!(watch_dir=watch_dir.name, error=as_error!(*err); "unable to watch new subdirectory"),
+                            }
+                        }
+                    }
+                    let name = match event.name {
+                        Some(name) if watch_dir.is_triggered_by(event.mask) => name,
+                        _ => continue,
+                    };
+                    paused = self.on_event(watch_dir, subdir, name, event.mask, &mut pending) || paused;
+                },
+                Some(event) = async { mount_events.as_mut()?.recv().await }, if !paused && mount_events.is_some() => {
+                    debug!(event_type=as_debug!(event.mask), path=event.path.to_str(); "received fanotify event");
+                    let Some((watch_dir, subdir, name)) = self.locate(&event.path) else { continue };
+                    if let Some(recorder) = &self.recorder {
+                        recorder.record(&watch_dir.name, &watch_dir.path, &subdir.join(&name), event.mask);
+                    }
+                    paused = self.queue_moved_dir(&watch_dir, &subdir.join(&name), event.mask, &mut pending);
+                    if watch_dir.is_triggered_by(event.mask) {
+                        paused = self.on_event(watch_dir, subdir, name, event.mask, &mut pending) || paused;
+                    }
+                },
+                _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                    let now = Instant::now();
+                    let due: Vec<_> = pending.iter()
+                        .filter(|(_, (due, _))| *due <= now)
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    for key in due {
+                        if let Some((_, job)) = pending.remove(&key) {
+                            self.enqueue(job);
+                        }
+                    }
+                    paused = paused || self.is_queue_full();
                 },
             }
         }
@@ -121,139 +432,1463 @@ impl Organiser {
         Ok(())
     }
 
-    async fn process_event(&self, event: std::result::Result<Event<OsString>, std::io::Error>) -> Result<()> {
-        let event = event?;
+    /// Queues the entry an event is for, or starts its cooldown, unless it's one the watch
+    /// directory passes over. Returns whether the queue is now full.
+    fn on_event(&self, watch_dir: Arc<WatchDir>, subdir: PathBuf, name: OsString, mask: EventMask, pending: &mut HashMap<PathBuf, (Instant, Job)>) -> bool {
+        if watch_dir.hidden_policy() == HiddenPolicy::Ignore && rules::is_hidden(&name.to_string_lossy()) {
+            debug!(filename=as_debug!(name); "ignoring hidden entry");
+            return false
+        }
+        if watch_dir.is_uploading(&name.to_string_lossy()) {
+            debug!(filename=as_debug!(name); "ignoring file still being uploaded");
+            return false
+        }
+        let relative = subdir.join(&name);
+        if watch_dir.is_excluded(&relative) {
+            debug!(path=relative.to_str(); "ignoring excluded path");
+            return false
+        }
 
-        debug!(event_type=as_debug!(event.mask), filename=as_debug!(event.name); "received filesystem event");
+        if let Some(gate) = &self.gate {
+            gate.hold(&watch_dir.path.join(&relative));
+        }
+        if self.deadline.is_some() {
+            self.arrivals.arrived(&watch_dir.path.join(&relative));
+        }
+        let cooldown = watch_dir.cooldown.unwrap_or(self.cooldown);
+        let key = watch_dir.path.join(&relative);
+        let job = Job { watch_dir, subdir, name, mask, origin: None };
+        if cooldown.is_zero() {
+            self.enqueue(job);
+            return self.is_queue_full()
+        }
+        if pending.contains_key(&key) {
+            debug!(filename=as_debug!(job.name); "coalescing repeated event within cooldown");
+        }
+        pending.insert(key, (Instant::now() + cooldown, job));
+        false
+    }
 
-        if event.mask != EventMask::CLOSE_WRITE && event.mask != EventMask::MOVED_TO {
+    /// Queues the entries of a directory moved into a recursive watch directory, as download
+    /// clients do with a finished item's folder. They were written somewhere else, so there are no
+    /// events for them. A created directory is left to the events for what's written into it.
+    /// Returns whether the queue is now full.
+    fn queue_moved_dir(&self, watch_dir: &Arc<WatchDir>, relative: &Path, mask: EventMask, pending: &mut HashMap<PathBuf, (Instant, Job)>) -> bool {
+        let hidden = watch_dir.hidden_policy() == HiddenPolicy::Ignore && relative.file_name().is_some_and(|n| rules::is_hidden(&n.to_string_lossy()));
+        if !mask.contains(EventMask::MOVED_TO | EventMask::ISDIR) || hidden || !watch_dir.watches_subdir(relative) {
+            return false
+        }
+        let mut found = Vec::new();
+        if let Err(err) = scan(watch_dir, relative.to_path_buf(), &mut |job| found.push(job)) {
+            warn!(watch_dir=watch_dir.name, path=relative.to_str(), error=as_error!(*err); "unable to list directory moved into watch directory");
+        }
+        if !found.is_empty() {
+            debug!(watch_dir=watch_dir.name, path=relative.to_str(), entries=found.len(); "queueing entries of directory moved in");
+        }
+        let mut full = false;
+        for job in found.into_iter().filter(|job| job.watch_dir.is_triggered_by(job.mask)) {
+            full = self.on_event(job.watch_dir, job.subdir, job.name, job.mask, pending) || full;
+        }
+        full
+    }
+
+    /// The watch directory a fanotify event's path is in, with its subdirectory and name, if an
+    /// inotify watch would have seen it: directly in the watch directory, or in a subdirectory
+    /// that recursive watching would have watched.
+    fn locate(&self, path: &Path) -> Option<(Arc<WatchDir>, PathBuf, OsString)> {
+        let dirs = self.watch_dirs.read().unwrap();
+        let (watch_dir, relative) = dirs.iter()
+            .filter_map(|dir| path.strip_prefix(&dir.path).ok().map(|relative| (dir, relative)))
+            .max_by_key(|(dir, _)| dir.path.as_os_str().len())?;
+        let name = relative.file_name()?.to_os_string();
+        let subdir = relative.parent()?.to_path_buf();
+        let hidden = watch_dir.hidden_policy() == HiddenPolicy::Ignore && subdir.iter().any(|part| rules::is_hidden(&part.to_string_lossy()));
+        let watched = subdir.as_os_str().is_empty() || (watch_dir.watches_subdir(&subdir) && !hidden);
+        watched.then(|| (Arc::clone(watch_dir), subdir, name))
+    }
+
+    /// Watches `subdir` of a watch directory and, when watching recursively, every subdirectory
+    /// below it that isn't excluded. With fanotify, the watch directory's filesystem is marked
+    /// instead, which covers its subdirectories.
+    fn add_watches(&self, handle: &mut Watches, watches: &mut WatchMap, watch_dir: &Arc<WatchDir>, subdir: PathBuf) -> Result<()> {
+        let path = watch_dir.path.join(&subdir);
+        if let Some(mount) = &self.fanotify {
+            mount.watch(&path)?;
+            if let Some(gate) = &self.gate {
+                gate.watch(&path)?;
+            }
             return Ok(())
         }
+        let wd = handle.add(&path, watch_dir.watch_mask())?;
+        debug!(path=path.to_str(); "added watch");
+        if let Some(gate) = &self.gate {
+            gate.watch(&path)?;
+        }
+        watches.insert(wd, (Arc::clone(watch_dir), subdir.clone()));
+
+        if !watch_dir.recursive {
+            return Ok(())
+        }
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let relative = subdir.join(entry.file_name());
+            let hidden = watch_dir.hidden_policy() == HiddenPolicy::Ignore && rules::is_hidden(&entry.file_name().to_string_lossy());
+            // symlinked directories aren't followed, so a link can't pull in a tree outside the watch directory
+            if entry.file_type()?.is_dir() && !hidden && watch_dir.watches_subdir(&relative) {
+                self.add_watches(handle, watches, watch_dir, relative)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a control API request. Runs on the event loop, which owns the watches.
+    fn handle_control(&self, request: control::Request, handle: &mut Watches, watches: &mut WatchMap, pending: &mut HashMap<PathBuf, (Instant, Job)>) {
+        match request {
+            control::Request::ListWatchDirs(reply) => {
+                let dirs = self.watch_dirs.read().unwrap().iter().map(|d| WatchDir::clone(d)).collect();
+                let _ = reply.send(dirs);
+            },
+            control::Request::AddWatchDir(dir, reply) => {
+                let _ = reply.send(self.add_watch_dir(dir, handle, watches, true).map(|_| ()));
+            },
+            control::Request::RemoveWatchDir(name, reply) => {
+                let _ = reply.send(self.remove_watch_dir(&name, handle, watches, pending, true));
+            },
+            control::Request::Rescan(reply) => {
+                let _ = reply.send(self.rescan());
+            },
+            control::Request::ProcessFile(path, reply) => {
+                let _ = reply.send(self.process_file(&path));
+            },
+            control::Request::Reload(reply) => {
+                let _ = reply.send(self.reload());
+            },
+            control::Request::SetPaused(rule, paused, reply) => {
+                let _ = reply.send(self.set_paused(rule.as_deref(), paused));
+            },
+            control::Request::Paused(reply) => {
+                let _ = reply.send(PauseStatus { state: self.pauses.state(), held: self.pauses.held(), queued: self.queue.len() });
+            },
+            control::Request::Pending(reply) => {
+                let _ = reply.send(self.confirmations.list());
+            },
+            control::Request::Confirm(path, approved, reply) => {
+                let _ = reply.send(self.confirm(&path, approved));
+            },
+            control::Request::Mounted(dir, mode, reply) => {
+                let _ = reply.send(self.mounted(dir, mode, handle, watches));
+            },
+            control::Request::Unmounted(name) => {
+                // a volume whose directory couldn't be watched has nothing to remove
+                let _ = self.remove_watch_dir(&name, handle, watches, pending, false);
+            },
+            control::Request::Reconciliation(reply) => {
+                let _ = reply.send(self.reconciliation.lock().unwrap().clone());
+            },
+            control::Request::Ping(reply) => {
+                let _ = reply.send(());
+            },
+            control::Request::Replay(event) => self.replay(event, pending),
+            control::Request::Settled(reply) => {
+                let _ = reply.send(pending.is_empty() && self.queue.len() == 0 && self.in_flight.is_empty());
+            },
+        }
+    }
+
+    /// Handles a recorded event as the event loop did when the watcher read it. Nothing is
+    /// watched, as the events in new subdirectories were recorded too, but the entries of a
+    /// directory moved in are queued from the tree being replayed against.
+    fn replay(&self, event: Recorded, pending: &mut HashMap<PathBuf, (Instant, Job)>) {
+        let watch_dir = {
+            let dirs = self.watch_dirs.read().unwrap();
+            let mut named = dirs.iter().filter(|d| d.name == event.watch_dir);
+            let first = named.clone().next();
+            named.find(|d| d.path == event.root).or(first).cloned()
+        };
+        let Some(watch_dir) = watch_dir else {
+            warn!(watch_dir=event.watch_dir, path=event.path.to_str(); "recorded event is for a watch directory that isn't configured - skipping it");
+            return
+        };
+        let (Some(subdir), Some(name)) = (event.path.parent(), event.path.file_name()) else { return };
+        let mask = event.mask();
+        debug!(event_type=as_debug!(mask), path=event.path.to_str(), watch_dir=watch_dir.name; "replaying filesystem event");
+        self.queue_moved_dir(&watch_dir, &event.path, mask, pending);
+        if watch_dir.is_triggered_by(mask) {
+            self.on_event(watch_dir, subdir.to_path_buf(), name.to_os_string(), mask, pending);
+        }
+    }
+
+    /// Queues what's already in a removable volume's directory, watching it from then on unless
+    /// it's organised just once.
+    fn mounted(&self, dir: WatchDir, mode: VolumeMode, handle: &mut Watches, watches: &mut WatchMap) -> std::result::Result<usize, Rejection> {
+        let dir = match mode {
+            VolumeMode::Watch => self.add_watch_dir(dir, handle, watches, false)?,
+            VolumeMode::Once => {
+                let dir = dir.resolve(&self.base_dir, self.hidden);
+                if !dir.path.is_dir() {
+                    return Err(Rejection::bad_request(format!("[{}] is not a directory", dir.path.display())))
+                }
+                Arc::new(dir)
+            },
+        };
+        Ok(self.rescan_dirs(vec![dir]))
+    }
+
+    /// Pauses or resumes processing, of everything or of the rule with `regex`, queueing the files
+    /// held for paused rules again on a resume.
+    fn set_paused(&self, regex: Option<&str>, paused: bool) -> std::result::Result<(), Rejection> {
+        if let Some(regex) = regex {
+            if !self.rules.read().unwrap().rules().iter().any(|r| r.regex.as_str() == regex) {
+                return Err(Rejection::not_found(format!("no rule with regex [{regex}]")))
+            }
+        }
+        let held = self.pauses.set(regex, paused);
+        let requeued = held.len();
+        for job in held {
+            self.enqueue(job);
+        }
+        match paused {
+            true => info!(profile=self.profile, rule=regex; "paused processing"),
+            false => info!(profile=self.profile, rule=regex, requeued=requeued; "resumed processing"),
+        }
+        Ok(())
+    }
+
+    /// Approves or rejects a file waiting for approval. An approved file is queued again and let
+    /// through; a rejected one is left where it is.
+    fn confirm(&self, path: &Path, approved: bool) -> std::result::Result<(), Rejection> {
+        let awaiting = self.confirmations.decide(path, approved)
+            .ok_or_else(|| Rejection::not_found(format!("[{}] is not awaiting approval", path.display())))?;
+        match approved {
+            true => {
+                info!(filename=path.to_str(), rule=awaiting.rule; "file approved - processing it");
+                self.process_file(path).inspect_err(|_| { self.confirmations.take_approval(path); })
+            },
+            false => {
+                info!(filename=path.to_str(), rule=awaiting.rule; "file rejected - leaving it where it is");
+                Ok(())
+            },
+        }
+    }
+
+    /// Decides for a file that nobody approved or rejected within the timeout.
+    fn timed_out(&self, awaiting: Awaiting) {
+        let path = awaiting.path.to_str();
+        match self.confirm.as_ref().map(|c| c.on_timeout).unwrap_or_default() {
+            OnTimeout::Skip => info!(filename=path, rule=awaiting.rule; "file was not approved in time - leaving it where it is"),
+            OnTimeout::Run => {
+                info!(filename=path, rule=awaiting.rule; "file was not approved in time - processing it");
+                self.confirmations.approve(&awaiting.path);
+                if let Err(Rejection(_, message)) = self.process_file(&awaiting.path) {
+                    self.confirmations.take_approval(&awaiting.path);
+                    warn!(filename=path, error=message; "unable to process file after approval timed out");
+                }
+            },
+        }
+    }
+
+    /// Queues every entry already in the watch directories, as if each had just arrived.
+    ///
+    /// With `snapshot` set, nothing is queued until enough files have been found to know whether
+    /// a snapshot is needed, and nothing at all if taking it fails.
+    fn rescan(&self) -> usize {
+        let dirs: Vec<_> = self.watch_dirs.read().unwrap().iter().cloned().collect();
+        self.rescan_dirs(dirs)
+    }
+
+    fn rescan_dirs(&self, dirs: Vec<Arc<WatchDir>>) -> usize {
+        let mut queued = 0;
+        let mut batch = Vec::with_capacity(RESCAN_BATCH);
+        let mut held = self.snapshot.is_some();
+        let mut failed = false;
+        for watch_dir in dirs {
+            // queued as they're found, so the workers start on a large tree before it's all been read
+            let result = scan(&watch_dir, PathBuf::new(), &mut |job| {
+                batch.push(job);
+                if let Some(snapshot) = self.snapshot.as_ref().filter(|s| held && !failed && batch.len() >= s.min_files.max(1)) {
+                    failed = !self.take_snapshot(snapshot, batch.len());
+                    held = failed;
+                }
+                if !held && batch.len() >= RESCAN_BATCH {
+                    queued += self.enqueue_batch(std::mem::take(&mut batch));
+                }
+            });
+            if let Err(err) = result {
+                warn!(watch_dir=watch_dir.name, error=as_error!(*err); "unable to rescan watch directory");
+            }
+        }
+        if failed {
+            error!(found=batch.len(); "not queueing rescanned files without a snapshot");
+            return 0
+        }
+        queued += self.enqueue_batch(batch);
+        info!(queued=queued; "rescanned watch directories");
+        queued
+    }
+
+    /// Takes the snapshot for a rescan that found `found` files so far, returning whether it was
+    /// taken.
+    fn take_snapshot(&self, snapshot: &SnapshotConfig, found: usize) -> bool {
+        if self.dry_run {
+            info!(found=found; "dry run - would take a snapshot before the rescan");
+            return true
+        }
+        match snapshot.take() {
+            Ok(name) => {
+                info!(snapshot=name, found=found; "took a snapshot before queueing rescanned files");
+                true
+            },
+            Err(err) => {
+                error!(found=found, error=as_error!(*err); "unable to take a snapshot before the rescan");
+                false
+            },
+        }
+    }
+
+    /// Queues a single file from one of the watch directories.
+    fn process_file(&self, path: &Path) -> std::result::Result<(), Rejection> {
+        let watch_dir = self.watch_dirs.read().unwrap().iter()
+            .find(|d| path.starts_with(&d.path) && path != d.path)
+            .cloned()
+            .ok_or_else(|| Rejection::bad_request(format!("[{}] is not in a watch directory", path.display())))?;
+        let metadata = fs::symlink_metadata(path)
+            .map_err(|e| Rejection::not_found(format!("[{}]: {e}", path.display())))?;
+        let relative = path.strip_prefix(&watch_dir.path).unwrap_or(path);
+        let subdir = relative.parent().map(Path::to_path_buf).unwrap_or_default();
+        if !subdir.as_os_str().is_empty() && !watch_dir.watches_subdir(&subdir) {
+            return Err(Rejection::bad_request(format!("[{}] is not in a watched subdirectory", path.display())))
+        }
+
+        if let Some(gate) = &self.gate {
+            gate.hold(path);
+        }
+        let name = relative.file_name().unwrap_or_default().to_os_string();
+        self.enqueue(Job { watch_dir, subdir, name, mask: event_mask(metadata.is_dir()), origin: None });
+        Ok(())
+    }
+
+    /// Reads the config again and swaps in this profile's rules. Other settings take effect on
+    /// restart.
+    fn reload(&self) -> std::result::Result<(), Rejection> {
+        let config = Config::load(Some(&self.profile))
+            .and_then(|mut profiles| {
+                let config = profiles.remove(0).config;
+                for rule in &config.rules {
+                    rule.run_as.as_ref().map(RunAs::resolve).transpose()?;
+                }
+                Ok(config)
+            })
+            .map_err(|e| Rejection::bad_request(format!("config not reloaded: {e}")))?;
+        let count = config.rules.len();
+        *self.rules.write().unwrap() = Arc::new(RuleSet::new(config.rules));
+        info!(profile=self.profile, rules=count; "reloaded rules");
+        Ok(())
+    }
 
-        let rules = &self.rules;
-        if let Some(raw_name) = event.name {
-            let name = raw_name.to_str().unwrap().to_string();
-            let source = self.watch_dir.join(&name);
+    /// Starts watching `dir`, saving it to be watched again after a restart if `persist`.
+    fn add_watch_dir(&self, dir: WatchDir, handle: &mut Watches, watches: &mut WatchMap, persist: bool) -> std::result::Result<Arc<WatchDir>, Rejection> {
+        let dir = Arc::new(dir.resolve(&self.base_dir, self.hidden));
+        dir.validate().map_err(|e| Rejection::bad_request(e.to_string()))?;
+        if let Some(existing) = self.watch_dirs.read().unwrap().iter().find(|d| d.name == dir.name || d.path == dir.path) {
+            return Err(Rejection::conflict(format!("watch directory [{}] already watches [{}]", existing.name, existing.path.display())))
+        }
+        // a removable drive that isn't mounted shouldn't have its mount point written to
+        if !dir.path.is_dir() {
+            return Err(Rejection::bad_request(format!("[{}] is not a directory", dir.path.display())))
+        }
 
-            if !source.exists() {
+        if let Err(err) = self.add_watches(handle, watches, &dir, PathBuf::new()) {
+            self.remove_watches(handle, watches, &dir);
+            return Err(Rejection::bad_request(format!("unable to watch [{}]: {err}", dir.path.display())))
+        }
+        self.watch_dirs.write().unwrap().push(Arc::clone(&dir));
+        if persist {
+            if let Err(err) = self.runtime_dirs.lock().unwrap().add(WatchDir::clone(&dir)) {
+                warn!(error=as_error!(*err); "unable to save runtime watch directories - the change will be lost on restart");
+            }
+        }
+        info!(watch_dir=dir.path.to_str(), name=dir.name, recursive=dir.recursive; "watching directory for file events");
+        Ok(dir)
+    }
+
+    fn remove_watch_dir(&self, name: &str, handle: &mut Watches, watches: &mut WatchMap, pending: &mut HashMap<PathBuf, (Instant, Job)>, persist: bool) -> std::result::Result<(), Rejection> {
+        // a per-user watch directory is one for each user, all with its name
+        let removed: Vec<Arc<WatchDir>> = {
+            let mut dirs = self.watch_dirs.write().unwrap();
+            let (removed, kept) = dirs.drain(..).partition(|d| d.name == name);
+            *dirs = kept;
+            removed
+        };
+        if removed.is_empty() {
+            return Err(Rejection::not_found(format!("no watch directory named [{name}]")))
+        }
+
+        for dir in &removed {
+            self.remove_watches(handle, watches, dir);
+        }
+        // queued jobs still run, but files waiting out their cooldown are dropped
+        pending.retain(|_, (_, job)| job.watch_dir.name != name);
+        if persist {
+            if let Err(err) = self.runtime_dirs.lock().unwrap().remove(name) {
+                warn!(error=as_error!(*err); "unable to save runtime watch directories - the change will be lost on restart");
+            }
+        }
+        for dir in removed {
+            info!(watch_dir=dir.path.to_str(), name=dir.name; "stopped watching directory");
+        }
+        Ok(())
+    }
+
+    /// Removes every watch belonging to `watch_dir`, including those on its subdirectories.
+    fn remove_watches(&self, handle: &mut Watches, watches: &mut WatchMap, watch_dir: &Arc<WatchDir>) {
+        if let Some(mount) = &self.fanotify {
+            mount.unwatch(&watch_dir.path);
+            if let Some(gate) = &self.gate {
+                gate.unwatch(&watch_dir.path);
+            }
+        }
+        let owned: Vec<_> = watches.iter()
+            .filter(|(_, (dir, _))| Arc::ptr_eq(dir, watch_dir))
+            .map(|(wd, (_, subdir))| (wd.clone(), subdir.clone()))
+            .collect();
+        for (wd, subdir) in owned {
+            watches.remove(&wd);
+            // fails harmlessly if the directory has already gone, e.g. an unmounted drive
+            let _ = handle.remove(wd);
+            if let Some(gate) = &self.gate {
+                gate.unwatch(&watch_dir.path.join(subdir));
+            }
+        }
+    }
+
+    /// Whether intake should pause, logging when it does. While paused, inotify events are left
+    /// in the kernel's buffer until the workers catch up.
+    fn is_queue_full(&self) -> bool {
+        let depth = self.queue.len();
+        match self.max_queue_depth {
+            Some(max) if depth >= max => {
+                warn!(queue_depth=depth, max_queue_depth=max; "queue is full - pausing intake until it drains");
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Queues a file for the workers, capturing what's needed to order it against other files.
+    fn enqueue(&self, job: Job) {
+        let metadata = match self.queue.order().uses_metadata() {
+            true => fs::symlink_metadata(job.watch_dir.path.join(&job.subdir).join(&job.name)).ok(),
+            false => None,
+        };
+        let rules = Arc::clone(&self.rules.read().unwrap());
+        self.enqueue_with(job, metadata, &rules);
+    }
+
+    /// Queues files found by a rescan. When the queue is ordered by size or age, they're stat'ed
+    /// in parallel first, which matters on network filesystems where each stat is a round trip.
+    fn enqueue_batch(&self, jobs: Vec<Job>) -> usize {
+        let count = jobs.len();
+        let metadata = match self.queue.order().uses_metadata() {
+            true => stat_all(&jobs),
+            false => vec![None; count],
+        };
+        let rules = Arc::clone(&self.rules.read().unwrap());
+        for (job, metadata) in jobs.into_iter().zip(metadata) {
+            if let Some(gate) = &self.gate {
+                gate.hold(&job.watch_dir.path.join(&job.subdir).join(&job.name));
+            }
+            self.enqueue_with(job, metadata, &rules);
+        }
+        count
+    }
+
+    fn enqueue_with(&self, job: Job, metadata: Option<fs::Metadata>, rules: &RuleSet) {
+        let watch_dir = &job.watch_dir;
+        let name = job.name.to_string_lossy().to_string();
+        let is_dir = job.mask.contains(EventMask::ISDIR);
+        let priority = rules.matching(&name)
+            .find(|r| r.applies_to(&watch_dir.name) && r.kind_matches(is_dir))
+            .map(|r| r.priority)
+            .unwrap_or(0);
+        let key = SortKey {
+            size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+            mtime: metadata.and_then(|m| m.modified().ok()).unwrap_or_else(SystemTime::now),
+            priority,
+        };
+
+        if self.deadline.is_some() {
+            self.arrivals.arrived(&watch_dir.path.join(&job.subdir).join(&job.name));
+        }
+        self.queue.push(job, key);
+        debug!(filename=name, queue_depth=self.queue.len(); "queued file for processing");
+    }
+
+    /// Writes or sends the report on everything since the last one.
+    async fn send_report(&self, report: &ReportConfig) {
+        let period = self.tally.take();
+        let file = match report.write(&self.base_dir, &period) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!(profile=self.profile, error=as_error!(*err); "unable to write daily report");
+                None
+            },
+        };
+        info!(profile=self.profile, file=file.as_ref().and_then(|f| f.to_str()), summary=period.headline(); "daily report");
+        if report.notify {
+            self.notifier.report(file.as_deref(), period.headline(), period.render(report::ReportFormat::Markdown), report.notify_via.as_deref()).await;
+        }
+    }
+
+    /// Reports the files that have gone past the deadline since the last check.
+    async fn check_deadline(&self) {
+        let Some(deadline) = &self.deadline else { return };
+        let (newly, count) = self.arrivals.overdue(deadline.after);
+        metrics::overdue(&self.profile, newly.len(), count);
+        for (path, waited) in newly {
+            warn!(profile=self.profile, path=path.to_str(), waited_secs=waited.as_secs(), deadline=as_display!(duration::format(&deadline.after));
+                "file hasn't been organised within the deadline - no rule may match it, or the queue may be stuck");
+            if deadline.notify {
+                self.notifier.overdue(&path, waited, deadline.notify_via.as_deref()).await;
+            }
+        }
+    }
+
+    /// Queues the files an action produced from `source` to go through the rules as if they had
+    /// arrived, unless they're already too many archives deep.
+    fn enqueue_derived(&self, watch_dir: &Arc<WatchDir>, source: &Path, parent: Option<&Origin>, outcome: &ProcessingOutcome) {
+        let depth = parent.map(|o| o.depth).unwrap_or(0) + 1;
+        if depth > MAX_DERIVED_DEPTH {
+            warn!(filename=source.to_str(), depth=depth, max_depth=MAX_DERIVED_DEPTH; "archives are nested too deeply - not processing extracted files");
+            return
+        }
+        let origin = Arc::new(Origin { archive: source.to_path_buf(), rule: outcome.rule.clone().unwrap_or_default(), depth });
+        for path in &outcome.derived {
+            let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else { continue };
+            // joining an absolute subdir onto the watch directory gives the subdir itself
+            self.enqueue(Job { watch_dir: Arc::clone(watch_dir), subdir: dir.to_path_buf(), name: name.to_os_string(), mask: event_mask(false), origin: Some(Arc::clone(&origin)) });
+        }
+        info!(filename=source.to_str(), files=outcome.derived.len(), depth=depth; "queued extracted files for processing");
+    }
+
+    async fn handle_job(&self, job: Job, waited: Duration) {
+        let name = job.name.to_string_lossy().to_string();
+        let path = job.watch_dir.path.join(&job.subdir).join(&job.name);
+        let job = match self.in_flight.claim(&path, job) {
+            Some(job) => job,
+            None => {
+                debug!(filename=name; "file is already being processed - checking it again once that's done");
+                return
+            },
+        };
+        let busy = self.watchdog.as_ref().map(|w| w.begin(&path));
+        let cancelled = async {
+            match &busy {
+                Some(busy) => busy.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        let mut outcome = ProcessingOutcome::begin(&self.profile, &path, &job.watch_dir.name, job.origin.as_deref(), waited);
+        let result = tokio::select! {
+            result = self.process_job(&job, busy.as_ref(), &mut outcome) => result,
+            _ = cancelled => Err("abandoned by the watchdog for taking too long".into()),
+        };
+        drop(busy);
+        if let Err(err) = result {
+            error!(filename=name, error=as_error!(*err); "encountered error processing event");
+            outcome.failed(err.to_string());
+        }
+        if !outcome.derived.is_empty() {
+            self.enqueue_derived(&job.watch_dir, &path, job.origin.as_deref(), &outcome);
+        }
+        match outcome.result {
+            Status::Processed => self.notifier.notify(NotifyEvent::Success, &outcome).await,
+            Status::Failed => self.notifier.notify(NotifyEvent::Failure, &outcome).await,
+            _ => {},
+        }
+        if outcome.result == Status::Paused {
+            self.pauses.hold(job);
+        }
+        if matches!(outcome.result, Status::Processed | Status::Skipped | Status::DryRun) {
+            self.arrivals.done(&path);
+        }
+        if self.report.is_some() {
+            self.tally.record(&outcome);
+        }
+        self.outcomes.publish(outcome).await;
+        if let Some(gate) = &self.gate {
+            gate.release(&path);
+        }
+        // an event that came in meanwhile only matters if the file is still there, e.g. rewritten
+        if let Some(next) = self.in_flight.release(&path) {
+            if fs::symlink_metadata(&path).is_ok() {
+                self.enqueue(next);
+            }
+        }
+        if let Some(index) = &self.duplicate_index {
+            if let Err(err) = index.lock().unwrap().save_if_due() {
+                warn!(error=as_error!(*err); "unable to save duplicate index");
+            }
+        }
+    }
+
+    async fn process_job(&self, job: &Job, busy: Option<&Busy>, outcome: &mut ProcessingOutcome) -> Result<()> {
+        let watch_dir = &job.watch_dir;
+        let name = match job.name.to_str() {
+            Some(name) => name.to_string(),
+            None => {
+                warn!(filename=job.name.to_string_lossy().as_ref(); "file name is not valid UTF-8 - skipping");
+                outcome.skipped("file name is not valid UTF-8");
+                return Ok(())
+            },
+        };
+        let path = watch_dir.path.join(&job.subdir).join(&name);
+
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
                 warn!(filename=name; "file does not exist - assuming processed by previous event, or checking if file is writable");
+                outcome.skipped("file no longer exists");
                 return Ok(())
-            }
+            },
+        };
+
+        let is_link = metadata.file_type().is_symlink();
+        let (source, is_dir) = match (is_link, self.symlinks) {
+            (false, _) => (path.clone(), job.mask.contains(EventMask::ISDIR)),
+            (true, SymlinkPolicy::Ignore) => {
+                info!(filename=name; "ignoring symlink");
+                outcome.skipped("symlinks are ignored");
+                return Ok(())
+            },
+            (true, SymlinkPolicy::Follow) => match fs::canonicalize(&path) {
+                Ok(target) => {
+                    let is_dir = target.is_dir();
+                    (target, is_dir)
+                },
+                Err(err) => {
+                    warn!(filename=name, error=as_debug!(err); "symlink target does not exist - skipping");
+                    outcome.skipped("symlink target does not exist");
+                    return Ok(())
+                },
+            },
+            (true, SymlinkPolicy::MoveLink) => (path.clone(), false),
+        };
+
+        // checked before anything opens the file, as reading a FIFO blocks until something writes to it
+        let special = match source == path {
+            true => fsutil::special_kind(metadata.file_type()),
+            false => fs::metadata(&source).ok().and_then(|m| fsutil::special_kind(m.file_type())),
+        };
+        if let Some(kind) = special {
+            return self.special_file(&name, &path, kind, outcome)
+        }
 
-            for rule in rules.iter() {
-                if rule.regex.is_match(&name) {
-                    debug!(regex=rule.regex.as_str(), filename=name; "rule matched regex for file");
-                    if let Some(min_size) = &rule.min_size {
-                        let file = std::fs::metadata(self.watch_dir.join(&name))?;
-                        if !self.size_matcher.is_gteq(file.len(), &min_size)? {
-                            info!(filename=name; "file is less than the minimum size for this rule - skipping rule");
-                            continue;
+        if let (Some(index), false, false) = (&self.duplicate_index, is_dir, is_link) {
+            let mut index = index.lock().unwrap();
+            if let Some(existing) = index.find_duplicate(&source)? {
+                match index.on_duplicate {
+                    OnDuplicate::Skip => {
+                        info!(filename=name, existing=existing.to_str(); "file already exists in the organised tree - skipping");
+                        outcome.skipped(&format!("duplicate of [{}]", existing.display()));
+                        return Ok(())
+                    },
+                    OnDuplicate::Report => {
+                        warn!(filename=name, existing=existing.to_str(); "file already exists in the organised tree");
+                    },
+                    OnDuplicate::Hardlink => {
+                        if self.dry_run {
+                            info!(filename=name, existing=existing.to_str(); "dry run - would replace duplicate with a hardlink");
+                        } else if !fsutil::same_inode(&source, &existing)? {
+                            let link = source.with_file_name(format!(".{name}.download-organiser-link"));
+                            fs::hard_link(&existing, &link)?;
+                            fs::rename(&link, &source)?;
+                            info!(filename=name, existing=existing.to_str(); "file already exists in the organised tree - replaced with a hardlink");
                         }
-                    }
-                    for action in &rule.actions {
-                        info!(action=as_debug!(action); "performing action");
-                        match action {
-                            Action::Move { dest, duplicate } => {
-                                let dest = self.base_dir.join(dest).join(&name);
-                                if dest.exists() {
-                                    match duplicate {
-                                        DuplicateAction::Skip => return Ok(()),
-                                        DuplicateAction::Overwrite => std::fs::rename(&source, dest)?,
-                                        DuplicateAction::RenameDate => {
-                                            let date = Local::now().format("%Y-%m-%dT%H_%M_%S").to_string();
-                                            let name = format!("{date}__{name}");
-                                            std::fs::rename(&source, dest.parent().unwrap().join(name))?;
-                                        },
-                                    }
-                                } else {
-                                    std::fs::rename(&source, dest)?;
-                                }
-                            },
-                            Action::Unzip { dest } => {
-                                let dest = self.base_dir.join(&dest);
-                                let fname = source.clone();
-                                let file = fs::File::open(fname)?;
-
-                                let mut archive = zip::ZipArchive::new(file)?;
-
-                                
-                                for i in 0..archive.len() {
-                                    let mut file = archive.by_index(i)?;
-                                    let outpath = match file.enclosed_name() {
-                                        Some(path) => dest.join(path),
-                                        None => continue,
-                                    };
-
-                                    {
-                                        let comment = file.comment();
-                                        if !comment.is_empty() {
-                                            info!(file_index=i, comment=comment; "File comment");
-                                        }
-                                    }
-
-                                    if (file.name()).ends_with('/') {
-                                        info!(file_index=i, destination=outpath.to_str(); "File extracted");
-                                        fs::create_dir_all(&outpath)?;
-                                    } else {
-                                        info!(
-                                            file_index=i,
-                                            destination=outpath.to_str(),
-                                            file_size=file.size();
-                                            "File extracted",
-                                        );
-                                        if let Some(p) = outpath.parent() {
-                                            if !p.exists() {
-                                                fs::create_dir_all(p)?;
-                                            }
-                                        }
-                                        let mut outfile = fs::File::create(&outpath)?;
-                                        io::copy(&mut file, &mut outfile)?;
-                                    }
-
-                                    // Get and Set permissions
-                                    #[cfg(unix)]
-                                    {
-                                        use std::os::unix::fs::PermissionsExt;
-
-                                        if let Some(mode) = file.unix_mode() {
-                                            fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
-                                        }
-                                    }
-                                }
-                            },
-                            Action::Delete => {
-                                std::fs::remove_file(&source)?;
-                            },
-                        };
-                        debug!(filename=name; "all actions for file processed successfully");
+                    },
+                }
+            }
+        }
+
+        let fingerprint = match &self.ledger {
+            Some(ledger) => {
+                let hash = ledger.lock().unwrap().hash;
+                let fingerprint = Fingerprint::of(&source, hash)?;
+                if ledger.lock().unwrap().is_processed(&path, &fingerprint) {
+                    if !self.force {
+                        info!(filename=name; "file has already been processed - skipping");
+                        outcome.skipped("already processed");
                         return Ok(())
                     }
-                } else {
-                    debug!(regex=rule.regex.as_str(), filename=name; "rule regex did not match file");
+                    info!(filename=name; "file has already been processed - processing again as --force is set");
                 }
+                Some(fingerprint)
+            },
+            None => None,
+        };
+
+        let result = self.apply_rules(watch_dir, &name, &source, is_dir, busy, outcome).await;
+
+        // a file given up on counts as processed too, so it isn't retried on every event
+        let handled = matches!(outcome.result, Status::Processed | Status::Failed);
+        if let (Some(ledger), Some(fingerprint), Ok(()), true) = (&self.ledger, fingerprint, &result, handled) {
+            if let Err(err) = ledger.lock().unwrap().record(&path, fingerprint) {
+                warn!(filename=name, error=as_error!(*err); "unable to record file in ledger");
             }
         }
+
+        if is_link && source != path && !path.exists() && !self.dry_run {
+            debug!(filename=name; "removing symlink whose target was moved or deleted");
+            fs::remove_file(&path)?;
+        }
+
+        result
+    }
+
+    /// Deals with a FIFO, socket or device node at `path` (or a followed symlink to one) according
+    /// to `specialFiles`. No rule is tried, as none of the actions can handle one.
+    fn special_file(&self, name: &str, path: &Path, kind: &str, outcome: &mut ProcessingOutcome) -> Result<()> {
+        match self.special_files {
+            SpecialFilePolicy::Ignore => {
+                info!(filename=name, kind=kind; "ignoring special file");
+                outcome.skipped(&format!("{kind}s are ignored"));
+            },
+            SpecialFilePolicy::Delete if self.dry_run => {
+                info!(filename=name, kind=kind; "dry run - would delete special file");
+                outcome.skipped(&format!("{kind} would be deleted"));
+            },
+            SpecialFilePolicy::Delete => {
+                fs::remove_file(path).map_err(|e| format!("unable to delete {kind} [{}]: {e}", path.display()))?;
+                info!(filename=name, kind=kind; "deleted special file");
+                outcome.skipped(&format!("{kind} deleted"));
+            },
+            SpecialFilePolicy::Report => {
+                warn!(filename=name, kind=kind; "special file in watch directory");
+                outcome.failed(format!("[{}] is a {kind}, not a regular file", path.display()));
+            },
+        }
         Ok(())
     }
+
+    /// Runs the actions of the first rule matching `name`, telling `busy` about each and recording
+    /// them in `outcome`.
+    async fn apply_rules(&self, watch_dir: &WatchDir, name: &str, source: &Path, is_dir: bool, busy: Option<&Busy>, outcome: &mut ProcessingOutcome) -> Result<()> {
+        let candidate = Candidate {
+            name,
+            source,
+            is_dir,
+            watch_dir: Some(&watch_dir.name),
+            size: None,
+            hidden: watch_dir.hidden_policy(),
+            base_dir: Some(&self.base_dir),
+            read_content: true,
+            from_archive: outcome.origin.is_some(),
+        };
+        let rules = Arc::clone(&self.rules.read().unwrap());
+        let matching = Instant::now();
+        let matched = rules.first_match(&candidate);
+        outcome.matching = matching.elapsed();
+        let rule = match matched? {
+            Some(rule) => rule,
+            None => {
+                outcome.result = Status::NoMatch;
+                return Ok(())
+            },
+        };
+        outcome.rule = Some(rule.regex.as_str().to_string());
+        if self.pauses.is_rule_paused(rule.regex.as_str()) {
+            info!(filename=name, rule=rule.regex.as_str(); "rule is paused - holding file until it's resumed");
+            outcome.result = Status::Paused;
+            return Ok(())
+        }
+        if let Some(confirm) = &self.confirm {
+            let holds = rule.needs_confirmation() && !(self.dry_run || rule.dry_run);
+            if holds && !self.confirmations.take_approval(&outcome.file) {
+                info!(filename=name, rule=rule.regex.as_str(); "rule deletes or overwrites - holding file until it's approved");
+                self.confirmations.hold(&outcome.file, rule.regex.as_str(), confirm.timeout);
+                outcome.result = Status::Pending;
+                return Ok(())
+            }
+        }
+        outcome.notify = Subscription::of(rule);
+        self.notifier.notify(NotifyEvent::Match, outcome).await;
+
+        if self.dry_run || rule.dry_run {
+            for action in &rule.actions {
+                let planned = plan::plan_action(&self.base_dir, rule, action, name, source);
+                info!(filename=name, rule=rule.regex.as_str(), planned=as_display!(planned); "dry run - action not performed");
+                outcome.planned(action.name(), planned.to_string());
+                if planned.stops() {
+                    break
+                }
+            }
+            outcome.result = Status::DryRun;
+            return Ok(())
+        }
+        // where the file is now, which changes as it's moved
+        let mut current = (name.to_string(), source.to_path_buf());
+        for action in &rule.actions {
+            info!(filename=name, action=as_debug!(action); "performing action");
+            if let Some(busy) = busy {
+                busy.action(action.name());
+            }
+            let policy = rule.retry.resolve(&self.retry, action.class());
+            let started = Instant::now();
+            let mut attempt = 1;
+            let flow = loop {
+                match self.perform_action(rule, action, &current.0, &current.1).await {
+                    Ok(flow) => break flow,
+                    Err(err) if policy.should_retry(attempt, err.as_ref()) => {
+                        let delay = policy.delay(attempt);
+                        warn!(filename=name, attempt=attempt, delay_ms=delay.as_millis() as u64, error=as_error!(*err); "action failed - retrying");
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    },
+                    Err(err) => {
+                        outcome.action(action.name(), attempt, started, Some(err.to_string()));
+                        return Err(err)
+                    },
+                }
+            };
+            outcome.action(action.name(), attempt, started, None);
+            match flow {
+                Flow::Continue => {},
+                Flow::Moved(path) => {
+                    outcome.dest = Some(path.clone());
+                    current = (path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(), path);
+                },
+                Flow::Linked(path) => outcome.dest = Some(path),
+                Flow::Stop => break,
+                Flow::Derived(paths) => outcome.derived.extend(paths),
+                Flow::Failed(reason) => {
+                    outcome.failed(reason);
+                    return Ok(())
+                },
+            }
+        }
+        debug!(filename=name; "all actions for file processed successfully");
+        Ok(())
+    }
+
+    /// Performs a single action against `source`, returning whether the rule's remaining actions
+    /// should still run.
+    async fn perform_action(&self, rule: &Rule, action: &Action, name: &str, source: &Path) -> Result<Flow> {
+        let run_as = rule.run_as.as_ref().map(RunAs::resolve).transpose()?;
+        // local actions don't await, so the identity stays on this thread until they finish
+        let _identity = match (action.class(), run_as) {
+            (ActionClass::Local, Some(credentials)) => Some(FsIdentity::assume(credentials)?),
+            _ => None,
+        };
+        match action {
+            Action::Move { dest, duplicate, compare, rename } => {
+                let target = plan::move_target(&self.base_dir, dest, rename.as_deref(), &*duplicate::strategy(duplicate, *compare), name, source, rule.normalise_names);
+                self.check_dest(rule, target.path())?;
+                // each date a template expands to gets its own directory
+                let flow = self.move_to(target, source, template::has_tokens(dest), rule.modes())?;
+                if let Flow::Moved(path) = &flow {
+                    self.relabel(rule, std::slice::from_ref(path));
+                }
+                return Ok(flow)
+            },
+            Action::LinkIntoLibrary { dest, duplicate, compare, rename } => {
+                let target = plan::link_target(&self.base_dir, dest, rename.as_deref(), &*duplicate::strategy(duplicate, *compare), name, source, rule.normalise_names);
+                self.check_dest(rule, target.path())?;
+                return self.link_to(target, source, rule.modes())
+            },
+            Action::Episode { dest, provider, api_key, duplicate, compare } => {
+                let episode = episodes::parse(name).ok_or_else(|| format!("[{name}] has no season and episode in its name"))?;
+                let named = episodes::lookup(&self.episode_cache, *provider, api_key, &episode).await?;
+                let extension = source.extension().map(|e| e.to_string_lossy());
+                let dest = template::dest(&self.base_dir, dest, source, rule.normalise_names).join(named.path(extension.as_deref()));
+                let dest = normalise::path(&self.base_dir, &dest, rule.normalise_names);
+                self.check_dest(rule, &dest)?;
+                // the lookup awaits, so the identity is only taken on for the move
+                let _identity = match run_as {
+                    Some(credentials) => Some(FsIdentity::assume(credentials)?),
+                    None => None,
+                };
+                let flow = self.move_to(plan::resolve_duplicate(dest, &*duplicate::strategy(duplicate, *compare), source, rule.normalise_names), source, true, rule.modes())?;
+                if let Flow::Moved(path) = &flow {
+                    self.relabel(rule, std::slice::from_ref(path));
+                }
+                return Ok(flow)
+            },
+            Action::Photo(photo) => {
+                let targets = photo.targets(&self.base_dir, source, &rule.regex, rule.normalise_names);
+                for (_, target) in &targets {
+                    self.check_dest(rule, target.path())?;
+                }
+                if let Some((_, MoveTarget::Skip(existing))) = targets.first() {
+                    info!(filename=name, existing=existing.to_str(); "shot is already at its destination - skipping it");
+                    return Ok(Flow::Stop)
+                }
+                let mut moved = Vec::new();
+                for (path, target) in targets {
+                    if let Flow::Moved(to) = self.move_to(target, &path, true, rule.modes())? {
+                        moved.push(to);
+                    }
+                }
+                self.relabel(rule, &moved);
+                debug!(filename=name, files=moved.len(); "moved photo with the rest of its shot");
+                return Ok(moved.into_iter().next().map(Flow::Moved).unwrap_or(Flow::Stop))
+            },
+            Action::Recompress(config) => {
+                let output = config.output(source);
+                if output != source && output.exists() {
+                    return Err(format!("[{}] already exists", output.display()).into())
+                }
+                let dir = source.parent().ok_or("file has no parent directory")?;
+                // written beside where it'll go, so the rename into place is atomic
+                let scratch = self.work_dirs.scratch(dir)?;
+                let written = recompress::recompress(source, scratch.path(), config)?;
+                let (before, after) = (fs::metadata(source)?.len(), fs::metadata(&written)?.len());
+                if after >= before {
+                    info!(filename=name, size=before, recompressed=after; "re-compressing doesn't make the archive smaller - keeping it as it is");
+                    return Ok(Flow::Continue)
+                }
+                fsutil::rename(&written, &output)?;
+                if output != source {
+                    fs::remove_file(source)?;
+                }
+                info!(filename=name, size=before, recompressed=after, output=output.to_str(); "re-compressed archive");
+                self.record_in_index(&output);
+                return Ok(Flow::Moved(output))
+            },
+            Action::LinkFarm(farm) => {
+                let mut linked = Vec::new();
+                for target in farm.targets(&self.base_dir, name, source, rule.normalise_names) {
+                    self.check_dest(rule, target.path())?;
+                    if let MoveTarget::Move(link) = target {
+                        farm.link(source, &link, rule.modes())?;
+                        linked.push(link);
+                    }
+                }
+                info!(filename=name, links=linked.len(); "symlinked into link farm");
+            },
+            Action::Split { chunk_size, dest } => {
+                if !source.is_file() {
+                    return Err(format!("[{name}] is not a file - only files can be split").into())
+                }
+                let dest = template::dest(&self.base_dir, dest, source, rule.normalise_names);
+                let whole = plan::existing_path(dest.join(name), rule.normalise_names);
+                let chunks = split::chunk_count(fs::metadata(source)?.len(), *chunk_size);
+                let placed = if chunks == 1 { whole.clone() } else { split::manifest_path(&whole) };
+                self.check_dest(rule, &placed)?;
+                if placed.exists() {
+                    return Err(format!("[{}] already exists", placed.display()).into())
+                }
+                if chunks == 1 {
+                    let flow = self.move_to(MoveTarget::Move(whole), source, true, rule.modes())?;
+                    if let Flow::Moved(path) = &flow {
+                        self.relabel(rule, std::slice::from_ref(path));
+                    }
+                    return Ok(flow)
+                }
+                // split and checked out of sight, so a failure leaves no partial set of chunks in dest
+                let scratch = self.work_dirs.scratch(&dest)?;
+                let chunk_name = whole.file_name().unwrap_or_default().to_string_lossy();
+                let manifest = split::split(source, scratch.path(), &chunk_name, *chunk_size)?;
+                if let Some(problem) = split::check(&manifest)? {
+                    return Err(format!("chunks of [{name}] don't join back into it: {problem}").into())
+                }
+                let published = scratch.publish(&dest, rule.normalise_names, rule.modes())?;
+                self.relabel(rule, &published);
+                fs::remove_file(source)?;
+                info!(filename=name, chunks=chunks, manifest=placed.to_str(); "split file into chunks");
+                return Ok(Flow::Moved(placed))
+            },
+            Action::Unzip { dest, verify, cleanup, reprocess, symlinks } => {
+                if *verify {
+                    if let Some(reason) = extract::verify(source)? {
+                        return self.fail(name, source, &reason).await
+                    }
+                    debug!(filename=name; "archive passed integrity check");
+                }
+                let dest = template::dest(&self.base_dir, dest, source, rule.normalise_names);
+                self.check_dest(rule, &dest)?;
+                // extracted and cleaned up out of sight, so a failure leaves nothing half-written in dest
+                let scratch = self.work_dirs.scratch(&dest)?;
+                let extracted = extract::unzip(source, scratch.path(), *symlinks)?;
+                if let Some(cleanup) = cleanup {
+                    extract::clean(scratch.path(), extracted, cleanup)?;
+                }
+                let published = scratch.publish(&dest, rule.normalise_names, rule.modes())?;
+                self.relabel(rule, &published);
+                for extracted in &published {
+                    self.record_in_index(extracted);
+                }
+                if *reprocess {
+                    return Ok(Flow::Derived(published))
+                }
+            },
+            Action::Torrent(config) => {
+                let category = config.category(&rule.regex, name);
+                torrent::submit(config, source, category.as_deref(), &self.base_dir).await?;
+                info!(filename=name, client=config.client.name(), category=category; "torrent submitted");
+            },
+            Action::Verify(config) => {
+                if let Some(reason) = verify::check(source, config)? {
+                    return self.fail(name, source, &reason).await
+                }
+                debug!(filename=name; "file passed verification");
+            },
+            Action::Delete => {
+                if source.is_dir() {
+                    fs::remove_dir_all(source)?;
+                } else {
+                    fs::remove_file(source)?;
+                }
+            },
+            Action::DeleteAfter { duration } => {
+                self.deletions.schedule(source, *duration)?;
+                info!(filename=name, after=duration::format(duration); "file will be deleted once the delay has passed");
+            },
+            Action::Webhook { url, method, headers } => {
+                let payload = webhook::json_object(&[
+                    ("file", name),
+                    ("source", &source.to_string_lossy()),
+                    ("rule", rule.regex.as_str()),
+                ]);
+                let status = webhook::send(url, method, headers, &payload).await?;
+                debug!(filename=name, status=status; "webhook delivered");
+            },
+            Action::Subtitles { languages, api_key, username, password } => {
+                let account = match (username, password) {
+                    (Some(username), Some(password)) => Some(subtitles::Account { username, password }),
+                    _ => None,
+                };
+                let written = subtitles::fetch(source, languages, api_key, account).await?;
+                debug!(filename=name, subtitles=written.len(); "subtitle download finished");
+            },
+            Action::Exec { command, dests, sandbox } => {
+                let dests: Vec<PathBuf> = dests.iter().map(|d| self.base_dir.join(d)).collect();
+                for dest in &dests {
+                    self.check_dest(rule, dest)?;
+                }
+                let (program, args) = command.split_first().ok_or("exec action has an empty command")?;
+                let args = args.iter().map(|arg| arg
+                    .replace("{path}", &source.to_string_lossy())
+                    .replace("{name}", name)
+                    .replace("{dest}", &dests.first().map(|d| d.to_string_lossy()).unwrap_or_default()));
+
+                let mut child = std::process::Command::new(program);
+                child.args(args);
+                if let Some(credentials) = run_as {
+                    use std::os::unix::process::CommandExt;
+                    child.uid(credentials.uid).gid(credentials.gid);
+                }
+                sandbox::confine(&mut child, sandbox, Path::new(program), source, &dests)?;
+                let status = child.status().map_err(|e| format!("unable to run [{program}]: {e}"))?;
+                if !status.success() {
+                    return Err(format!("command [{program}] failed with {status}").into())
+                }
+                debug!(filename=name, program=program; "command completed");
+            },
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    /// Refuses a destination outside `baseDir`, which a template can expand to even when the
+    /// configured `dest` is inside it, unless the rule allows it.
+    fn check_dest(&self, rule: &Rule, dest: &Path) -> Result<()> {
+        if !rule.allow_outside_base && !fsutil::is_within(&self.base_dir, dest) {
+            return Err(format!("destination [{}] is outside baseDir - set allowOutsideBase on the rule to allow it", dest.display()).into())
+        }
+        Ok(())
+    }
+
+    /// Gives up on a file that can't be processed, moving it to `failedDir` (with the reason in a
+    /// `.reason.txt` file beside it) if one is configured.
+    async fn fail(&self, name: &str, source: &Path, reason: &str) -> Result<Flow> {
+        let failed_dir = match &self.failed_dir {
+            Some(dir) => self.base_dir.join(dir),
+            None => return Err(format!("{name}: {reason}").into()),
+        };
+        fs::create_dir_all(&failed_dir)?;
+        let mut dest = failed_dir.join(name);
+        if dest.exists() {
+            dest = failed_dir.join(fsutil::date_prefixed(name));
+        }
+        fsutil::rename(source, &dest)?;
+        let mut reason_file = dest.clone().into_os_string();
+        reason_file.push(".reason.txt");
+        fs::write(&reason_file, format!("{reason}\n"))?;
+        error!(filename=name, reason=reason, moved_to=dest.to_str(); "file failed - moved to failed directory");
+        Ok(Flow::Failed(reason.to_string()))
+    }
+
+    /// Moves `source` to where a move action decided, creating the directory it goes into when
+    /// `create_parent` is set. `modes` are given to `source` before it moves, so a merge leaves
+    /// what was already in the destination alone.
+    fn move_to(&self, target: MoveTarget, source: &Path, create_parent: bool, modes: Modes) -> Result<Flow> {
+        if !matches!(target, MoveTarget::Skip(_) | MoveTarget::Already(_)) {
+            modes.apply(source)?;
+        }
+        let target = match target {
+            MoveTarget::Skip(_) | MoveTarget::Already(_) => return Ok(Flow::Stop),
+            MoveTarget::Move(target) => {
+                if let (true, Some(parent)) = (create_parent, target.parent()) {
+                    modes.create_dir_all(parent)?;
+                }
+                fsutil::rename(source, &target)?;
+                target
+            },
+            MoveTarget::Replace(target) => {
+                if target.is_dir() && source.is_dir() {
+                    fs::remove_dir_all(&target)?;
+                }
+                fsutil::rename(source, &target)?;
+                target
+            },
+            MoveTarget::Merge(target) => {
+                fsutil::merge_dirs(source, &target)?;
+                target
+            },
+        };
+        self.record_in_index(&target);
+        Ok(Flow::Moved(target))
+    }
+
+    /// Hard links `source` at `target`, leaving it where it is. Linked files share the original's
+    /// mode, so only the directories created get `modes`.
+    fn link_to(&self, target: MoveTarget, source: &Path, modes: Modes) -> Result<Flow> {
+        let target = match target {
+            MoveTarget::Skip(_) => return Ok(Flow::Stop),
+            MoveTarget::Already(target) => {
+                debug!(source=source.to_str(), dest=target.to_str(); "already linked into the library");
+                return Ok(Flow::Linked(target))
+            },
+            MoveTarget::Move(target) => {
+                if let Some(parent) = target.parent() {
+                    modes.create_dir_all(parent)?;
+                }
+                target
+            },
+            MoveTarget::Replace(target) => {
+                if target.is_dir() {
+                    fs::remove_dir_all(&target)?;
+                } else {
+                    fs::remove_file(&target)?;
+                }
+                target
+            },
+            MoveTarget::Merge(target) => target,
+        };
+        fsutil::link(source, &target, modes)?;
+        info!(source=source.to_str(), dest=target.to_str(); "linked into the library");
+        self.record_in_index(&target);
+        Ok(Flow::Linked(target))
+    }
+
+    /// Gives `paths` the SELinux label of their destination, if the rule asks for it. They're
+    /// already in place by then, so a failure is only logged.
+    fn relabel(&self, rule: &Rule, paths: &[PathBuf]) {
+        if let Some(how) = rule.relabel {
+            if let Err(err) = selinux::relabel(paths, how) {
+                warn!(rule=rule.regex.as_str(), error=as_error!(*err); "unable to relabel files - they keep the SELinux label they had");
+            }
+        }
+    }
+
+    fn record_in_index(&self, path: &Path) {
+        if let Some(index) = &self.duplicate_index {
+            if let Err(err) = index.lock().unwrap().record(path) {
+                warn!(path=path.to_str(), error=as_error!(*err); "unable to add file to duplicate index");
+            }
+        }
+    }
+}
+
+/// Mask for a job that didn't come from an inotify event, as though the entry had been moved in.
+fn event_mask(is_dir: bool) -> EventMask {
+    if is_dir { EventMask::MOVED_TO | EventMask::ISDIR } else { EventMask::MOVED_TO }
+}
+
+/// Collects jobs for the entries in `subdir` of a watch directory, descending into the
+/// subdirectories that would be watched.
+fn scan(watch_dir: &Arc<WatchDir>, subdir: PathBuf, found: &mut impl FnMut(Job)) -> Result<()> {
+    for entry in fs::read_dir(watch_dir.path.join(&subdir))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let relative = subdir.join(&name);
+        if (watch_dir.hidden_policy() == HiddenPolicy::Ignore && rules::is_hidden(&name.to_string_lossy())) || watch_dir.is_excluded(&relative) {
+            continue
+        }
+        let is_dir = entry.file_type()?.is_dir();
+        if is_dir && watch_dir.watches_subdir(&relative) {
+            scan(watch_dir, relative, found)?;
+        }
+        if watch_dir.is_uploading(&name.to_string_lossy()) {
+            continue
+        }
+        found(Job { watch_dir: Arc::clone(watch_dir), subdir: subdir.clone(), name, mask: event_mask(is_dir), origin: None });
+    }
+    Ok(())
+}
+
+/// Stats the jobs' files, spread over a few threads.
+fn stat_all(jobs: &[Job]) -> Vec<Option<fs::Metadata>> {
+    let chunk = jobs.len().div_ceil(STAT_THREADS).max(1);
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = jobs.chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter()
+                .map(|job| fs::symlink_metadata(job.watch_dir.path.join(&job.subdir).join(&job.name)).ok())
+                .collect::<Vec<_>>()))
+            .collect();
+        threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+    })
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    std_logger::Config::logfmt().init();
-    let config_file = include_str!("rules.yml");
-    let config: Config = serde_yaml::from_str(&config_file)?;
-    
-    let base_dir = PathBuf::from(&config.base_dir);
-    let watch_dir = base_dir.join(&config.watch_dir);
+    let args = cli::parse(std::env::args().skip(1))?;
+    logging::init(args.log_format, args.verbosity)?;
+    if let Some(file) = args.config {
+        config::use_file(file);
+    }
+    let result = execute(args.command, args.profile).await;
+    // writes out any pending repeated-warning summaries
+    log::logger().flush();
+    result
+}
 
-    let organiser = Organiser {
-        base_dir,
-        watch_dir,
-        rules: config.rules,
-        size_matcher: SizeMatcher::new()?,
+async fn execute(command: Command, profile: Option<String>) -> Result<()> {
+    match command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(())
+        },
+        Command::Version => {
+            version::print();
+            return Ok(())
+        },
+        Command::Import { from, path } => return commands::import::run(&from, &path),
+        Command::Join { manifest, output, check } => return commands::join::run(&manifest, output.as_deref(), check),
+        Command::Bench(workload) => return commands::bench::run(&workload).await,
+        _ => {},
+    }
+
+    let mut profiles = Config::load(profile.as_deref())?;
+    // logging is process-wide, so it follows the top-level settings every profile inherits
+    let shared = &profiles[0].config;
+    logging::configure(shared.log_filter.as_deref(), shared.log_repeat_window)?;
+
+    let (force, dry_run, audit) = match command {
+        Command::Run { force, dry_run, audit } => (force, dry_run, audit),
+        Command::Inspect { archive, rule } => return commands::inspect::run(&single(profiles)?, &archive, rule),
+        Command::Simulate { dir, watch } => return commands::simulate::run(&single(profiles)?, &dir, watch.as_deref()),
+        Command::Replay { events, instant, dry_run } => {
+            if profiles.len() > 1 {
+                return Err("config has several profiles - choose one with --profile".into())
+            }
+            let profile = profiles.remove(0);
+            return commands::replay::run(profile.name, profile.config, &events, instant, dry_run).await
+        },
+        Command::Check => {
+            let mut result = Ok(());
+            for profile in &profiles {
+                if profiles.len() > 1 {
+                    println!("profile {}:", profile.name);
+                }
+                result = commands::check::run(&profile.config).and(result);
+            }
+            return result
+        },
+        Command::Pause { rule, paused } => {
+            let control = shared.control.as_ref().ok_or("pause and resume need the control API - set control.socket or control.listen")?;
+            return commands::pause::run(control, profile.as_deref(), rule.as_deref(), paused).await
+        },
+        Command::Pending => {
+            let control = shared.control.as_ref().ok_or("pending needs the control API - set control.socket or control.listen")?;
+            return commands::confirm::list(control, profile.as_deref()).await
+        },
+        Command::Confirm { path, approved } => {
+            let control = shared.control.as_ref().ok_or("approve and reject need the control API - set control.socket or control.listen")?;
+            return commands::confirm::decide(control, profile.as_deref(), &path, approved).await
+        },
+        Command::ConfigDump => {
+            let many = profiles.len() > 1;
+            for profile in profiles {
+                if many {
+                    println!("---\n# profile: {}", profile.name);
+                }
+                commands::config::dump(profile.config)?;
+            }
+            return Ok(())
+        },
+        Command::Help | Command::Version | Command::Import { .. } | Command::Join { .. } | Command::Bench(_) => unreachable!("handled before loading config"),
+    };
+
+    // like logging, the control API and D-Bus service are process-wide and configured at the top level
+    let control_config = profiles[0].config.control.take();
+    let dbus_config = profiles[0].config.dbus.take();
+    let (senders, receivers): (Vec<_>, Vec<_>) = profiles.iter()
+        .map(|profile| {
+            let (sender, receiver) = mpsc::channel(8);
+            ((profile.name.clone(), sender), receiver)
+        })
+        .unzip();
+    let signals = match &dbus_config {
+        Some(dbus_config) => Some(dbus::serve(dbus_config, senders.clone()).await?),
+        None => None,
     };
 
-    organiser.run().await?;
+    let mut pipelines = tokio::task::JoinSet::new();
+    for ((mut profile, receiver), (_, sender)) in profiles.into_iter().zip(receivers).zip(&senders) {
+        removable::watch(std::mem::take(&mut profile.config.removable), profile.name.clone(), sender.clone());
+        let name = profile.name;
+        let organiser = Arc::new(organiser(name.clone(), profile.config, force, dry_run, audit, false, signals.clone())?);
+        info!(profile=name; "starting profile");
+        pipelines.spawn(async move {
+            organiser.run(receiver).await.map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { format!("profile [{name}]: {e}").into() })
+        });
+    }
+    if let Some(file) = Config::file() {
+        reload::watch(file, senders.clone())?;
+    }
+    reload::on_hangup(senders.clone())?;
+    if let Some(control_config) = control_config {
+        control::serve(&control_config, senders).await?;
+    }
+    while let Some(result) = pipelines.join_next().await {
+        result??;
+    }
 
     Ok(())
 }
+
+/// The only profile, for commands that work on a single config.
+fn single(mut profiles: Vec<Profile>) -> Result<Config> {
+    match profiles.len() {
+        1 => Ok(profiles.remove(0).config),
+        _ => Err("config has several profiles - choose one with --profile".into()),
+    }
+}
+
+/// Sets up the organiser for one profile.
+fn organiser(profile: String, config: Config, force: bool, dry_run: bool, audit: bool, replaying: bool, signals: Option<dbus::Signals>) -> Result<Organiser> {
+    let audit = audit || config.audit;
+    let dry_run = dry_run || config.dry_run || audit;
+    if audit {
+        info!(profile=profile; "auditing - rules are evaluated but nothing is changed");
+    }
+    let base_dir = PathBuf::from(&config.base_dir);
+    let work_dirs = WorkDirs::new(&base_dir, config.work_dir.clone());
+    // an audit or replay keeps its state in memory, so stateDir is left alone too, along with the
+    // running daemon's schedule and pending files
+    let state_dir = match audit || replaying {
+        true => None,
+        false => state::prepare(&config.state_dir()),
+    };
+    let runtime_dirs = RuntimeWatchDirs::open(state_dir.as_ref().map(|dir| dir.join("watch-dirs.yml")))?;
+    let watch_dirs = users::expand(runtime_dirs.apply(config.watch_dirs()));
+
+    let duplicate_index = match &config.duplicate_index {
+        Some(index_config) => {
+            let file = state_dir.as_ref().map(|dir| dir.join("duplicate-index.tsv"));
+            let mut excludes: Vec<PathBuf> = watch_dirs.iter().map(|d| d.path.clone()).collect();
+            excludes.push(config.state_dir());
+            excludes.push(work_dirs.root_for(&base_dir));
+            Some(Mutex::new(DuplicateIndex::open(index_config, &base_dir, file, excludes)?))
+        },
+        None => None,
+    };
+
+    let ledger = match &config.ledger {
+        Some(ledger_config) => {
+            let file = state_dir.as_ref().map(|dir| dir.join("ledger.tsv"));
+            Some(Mutex::new(Ledger::open(ledger_config, file)?))
+        },
+        None => None,
+    };
+
+    hashcache::open(state_dir.as_ref().map(|dir| dir.join("hash-cache.tsv")))?;
+    let episode_cache = EpisodeCache::open(state_dir.as_ref().map(|dir| dir.join("episodes.json")))?;
+    let pauses = Pauses::open(state_dir.as_ref().map(|dir| dir.join("paused.yml")))?;
+    let deletions = Deletions::open(state_dir.as_ref().map(|dir| dir.join("deletions.yml")))?;
+    let confirmations = Confirmations::open(state_dir.as_ref().map(|dir| dir.join("pending.yml")))?;
+    let awaiting = confirmations.list().len();
+    if awaiting > 0 {
+        match config.confirm {
+            Some(_) => warn!(profile=profile, files=awaiting; "files are still awaiting approval from before the restart"),
+            None => warn!(profile=profile, files=awaiting; "files are awaiting approval but confirm is no longer set - approve or reject them"),
+        }
+    }
+    let paused = pauses.state();
+    if paused.all || !paused.rules.is_empty() {
+        warn!(profile=profile, all=paused.all, rules=paused.rules.join(","); "processing is still paused from before the restart");
+    }
+
+    let gate = match &config.gate {
+        Some(_) if audit => {
+            warn!(profile=profile; "not starting the gate during an audit, as it holds back other programs opening files");
+            None
+        },
+        Some(gate_config) => Some(Gate::start(gate_config)?),
+        None => None,
+    };
+
+    let fanotify = match config.watcher {
+        Watcher::Fanotify => Some(MountWatch::start()?),
+        Watcher::Inotify => None,
+    };
+
+    let run_as = config.run_as.as_ref().map(RunAs::resolve).transpose()?;
+    let watchdog = config.watchdog.as_ref().map(|w| Watchdog::start(&profile, w)).transpose()?;
+    for rule in &config.rules {
+        // resolved again when used, this catches unknown users at start
+        rule.run_as.as_ref().map(RunAs::resolve).transpose()?;
+    }
+
+    let recorder = match (&state_dir, config.record_events) {
+        (Some(dir), true) => Some(Recorder::open(&dir.join(recording::FILE_NAME), &profile)?),
+        _ => None,
+    };
+
+    let outcomes = Outcomes { sinks: config.outcomes, signals, base_dir: base_dir.clone(), subscribers: Mutex::default() };
+    let notifier = Notifier::new(&profile, config.notifications);
+    Ok(Organiser {
+        profile,
+        base_dir,
+        watch_dirs: RwLock::new(watch_dirs.into_iter().map(Arc::new).collect()),
+        runtime_dirs: Mutex::new(runtime_dirs),
+        hidden: config.hidden,
+        retry: config.retry,
+        rules: RwLock::new(Arc::new(RuleSet::new(config.rules))),
+        duplicate_index,
+        symlinks: config.symlinks,
+        special_files: config.special_files,
+        failed_dir: config.failed_dir,
+        work_dirs,
+        gate,
+        fanotify,
+        run_as,
+        cooldown: config.cooldown,
+        queue: WorkQueue::new(config.order),
+        in_flight: InFlight::default(),
+        workers: config.workers,
+        max_queue_depth: config.max_queue_depth,
+        ledger,
+        episode_cache: Mutex::new(episode_cache),
+        outcomes,
+        notifier,
+        pauses,
+        deletions,
+        confirm: config.confirm,
+        confirmations,
+        maintenance: config.maintenance,
+        snapshot: config.snapshot,
+        watchdog,
+        deadline: config.deadline,
+        arrivals: Arrivals::default(),
+        report: config.report,
+        tally: Tally::default(),
+        reconcile: config.reconcile,
+        reconciliation: Mutex::new(None),
+        recorder,
+        replaying,
+        force,
+        dry_run,
+        audit,
+    })
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{as_debug, as_error, debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::WatchDir;
+use crate::duration;
+use crate::fsutil;
+use crate::rules;
+use crate::Result;
+
+/// Housekeeping run in the background on a schedule.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MaintenanceConfig {
+    /// How often it runs.
+    #[serde(default="default_interval", deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+    pub interval: Duration,
+    /// Destinations whose entries are deleted once they've been there long enough.
+    #[serde(default)]
+    pub retention: Vec<Retention>,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+/// How long entries are kept in a destination, like an age in a tmpfiles.d line.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Retention {
+    /// The directory, relative to `baseDir`.
+    pub path: PathBuf,
+    /// How long an entry is kept after it was last changed or moved in.
+    #[serde(rename="keepFor", deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+    pub keep_for: Duration,
+}
+
+impl MaintenanceConfig {
+    pub fn validate(&self, base_dir: &Path, watch_dirs: &[WatchDir]) -> Result<()> {
+        if self.interval.is_zero() {
+            return Err("maintenance interval must be more than zero".into())
+        }
+        for retention in &self.retention {
+            let path = base_dir.join(&retention.path);
+            if !fsutil::is_within(base_dir, &path) || fsutil::is_within(&path, base_dir) {
+                return Err(format!("retention path [{}] must be a directory inside baseDir", retention.path.display()).into())
+            }
+            if let Some(dir) = watch_dirs.iter().find(|d| fsutil::is_within(&path, &d.path) || fsutil::is_within(&d.path, &path)) {
+                return Err(format!("retention path [{}] overlaps watch directory [{}]", retention.path.display(), dir.name).into())
+            }
+            if retention.keep_for.is_zero() {
+                return Err(format!("retention for [{}] must keep entries for more than zero", retention.path.display()).into())
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the maintenance every `interval`, forever, starting straight away.
+    pub async fn run(&self, base_dir: &Path, dry_run: bool) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            for retention in &self.retention {
+                retention.sweep(base_dir, dry_run);
+            }
+        }
+    }
+}
+
+impl Retention {
+    /// Deletes the entries of the directory that are older than `keepFor`. Dot-files are left
+    /// alone, as the organiser keeps its own state in them.
+    fn sweep(&self, base_dir: &Path, dry_run: bool) {
+        let dir = base_dir.join(&self.path);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                debug!(dir=dir.to_str(), error=as_debug!(err); "unable to read retention directory - skipping it");
+                return
+            },
+        };
+        let cutoff = SystemTime::now().checked_sub(self.keep_for).and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        let mut deleted = 0;
+        for entry in entries.flatten() {
+            if entry.file_name().to_str().is_some_and(rules::is_hidden) {
+                continue
+            }
+            let path = entry.path();
+            match newest_change(&path) {
+                Ok(changed) if changed < cutoff => {},
+                Ok(_) => continue,
+                Err(err) => {
+                    warn!(path=path.to_str(), error=as_error!(*err); "unable to read the age of an entry under retention");
+                    continue
+                },
+            }
+            if dry_run {
+                info!(path=path.to_str(), keep_for=duration::format(&self.keep_for); "dry run - would delete entry older than its retention");
+                continue
+            }
+            let removed = match entry.file_type().map(|t| t.is_dir()) {
+                Ok(true) => fs::remove_dir_all(&path),
+                _ => fs::remove_file(&path),
+            };
+            match removed {
+                Ok(()) => {
+                    info!(path=path.to_str(), keep_for=duration::format(&self.keep_for); "deleted entry older than its retention");
+                    deleted += 1;
+                },
+                Err(err) => warn!(path=path.to_str(), error=as_debug!(err); "unable to delete entry older than its retention"),
+            }
+        }
+        debug!(dir=dir.to_str(), deleted=deleted; "retention sweep done");
+    }
+}
+
+/// When `path`, or anything inside it, was last modified or had its inode changed (which a move
+/// does), in seconds since the epoch.
+fn newest_change(path: &Path) -> Result<i64> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut newest = metadata.mtime().max(metadata.ctime());
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            newest = newest.max(newest_change(&entry?.path())?);
+        }
+    }
+    Ok(newest)
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+
+use crate::outcome::ProcessingOutcome;
+
+/// Upper bounds, in seconds, of the processing time histogram's buckets.
+const BUCKETS: [f64; 14] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Content type of `render`'s output.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Counts and timings of every profile's files, served by the control API. Process-wide, as the
+/// control API is.
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics { files: BTreeMap::new(), durations: BTreeMap::new(), overdue: BTreeMap::new() });
+
+struct Metrics {
+    /// By profile and result.
+    files: BTreeMap<(String, &'static str), Counter>,
+    /// By profile.
+    durations: BTreeMap<String, Histogram>,
+    /// By profile.
+    overdue: BTreeMap<String, Overdue>,
+}
+
+/// Files past the processing deadline.
+#[derive(Default)]
+struct Overdue {
+    /// Every file that has gone past it.
+    total: u64,
+    /// Files past it now.
+    current: u64,
+}
+
+/// The most recent file behind a sample, so a spike on a dashboard leads to the outcome events of
+/// a file that caused it.
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    /// Seconds since the epoch.
+    timestamp: f64,
+}
+
+#[derive(Default)]
+struct Counter {
+    value: u64,
+    exemplar: Option<Exemplar>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Files in each bucket alone, with the last for those over the largest bound.
+    counts: [u64; BUCKETS.len() + 1],
+    exemplars: [Option<Exemplar>; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+/// A new trace ID for a file's outcome, as 32 hex digits like a W3C trace ID.
+pub fn trace_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let mut hasher = Sha256::new();
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hasher.finalize()[..16].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Counts `outcome`, which has finished, keeping it as the exemplar for its samples.
+pub fn record(outcome: &ProcessingOutcome) {
+    let seconds = outcome.duration.as_secs_f64();
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+    let exemplar = |value| Some(Exemplar { trace_id: outcome.trace_id.clone(), value, timestamp });
+
+    let mut metrics = METRICS.lock().unwrap();
+    let counter = metrics.files.entry((outcome.profile.clone(), outcome.result.name())).or_default();
+    counter.value += 1;
+    counter.exemplar = exemplar(1.0);
+
+    let histogram = metrics.durations.entry(outcome.profile.clone()).or_default();
+    let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+    histogram.counts[bucket] += 1;
+    histogram.exemplars[bucket] = exemplar(seconds);
+    histogram.sum += seconds;
+}
+
+/// Counts `newly` files that have gone past the processing deadline, of `current` past it now.
+pub fn overdue(profile: &str, newly: usize, current: usize) {
+    let mut metrics = METRICS.lock().unwrap();
+    let overdue = metrics.overdue.entry(profile.to_string()).or_default();
+    overdue.total += newly as u64;
+    overdue.current = current as u64;
+}
+
+/// Every metric in the OpenMetrics text format, with exemplars.
+pub fn render() -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut text = String::new();
+
+    text.push_str("# TYPE download_organiser_files counter\n");
+    text.push_str("# HELP download_organiser_files Files handled, by result.\n");
+    for ((profile, result), counter) in &metrics.files {
+        let _ = write!(text, "download_organiser_files_total{{profile=\"{}\",result=\"{result}\"}} {}", escape(profile), counter.value);
+        exemplar(&mut text, counter.exemplar.as_ref());
+    }
+
+    text.push_str("# TYPE download_organiser_processing_seconds histogram\n");
+    text.push_str("# HELP download_organiser_processing_seconds Time from a worker taking a file to its outcome.\n");
+    for (profile, histogram) in &metrics.durations {
+        let profile = escape(profile);
+        let mut cumulative = 0;
+        for (i, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            let bound = BUCKETS.get(i).map(|b| format!("{b:?}")).unwrap_or_else(|| "+Inf".to_string());
+            let _ = write!(text, "download_organiser_processing_seconds_bucket{{profile=\"{profile}\",le=\"{bound}\"}} {cumulative}");
+            exemplar(&mut text, histogram.exemplars[i].as_ref());
+        }
+        let _ = writeln!(text, "download_organiser_processing_seconds_sum{{profile=\"{profile}\"}} {}", histogram.sum);
+        let _ = writeln!(text, "download_organiser_processing_seconds_count{{profile=\"{profile}\"}} {cumulative}");
+    }
+
+    if !metrics.overdue.is_empty() {
+        text.push_str("# TYPE download_organiser_overdue_files counter\n");
+        text.push_str("# HELP download_organiser_overdue_files Files that went past the processing deadline.\n");
+        for (profile, overdue) in &metrics.overdue {
+            let _ = writeln!(text, "download_organiser_overdue_files_total{{profile=\"{}\"}} {}", escape(profile), overdue.total);
+        }
+        text.push_str("# TYPE download_organiser_overdue_files_waiting gauge\n");
+        text.push_str("# HELP download_organiser_overdue_files_waiting Files past the processing deadline now.\n");
+        for (profile, overdue) in &metrics.overdue {
+            let _ = writeln!(text, "download_organiser_overdue_files_waiting{{profile=\"{}\"}} {}", escape(profile), overdue.current);
+        }
+    }
+
+    text.push_str("# EOF\n");
+    text
+}
+
+/// Ends a sample's line, with its exemplar if it has one.
+fn exemplar(text: &mut String, exemplar: Option<&Exemplar>) {
+    if let Some(exemplar) = exemplar {
+        let _ = write!(text, " # {{trace_id=\"{}\"}} {} {:.3}", exemplar.trace_id, exemplar.value, exemplar.timestamp);
+    }
+    text.push('\n');
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,84 @@
+use log::warn;
+use serde_yaml::{Mapping, Value};
+
+use crate::config::SCHEMA_VERSION;
+use crate::Result;
+
+/// Upgrades a config mapping written for one version to the next.
+type Migration = fn(&mut Mapping, &str) -> Result<()>;
+
+/// Migrations in order, the first upgrading version 1 to 2.
+const MIGRATIONS: [Migration; 1] = [watch_dir_to_watch_dirs];
+
+/// Keys dropped from the format, with the version that dropped them and what replaced them. A
+/// config of that version or later that still sets one is an error rather than silently ignored.
+const REMOVED: [(&str, u32, &str); 1] = [("watchDir", 2, "watchDirs")];
+
+/// Upgrades a config document written for an older `version` to the current one, logging a
+/// warning for each change so the file can be updated to match. A document without `version` is
+/// version 1. Profiles are migrated along with the top level.
+pub fn migrate(document: &mut Value) -> Result<()> {
+    let config = document.as_mapping_mut().ok_or("config must be a mapping")?;
+    let version = match config.get("version") {
+        Some(version) => version.as_u64().and_then(|v| u32::try_from(v).ok()).filter(|v| *v >= 1)
+            .ok_or_else(|| format!("config version must be a whole number from 1, not {}", describe(version)))?,
+        None => 1,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(format!("config version {version} is newer than this build understands ({SCHEMA_VERSION}) - upgrade the organiser").into())
+    }
+
+    let mut profiles = match config.get_mut("profiles").and_then(Value::as_sequence_mut) {
+        Some(profiles) => profiles.iter_mut().filter_map(Value::as_mapping_mut).collect(),
+        None => Vec::new(),
+    };
+    for (i, profile) in profiles.iter_mut().enumerate() {
+        let name = match profile.get("name").and_then(Value::as_str) {
+            Some(name) => format!("profile [{name}]"),
+            None => format!("profile #{}", i + 1),
+        };
+        if profile.contains_key("version") {
+            return Err(format!("{name}: version can only be set at the top level").into())
+        }
+        upgrade(profile, &name, version)?;
+    }
+    upgrade(config, "config", version)?;
+
+    if version < SCHEMA_VERSION {
+        warn!(from=version, to=SCHEMA_VERSION; "config uses an older version and was migrated - `config dump` shows it upgraded");
+    }
+    config.insert("version".into(), SCHEMA_VERSION.into());
+    Ok(())
+}
+
+fn upgrade(config: &mut Mapping, name: &str, version: u32) -> Result<()> {
+    for (key, removed_in, replacement) in REMOVED {
+        if version >= removed_in && config.contains_key(key) {
+            return Err(format!("{name}: {key} was replaced by {replacement} in config version {removed_in}").into())
+        }
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(config, name)?;
+    }
+    Ok(())
+}
+
+/// Version 2: the single `watchDir` became the first of `watchDirs`, named `default`.
+fn watch_dir_to_watch_dirs(config: &mut Mapping, name: &str) -> Result<()> {
+    let Some(path) = config.remove("watchDir") else {
+        return Ok(())
+    };
+    let mut dir = Mapping::new();
+    dir.insert("name".into(), "default".into());
+    dir.insert("path".into(), path);
+    match config.entry("watchDirs".into()).or_insert_with(|| Value::Sequence(Vec::new())) {
+        Value::Sequence(dirs) => dirs.insert(0, Value::Mapping(dir)),
+        _ => return Err(format!("{name}: watchDirs must be a list").into()),
+    }
+    warn!(migrated=name; "watchDir was replaced by watchDirs in config version 2 - moved it into watchDirs as [default]");
+    Ok(())
+}
+
+fn describe(value: &Value) -> String {
+    serde_yaml::to_string(value).map(|s| s.trim_end().to_string()).unwrap_or_default()
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::Result;
+
+/// Permissions a rule gives the files and directories it creates, in place of whatever the
+/// archive or the process umask would have given them.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Modes {
+    pub file: Option<u32>,
+    pub dir: Option<u32>,
+}
+
+impl Modes {
+    pub fn is_set(&self) -> bool {
+        self.file.is_some() || self.dir.is_some()
+    }
+
+    /// Creates `dir` and any missing parents, giving the ones it created `dir` mode.
+    pub fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        let missing: Vec<&Path> = dir.ancestors().take_while(|d| fs::symlink_metadata(d).is_err()).collect();
+        fs::create_dir_all(dir)?;
+        if let Some(mode) = self.dir {
+            for created in missing {
+                set(created, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives `path`, and everything inside it if it's a directory, the configured modes. Symlinks
+    /// are left alone, as their own mode means nothing and following them could reach anywhere.
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        if !self.is_set() {
+            return Ok(())
+        }
+        let file_type = fs::symlink_metadata(path)?.file_type();
+        if file_type.is_dir() {
+            for entry in fs::read_dir(path)? {
+                self.apply(&entry?.path())?;
+            }
+            if let Some(mode) = self.dir {
+                set(path, mode)?;
+            }
+        } else if let (true, Some(mode)) = (file_type.is_file(), self.file) {
+            set(path, mode)?;
+        }
+        Ok(())
+    }
+}
+
+fn set(path: &Path, mode: u32) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("unable to set mode {mode:04o} on [{}]: {e}", path.display()).into())
+}
+
+/// Parses an octal mode such as `0640` or `750`.
+pub fn parse(value: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(value.trim().trim_start_matches("0o"), 8)
+        .map_err(|_| format!("mode [{value}] is not an octal number such as 0644"))?;
+    if mode > 0o7777 {
+        return Err(format!("mode [{value}] is more than 07777").into())
+    }
+    Ok(mode)
+}
+
+/// Serde helper for optional modes, used with `#[serde(default)]`. Modes are read from the scalar
+/// as written, so `0640` is octal rather than the number YAML would make of it.
+pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<u32>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Serde helper for `#[serde(serialize_with = "mode::serialize_option")]`.
+pub fn serialize_option<S: Serializer>(mode: &Option<u32>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match mode {
+        Some(mode) => serializer.serialize_str(&format!("{mode:04o}")),
+        None => serializer.serialize_none(),
+    }
+}
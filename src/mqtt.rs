@@ -0,0 +1,90 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::Result;
+
+/// How long the broker gets to accept the connection and the message.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Login for a broker that requires one.
+pub struct Credentials<'a> {
+    pub username: &'a str,
+    pub password: Option<&'a str>,
+}
+
+/// Publishes a single message with MQTT 3.1.1 at QoS 0, over a connection of its own. Plain TCP
+/// only - put a local broker or bridge in front of one that needs TLS.
+pub async fn publish(address: &str, client_id: &str, credentials: Option<Credentials<'_>>, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+    tokio::time::timeout(TIMEOUT, async {
+        let mut stream = TcpStream::connect(address).await
+            .map_err(|e| format!("unable to connect to MQTT broker [{address}]: {e}"))?;
+
+        let mut flags = 0x02; // clean session
+        let mut payload_fields = string(client_id);
+        if let Some(credentials) = &credentials {
+            flags |= 0x80;
+            payload_fields.extend(string(credentials.username));
+            if let Some(password) = credentials.password {
+                flags |= 0x40;
+                payload_fields.extend(string(password));
+            }
+        }
+        let mut connect = string("MQTT");
+        connect.extend([4, flags, 0, 60]); // protocol level 4, 60s keep-alive
+        connect.extend(payload_fields);
+        stream.write_all(&packet(0x10, &connect)).await?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).await?;
+        if connack[0] != 0x20 {
+            return Err(format!("MQTT broker [{address}] didn't acknowledge the connection").into())
+        }
+        if connack[3] != 0 {
+            return Err(format!("MQTT broker [{address}] refused the connection: {}", refusal(connack[3])).into())
+        }
+
+        let mut publish = string(topic);
+        publish.extend_from_slice(payload);
+        stream.write_all(&packet(if retain { 0x31 } else { 0x30 }, &publish)).await?;
+        stream.write_all(&packet(0xe0, &[])).await?;
+        stream.flush().await?;
+        Ok(())
+    }).await.map_err(|_| format!("MQTT broker [{address}] timed out"))?
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    // remaining length, 7 bits at a time
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut encoded = (value.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(value.as_bytes());
+    encoded
+}
+
+fn refusal(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad username or password",
+        5 => "not authorised",
+        _ => "unknown reason",
+    }
+}
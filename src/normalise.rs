@@ -0,0 +1,57 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+/// The Unicode normalisation form destination names are written in. macOS writes names decomposed
+/// (NFD), most everything else composed (NFC), and the two look identical.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Normalisation {
+    #[serde(rename="nfc")]
+    Nfc,
+    #[serde(rename="nfd")]
+    Nfd,
+}
+
+impl Normalisation {
+    fn apply(&self, name: &OsStr) -> OsString {
+        match (self, name.to_str()) {
+            (Normalisation::Nfc, Some(name)) => name.nfc().collect::<String>().into(),
+            (Normalisation::Nfd, Some(name)) => name.nfd().collect::<String>().into(),
+            // a name that isn't UTF-8 has nothing to normalise
+            (_, None) => name.to_os_string(),
+        }
+    }
+}
+
+/// Where `name` goes in `dir`: an entry already there whose name only differs in normalisation, so
+/// it's treated as the same file or directory, otherwise `name` in `form`. `name` is left as it is
+/// without a form.
+pub fn resolve(dir: &Path, name: &OsStr, form: Option<Normalisation>) -> PathBuf {
+    let form = match form {
+        Some(form) => form,
+        None => return dir.join(name),
+    };
+    let normalised = form.apply(name);
+    let path = dir.join(&normalised);
+    if fs::symlink_metadata(&path).is_ok() {
+        return path
+    }
+    let equivalent = fs::read_dir(dir).ok().and_then(|entries| entries.flatten()
+        .map(|entry| entry.file_name())
+        .find(|existing| form.apply(existing) == normalised));
+    match equivalent {
+        Some(existing) => dir.join(existing),
+        None => path,
+    }
+}
+
+/// `path` with every name below `base` resolved as by `resolve`. Paths outside `base` are left as
+/// they are.
+pub fn path(base: &Path, path: &Path, form: Option<Normalisation>) -> PathBuf {
+    match (form, path.strip_prefix(base)) {
+        (Some(_), Ok(relative)) => relative.iter().fold(base.to_path_buf(), |dir, name| resolve(&dir, name, form)),
+        _ => path.to_path_buf(),
+    }
+}
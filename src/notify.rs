@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+#[cfg(feature="dbus")]
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use log::{as_error, debug, warn};
+use serde::{Deserialize, Serialize};
+#[cfg(feature="dbus")]
+use zbus::zvariant::Value;
+#[cfg(feature="dbus")]
+use zbus::Connection;
+
+use crate::config::Rule;
+use crate::duration;
+use crate::http;
+use crate::outcome::ProcessingOutcome;
+use crate::secret::SecretRef;
+#[cfg(not(feature="dbus"))]
+use crate::version;
+use crate::webhook;
+use crate::Result;
+
+/// When a rule sends a notification about a file.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum NotifyEvent {
+    /// The rule matched, before any of its actions ran.
+    #[serde(rename="match")]
+    Match,
+    /// All of the rule's actions ran.
+    #[serde(rename="success")]
+    Success,
+    /// An action failed, or gave up on the file.
+    #[serde(rename="failure")]
+    Failure,
+}
+
+impl NotifyEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            NotifyEvent::Match => "match",
+            NotifyEvent::Success => "success",
+            NotifyEvent::Failure => "failure",
+        }
+    }
+}
+
+/// Somewhere notifications are sent, configured once under `notifications` and named by rules.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag="type")]
+pub enum Channel {
+    /// A desktop notification, through the session bus's notification server.
+    #[serde(rename="desktop")]
+    Desktop,
+    /// A message published to an [ntfy](https://ntfy.sh) topic, given as its full URL.
+    #[serde(rename="ntfy")]
+    Ntfy {
+        url: SecretRef,
+        /// Access token for a protected topic.
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        token: Option<SecretRef>,
+    },
+    /// The notification as JSON, POSTed to `url`.
+    #[serde(rename="webhook")]
+    Webhook {
+        url: SecretRef,
+        #[serde(default)]
+        headers: BTreeMap<String, SecretRef>,
+    },
+}
+
+/// The notifications a matched rule asked for, carried with the file's outcome until it's done.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    events: Vec<NotifyEvent>,
+    /// Channel names. Every channel when unset.
+    channels: Option<Vec<String>>,
+}
+
+impl Subscription {
+    /// What `rule` asked to be told about, if anything.
+    pub fn of(rule: &Rule) -> Option<Subscription> {
+        match rule.notify_on.is_empty() {
+            true => None,
+            false => Some(Subscription { events: rule.notify_on.clone(), channels: rule.notify_via.clone() }),
+        }
+    }
+}
+
+/// The configured channels.
+pub struct Notifier {
+    profile: String,
+    channels: BTreeMap<String, Channel>,
+}
+
+impl Notifier {
+    pub fn new(profile: &str, channels: BTreeMap<String, Channel>) -> Notifier {
+        Notifier { profile: profile.to_string(), channels }
+    }
+
+    /// Sends the notification for `event` about `outcome`, if its rule subscribed to it. A channel
+    /// that fails is logged, and doesn't stop the others or affect the file.
+    pub async fn notify(&self, event: NotifyEvent, outcome: &ProcessingOutcome) {
+        let subscription = match &outcome.notify {
+            Some(subscription) if subscription.events.contains(&event) => subscription,
+            _ => return,
+        };
+        self.send(&Notification::of(event, outcome), subscription.channels.as_deref()).await;
+        // a channel added by a reload is only known after a restart
+        for name in subscription.channels.iter().flatten().filter(|n| !self.channels.contains_key(*n)) {
+            warn!(channel=name; "rule notifies a channel that isn't configured - restart to pick up new channels");
+        }
+    }
+
+    /// Sends a notification that `file` has waited `waited` to be organised, past the deadline, to
+    /// `channels`, or every channel when unset.
+    pub async fn overdue(&self, file: &Path, waited: Duration, channels: Option<&[String]>) {
+        let name = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let notification = Notification {
+            event: "overdue",
+            urgent: true,
+            file: Some(file),
+            rule: None,
+            dest: None,
+            reason: None,
+            title: format!("{name} is overdue"),
+            message: format!("Not organised {} after it arrived", duration::format(&Duration::from_secs(waited.as_secs()))),
+        };
+        self.send(&notification, channels).await;
+    }
+
+    /// Sends the daily report, written to `file` if it was, to `channels`, or every channel when
+    /// unset.
+    pub async fn report(&self, file: Option<&Path>, headline: String, report: String, channels: Option<&[String]>) {
+        let notification = Notification {
+            event: "report",
+            urgent: false,
+            file,
+            rule: None,
+            dest: None,
+            reason: None,
+            title: format!("Daily report: {headline}"),
+            message: report,
+        };
+        self.send(&notification, channels).await;
+    }
+
+    async fn send(&self, notification: &Notification<'_>, channels: Option<&[String]>) {
+        let (event, file) = (notification.event, notification.file.and_then(Path::to_str));
+        for (name, channel) in &self.channels {
+            if channels.is_some_and(|names| !names.contains(name)) {
+                continue
+            }
+            match channel.send(&self.profile, notification).await {
+                Ok(()) => debug!(channel=name, event=event, file=file; "notification sent"),
+                Err(err) => warn!(channel=name, event=event, file=file, error=as_error!(*err); "unable to send notification"),
+            }
+        }
+    }
+}
+
+struct Notification<'a> {
+    /// As `notifyOn` names it, or `overdue` or `report`.
+    event: &'static str,
+    /// Whether something went wrong, which channels that can show it more prominently.
+    urgent: bool,
+    file: Option<&'a Path>,
+    rule: Option<&'a str>,
+    dest: Option<&'a Path>,
+    reason: Option<&'a str>,
+    title: String,
+    message: String,
+}
+
+impl<'a> Notification<'a> {
+    fn of(event: NotifyEvent, outcome: &'a ProcessingOutcome) -> Notification<'a> {
+        let name = outcome.file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let rule = outcome.rule.as_deref().unwrap_or_default();
+        let (title, message) = match event {
+            NotifyEvent::Match => (format!("{name} matched"), format!("Matched rule {rule}")),
+            NotifyEvent::Success => (format!("{name} organised"), match &outcome.dest {
+                Some(dest) => format!("Moved to {}", dest.display()),
+                None => format!("Processed by rule {rule}"),
+            }),
+            NotifyEvent::Failure => (format!("{name} failed"), outcome.reason.clone().unwrap_or_else(|| format!("Rule {rule} failed"))),
+        };
+        Notification {
+            event: event.name(),
+            urgent: event == NotifyEvent::Failure,
+            file: Some(&outcome.file),
+            rule: outcome.rule.as_deref(),
+            dest: outcome.dest.as_deref(),
+            reason: outcome.reason.as_deref(),
+            title,
+            message,
+        }
+    }
+}
+
+impl Channel {
+    /// The `type` of every channel.
+    pub const NAMES: &'static [&'static str] = &["desktop", "ntfy", "webhook"];
+
+    /// The channel's `type` in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Desktop => "desktop",
+            Channel::Ntfy { .. } => "ntfy",
+            Channel::Webhook { .. } => "webhook",
+        }
+    }
+
+    async fn send(&self, profile: &str, notification: &Notification<'_>) -> Result<()> {
+        match self {
+            #[cfg(feature="dbus")]
+            Channel::Desktop => {
+                let connection = Connection::session().await
+                    .map_err(|e| format!("unable to connect to the session bus for desktop notifications: {e}"))?;
+                let urgency = match notification.urgent {
+                    true => 2u8,
+                    false => 1u8,
+                };
+                let hints = HashMap::from([("urgency", Value::from(urgency))]);
+                connection.call_method(Some("org.freedesktop.Notifications"), "/org/freedesktop/Notifications",
+                    Some("org.freedesktop.Notifications"), "Notify",
+                    &("download-organiser", 0u32, "", notification.title.as_str(), notification.message.as_str(), Vec::<&str>::new(), hints, -1i32))
+                    .await
+                    .map_err(|e| format!("unable to show desktop notification: {e}"))?;
+            },
+            // refused when the config is loaded, so never sent
+            #[cfg(not(feature="dbus"))]
+            Channel::Desktop => version::require("notification channel", "desktop")?,
+            Channel::Ntfy { url, token } => {
+                let mut headers = vec![format!("Title: {}", notification.title)];
+                if notification.urgent {
+                    headers.push("Priority: high".to_string());
+                    headers.push("Tags: warning".to_string());
+                }
+                if let Some(token) = token {
+                    headers.push(format!("Authorization: Bearer {}", token.resolve()?.expose()));
+                }
+                http::request("POST", url.resolve()?.expose(), &headers, Some(&notification.message)).await?;
+            },
+            Channel::Webhook { url, headers } => {
+                let payload = serde_json::json!({
+                    "event": notification.event,
+                    "profile": profile,
+                    "file": notification.file,
+                    "rule": notification.rule,
+                    "dest": notification.dest,
+                    "reason": notification.reason,
+                    "title": notification.title,
+                    "message": notification.message,
+                });
+                webhook::send(url, "POST", headers, &payload.to_string()).await?;
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::debug;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Extensions of images tesseract can read directly.
+const IMAGE_EXTENSIONS: [&str; 8] = ["png", "jpg", "jpeg", "tif", "tiff", "bmp", "gif", "webp"];
+
+/// Resolution PDF pages are rendered at for OCR. Tesseract is most accurate around 300 DPI.
+const PDF_DPI: &str = "300";
+
+/// A rule condition on the text inside a PDF or image.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ContentCondition {
+    /// Matched against the extracted text, e.g. `(?i)acme energy`.
+    #[serde(with = "serde_regex")]
+    pub regex: Regex,
+    /// Tesseract language packs to recognise with, such as `[eng, deu]`.
+    #[serde(default="default_languages")]
+    pub languages: Vec<String>,
+}
+
+fn default_languages() -> Vec<String> {
+    vec!["eng".to_string()]
+}
+
+/// Whether `path` is a kind of file text can be extracted from.
+pub fn is_supported(path: &Path) -> bool {
+    match extension(path) {
+        Some(extension) => extension == "pdf" || IMAGE_EXTENSIONS.contains(&extension.as_str()),
+        None => false,
+    }
+}
+
+/// The text in a PDF or image. A PDF's text layer is used when it has one, so only scanned PDFs
+/// go through OCR, which needs `tesseract` (and `pdftotext`/`pdftoppm` from poppler for PDFs).
+pub fn text(path: &Path, languages: &[String]) -> Result<String> {
+    if extension(path).as_deref() != Some("pdf") {
+        return tesseract(path, languages)
+    }
+
+    let text = run(Command::new("pdftotext").arg("-layout").arg(path).arg("-"))?;
+    if !text.trim().is_empty() {
+        debug!(path=path.to_str(); "using PDF's text layer");
+        return Ok(text)
+    }
+
+    debug!(path=path.to_str(); "PDF has no text layer - running OCR");
+    let pages = ScratchDir::create()?;
+    run(Command::new("pdftoppm").args(["-r", PDF_DPI, "-png"]).arg(path).arg(pages.0.join("page")))?;
+    let mut rendered: Vec<PathBuf> = fs::read_dir(&pages.0)?.map(|e| e.map(|e| e.path())).collect::<io::Result<_>>()?;
+    rendered.sort();
+    let mut text = String::new();
+    for page in rendered {
+        text.push_str(&tesseract(&page, languages)?);
+    }
+    Ok(text)
+}
+
+fn tesseract(image: &Path, languages: &[String]) -> Result<String> {
+    run(Command::new("tesseract").arg(image).arg("stdout").arg("-l").arg(languages.join("+")))
+}
+
+/// Runs a command, returning its standard output.
+fn run(command: &mut Command) -> Result<String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!("[{program}] is not installed - it's needed for content conditions"),
+        _ => format!("unable to run [{program}]: {e}"),
+    })?;
+    if !output.status.success() {
+        return Err(format!("[{program}] failed with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).into())
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+/// A temporary directory for rendered pages, removed on drop.
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn create() -> Result<ScratchDir> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir()
+            .join(format!("download-organiser-ocr-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir)?;
+        Ok(ScratchDir(dir))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{SecondsFormat, Utc};
+use log::{as_error, info, warn};
+use serde::{Deserialize, Serialize, Serializer};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::dbus::Signals;
+use crate::metrics;
+use crate::mqtt;
+use crate::notify::Subscription;
+use crate::queue::Origin;
+use crate::secret::SecretRef;
+use crate::webhook;
+use crate::Result;
+
+/// What happened to a file, as reported to every sink.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// A rule's actions all ran.
+    #[serde(rename="processed")]
+    Processed,
+    /// An action failed, or gave up on the file.
+    #[serde(rename="failed")]
+    Failed,
+    /// Left alone before any rule was tried: already processed, a duplicate, gone, or a symlink or
+    /// special file being ignored.
+    #[serde(rename="skipped")]
+    Skipped,
+    /// A rule matched, but in dry-run mode.
+    #[serde(rename="dry-run")]
+    DryRun,
+    #[serde(rename="no-match")]
+    NoMatch,
+    /// A rule matched, but it's paused, so the file is held until it's resumed.
+    #[serde(rename="paused")]
+    Paused,
+    /// A rule that deletes or overwrites matched, so the file waits for approval.
+    #[serde(rename="pending")]
+    Pending,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Processed => "processed",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+            Status::DryRun => "dry-run",
+            Status::NoMatch => "no-match",
+            Status::Paused => "paused",
+            Status::Pending => "pending",
+        }
+    }
+}
+
+/// The outcome of processing one file: which rule matched, what each of its actions did and how
+/// long it all took.
+#[derive(Serialize, Debug, Clone)]
+pub struct ProcessingOutcome {
+    /// Identifies this outcome in the exemplars of the metrics it's counted in.
+    #[serde(rename="traceId")]
+    pub trace_id: String,
+    pub profile: String,
+    /// Where the file was found.
+    pub file: PathBuf,
+    #[serde(rename="watchDir")]
+    pub watch_dir: String,
+    /// Regex of the rule that matched.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub rule: Option<String>,
+    pub actions: Vec<ActionOutcome>,
+    /// Where the file ended up, when an action moved it.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub dest: Option<PathBuf>,
+    /// Files its actions produced that were queued to go through the rules themselves.
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    pub derived: Vec<PathBuf>,
+    /// The archive the file was extracted from, when it didn't arrive in the watch directory.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub origin: Option<Origin>,
+    /// When processing started, in RFC 3339.
+    pub started: String,
+    /// How long the file waited in the queue for a worker.
+    #[serde(rename="queuedMs", serialize_with="millis")]
+    pub queued: Duration,
+    /// How long finding the matching rule took, including any content it had to read.
+    #[serde(rename="matchMs", serialize_with="millis")]
+    pub matching: Duration,
+    #[serde(rename="durationMs", serialize_with="millis")]
+    pub duration: Duration,
+    pub result: Status,
+    /// Why the file failed or was skipped.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub reason: Option<String>,
+    /// Notifications the matched rule asked for.
+    #[serde(skip)]
+    pub notify: Option<Subscription>,
+    #[serde(skip)]
+    clock: Instant,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ActionOutcome {
+    pub action: &'static str,
+    /// Attempts made, which is more than one when the action was retried.
+    pub attempts: u32,
+    #[serde(rename="durationMs", serialize_with="millis")]
+    pub duration: Duration,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub error: Option<String>,
+    /// What a dry run would have done instead of performing the action.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub planned: Option<String>,
+}
+
+impl ProcessingOutcome {
+    /// Starts the outcome of a file that waited `queued` for a worker.
+    pub fn begin(profile: &str, file: &Path, watch_dir: &str, origin: Option<&Origin>, queued: Duration) -> ProcessingOutcome {
+        ProcessingOutcome {
+            trace_id: metrics::trace_id(),
+            profile: profile.to_string(),
+            file: file.to_path_buf(),
+            watch_dir: watch_dir.to_string(),
+            rule: None,
+            actions: Vec::new(),
+            dest: None,
+            derived: Vec::new(),
+            origin: origin.cloned(),
+            started: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            queued,
+            matching: Duration::ZERO,
+            duration: Duration::ZERO,
+            result: Status::Processed,
+            reason: None,
+            notify: None,
+            clock: Instant::now(),
+        }
+    }
+
+    pub fn skipped(&mut self, reason: &str) {
+        self.result = Status::Skipped;
+        self.reason = Some(reason.to_string());
+    }
+
+    pub fn failed(&mut self, reason: String) {
+        self.result = Status::Failed;
+        self.reason = Some(reason);
+    }
+
+    /// Records an action that has finished, successfully or not, having started at `started`.
+    pub fn action(&mut self, action: &'static str, attempts: u32, started: Instant, error: Option<String>) {
+        self.actions.push(ActionOutcome { action, attempts, duration: started.elapsed(), error, planned: None });
+    }
+
+    /// Records what a dry run would have done for an action it didn't perform.
+    pub fn planned(&mut self, action: &'static str, planned: String) {
+        self.actions.push(ActionOutcome { action, attempts: 0, duration: Duration::ZERO, error: None, planned: Some(planned) });
+    }
+
+    fn finish(&mut self) {
+        self.duration = self.clock.elapsed();
+    }
+}
+
+/// Durations are reported in milliseconds, to the microsecond.
+fn millis<S: Serializer>(duration: &Duration, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(as_millis(duration))
+}
+
+fn as_millis(duration: &Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Somewhere outcomes are sent.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag="type")]
+pub enum Sink {
+    /// A log line per file, at info level.
+    #[serde(rename="log")]
+    Log,
+    /// A JSON object per line, appended to `file` (relative to `baseDir`).
+    #[serde(rename="jsonl")]
+    Jsonl { file: PathBuf },
+    /// A row per file in the `outcomes` table of an SQLite database, written with `sqlite3`.
+    #[serde(rename="sqlite")]
+    Sqlite { file: PathBuf },
+    /// The outcome as JSON, POSTed to `url`.
+    #[serde(rename="webhook")]
+    Webhook {
+        url: SecretRef,
+        #[serde(default)]
+        headers: BTreeMap<String, SecretRef>,
+    },
+    /// The outcome as JSON, published to an MQTT broker.
+    #[serde(rename="mqtt")]
+    Mqtt {
+        /// `host:port` of the broker.
+        broker: String,
+        /// `{result}` is replaced with the outcome's result, e.g. `downloads/{result}`.
+        topic: String,
+        #[serde(rename="clientId", default="default_client_id")]
+        client_id: String,
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        username: Option<SecretRef>,
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        password: Option<SecretRef>,
+        #[serde(default)]
+        retain: bool,
+    },
+}
+
+fn default_client_id() -> String {
+    "download-organiser".to_string()
+}
+
+/// Every sink outcomes go to, including the D-Bus signals.
+pub struct Outcomes {
+    pub sinks: Vec<Sink>,
+    pub signals: Option<Signals>,
+    /// What relative sink files are relative to.
+    pub base_dir: PathBuf,
+    /// Also given every outcome, one for each stream from `subscribe`.
+    pub subscribers: Mutex<Vec<mpsc::UnboundedSender<ProcessingOutcome>>>,
+}
+
+impl Outcomes {
+    /// Sends `outcome` to every sink. A sink that fails is logged, and doesn't stop the others.
+    pub async fn publish(&self, mut outcome: ProcessingOutcome) {
+        outcome.finish();
+        metrics::record(&outcome);
+        if let Some(signals) = &self.signals {
+            match outcome.result {
+                Status::Processed => signals.file_organised(&outcome.file, outcome.rule.as_deref().unwrap_or_default()).await,
+                Status::Failed => signals.action_failed(&outcome.file, outcome.reason.as_deref().unwrap_or_default()).await,
+                _ => {},
+            }
+        }
+        for sink in &self.sinks {
+            if let Err(err) = sink.emit(&outcome, &self.base_dir).await {
+                warn!(sink=sink.name(), file=outcome.file.to_str(), error=as_error!(*err); "unable to report file outcome");
+            }
+        }
+        // a stream that's been dropped is forgotten
+        self.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(outcome.clone()).is_ok());
+    }
+
+    /// Every outcome published from now on.
+    pub fn subscribe(&self) -> UnboundedReceiverStream<ProcessingOutcome> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(sender);
+        UnboundedReceiverStream::new(receiver)
+    }
+}
+
+impl Sink {
+    /// The `type` of every sink.
+    pub const NAMES: &'static [&'static str] = &["log", "jsonl", "sqlite", "webhook", "mqtt"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sink::Log => "log",
+            Sink::Jsonl { .. } => "jsonl",
+            Sink::Sqlite { .. } => "sqlite",
+            Sink::Webhook { .. } => "webhook",
+            Sink::Mqtt { .. } => "mqtt",
+        }
+    }
+
+    async fn emit(&self, outcome: &ProcessingOutcome, base_dir: &Path) -> Result<()> {
+        match self {
+            Sink::Log => {
+                let actions: Vec<&str> = outcome.actions.iter().map(|a| a.action).collect();
+                info!(file=outcome.file.to_str(), rule=outcome.rule, result=outcome.result.name(), reason=outcome.reason,
+                    actions=actions.join(","), dest=outcome.dest.as_ref().and_then(|d| d.to_str()), duration_ms=as_millis(&outcome.duration),
+                    trace_id=outcome.trace_id;
+                    "file outcome");
+            },
+            Sink::Jsonl { file } => {
+                let mut line = serde_json::to_string(outcome)?;
+                line.push('\n');
+                // a single append per line, so concurrent workers don't interleave
+                fs::OpenOptions::new().create(true).append(true).open(base_dir.join(file))?.write_all(line.as_bytes())?;
+            },
+            Sink::Sqlite { file } => sqlite(&base_dir.join(file), outcome).await?,
+            Sink::Webhook { url, headers } => {
+                webhook::send(url, "POST", headers, &serde_json::to_string(outcome)?).await?;
+            },
+            Sink::Mqtt { broker, topic, client_id, username, password, retain } => {
+                let username = username.as_ref().map(SecretRef::resolve).transpose()?;
+                let password = password.as_ref().map(SecretRef::resolve).transpose()?;
+                let credentials = username.as_ref().map(|username| mqtt::Credentials {
+                    username: username.expose(),
+                    password: password.as_ref().map(|p| p.expose()),
+                });
+                let topic = topic.replace("{result}", outcome.result.name());
+                mqtt::publish(broker, client_id, credentials, &topic, serde_json::to_string(outcome)?.as_bytes(), *retain).await?;
+            },
+        }
+        Ok(())
+    }
+}
+
+async fn sqlite(file: &Path, outcome: &ProcessingOutcome) -> Result<()> {
+    let text = |value: Option<&str>| value.map(|v| format!("'{}'", v.replace('\'', "''"))).unwrap_or_else(|| "NULL".to_string());
+    let statements = format!(
+        ".timeout 5000\n\
+        CREATE TABLE IF NOT EXISTS outcomes (started TEXT, profile TEXT, file TEXT, watch_dir TEXT, rule TEXT, \
+        actions TEXT, dest TEXT, duration_ms REAL, result TEXT, reason TEXT);\n\
+        INSERT INTO outcomes VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {});\n",
+        text(Some(&outcome.started)), text(Some(&outcome.profile)), text(outcome.file.to_str()), text(Some(&outcome.watch_dir)),
+        text(outcome.rule.as_deref()), text(Some(&serde_json::to_string(&outcome.actions)?)),
+        text(outcome.dest.as_ref().and_then(|d| d.to_str())), as_millis(&outcome.duration), text(Some(outcome.result.name())),
+        text(outcome.reason.as_deref()));
+
+    let mut child = Command::new("sqlite3")
+        .arg("-batch")
+        .arg(file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run [sqlite3] - is it installed? {e}"))?;
+    child.stdin.take().ok_or("sqlite3 has no stdin")?.write_all(statements.as_bytes()).await?;
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(format!("sqlite3 failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into())
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use log::{as_display, warn};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Steps fancy-regex may backtrack through for one name before it gives up, which counts as no
+/// match. Names are short, so only a pathological pattern gets near it.
+const BACKTRACK_LIMIT: usize = 1_000_000;
+
+/// Which regex engine a rule's `regex` and `excludeRegex` are run by.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Engine {
+    /// The `regex` crate, which runs in time linear in the name, but without lookaround or
+    /// backreferences.
+    #[default]
+    #[serde(rename="regex")]
+    Regex,
+    /// fancy-regex, which adds lookaround, backreferences and atomic groups by backtracking.
+    #[serde(rename="fancy")]
+    Fancy,
+}
+
+/// A rule's regex. Compiled by the default engine whenever it can be, and by fancy-regex when it
+/// needs lookaround or backreferences, which the rule has to opt in to with `engine: fancy`.
+pub enum Pattern {
+    Regex(Regex),
+    /// Along with why the default engine refused it.
+    Fancy(fancy_regex::Regex, String),
+}
+
+impl Pattern {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Pattern::Regex(regex) => regex.as_str(),
+            Pattern::Fancy(regex, _) => regex.as_str(),
+        }
+    }
+
+    /// The default engine's regex, for a pattern it compiled.
+    pub fn as_regex(&self) -> Option<&Regex> {
+        match self {
+            Pattern::Regex(regex) => Some(regex),
+            Pattern::Fancy(..) => None,
+        }
+    }
+
+    /// Why the default engine can't run the pattern, if it can't.
+    pub fn needs_fancy(&self) -> Option<&str> {
+        match self {
+            Pattern::Regex(_) => None,
+            Pattern::Fancy(_, reason) => Some(reason),
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            Pattern::Regex(regex) => regex.is_match(name),
+            Pattern::Fancy(regex, _) => regex.is_match(name).unwrap_or_else(|err| {
+                warn!(regex=regex.as_str(), filename=name, error=as_display!(err); "regex gave up on name - treating it as no match");
+                false
+            }),
+        }
+    }
+
+    /// `template` with `$1` or `${name}` replaced by the capture groups from matching `name`, or
+    /// `None` if it doesn't match.
+    pub fn expand(&self, name: &str, template: &str) -> Option<String> {
+        let mut expanded = String::new();
+        match self {
+            Pattern::Regex(regex) => regex.captures(name)?.expand(template, &mut expanded),
+            Pattern::Fancy(regex, _) => regex.captures(name).ok()??.expand(template, &mut expanded),
+        }
+        Some(expanded)
+    }
+
+    fn compile(pattern: &str) -> std::result::Result<Pattern, String> {
+        let refused = match Regex::new(pattern) {
+            Ok(regex) => return Ok(Pattern::Regex(regex)),
+            Err(err) => err.to_string(),
+        };
+        match fancy_regex::RegexBuilder::new(pattern).backtrack_limit(BACKTRACK_LIMIT).build() {
+            Ok(regex) => Ok(Pattern::Fancy(regex, refused)),
+            // the default engine's error reads better for a pattern neither engine takes
+            Err(_) => Err(refused),
+        }
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Pattern, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Pattern::compile(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
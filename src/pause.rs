@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use log::{as_error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::queue::Job;
+use crate::Result;
+
+/// What's paused, as reported by the control API and saved so a pause outlasts a restart.
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct PauseState {
+    /// Every rule, so the workers take nothing off the queue.
+    #[serde(default)]
+    pub all: bool,
+    /// Regexes of the rules that are paused on their own.
+    #[serde(default)]
+    pub rules: Vec<String>,
+}
+
+/// What's paused and how many files are waiting for it, for `GET /pause`.
+#[derive(Deserialize, Serialize)]
+pub struct PauseStatus {
+    #[serde(flatten)]
+    pub state: PauseState,
+    /// Files that matched a paused rule.
+    pub held: usize,
+    pub queued: usize,
+}
+
+/// Pauses processing, as a whole or by rule, while files keep being queued.
+pub struct Pauses {
+    state: Mutex<PauseState>,
+    file: Option<PathBuf>,
+    /// Woken when processing is resumed.
+    resumed: Notify,
+    /// Files that matched a paused rule, queued again when a rule is resumed.
+    held: Mutex<Vec<Job>>,
+}
+
+impl Pauses {
+    /// Loads the saved state from `file`, if there is one.
+    pub fn open(file: Option<PathBuf>) -> Result<Pauses> {
+        let state = match file.as_ref().filter(|f| f.exists()) {
+            Some(file) => serde_yaml::from_str(&fs::read_to_string(file)?)
+                .map_err(|e| format!("unable to read [{}]: {e}", file.display()))?,
+            None => PauseState::default(),
+        };
+        Ok(Pauses { state: Mutex::new(state), file, resumed: Notify::new(), held: Mutex::new(Vec::new()) })
+    }
+
+    pub fn state(&self) -> PauseState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn is_rule_paused(&self, regex: &str) -> bool {
+        self.state.lock().unwrap().rules.iter().any(|r| r == regex)
+    }
+
+    /// Pauses or resumes everything, or the rule with `regex`, returning the files held for
+    /// paused rules when something is resumed so they can be queued again.
+    pub fn set(&self, regex: Option<&str>, paused: bool) -> Vec<Job> {
+        let mut state = self.state.lock().unwrap();
+        match regex {
+            None => state.all = paused,
+            Some(regex) => {
+                state.rules.retain(|r| r != regex);
+                if paused {
+                    state.rules.push(regex.to_string());
+                }
+            },
+        }
+        if let Err(err) = self.save(&state) {
+            warn!(error=as_error!(*err); "unable to save paused state - it will be lost on restart");
+        }
+        drop(state);
+        if paused {
+            return Vec::new()
+        }
+        self.resumed.notify_waiters();
+        // anything still paused is held again when it comes round
+        self.held.lock().unwrap().drain(..).collect()
+    }
+
+    /// Keeps `job` until a rule is resumed.
+    pub fn hold(&self, job: Job) {
+        self.held.lock().unwrap().push(job);
+    }
+
+    /// How many files are held for paused rules.
+    pub fn held(&self) -> usize {
+        self.held.lock().unwrap().len()
+    }
+
+    fn save(&self, state: &PauseState) -> Result<()> {
+        match &self.file {
+            Some(file) => Ok(fs::write(file, serde_yaml::to_string(state)?)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits until processing as a whole isn't paused.
+    pub async fn running(&self) {
+        loop {
+            let resumed = self.resumed.notified();
+            if !self.state.lock().unwrap().all {
+                return
+            }
+            resumed.await;
+        }
+    }
+}
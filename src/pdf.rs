@@ -0,0 +1,100 @@
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::size::{self, SizeRange};
+use crate::Result;
+
+/// A rule condition on a PDF's document information. Every field that is set has to match.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PdfCondition {
+    #[serde(with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub title: Option<Regex>,
+    #[serde(with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub author: Option<Regex>,
+    /// The software that wrote the PDF, often the only clue to which company's billing system
+    /// produced it.
+    #[serde(with="serde_regex", default, skip_serializing_if="Option::is_none")]
+    pub producer: Option<Regex>,
+    /// Number of pages, such as `1`, `> 2` or `1..4`.
+    #[serde(default, deserialize_with="size::deserialize_count", skip_serializing_if="Option::is_none")]
+    pub pages: Option<SizeRange>,
+}
+
+impl PdfCondition {
+    pub fn matches(&self, info: &PdfInfo, filename: &str) -> bool {
+        let fields = [("title", &self.title, &info.title), ("author", &self.author, &info.author), ("producer", &self.producer, &info.producer)];
+        for (field, regex, value) in fields {
+            if let Some(regex) = regex {
+                if !value.as_deref().is_some_and(|v| regex.is_match(v)) {
+                    info!(filename=filename, field=field, value=value.as_deref(), regex=regex.as_str(); "PDF metadata doesn't match - skipping rule");
+                    return false
+                }
+            }
+        }
+        if let Some(range) = &self.pages {
+            if !info.pages.is_some_and(|pages| range.contains(pages)) {
+                info!(filename=filename, pages=info.pages, required=log::as_display!(range); "PDF page count doesn't match - skipping rule");
+                return false
+            }
+        }
+        true
+    }
+}
+
+/// A PDF's document information, with empty fields left unset.
+#[derive(Default)]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub producer: Option<String>,
+    pub pages: Option<u64>,
+}
+
+impl PdfInfo {
+    /// Reads the document information with `pdfinfo` from poppler.
+    pub fn read(path: &Path) -> Result<PdfInfo> {
+        let output = Command::new("pdfinfo").args(["-enc", "UTF-8"]).arg(path).output().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => "[pdfinfo] is not installed - it's needed for PDF metadata".to_string(),
+            _ => format!("unable to run [pdfinfo]: {e}"),
+        })?;
+        if !output.status.success() {
+            return Err(format!("[pdfinfo] failed to read [{}]: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()).into())
+        }
+
+        let mut info = PdfInfo::default();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) if !value.trim().is_empty() => (key, value.trim().to_string()),
+                _ => continue,
+            };
+            match key {
+                "Title" => info.title = Some(value),
+                "Author" => info.author = Some(value),
+                "Producer" => info.producer = Some(value),
+                "Pages" => info.pages = value.parse().ok(),
+                _ => {},
+            }
+        }
+        Ok(info)
+    }
+
+    /// A field by the name used in `{pdf:...}` template tokens.
+    pub fn field(&self, name: &str) -> Option<String> {
+        match name {
+            "title" => self.title.clone(),
+            "author" => self.author.clone(),
+            "producer" => self.producer.clone(),
+            "pages" => self.pages.map(|p| p.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `path` looks like a PDF, going by its extension.
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf"))
+}
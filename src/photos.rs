@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+
+use crate::config::DuplicateAction;
+use crate::fsutil;
+use crate::normalise::Normalisation;
+use crate::pattern::Pattern;
+use crate::plan::{self, MoveTarget};
+use crate::template;
+
+/// Extensions of camera RAW formats, compared case-insensitively.
+const RAW_EXTENSIONS: [&str; 19] = ["3fr", "arw", "cr2", "cr3", "crw", "dng", "erf", "nef", "nrw", "orf", "pef", "raf", "raw",
+    "rw2", "rwl", "sr2", "srf", "srw", "x3f"];
+
+/// Moves a photo together with the other files of the same shot, such as the JPEG a camera writes
+/// beside each RAW, sending RAW files and the rest into parallel directory trees.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PhotoConfig {
+    /// Where RAW files go, relative to `baseDir`.
+    pub raw: String,
+    /// Where every other file of the shot goes, such as its JPEG.
+    pub jpeg: String,
+    pub duplicate: DuplicateAction,
+    /// Extensions counted as RAW, in place of the common camera formats.
+    #[serde(rename="rawExtensions", default="default_raw_extensions")]
+    pub raw_extensions: Vec<String>,
+}
+
+fn default_raw_extensions() -> Vec<String> {
+    RAW_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+}
+
+impl PhotoConfig {
+    /// The destination for `path`: `raw` for RAW files, `jpeg` for everything else.
+    pub fn dest_for(&self, path: &Path) -> &str {
+        let extension = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+        match self.raw_extensions.iter().any(|r| r.eq_ignore_ascii_case(&extension)) {
+            true => &self.raw,
+            false => &self.jpeg,
+        }
+    }
+
+    /// The files of the shot `source` is part of: `source` first, then the files beside it with the
+    /// same name up to the extension that `regex` also matches.
+    fn shot(&self, source: &Path, regex: &Pattern) -> Vec<PathBuf> {
+        let mut shot = vec![source.to_path_buf()];
+        let (Some(dir), Some(stem)) = (source.parent(), source.file_stem()) else {
+            return shot
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return shot
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let partner = path != source && path.file_stem() == Some(stem)
+                && entry.file_type().is_ok_and(|t| t.is_file())
+                && regex.is_match(&entry.file_name().to_string_lossy());
+            if partner {
+                shot.push(path);
+            }
+        }
+        shot
+    }
+
+    /// Where each file of `source`'s shot would go, starting with `source` itself.
+    ///
+    /// Dates in the destinations come from the shot's oldest file, so a RAW and JPEG written either
+    /// side of midnight still end up in matching directories. The duplicate strategy covers the shot
+    /// as a whole: when any of its files is already at its destination, `skip` leaves them all
+    /// where they are, and `rename-date` gives them all the same date prefix.
+    pub fn targets(&self, base_dir: &Path, source: &Path, regex: &Pattern, form: Option<Normalisation>) -> Vec<(PathBuf, MoveTarget)> {
+        let shot = self.shot(source, regex);
+        let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+        let oldest = shot.iter().min_by_key(|path| modified(path)).unwrap_or(&shot[0]).clone();
+        let dests: Vec<PathBuf> = shot.iter().map(|path| {
+            let name = path.file_name().unwrap_or_default();
+            plan::existing_path(template::dest(base_dir, self.dest_for(path), &oldest, form).join(name), form)
+        }).collect();
+
+        let clash = dests.iter().any(|dest| dest.exists());
+        let prefix = fsutil::date_prefixed("");
+        shot.into_iter().zip(dests).map(|(path, dest)| {
+            let target = match (clash, &self.duplicate) {
+                (false, _) => MoveTarget::Move(dest),
+                (true, DuplicateAction::Skip) => MoveTarget::Skip(dest),
+                (true, DuplicateAction::Overwrite) if dest.exists() => MoveTarget::Replace(dest),
+                (true, DuplicateAction::Overwrite) => MoveTarget::Move(dest),
+                (true, DuplicateAction::RenameDate | DuplicateAction::Merge) => {
+                    let name = dest.file_name().unwrap_or_default().to_string_lossy().to_string();
+                    MoveTarget::Move(fsutil::fit(&dest.with_file_name(format!("{prefix}{name}"))))
+                },
+            };
+            (path, target)
+        }).collect()
+    }
+}
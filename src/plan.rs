@@ -0,0 +1,192 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{Action, Rule};
+use crate::duplicate::{self, Strategy};
+use crate::duration;
+use crate::episodes;
+use crate::fsutil;
+use crate::normalise::{self, Normalisation};
+use crate::split;
+use crate::template;
+
+/// Where a move would put a file, taking the rule's duplicate strategy into account.
+pub enum MoveTarget {
+    /// The destination already exists and the duplicate strategy leaves the file where it is.
+    Skip(PathBuf),
+    Move(PathBuf),
+    /// The destination exists and will be overwritten.
+    Replace(PathBuf),
+    /// The source directory's contents will be merged into the existing destination directory.
+    Merge(PathBuf),
+    /// The destination is already the same file, such as a hard link made by an earlier run.
+    Already(PathBuf),
+}
+
+impl MoveTarget {
+    pub fn path(&self) -> &Path {
+        match self {
+            MoveTarget::Skip(path) | MoveTarget::Move(path) | MoveTarget::Replace(path) | MoveTarget::Merge(path) | MoveTarget::Already(path) => path,
+        }
+    }
+}
+
+/// What an action would do, worked out without changing anything.
+pub enum Planned {
+    Move(MoveTarget),
+    /// Hard links the file, leaving it where it is.
+    Link(MoveTarget),
+    Extract(PathBuf),
+    Delete,
+    /// Deletes the file once this long has passed.
+    DeleteAfter(Duration),
+    Webhook(String),
+    /// Downloads subtitles in these languages.
+    Subtitles(Vec<String>),
+    /// Looks up the episode the name says it is, e.g. `Show S01E02`, and moves it into a directory.
+    /// `None` when the name doesn't say, which fails the action.
+    Episode(Option<String>, PathBuf),
+    /// Submits the torrent to the named client, with the category it would get.
+    Torrent(&'static str, Option<String>),
+    /// Checks the file's checksum and signature.
+    Verify,
+    /// Runs the named program.
+    Exec(String),
+    /// Moves the files of a photo's shot, the photo's own first.
+    Photo(Vec<MoveTarget>),
+    /// Re-compresses the archive into the path.
+    Recompress(PathBuf),
+    /// Splits the file into this many chunks beside the manifest at the path, or moves it there
+    /// whole when it's just one.
+    Split(PathBuf, u64),
+    /// Symlinks the file into each of the directories, those already linked being `Already`.
+    LinkFarm(Vec<MoveTarget>),
+}
+
+impl Planned {
+    /// Whether the rule's remaining actions would run after this one.
+    pub fn stops(&self) -> bool {
+        matches!(self, Planned::Move(MoveTarget::Skip(_) | MoveTarget::Already(_)) | Planned::Link(MoveTarget::Skip(_)))
+            || matches!(self, Planned::Photo(targets) if matches!(targets.first(), Some(MoveTarget::Skip(_))))
+    }
+}
+
+impl fmt::Display for Planned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Planned::Move(MoveTarget::Skip(existing)) => write!(f, "skip - {} already exists", existing.display()),
+            Planned::Move(MoveTarget::Move(to)) => write!(f, "move to {}", to.display()),
+            Planned::Move(MoveTarget::Replace(to)) => write!(f, "move to {}, replacing the existing copy", to.display()),
+            Planned::Move(MoveTarget::Merge(into)) => write!(f, "merge into {}", into.display()),
+            Planned::Move(MoveTarget::Already(at)) | Planned::Link(MoveTarget::Already(at)) => write!(f, "nothing to do - {} is already the same file", at.display()),
+            Planned::Link(MoveTarget::Skip(existing)) => write!(f, "skip - {} already exists", existing.display()),
+            Planned::Link(MoveTarget::Move(to)) => write!(f, "link to {}", to.display()),
+            Planned::Link(MoveTarget::Replace(to)) => write!(f, "link to {}, replacing the existing copy", to.display()),
+            Planned::Link(MoveTarget::Merge(into)) => write!(f, "link contents into {}", into.display()),
+            Planned::Extract(into) => write!(f, "extract into {}", into.display()),
+            Planned::Delete => write!(f, "delete"),
+            Planned::DeleteAfter(after) => write!(f, "delete in {}", duration::format(after)),
+            Planned::Webhook(method) => write!(f, "send webhook {method} request"),
+            Planned::Subtitles(languages) => write!(f, "download {} subtitles", languages.join(", ")),
+            Planned::Episode(Some(episode), into) => write!(f, "look up {episode} and move under {}", into.display()),
+            Planned::Episode(None, _) => write!(f, "fail - no season and episode in the name"),
+            Planned::Torrent(client, Some(category)) => write!(f, "submit to {client} as {category}"),
+            Planned::Torrent(client, None) => write!(f, "submit to {client}"),
+            Planned::Verify => write!(f, "verify checksum and signature"),
+            Planned::Exec(program) => write!(f, "run {program}"),
+            Planned::Photo(targets) => match targets.first() {
+                Some(MoveTarget::Skip(existing)) => write!(f, "skip the shot - {} already exists", existing.display()),
+                _ => {
+                    let moves: Vec<String> = targets.iter().map(|t| t.path().display().to_string()).collect();
+                    write!(f, "move shot to {}", moves.join(", "))
+                },
+            },
+            Planned::Recompress(to) => write!(f, "recompress into {}", to.display()),
+            Planned::LinkFarm(targets) => {
+                let links: Vec<String> = targets.iter().filter(|t| matches!(t, MoveTarget::Move(_))).map(|t| t.path().display().to_string()).collect();
+                match links.is_empty() {
+                    true => write!(f, "nothing to do - already symlinked"),
+                    false => write!(f, "symlink at {}", links.join(", ")),
+                }
+            },
+            Planned::Split(to, 1) => write!(f, "move to {}", to.display()),
+            Planned::Split(manifest, chunks) => write!(f, "split into {chunks} chunks listed in {}", manifest.display()),
+        }
+    }
+}
+
+/// Works out what `action`, one of `rule`'s, would do to `source` (named `name`).
+pub fn plan_action(base_dir: &Path, rule: &Rule, action: &Action, name: &str, source: &Path) -> Planned {
+    match action {
+        Action::Move { dest, duplicate, compare, rename } => Planned::Move(move_target(base_dir, dest, rename.as_deref(), &*duplicate::strategy(duplicate, *compare), name, source, rule.normalise_names)),
+        Action::LinkIntoLibrary { dest, duplicate, compare, rename } => Planned::Link(link_target(base_dir, dest, rename.as_deref(), &*duplicate::strategy(duplicate, *compare), name, source, rule.normalise_names)),
+        Action::Unzip { dest, .. } => Planned::Extract(template::dest(base_dir, dest, source, rule.normalise_names)),
+        Action::Delete => Planned::Delete,
+        Action::DeleteAfter { duration } => Planned::DeleteAfter(*duration),
+        Action::Webhook { method, .. } => Planned::Webhook(method.clone()),
+        Action::Subtitles { languages, .. } => Planned::Subtitles(languages.clone()),
+        Action::Episode { dest, .. } => Planned::Episode(episodes::parse(name).map(|e| format!("{} {}", e.show, e.code())), template::dest(base_dir, dest, source, rule.normalise_names)),
+        Action::Torrent(config) => Planned::Torrent(config.client.name(), config.category(&rule.regex, name)),
+        Action::Verify(_) => Planned::Verify,
+        Action::Exec { command, .. } => Planned::Exec(command.first().cloned().unwrap_or_default()),
+        Action::Photo(photo) => Planned::Photo(photo.targets(base_dir, source, &rule.regex, rule.normalise_names).into_iter().map(|(_, t)| t).collect()),
+        Action::Recompress(config) => Planned::Recompress(config.output(source)),
+        Action::LinkFarm(farm) => Planned::LinkFarm(farm.targets(base_dir, name, source, rule.normalise_names)),
+        Action::Split { chunk_size, dest } => {
+            let whole = existing_path(template::dest(base_dir, dest, source, rule.normalise_names).join(name), rule.normalise_names);
+            match split::chunk_count(fs::metadata(source).map(|m| m.len()).unwrap_or(0), *chunk_size) {
+                1 => Planned::Split(whole, 1),
+                chunks => Planned::Split(split::manifest_path(&whole), chunks),
+            }
+        },
+    }
+}
+
+pub fn move_target(base_dir: &Path, dest: &str, rename: Option<&str>, duplicate: &dyn Strategy, name: &str, source: &Path, form: Option<Normalisation>) -> MoveTarget {
+    let dir = template::dest(base_dir, dest, source, form);
+    let name = moved_name(rename, name, source, &dir);
+    resolve_duplicate(dir.join(name), duplicate, source, form)
+}
+
+/// Where a link into `dest` would go. A destination that's already the same file, e.g. linked on
+/// an earlier run, is left as it is whatever the duplicate strategy, so a file still being seeded
+/// isn't linked again on every rescan.
+pub fn link_target(base_dir: &Path, dest: &str, rename: Option<&str>, duplicate: &dyn Strategy, name: &str, source: &Path, form: Option<Normalisation>) -> MoveTarget {
+    let dir = template::dest(base_dir, dest, source, form);
+    let name = moved_name(rename, name, source, &dir);
+    let dest = existing_path(dir.join(name), form);
+    if dest.exists() && fsutil::same_inode(source, &dest).unwrap_or(false) {
+        return MoveTarget::Already(dest)
+    }
+    resolve_duplicate(dest, duplicate, source, form)
+}
+
+/// Where a move to `dest` would put `source`, given what's already there. With a `form`, a file
+/// whose name only differs from `dest`'s in normalisation counts as already being there.
+pub fn resolve_duplicate(dest: PathBuf, duplicate: &dyn Strategy, source: &Path, form: Option<Normalisation>) -> MoveTarget {
+    let dest = existing_path(dest, form);
+    if !dest.exists() {
+        return MoveTarget::Move(dest)
+    }
+    duplicate.resolve(source, dest)
+}
+
+/// `dest` as it would be written: in `form`, or as an existing name that only differs in
+/// normalisation, and short enough for the filesystem.
+pub fn existing_path(dest: PathBuf, form: Option<Normalisation>) -> PathBuf {
+    let dest = match (dest.parent(), dest.file_name()) {
+        (Some(dir), Some(name)) => normalise::resolve(dir, name, form),
+        _ => dest,
+    };
+    fsutil::fit(&dest)
+}
+
+/// The name `name` will have once moved into `dir`, after any `rename`.
+pub fn moved_name(rename: Option<&str>, name: &str, source: &Path, dir: &Path) -> String {
+    match rename {
+        Some(rename) => template::expand_name(rename, source, dir),
+        None => name.to_string(),
+    }
+}
@@ -0,0 +1,129 @@
+use std::ffi::CString;
+use std::io;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// A user, and optionally group, to act as. Either can be a name or a numeric id; the group
+/// defaults to the user's primary group.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RunAs {
+    pub user: String,
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunAs {
+    pub fn resolve(&self) -> Result<Credentials> {
+        let (uid, primary_gid) = match lookup_user(&self.user)? {
+            Some((uid, gid)) => (uid, Some(gid)),
+            None => match self.user.parse::<u32>() {
+                Ok(uid) => (uid, None),
+                Err(_) => return Err(format!("unknown user [{}]", self.user).into()),
+            },
+        };
+        let gid = match &self.group {
+            Some(group) => match lookup_group(group)? {
+                Some(gid) => gid,
+                None => group.parse().map_err(|_| format!("unknown group [{group}]"))?,
+            },
+            None => primary_gid.ok_or_else(|| format!("user [{}] has no passwd entry - set a group", self.user))?,
+        };
+        Ok(Credentials { uid, gid })
+    }
+}
+
+/// Permanently switches the whole process to `credentials`, dropping supplementary groups.
+/// Needs to be running as root.
+pub fn drop_to(credentials: Credentials) -> Result<()> {
+    // SAFETY: plain syscalls; glibc applies set*id to every thread of the process
+    unsafe {
+        if libc::setgroups(0, std::ptr::null()) != 0
+            || libc::setgid(credentials.gid) != 0
+            || libc::setuid(credentials.uid) != 0 {
+            return Err(format!("unable to drop privileges to uid {} gid {}: {}", credentials.uid, credentials.gid, io::Error::last_os_error()).into())
+        }
+        if credentials.uid != 0 && libc::setuid(0) == 0 {
+            return Err("privileges were not dropped - able to regain root".into())
+        }
+    }
+    Ok(())
+}
+
+/// Filesystem access as another user on the current thread, until dropped.
+///
+/// Only the filesystem ids change, so the thread can switch back afterwards. Must not be held
+/// across an `.await`, since the task could resume on another thread.
+pub struct FsIdentity(());
+
+impl FsIdentity {
+    pub fn assume(credentials: Credentials) -> Result<FsIdentity> {
+        // SAFETY: plain syscalls affecting only the calling thread. setfs*id don't report errors,
+        // so a second call returning the requested id is how success is detected.
+        unsafe {
+            libc::setfsgid(credentials.gid);
+            libc::setfsuid(credentials.uid);
+            if libc::setfsgid(credentials.gid) as u32 != credentials.gid || libc::setfsuid(credentials.uid) as u32 != credentials.uid {
+                restore_fs_ids();
+                return Err(format!("unable to act as uid {} gid {} - the daemon must be running as root", credentials.uid, credentials.gid).into())
+            }
+        }
+        Ok(FsIdentity(()))
+    }
+}
+
+impl Drop for FsIdentity {
+    fn drop(&mut self) {
+        restore_fs_ids();
+    }
+}
+
+fn restore_fs_ids() {
+    // SAFETY: plain syscalls affecting only the calling thread
+    unsafe {
+        libc::setfsuid(libc::geteuid());
+        libc::setfsgid(libc::getegid());
+    }
+}
+
+/// Uid and primary gid of the named user, or `None` if there is no such user.
+fn lookup_user(name: &str) -> Result<Option<(u32, u32)>> {
+    let c_name = CString::new(name)?;
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call and the buffer's length is passed
+    let err = unsafe { libc::getpwnam_r(c_name.as_ptr(), passwd.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if err != 0 {
+        return Err(format!("unable to look up user [{name}]: {}", io::Error::from_raw_os_error(err)).into())
+    }
+    if result.is_null() {
+        return Ok(None)
+    }
+    // SAFETY: getpwnam_r succeeded and found an entry, so passwd is initialised
+    let passwd = unsafe { passwd.assume_init() };
+    Ok(Some((passwd.pw_uid, passwd.pw_gid)))
+}
+
+/// Gid of the named group, or `None` if there is no such group.
+fn lookup_group(name: &str) -> Result<Option<u32>> {
+    let c_name = CString::new(name)?;
+    let mut group = std::mem::MaybeUninit::<libc::group>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call and the buffer's length is passed
+    let err = unsafe { libc::getgrnam_r(c_name.as_ptr(), group.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if err != 0 {
+        return Err(format!("unable to look up group [{name}]: {}", io::Error::from_raw_os_error(err)).into())
+    }
+    if result.is_null() {
+        return Ok(None)
+    }
+    // SAFETY: getgrnam_r succeeded and found an entry, so group is initialised
+    Ok(Some(unsafe { group.assume_init() }.gr_gid))
+}
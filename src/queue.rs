@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use inotify::EventMask;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::config::WatchDir;
+
+/// Order in which queued files are handed to workers.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+pub enum QueueOrder {
+    /// In the order their events arrived.
+    #[default]
+    #[serde(rename="fifo")]
+    Fifo,
+    #[serde(rename="smallest-first")]
+    SmallestFirst,
+    /// By modification time, oldest first.
+    #[serde(rename="oldest-first")]
+    OldestFirst,
+    /// By the `priority` of the first rule matching the file, highest first.
+    #[serde(rename="priority")]
+    Priority,
+}
+
+/// A file waiting to be processed.
+pub struct Job {
+    pub watch_dir: Arc<WatchDir>,
+    /// Subdirectory of the watch directory the file is in, empty unless watching recursively.
+    pub subdir: PathBuf,
+    pub name: OsString,
+    pub mask: EventMask,
+    /// Where the file came from when an action produced it, rather than it arriving in the watch
+    /// directory. `subdir` is then the absolute directory it was written to.
+    pub origin: Option<Arc<Origin>>,
+}
+
+/// How a derived file came about, which rules can match on with `fromArchive`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Origin {
+    /// The archive it was extracted from.
+    pub archive: PathBuf,
+    /// Regex of the rule whose action produced it.
+    pub rule: String,
+    /// How many actions deep the file is: 1 for a file extracted from a file that arrived.
+    pub depth: u32,
+}
+
+/// Attributes used to order jobs, captured when the job is queued.
+pub struct SortKey {
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub priority: i32,
+}
+
+impl QueueOrder {
+    /// Whether jobs are ordered by their size or modification time, which have to be read when
+    /// they're queued.
+    pub fn uses_metadata(&self) -> bool {
+        matches!(self, QueueOrder::SmallestFirst | QueueOrder::OldestFirst)
+    }
+
+    /// Where a job goes in the queue, lowest first. Ties go in arrival order.
+    fn rank(&self, key: &SortKey) -> i128 {
+        match self {
+            QueueOrder::Fifo => 0,
+            QueueOrder::SmallestFirst => key.size as i128,
+            QueueOrder::OldestFirst => key.mtime.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i128).unwrap_or(0),
+            QueueOrder::Priority => -(key.priority as i128),
+        }
+    }
+}
+
+struct Queued {
+    job: Job,
+    /// The watch directory's priority, which comes before the rank.
+    tier: i32,
+    rank: i128,
+    seq: u64,
+    queued: Instant,
+}
+
+// ordered so that the max-heap hands out the highest tier, then the lowest rank, then the earliest
+// arrival, first
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tier.cmp(&other.tier).then_with(|| (other.rank, other.seq).cmp(&(self.rank, self.seq)))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        (self.tier, self.rank, self.seq) == (other.tier, other.rank, other.seq)
+    }
+}
+
+impl Eq for Queued {}
+
+/// Jobs waiting for a worker, handed out according to a [`QueueOrder`].
+pub struct WorkQueue {
+    order: QueueOrder,
+    items: Mutex<(u64, BinaryHeap<Queued>)>,
+    notify: Notify,
+    popped: Notify,
+}
+
+impl WorkQueue {
+    pub fn new(order: QueueOrder) -> Self {
+        WorkQueue {
+            order,
+            items: Mutex::new((0, BinaryHeap::new())),
+            notify: Notify::new(),
+            popped: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, job: Job, key: SortKey) {
+        let mut items = self.items.lock().unwrap();
+        let seq = items.0;
+        items.0 += 1;
+        let tier = job.watch_dir.priority;
+        items.1.push(Queued { job, tier, rank: self.order.rank(&key), seq, queued: Instant::now() });
+        drop(items);
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next job, returning it with how long it was queued.
+    pub async fn pop(&self) -> (Job, Duration) {
+        loop {
+            if let Some(job) = self.take() {
+                return job
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn order(&self) -> QueueOrder {
+        self.order
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().1.len()
+    }
+
+    /// Waits until no more than `depth` jobs are queued.
+    pub async fn drained_to(&self, depth: usize) {
+        loop {
+            let popped = self.popped.notified();
+            if self.len() <= depth {
+                return
+            }
+            popped.await;
+        }
+    }
+
+    fn take(&self) -> Option<(Job, Duration)> {
+        let mut items = self.items.lock().unwrap();
+        let queued = items.1.pop()?;
+        self.popped.notify_waiters();
+        if !items.1.is_empty() {
+            // Let another idle worker pick up the remaining jobs.
+            self.notify.notify_one();
+        }
+        Some((queued.job, queued.queued.elapsed()))
+    }
+}
+
+/// Paths being processed, so that two events for the same file (such as `close-write` then
+/// `moved-to`) never have it processed by two workers at once.
+#[derive(Default)]
+pub struct InFlight {
+    /// Each path being processed, with the latest job for it that arrived in the meantime.
+    paths: Mutex<HashMap<PathBuf, Option<Job>>>,
+}
+
+impl InFlight {
+    /// Claims `path` for `job`, returning the job to go ahead with. When another worker has the
+    /// path, the job is kept to be run once that worker is done, replacing any kept before it.
+    pub fn claim(&self, path: &Path, job: Job) -> Option<Job> {
+        let mut paths = self.paths.lock().unwrap();
+        match paths.get_mut(path) {
+            Some(waiting) => {
+                *waiting = Some(job);
+                None
+            },
+            None => {
+                paths.insert(path.to_path_buf(), None);
+                Some(job)
+            },
+        }
+    }
+
+    /// Gives up the claim on `path`, returning the job that arrived for it while it was held.
+    pub fn release(&self, path: &Path) -> Option<Job> {
+        self.paths.lock().unwrap().remove(path).flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.lock().unwrap().is_empty()
+    }
+}
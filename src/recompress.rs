@@ -0,0 +1,270 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Re-compresses a downloaded zip more tightly before it's archived, trading CPU for space.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RecompressConfig {
+    #[serde(default)]
+    pub format: Format,
+    /// Compression level: 1 to 22 for zstd (default 19), 0 to 9 for deflate (default 9).
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub level: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A tar compressed with zstd, named after the zip with `.tar.zst` in place of `.zip`.
+    #[default]
+    #[serde(rename="tar.zst")]
+    TarZstd,
+    /// The same zip, with every entry deflated again at a higher level.
+    #[serde(rename="zip")]
+    Zip,
+}
+
+impl RecompressConfig {
+    pub fn validate(&self) -> Result<()> {
+        let range = match self.format {
+            Format::TarZstd => 1..=22,
+            Format::Zip => 0..=9,
+        };
+        match self.level {
+            Some(level) if !range.contains(&level) => {
+                Err(format!("recompress level {level} is outside {}..={} for {}", range.start(), range.end(), self.format.name()).into())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(feature="archives")]
+    fn level(&self) -> i32 {
+        self.level.unwrap_or(match self.format {
+            Format::TarZstd => 19,
+            Format::Zip => 9,
+        })
+    }
+
+    /// Where the re-compressed copy of the zip at `source` goes.
+    pub fn output(&self, source: &Path) -> PathBuf {
+        let name = source.file_name().unwrap_or_default().to_string_lossy();
+        match self.format {
+            Format::TarZstd => {
+                let stem = match name.len().checked_sub(4).filter(|&i| name.is_char_boundary(i)) {
+                    Some(i) if name[i..].eq_ignore_ascii_case(".zip") => &name[..i],
+                    _ => &name,
+                };
+                source.with_file_name(format!("{stem}.tar.zst"))
+            },
+            Format::Zip => source.to_path_buf(),
+        }
+    }
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::TarZstd => "tar.zst",
+            Format::Zip => "zip",
+        }
+    }
+}
+
+#[cfg(feature="archives")]
+pub use supported::recompress;
+
+#[cfg(feature="archives")]
+mod supported {
+    use std::fs::File;
+    use std::io::{self, BufWriter, Read, Write};
+    use std::path::{Path, PathBuf};
+    use chrono::TimeZone;
+
+    use crate::extract::{self, is_symlink};
+    use crate::Result;
+    use super::{Format, RecompressConfig};
+
+    /// Largest size the octal size field of a tar header holds.
+    const USTAR_MAX_SIZE: u64 = 0o77777777777;
+
+    /// Writes a re-compressed copy of the zip at `source` into the directory `into`, returning its
+    /// path there. The copy is read back before it's returned.
+    pub fn recompress(source: &Path, into: &Path, config: &RecompressConfig) -> Result<PathBuf> {
+        let mut archive = zip::ZipArchive::new(File::open(source)?)
+            .map_err(|e| format!("[{}] is not a zip archive: {e}", source.display()))?;
+        let output = into.join(config.output(source).file_name().unwrap_or_default());
+        match config.format {
+            Format::TarZstd => {
+                let mut encoder = zstd::Encoder::new(BufWriter::new(File::create(&output)?), config.level())?;
+                encoder.include_checksum(true)?;
+                let written = write_tar(&mut archive, &mut CountingWriter { inner: &mut encoder, count: 0 })?;
+                encoder.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+                // the frame checksum is checked as it's decoded
+                let decoded = io::copy(&mut zstd::Decoder::new(File::open(&output)?)?, &mut io::sink())?;
+                if decoded != written {
+                    return Err(format!("[{}] decodes to {decoded} bytes but {written} were written", output.display()).into())
+                }
+            },
+            Format::Zip => {
+                let mut writer = zip::ZipWriter::new(BufWriter::new(File::create(&output)?));
+                copy_zip(&mut archive, &mut writer, config.level())?;
+                writer.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+                if let Some(problem) = extract::verify(&output)? {
+                    return Err(format!("re-compressed [{}] is corrupt: {problem}", output.display()).into())
+                }
+            },
+        }
+        Ok(output)
+    }
+
+    fn copy_zip(archive: &mut zip::ZipArchive<File>, writer: &mut zip::ZipWriter<BufWriter<File>>, level: i32) -> Result<()> {
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let name = entry.name().to_string();
+            let mut options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(level))
+                .last_modified_time(entry.last_modified())
+                .large_file(entry.size() >= u32::MAX as u64);
+            if let Some(mode) = entry.unix_mode() {
+                options = options.unix_permissions(mode & 0o7777);
+            }
+            if is_symlink(entry.unix_mode()) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                writer.add_symlink(name, target, options)?;
+            } else if entry.is_dir() {
+                writer.add_directory(name, options)?;
+            } else {
+                writer.start_file(name, options)?;
+                io::copy(&mut entry, writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the zip's entries to `out` as a tar, returning how many bytes that took. Entries whose
+    /// paths would leave the archive's root are refused, as `unzip` would skip them.
+    fn write_tar(archive: &mut zip::ZipArchive<File>, out: &mut CountingWriter<impl Write>) -> Result<u64> {
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let path = entry.enclosed_name().map(|p| p.to_string_lossy().to_string())
+                .ok_or_else(|| format!("entry [{}] leaves the archive's root - not re-compressing it", entry.name()))?;
+            let mtime = mtime(entry.last_modified());
+            let mode = entry.unix_mode().unwrap_or(0);
+            if is_symlink(entry.unix_mode()) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                write_header(out, &path, b'2', 0o777, 0, mtime, &target)?;
+            } else if entry.is_dir() {
+                let mode = if mode & 0o7777 == 0 { 0o755 } else { mode & 0o7777 };
+                write_header(out, &format!("{}/", path.trim_end_matches('/')), b'5', mode, 0, mtime, "")?;
+            } else {
+                let mode = if mode & 0o7777 == 0 { 0o644 } else { mode & 0o7777 };
+                let size = entry.size();
+                write_header(out, &path, b'0', mode, size, mtime, "")?;
+                let copied = io::copy(&mut entry, out)?;
+                if copied != size {
+                    return Err(format!("entry [{path}] is {copied} bytes but the zip says {size}").into())
+                }
+                pad(out, size)?;
+            }
+        }
+        // two empty blocks end the archive
+        out.write_all(&[0; 1024])?;
+        Ok(out.count)
+    }
+
+    /// Writes a ustar header, preceded by a PAX extended header for a path, link target or size
+    /// that doesn't fit in it.
+    fn write_header(out: &mut impl Write, path: &str, kind: u8, mode: u32, size: u64, mtime: u64, link: &str) -> Result<()> {
+        let mut records = String::new();
+        if path.len() > 100 {
+            records.push_str(&pax_record("path", path));
+        }
+        if link.len() > 100 {
+            records.push_str(&pax_record("linkpath", link));
+        }
+        if size > USTAR_MAX_SIZE {
+            records.push_str(&pax_record("size", &size.to_string()));
+        }
+        if !records.is_empty() {
+            out.write_all(&header("././@PaxHeader", b'x', 0o644, records.len() as u64, mtime, ""))?;
+            out.write_all(records.as_bytes())?;
+            pad(out, records.len() as u64)?;
+        }
+        out.write_all(&header(path, kind, mode, size.min(USTAR_MAX_SIZE), mtime, link))?;
+        Ok(())
+    }
+
+    fn header(path: &str, kind: u8, mode: u32, size: u64, mtime: u64, link: &str) -> [u8; 512] {
+        let mut block = [0u8; 512];
+        let truncated = |value: &str, len: usize| value.as_bytes()[..value.len().min(len)].to_vec();
+        block[..100.min(path.len())].copy_from_slice(&truncated(path, 100));
+        block[100..108].copy_from_slice(format!("{mode:07o}\0").as_bytes());
+        block[108..116].copy_from_slice(b"0000000\0");
+        block[116..124].copy_from_slice(b"0000000\0");
+        block[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+        block[136..148].copy_from_slice(format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes());
+        block[156] = kind;
+        block[157..157 + 100.min(link.len())].copy_from_slice(&truncated(link, 100));
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        // the checksum is worked out with its own field as spaces
+        block[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+        block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+        block
+    }
+
+    /// A PAX record, `<length> <key>=<value>\n`, where the length counts itself.
+    fn pax_record(key: &str, value: &str) -> String {
+        let rest = key.len() + value.len() + 3;
+        let mut length = rest + rest.to_string().len();
+        if length.to_string().len() != rest.to_string().len() {
+            length += 1;
+        }
+        format!("{length} {key}={value}\n")
+    }
+
+    fn pad(out: &mut impl Write, written: u64) -> io::Result<()> {
+        let padding = (512 - written % 512) % 512;
+        out.write_all(&vec![0; padding as usize])
+    }
+
+    /// Seconds since the epoch of a zip entry's modification time, which zips keep in local time.
+    fn mtime(modified: zip::DateTime) -> u64 {
+        chrono::NaiveDate::from_ymd_opt(modified.year() as i32, modified.month() as u32, modified.day() as u32)
+            .and_then(|date| date.and_hms_opt(modified.hour() as u32, modified.minute() as u32, modified.second() as u32))
+            .and_then(|time| chrono::Local.from_local_datetime(&time).earliest())
+            .map(|time| time.timestamp().max(0) as u64)
+            .unwrap_or(0)
+    }
+
+    /// Counts what's written through it, to check the tar decodes to the same length.
+    struct CountingWriter<W> {
+        inner: W,
+        count: u64,
+    }
+
+    impl<W: Write> Write for CountingWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let written = self.inner.write(buf)?;
+            self.count += written as u64;
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+}
+
+/// Stand-in for when zip support isn't compiled in, where rules that re-compress are refused
+/// when the config is loaded.
+#[cfg(not(feature="archives"))]
+pub fn recompress(_source: &Path, _into: &Path, _config: &RecompressConfig) -> Result<PathBuf> {
+    crate::version::require("action", "recompress")?;
+    unreachable!("the archives feature is compiled in")
+}
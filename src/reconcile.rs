@@ -0,0 +1,189 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use chrono::{SecondsFormat, Utc};
+use log::{as_debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::deletions::Deletions;
+use crate::outcome::Sink;
+use crate::Result;
+
+/// Most paths listed in each part of the report, so a tree that's been reorganised by hand doesn't
+/// make an enormous one.
+const MAX_LISTED: usize = 100;
+
+/// Suffix of the hidden files a copy or split writes before renaming them into place.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Checks, when the organiser starts, that what the outcome history and saved state say matches
+/// the filesystem.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ReconcileConfig {
+    /// Delete stale partial files and drop scheduled deletions of files that are gone, rather than
+    /// only reporting them.
+    #[serde(rename="cleanUp", default)]
+    pub clean_up: bool,
+}
+
+/// What the check on start found.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Reconciliation {
+    pub finished: String,
+    /// Destinations in the outcome history that were checked, or none without a `jsonl` or
+    /// `sqlite` sink.
+    pub checked: usize,
+    /// Destinations files were organised to that are no longer there, having been moved or
+    /// deleted by something else.
+    pub missing: Listed,
+    /// Partial files left by copies or splits that were interrupted.
+    #[serde(rename="stalePartials")]
+    pub stale_partials: Listed,
+    /// Files still waiting out a `deleteAfter` delay.
+    #[serde(rename="pendingDeletions")]
+    pub pending_deletions: usize,
+    /// Deletions that fell due while the organiser wasn't running, which it makes straight away.
+    #[serde(rename="overdueDeletions")]
+    pub overdue_deletions: usize,
+    /// Deletions scheduled for files that are gone or have been replaced.
+    #[serde(rename="orphanedDeletions")]
+    pub orphaned_deletions: usize,
+    /// Whether stale partial files and orphaned deletions were cleaned up.
+    pub cleaned: bool,
+}
+
+/// Paths found, with how many there were in all.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Listed {
+    pub count: usize,
+    pub paths: Vec<PathBuf>,
+}
+
+impl Listed {
+    fn push(&mut self, path: PathBuf) {
+        self.count += 1;
+        if self.paths.len() < MAX_LISTED {
+            self.paths.push(path);
+        }
+    }
+}
+
+impl ReconcileConfig {
+    /// Checks the history in `sinks` and the `deletions` schedule against the filesystem, cleaning
+    /// up when set to and `dry_run` isn't. Run before any worker starts, so no partial file can be
+    /// one that's still being written.
+    pub fn run(&self, sinks: &[Sink], base_dir: &Path, deletions: &Deletions, dry_run: bool) -> Reconciliation {
+        let clean_up = self.clean_up && !dry_run;
+        let mut found = Reconciliation { cleaned: clean_up, ..Reconciliation::default() };
+
+        let dests = match history(sinks, base_dir) {
+            Ok(dests) => dests,
+            Err(err) => {
+                warn!(error=as_debug!(err); "unable to read the outcome history - not checking destinations");
+                BTreeSet::new()
+            },
+        };
+        found.checked = dests.len();
+        let mut dirs = BTreeSet::new();
+        for dest in dests {
+            if fs::symlink_metadata(&dest).is_err() {
+                found.missing.push(dest);
+            } else if let Some(dir) = dest.parent() {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+        for dir in dirs {
+            for partial in partials(&dir) {
+                if clean_up {
+                    let removed = match fs::symlink_metadata(&partial).map(|m| m.is_dir()) {
+                        Ok(true) => fs::remove_dir_all(&partial),
+                        _ => fs::remove_file(&partial),
+                    };
+                    if let Err(err) = removed {
+                        warn!(path=partial.to_str(), error=as_debug!(err); "unable to remove stale partial file");
+                    }
+                }
+                found.stale_partials.push(partial);
+            }
+        }
+
+        let schedule = deletions.reconcile(clean_up);
+        found.pending_deletions = schedule.pending;
+        found.overdue_deletions = schedule.overdue;
+        found.orphaned_deletions = schedule.orphaned;
+        found.finished = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        let log_level = match found.missing.count + found.stale_partials.count + found.orphaned_deletions {
+            0 => log::Level::Info,
+            _ => log::Level::Warn,
+        };
+        log::log!(log_level, checked=found.checked, missing=found.missing.count, stale_partials=found.stale_partials.count,
+            pending_deletions=found.pending_deletions, orphaned_deletions=found.orphaned_deletions, cleaned=found.cleaned;
+            "reconciled history with the filesystem");
+        found
+    }
+}
+
+/// Where files in the history were last organised to, leaving out destinations that were later
+/// organised again from. Read from the first `jsonl` or `sqlite` sink.
+fn history(sinks: &[Sink], base_dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut dests = BTreeSet::new();
+    let mut organised = |file: PathBuf, dest: Option<PathBuf>| {
+        dests.remove(&file);
+        dests.extend(dest);
+    };
+    for sink in sinks {
+        match sink {
+            Sink::Jsonl { file } => {
+                let file = match fs::File::open(base_dir.join(file)) {
+                    Ok(file) => file,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(dests),
+                    Err(err) => return Err(err.into()),
+                };
+                for line in io::BufReader::new(file).lines() {
+                    let Ok(outcome) = serde_json::from_str::<serde_json::Value>(&line?) else { continue };
+                    if outcome["result"] != "processed" {
+                        continue
+                    }
+                    let path = |key: &str| outcome[key].as_str().map(PathBuf::from);
+                    organised(path("file").unwrap_or_default(), path("dest"));
+                }
+                break
+            },
+            Sink::Sqlite { file } => {
+                let file = base_dir.join(file);
+                if !file.exists() {
+                    break
+                }
+                let output = Command::new("sqlite3")
+                    .arg("-batch")
+                    .arg("-separator").arg("\t")
+                    .arg(&file)
+                    .arg("SELECT file, dest FROM outcomes WHERE result = 'processed' ORDER BY rowid")
+                    .output()
+                    .map_err(|e| format!("unable to run [sqlite3] - is it installed? {e}"))?;
+                if !output.status.success() {
+                    return Err(format!("sqlite3 failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into())
+                }
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    let (file, dest) = line.split_once('\t').unwrap_or((line, ""));
+                    organised(PathBuf::from(file), Some(PathBuf::from(dest)).filter(|d| !d.as_os_str().is_empty()));
+                }
+                break
+            },
+            _ => {},
+        }
+    }
+    Ok(dests)
+}
+
+/// The partial files in `dir`, named like `.name.partial`.
+fn partials(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    entries.flatten()
+        .filter(|e| e.file_name().to_str().is_some_and(|name| name.starts_with('.') && name.ends_with(PARTIAL_SUFFIX)))
+        .map(|e| e.path())
+        .collect()
+}
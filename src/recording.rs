@@ -0,0 +1,89 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use inotify::EventMask;
+use log::{as_debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Name of the recording in `stateDir`.
+pub const FILE_NAME: &str = "events.jsonl";
+
+/// A watcher event as it was read, before the watch directory's filters were applied to it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Recorded {
+    /// When it was read, in RFC 3339 to the millisecond.
+    pub time: String,
+    pub profile: String,
+    #[serde(rename="watchDir")]
+    pub watch_dir: String,
+    /// The watch directory's path, which tells apart the directories a `*` in its path expands to.
+    pub root: PathBuf,
+    /// Relative to the watch directory.
+    pub path: PathBuf,
+    /// The inotify event mask, with fanotify events as the inotify events they stand for.
+    pub mask: u32,
+}
+
+impl Recorded {
+    pub fn mask(&self) -> EventMask {
+        EventMask::from_bits_truncate(self.mask)
+    }
+
+    pub fn time(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(&self.time).ok()
+    }
+}
+
+/// Appends every event a profile's watcher reads to a file, for `replay`.
+pub struct Recorder {
+    profile: String,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn open(file: &Path, profile: &str) -> Result<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(file)
+            .map_err(|e| format!("unable to open event recording [{}]: {e}", file.display()))?;
+        Ok(Recorder { profile: profile.to_string(), file: Mutex::new(file) })
+    }
+
+    /// Records an event for `path` in the watch directory called `watch_dir` at `root`. A failure
+    /// is logged rather than getting in the way of handling the event.
+    pub fn record(&self, watch_dir: &str, root: &Path, path: &Path, mask: EventMask) {
+        let event = Recorded {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            profile: self.profile.clone(),
+            watch_dir: watch_dir.to_string(),
+            root: root.to_path_buf(),
+            path: path.to_path_buf(),
+            mask: mask.bits(),
+        };
+        let written = serde_json::to_string(&event).map_err(|e| e.into())
+            .and_then(|line| self.file.lock().unwrap().write_all(format!("{line}\n").as_bytes()));
+        if let Err(err) = written {
+            warn!(path=path.to_str(), error=as_debug!(err); "unable to record event");
+        }
+    }
+}
+
+/// The events recorded for `profile` in `file`, in the order they were read.
+pub fn read(file: &Path, profile: &str) -> Result<Vec<Recorded>> {
+    let reader = BufReader::new(File::open(file).map_err(|e| format!("unable to open event recording [{}]: {e}", file.display()))?);
+    let mut events = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let event: Recorded = serde_json::from_str(&line)
+            .map_err(|e| format!("invalid event on line {} of [{}]: {e}", i + 1, file.display()))?;
+        if event.profile == profile {
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
@@ -0,0 +1,76 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use inotify::{Inotify, WatchMask};
+use log::{as_debug, info, warn};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt;
+
+use crate::control::{Profiles, Rejection, Request};
+use crate::Result;
+
+/// How long the config directory must be quiet before reloading, so the several renames of a
+/// ConfigMap update or an editor's save lead to one reload.
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// Name of the symlink Kubernetes swaps when a mounted ConfigMap changes.
+const CONFIGMAP_DATA: &str = "..data";
+
+/// Reloads every profile's rules whenever `file` changes, whether written in place, replaced by
+/// rename, or updated through a mounted ConfigMap.
+pub fn watch(file: PathBuf, profiles: Profiles) -> Result<()> {
+    let dir = file.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = file.file_name().ok_or_else(|| format!("config file [{}] has no file name", file.display()))?.to_os_string();
+    let inotify = Inotify::init()?;
+    inotify.watches().add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE)
+        .map_err(|e| format!("unable to watch config directory [{}]: {e}", dir.display()))?;
+    info!(config_file=file.to_str(); "reloading rules when the config file changes");
+
+    tokio::spawn(async move {
+        let mut stream = match inotify.into_event_stream([0; 1024]) {
+            Ok(stream) => stream,
+            Err(err) => return warn!(error=as_debug!(err); "unable to read config file events - changes won't be reloaded"),
+        };
+        while let Some(event) = stream.next().await {
+            let changed = match &event {
+                Ok(event) => event.name.as_deref().is_some_and(|n| n == name || n == OsStr::new(CONFIGMAP_DATA)),
+                Err(err) => {
+                    warn!(error=as_debug!(err); "error reading config file events");
+                    false
+                },
+            };
+            if !changed {
+                continue
+            }
+            while let Ok(Some(_)) = tokio::time::timeout(SETTLE, stream.next()).await {}
+            info!(config_file=file.to_str(); "config file changed - reloading");
+            reload(&profiles).await;
+        }
+    });
+    Ok(())
+}
+
+/// Reloads every profile's rules on SIGHUP, as `systemctl reload` or `kill -HUP` sends.
+pub fn on_hangup(profiles: Profiles) -> Result<()> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("SIGHUP received - reloading");
+            reload(&profiles).await;
+        }
+    });
+    Ok(())
+}
+
+async fn reload(profiles: &Profiles) {
+    for (name, sender) in profiles {
+        let (reply, response) = oneshot::channel();
+        if sender.send(Request::Reload(reply)).await.is_err() {
+            continue
+        }
+        if let Ok(Err(Rejection(_, message))) = response.await {
+            warn!(profile=name, error=message; "unable to reload config - keeping the current rules");
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{as_debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::WatchDir;
+use crate::control::{Rejection, Request};
+use crate::Result;
+
+/// How often the mount table is read for volumes coming and going.
+const POLL: Duration = Duration::from_secs(2);
+
+/// A removable volume, such as a camera's SD card, with a directory that's organised whenever
+/// it's mounted.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Volume {
+    /// Filesystem label, as listed in `/dev/disk/by-label`.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub label: Option<String>,
+    /// Filesystem UUID, as listed in `/dev/disk/by-uuid`.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub uuid: Option<String>,
+    /// Where the volume is mounted, for volumes without a usable label or UUID.
+    #[serde(rename="mountPoint", skip_serializing_if="Option::is_none")]
+    pub mount_point: Option<PathBuf>,
+    #[serde(default)]
+    pub mode: VolumeMode,
+    /// The directory to organise, with `path` relative to the root of the volume.
+    #[serde(flatten)]
+    pub dir: WatchDir,
+}
+
+/// What happens while a volume is mounted.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum VolumeMode {
+    /// Organise what's already there, then watch it until it's unmounted.
+    #[default]
+    #[serde(rename="watch")]
+    Watch,
+    /// Organise what's already there once.
+    #[serde(rename="once")]
+    Once,
+}
+
+/// An entry of the mount table.
+struct Mount {
+    /// Device the filesystem is on, such as `/dev/sdb1`.
+    source: PathBuf,
+    target: PathBuf,
+}
+
+impl Volume {
+    pub fn validate(&self) -> Result<()> {
+        if self.label.is_none() && self.uuid.is_none() && self.mount_point.is_none() {
+            return Err(format!("removable volume [{}] needs a label, uuid or mountPoint to recognise it by", self.dir.name).into())
+        }
+        if self.dir.path.is_absolute() {
+            return Err(format!("removable volume [{}] path must be relative to the volume", self.dir.name).into())
+        }
+        self.dir.validate()
+    }
+
+    /// Where the volume is mounted, if it is.
+    fn find<'a>(&self, mounts: &'a [Mount]) -> Option<&'a Mount> {
+        let device = |dir: &str, name: &str| fs::canonicalize(Path::new(dir).join(udev_escape(name))).ok();
+        let label = self.label.as_deref().map(|l| device("/dev/disk/by-label", l));
+        let uuid = self.uuid.as_deref().map(|u| device("/dev/disk/by-uuid", u));
+        mounts.iter().find(|mount| {
+            label.as_ref().is_none_or(|d| d.as_ref() == Some(&mount.source))
+                && uuid.as_ref().is_none_or(|d| d.as_ref() == Some(&mount.source))
+                && self.mount_point.as_ref().is_none_or(|p| p == &mount.target)
+        })
+    }
+}
+
+/// Follows the mount table, asking the organiser behind `sender` to organise each volume's
+/// directory when it's mounted, including ones mounted already, and to stop watching it when it's
+/// unmounted. Volumes are mounted by whatever normally does it, such as udisks.
+pub fn watch(volumes: Vec<Volume>, profile: String, sender: mpsc::Sender<Request>) {
+    if volumes.is_empty() {
+        return
+    }
+    tokio::spawn(async move {
+        // names of the volumes found mounted
+        let mut attached: HashSet<String> = HashSet::new();
+        let mut interval = tokio::time::interval(POLL);
+        loop {
+            interval.tick().await;
+            let mounts = match mounts() {
+                Ok(mounts) => mounts,
+                Err(err) => {
+                    warn!(error=as_debug!(err); "unable to read the mount table - not following removable volumes");
+                    return
+                },
+            };
+            for volume in &volumes {
+                let name = &volume.dir.name;
+                match (volume.find(&mounts), attached.contains(name)) {
+                    (Some(mount), false) => {
+                        let dir = WatchDir { path: mount.target.join(&volume.dir.path), ..volume.dir.clone() };
+                        let path = dir.path.clone();
+                        let (reply, response) = oneshot::channel();
+                        if sender.send(Request::Mounted(dir, volume.mode, reply)).await.is_err() {
+                            return
+                        }
+                        match response.await {
+                            Ok(Ok(queued)) => info!(profile=profile, volume=name, path=path.to_str(), queued=queued; "removable volume mounted"),
+                            Ok(Err(Rejection(_, message))) => warn!(profile=profile, volume=name, path=path.to_str(), error=message; "unable to organise removable volume"),
+                            Err(_) => return,
+                        }
+                        attached.insert(name.clone());
+                    },
+                    (None, true) => {
+                        if volume.mode == VolumeMode::Watch && sender.send(Request::Unmounted(name.clone())).await.is_err() {
+                            return
+                        }
+                        info!(profile=profile, volume=name; "removable volume unmounted");
+                        attached.remove(name);
+                    },
+                    _ => {},
+                }
+            }
+        }
+    });
+}
+
+/// The mounts in `/proc/self/mountinfo`.
+fn mounts() -> Result<Vec<Mount>> {
+    let table = fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(table.lines().filter_map(|line| {
+        let (fields, filesystem) = line.split_once(" - ")?;
+        let target = fields.split(' ').nth(4)?;
+        let source = filesystem.split(' ').nth(1)?;
+        // a device is listed by the path it was mounted with, which may be a by-label link
+        let source = PathBuf::from(unescape(source));
+        let source = match source.is_absolute() {
+            true => fs::canonicalize(&source).unwrap_or(source),
+            false => source,
+        };
+        Some(Mount { source, target: PathBuf::from(unescape(target)) })
+    }).collect())
+}
+
+/// Undoes the octal escapes, such as `\040` for a space, in a mount table field.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes.get(i + 1..i + 4).and_then(|d| std::str::from_utf8(d).ok()).and_then(|d| u8::from_str_radix(d, 8).ok());
+        match (bytes[i], code) {
+            (b'\\', Some(code)) => {
+                out.push(code);
+                i += 4;
+            },
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A label as udev writes it in `/dev/disk/by-label`, with ASCII other than letters, digits and a
+/// few punctuation marks escaped as `\xNN`.
+fn udev_escape(name: &str) -> String {
+    name.chars().map(|c| match c {
+        '0'..='9' | 'A'..='Z' | 'a'..='z' | '#' | '+' | '-' | '.' | ':' | '=' | '@' | '_' => c.to_string(),
+        c if c.is_ascii() => format!("\\x{:02x}", c as u8),
+        c => c.to_string(),
+    }).collect()
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{DateTime, Local, NaiveTime};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::fsutil;
+use crate::outcome::{ProcessingOutcome, Status};
+use crate::size;
+use crate::template;
+use crate::Result;
+
+/// Most failed or unmatched files listed in a report, so a bad day doesn't make an unreadable one.
+const MAX_LISTED: usize = 50;
+
+/// A summary of what the organiser did, written once a day.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ReportConfig {
+    /// Local time of day it's written, such as `07:00`.
+    #[serde(default, deserialize_with="deserialize_time", serialize_with="serialize_time")]
+    pub at: NaiveTime,
+    /// Where it's written, relative to `baseDir`, such as `Reports/{now:%Y-%m-%d}.md`.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub format: ReportFormat,
+    /// Send it to the notification channels too.
+    #[serde(default)]
+    pub notify: bool,
+    /// Names of the `notifications` channels to send it to. Every channel when unset.
+    #[serde(rename="notifyVia", skip_serializing_if="Option::is_none")]
+    pub notify_via: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    #[default]
+    #[serde(rename="markdown")]
+    Markdown,
+    #[serde(rename="html")]
+    Html,
+}
+
+impl ReportConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.file.is_none() && !self.notify {
+            return Err("report needs a file to write, or notify set".into())
+        }
+        if let Some(file) = &self.file {
+            template::validate(file).map_err(|e| format!("report file: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// How long until the next report is due.
+    pub fn until_next(&self) -> Duration {
+        let now = Local::now();
+        let today = now.date_naive().and_time(self.at);
+        let next = match today > now.naive_local() {
+            true => today,
+            false => today + chrono::Duration::days(1),
+        };
+        (next - now.naive_local()).to_std().unwrap_or_default()
+    }
+
+    /// Writes the report on `period` to its file, returning where it went.
+    pub fn write(&self, base_dir: &Path, period: &Period) -> Result<Option<PathBuf>> {
+        let Some(file) = &self.file else { return Ok(None) };
+        let path = base_dir.join(template::expand(file, base_dir));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, period.render(self.format))
+            .map_err(|e| format!("unable to write report [{}]: {e}", path.display()))?;
+        Ok(Some(path))
+    }
+}
+
+/// What happened to files since the last report.
+#[derive(Default)]
+pub struct Tally {
+    period: Mutex<Period>,
+}
+
+pub struct Period {
+    pub since: DateTime<Local>,
+    pub until: DateTime<Local>,
+    /// Files by result.
+    results: BTreeMap<&'static str, u64>,
+    /// Files organised and bytes moved, by rule.
+    rules: BTreeMap<String, (u64, u64)>,
+    failures: Vec<(PathBuf, String)>,
+    unmatched: Vec<PathBuf>,
+}
+
+impl Default for Period {
+    fn default() -> Period {
+        let now = Local::now();
+        Period { since: now, until: now, results: BTreeMap::new(), rules: BTreeMap::new(), failures: Vec::new(), unmatched: Vec::new() }
+    }
+}
+
+impl Tally {
+    /// Counts `outcome` towards the next report.
+    pub fn record(&self, outcome: &ProcessingOutcome) {
+        let mut period = self.period.lock().unwrap();
+        *period.results.entry(outcome.result.name()).or_default() += 1;
+        match outcome.result {
+            Status::Processed => {
+                let bytes = outcome.dest.as_deref().and_then(|dest| fsutil::tree_size(dest).ok()).unwrap_or(0);
+                let rule = period.rules.entry(outcome.rule.clone().unwrap_or_default()).or_default();
+                rule.0 += 1;
+                rule.1 += bytes;
+            },
+            Status::Failed if period.failures.len() < MAX_LISTED => {
+                period.failures.push((outcome.file.clone(), outcome.reason.clone().unwrap_or_default()));
+            },
+            Status::NoMatch if period.unmatched.len() < MAX_LISTED => period.unmatched.push(outcome.file.clone()),
+            _ => {},
+        }
+    }
+
+    /// Everything counted since the last report, starting a new period.
+    pub fn take(&self) -> Period {
+        let mut period = std::mem::take(&mut *self.period.lock().unwrap());
+        period.until = Local::now();
+        period
+    }
+}
+
+impl Period {
+    fn count(&self, status: Status) -> u64 {
+        self.results.get(status.name()).copied().unwrap_or(0)
+    }
+
+    /// One line on the period, for a notification's title.
+    pub fn headline(&self) -> String {
+        format!("{} organised, {} failed, {} unmatched", self.count(Status::Processed), self.count(Status::Failed), self.count(Status::NoMatch))
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        let mut doc = Document { format, text: String::new() };
+        if format == ReportFormat::Html {
+            doc.text.push_str("<!DOCTYPE html>\n<meta charset=\"utf-8\">\n<title>Download organiser report</title>\n");
+        }
+        doc.heading(1, "Download organiser report");
+        doc.paragraph(&format!("{} to {}", self.since.format("%Y-%m-%d %H:%M"), self.until.format("%Y-%m-%d %H:%M")));
+
+        doc.heading(2, "Summary");
+        let summary: Vec<Vec<String>> = self.results.iter().map(|(result, files)| vec![result.to_string(), files.to_string()]).collect();
+        doc.table(&["Result", "Files"], &summary);
+        let bytes: u64 = self.rules.values().map(|(_, bytes)| bytes).sum();
+        doc.paragraph(&format!("{} moved.", size::format(bytes)));
+
+        if !self.rules.is_empty() {
+            doc.heading(2, "By rule");
+            let rows: Vec<Vec<String>> = self.rules.iter()
+                .map(|(rule, (files, bytes))| vec![doc.code(rule), files.to_string(), size::format(*bytes)])
+                .collect();
+            doc.table(&["Rule", "Files", "Size"], &rows);
+        }
+        let failures = self.failures.iter().map(|(file, reason)| format!("{} - {}", doc.code(&file.to_string_lossy()), doc.escape(reason))).collect();
+        self.list(&mut doc, "Failures", Status::Failed, failures);
+        let unmatched = self.unmatched.iter().map(|file| doc.code(&file.to_string_lossy())).collect();
+        self.list(&mut doc, "Unmatched", Status::NoMatch, unmatched);
+        doc.text
+    }
+
+    fn list(&self, doc: &mut Document, heading: &str, status: Status, items: Vec<String>) {
+        if items.is_empty() {
+            return
+        }
+        doc.heading(2, heading);
+        doc.list(&items);
+        let more = self.count(status).saturating_sub(items.len() as u64);
+        if more > 0 {
+            doc.paragraph(&format!("and {more} more"));
+        }
+    }
+}
+
+/// A report being written as Markdown or HTML.
+struct Document {
+    format: ReportFormat,
+    text: String,
+}
+
+impl Document {
+    fn heading(&mut self, level: usize, text: &str) {
+        let _ = match self.format {
+            ReportFormat::Markdown => writeln!(self.text, "{} {text}\n", "#".repeat(level)),
+            ReportFormat::Html => writeln!(self.text, "<h{level}>{}</h{level}>", self.escape(text)),
+        };
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        let _ = match self.format {
+            ReportFormat::Markdown => writeln!(self.text, "{text}\n"),
+            ReportFormat::Html => writeln!(self.text, "<p>{}</p>", self.escape(text)),
+        };
+    }
+
+    /// A table whose cells are already escaped.
+    fn table(&mut self, headings: &[&str], rows: &[Vec<String>]) {
+        match self.format {
+            ReportFormat::Markdown => {
+                let _ = writeln!(self.text, "| {} |", headings.join(" | "));
+                let _ = writeln!(self.text, "|{}", " --- |".repeat(headings.len()));
+                for row in rows {
+                    let _ = writeln!(self.text, "| {} |", row.join(" | "));
+                }
+                self.text.push('\n');
+            },
+            ReportFormat::Html => {
+                let cells = |tag: &str, cells: &[String]| cells.iter().map(|c| format!("<{tag}>{c}</{tag}>")).collect::<String>();
+                let _ = writeln!(self.text, "<table>\n<tr>{}</tr>", cells("th", &headings.iter().map(|h| h.to_string()).collect::<Vec<_>>()));
+                for row in rows {
+                    let _ = writeln!(self.text, "<tr>{}</tr>", cells("td", row));
+                }
+                self.text.push_str("</table>\n");
+            },
+        }
+    }
+
+    /// A list whose items are already escaped.
+    fn list(&mut self, items: &[String]) {
+        match self.format {
+            ReportFormat::Markdown => {
+                for item in items {
+                    let _ = writeln!(self.text, "- {item}");
+                }
+                self.text.push('\n');
+            },
+            ReportFormat::Html => {
+                let _ = writeln!(self.text, "<ul>\n{}</ul>", items.iter().map(|i| format!("<li>{i}</li>\n")).collect::<String>());
+            },
+        }
+    }
+
+    fn code(&self, text: &str) -> String {
+        match self.format {
+            // a regex's pipes would otherwise split its table cell
+            ReportFormat::Markdown => format!("`{}`", text.replace('`', "'").replace('|', "\\|")),
+            ReportFormat::Html => format!("<code>{}</code>", self.escape(text)),
+        }
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match self.format {
+            ReportFormat::Markdown => text.replace('|', "\\|"),
+            ReportFormat::Html => text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;"),
+        }
+    }
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<NaiveTime, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&value, "%H:%M").map_err(|_| serde::de::Error::custom(format!("[{value}] is not a time such as 07:00")))
+}
+
+fn serialize_time<S: Serializer>(time: &NaiveTime, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.format("%H:%M").to_string())
+}
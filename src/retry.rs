@@ -0,0 +1,172 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+use crate::duration;
+use crate::Result;
+
+/// An HTTP response status that network actions treat as a failure.
+#[derive(Debug)]
+pub struct HttpStatusError(pub u16);
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "remote returned HTTP status {}", self.0)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// A request that couldn't reach the remote, or timed out, as opposed to one it answered with an
+/// error. Only these count as `network` failures for `retryOn`.
+#[derive(Debug)]
+pub struct NetworkError(pub String);
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// Broad groups of actions that warrant different retry behaviour.
+#[derive(Debug, Clone, Copy)]
+pub enum ActionClass {
+    /// Filesystem operations - these rarely benefit from retrying.
+    Local,
+    /// Anything talking to a remote service.
+    Network,
+}
+
+/// Retry policies keyed by action class, set globally and optionally overridden per rule.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct RetryPolicies {
+    local: Option<RetryPolicy>,
+    network: Option<RetryPolicy>,
+}
+
+impl RetryPolicies {
+    /// Returns the policy for `class`, preferring `self` and falling back to `fallback`.
+    pub fn resolve(&self, fallback: &RetryPolicies, class: ActionClass) -> RetryPolicy {
+        let (own, fallback, default) = match class {
+            ActionClass::Local => (&self.local, &fallback.local, RetryPolicy::local()),
+            ActionClass::Network => (&self.network, &fallback.network, RetryPolicy::network()),
+        };
+        own.clone().or_else(|| fallback.clone()).unwrap_or(default)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for policy in self.local.iter().chain(&self.network) {
+            policy.validate()?;
+        }
+        Ok(())
+    }
+
+    /// These policies with the built-in defaults filled in for any class left unset.
+    pub fn effective(&self) -> RetryPolicies {
+        let none = RetryPolicies::default();
+        RetryPolicies {
+            local: Some(self.resolve(&none, ActionClass::Local)),
+            network: Some(self.resolve(&none, ActionClass::Network)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RetryPolicy {
+    #[serde(rename="maxAttempts", default="default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(rename="baseDelay", deserialize_with="duration::deserialize", serialize_with="duration::serialize", default="default_base_delay")]
+    base_delay: Duration,
+    #[serde(rename="maxDelay", deserialize_with="duration::deserialize", serialize_with="duration::serialize", default="default_max_delay")]
+    max_delay: Duration,
+    /// Fraction (0-1) of each delay that is randomised, to stop retries from many files lining up.
+    #[serde(default="default_jitter")]
+    jitter: f64,
+    /// Failures worth retrying: `network` (connection errors), `4xx`/`5xx` status classes, exact
+    /// statuses such as `429`, or `any`.
+    #[serde(rename="retryOn", default="default_retry_on")]
+    retry_on: Vec<String>,
+}
+
+fn default_max_attempts() -> u32 { 3 }
+fn default_base_delay() -> Duration { Duration::from_secs(1) }
+fn default_max_delay() -> Duration { Duration::from_secs(60) }
+fn default_jitter() -> f64 { 0.5 }
+fn default_retry_on() -> Vec<String> { vec!["network".into(), "5xx".into(), "429".into()] }
+
+impl RetryPolicy {
+    fn local() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            jitter: default_jitter(),
+            retry_on: vec!["any".into()],
+        }
+    }
+
+    fn network() -> Self {
+        RetryPolicy {
+            max_attempts: default_max_attempts(),
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            jitter: default_jitter(),
+            retry_on: default_retry_on(),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        for condition in &self.retry_on {
+            let known = match condition.as_str() {
+                "any" | "network" => true,
+                class if class.len() == 3 && class.ends_with("xx") => matches!(class.as_bytes()[0], b'1'..=b'5'),
+                code => code.parse::<u16>().is_ok_and(|c| (100..600).contains(&c)),
+            };
+            if !known {
+                return Err(format!("unknown retryOn condition [{condition}] - expected network, any, a status class such as 5xx or a status such as 429").into())
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the failure of attempt number `attempt` (starting at 1) should be retried.
+    pub fn should_retry(&self, attempt: u32, err: &(dyn std::error::Error + 'static)) -> bool {
+        if attempt >= self.max_attempts {
+            return false
+        }
+
+        let status = err.downcast_ref::<HttpStatusError>().map(|e| e.0);
+        let network = err.is::<NetworkError>();
+        self.retry_on.iter().any(|condition| match (condition.as_str(), status) {
+            ("any", _) => true,
+            ("network", _) => network,
+            (class, Some(status)) if class.len() == 3 && class.ends_with("xx") => {
+                class[..1].parse::<u16>().map(|c| c == status / 100).unwrap_or(false)
+            },
+            (code, Some(status)) => code.parse::<u16>().map(|c| c == status).unwrap_or(false),
+            _ => false,
+        })
+    }
+
+    /// Exponential backoff for the given attempt, capped at `maxDelay` and jittered.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self.base_delay.saturating_mul(2u32.pow(exponent)).min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * random_fraction())
+    }
+}
+
+/// Cheap non-cryptographic randomness, good enough to spread retries out.
+fn random_fraction() -> f64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    let mut x = STATE.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed) ^ seed;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
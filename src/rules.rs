@@ -0,0 +1,391 @@
+use std::fs;
+use std::path::Path;
+use log::{as_display, debug, info, warn};
+use regex::{RegexSet, RegexSetBuilder};
+
+use crate::config::{Action, HiddenPolicy, Rule};
+use crate::diskimage::{self, DiskImage, ImageCondition};
+use crate::fsutil;
+use crate::group::{GroupConditions, RuleGroup};
+use crate::header::{self, HeaderCondition};
+use crate::normalise;
+use crate::ocr::{self, ContentCondition};
+use crate::pdf::{self, PdfCondition, PdfInfo};
+use crate::size::SizeRange;
+use crate::plan;
+use crate::template;
+use crate::Result;
+
+/// A file or directory being matched against the rules.
+pub struct Candidate<'a> {
+    pub name: &'a str,
+    pub source: &'a Path,
+    pub is_dir: bool,
+    /// Name of the watch directory it arrived in, used for `appliesTo`. `None` considers every rule.
+    pub watch_dir: Option<&'a str>,
+    /// Size to check `minSize` against. Measured from `source` when unset.
+    pub size: Option<u64>,
+    /// How the entry is treated if its name starts with a dot.
+    pub hidden: HiddenPolicy,
+    /// Directory rule destinations are relative to. Destination conditions are only checked when set.
+    pub base_dir: Option<&'a Path>,
+    /// Whether `source` can be read for content and hard link conditions, which are ignored otherwise.
+    pub read_content: bool,
+    /// Whether it was extracted from an archive by an earlier rule, for `fromArchive`.
+    pub from_archive: bool,
+}
+
+/// Room the lazy DFA matching a rule index gets per rule. The regex crate's default of 2MiB for the
+/// whole set keeps a few hundred rules thrashing its cache, which makes the set slower than
+/// matching the rules one by one.
+const DFA_SIZE_PER_RULE: usize = 64 << 10;
+
+/// Most room the lazy DFA matching a rule index gets. It grows to this only as names need it.
+const DFA_SIZE_MAX: usize = 32 << 20;
+
+/// Rule regexes compiled into one set, so that a name is matched against all of them in a single
+/// pass, and only the rules whose regex matched have the rest of their conditions checked. Worth it
+/// with hundreds of rules, or many files going through the same rules as in a rescan.
+pub struct RuleIndex {
+    /// `None` if the regexes are too big to combine, when they're matched one at a time.
+    regexes: Option<RegexSet>,
+    /// Positions of the rules in the set, by their position in it. Rules on the fancy engine are
+    /// left out and matched one at a time.
+    indexed: Vec<usize>,
+}
+
+impl RuleIndex {
+    pub fn new(rules: &[Rule]) -> RuleIndex {
+        let indexed: Vec<usize> = rules.iter().enumerate().filter(|(_, r)| r.regex.as_regex().is_some()).map(|(i, _)| i).collect();
+        let dfa_size = (indexed.len() * DFA_SIZE_PER_RULE).clamp(2 << 20, DFA_SIZE_MAX);
+        let regexes = match RegexSetBuilder::new(indexed.iter().map(|&i| rules[i].regex.as_str())).dfa_size_limit(dfa_size).build() {
+            Ok(regexes) => Some(regexes),
+            Err(err) => {
+                warn!(error=as_display!(err); "rule regexes are too large to match together - matching them one by one");
+                None
+            },
+        };
+        RuleIndex { regexes, indexed }
+    }
+
+    /// Positions in `rules`, the rules the index was built from, of those whose regex matches
+    /// `name` and whose `excludeRegex` doesn't, in order.
+    pub fn candidates(&self, rules: &[Rule], name: &str) -> Vec<usize> {
+        let matched: Vec<usize> = match &self.regexes {
+            Some(regexes) => {
+                let fancy = rules.iter().enumerate().filter(|(_, rule)| rule.regex.as_regex().is_none() && rule.regex.is_match(name));
+                let mut matched: Vec<usize> = regexes.matches(name).into_iter().map(|j| self.indexed[j]).chain(fancy.map(|(i, _)| i)).collect();
+                matched.sort_unstable();
+                matched
+            },
+            None => rules.iter().enumerate().filter(|(_, rule)| rule.regex.is_match(name)).map(|(i, _)| i).collect(),
+        };
+        matched.into_iter().filter(|&i| {
+            let excluded = rules[i].excludes(name);
+            if excluded {
+                debug!(regex=rules[i].regex.as_str(), filename=name; "file matches the rule's excludeRegex - skipping rule");
+            }
+            !excluded
+        }).collect()
+    }
+
+    /// Returns the first of `rules`, the rules the index was built from, that should handle
+    /// `candidate`.
+    pub fn first_match<'a>(&self, rules: &'a [Rule], candidate: &Candidate) -> Result<Option<&'a Rule>> {
+        first_match_among(rules, self.candidates(rules, candidate.name), candidate)
+    }
+}
+
+/// Rules with their regexes compiled into a [`RuleIndex`].
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    index: RuleIndex,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> RuleSet {
+        let index = RuleIndex::new(&rules);
+        RuleSet { rules, index }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The rules whose regex matches `name`, and whose `excludeRegex` doesn't, in order.
+    pub fn matching(&self, name: &str) -> impl Iterator<Item=&Rule> {
+        self.index.candidates(&self.rules, name).into_iter().map(|i| &self.rules[i])
+    }
+
+    /// Returns the first rule that should handle `candidate`.
+    pub fn first_match(&self, candidate: &Candidate) -> Result<Option<&Rule>> {
+        self.index.first_match(&self.rules, candidate)
+    }
+}
+
+/// Returns the first rule that should handle `candidate`, checking only the rules at the positions
+/// in `matched`, those whose regex matches its name.
+fn first_match_among<'a>(rules: &'a [Rule], matched: Vec<usize>, candidate: &Candidate) -> Result<Option<&'a Rule>> {
+    let name = candidate.name;
+    let hidden = is_hidden(name);
+    if hidden && candidate.hidden == HiddenPolicy::Ignore {
+        debug!(filename=name; "ignoring hidden entry");
+        return Ok(None)
+    }
+    if matched.is_empty() {
+        debug!(filename=name; "no rule regex matched file");
+    }
+    let applicable = matched.into_iter().map(|i| &rules[i])
+        .filter(|r| candidate.watch_dir.map(|w| r.applies_to(w)).unwrap_or(true))
+        .filter(|r| r.kind_matches(candidate.is_dir))
+        .filter(|r| r.from_archive.map(|f| f == candidate.from_archive).unwrap_or(true))
+        .filter(|r| !hidden || candidate.hidden == HiddenPolicy::Process || r.match_hidden);
+
+    let mut probe = Probe::default();
+    for rule in applicable {
+        debug!(regex=rule.regex.as_str(), filename=name; "rule matched regex for file");
+        if let Some(group) = &rule.group {
+            if !probe.group_matches(group, candidate)? {
+                continue
+            }
+        }
+        if let (Some(base_dir), Some(dest)) = (candidate.base_dir, rule.destination()) {
+            let dest = template::dest(base_dir, dest, candidate.source, rule.normalise_names);
+            if !destination_has_room(rule, &dest)? || !destination_state_matches(rule, &dest, candidate)? {
+                continue
+            }
+        }
+        if !probe.matches(&Conditions::of_rule(rule), candidate)? {
+            continue
+        }
+        return Ok(Some(rule))
+    }
+
+    Ok(None)
+}
+
+/// The conditions of a rule, or of the group it's in, that look at the entry itself.
+struct Conditions<'r> {
+    /// The rule's regex, or the group's, for the log.
+    label: &'r str,
+    min_size: Option<&'r SizeRange>,
+    max_size: Option<&'r SizeRange>,
+    is_hardlinked_elsewhere: Option<bool>,
+    hardlinked_into: Option<&'r Path>,
+    pdf: Option<&'r PdfCondition>,
+    image: Option<&'r ImageCondition>,
+    content: Option<&'r ContentCondition>,
+    header: Option<HeaderCondition<'r>>,
+}
+
+impl<'r> Conditions<'r> {
+    fn of_rule(rule: &'r Rule) -> Conditions<'r> {
+        Conditions {
+            label: rule.regex.as_str(),
+            min_size: rule.min_size.as_ref(),
+            max_size: rule.max_size.as_ref(),
+            is_hardlinked_elsewhere: rule.is_hardlinked_elsewhere,
+            hardlinked_into: rule.hardlinked_into.as_deref(),
+            pdf: rule.pdf.as_ref(),
+            image: rule.image.as_ref(),
+            content: rule.content.as_ref(),
+            header: rule.header(),
+        }
+    }
+
+    fn of_group(group: &'r GroupConditions) -> Conditions<'r> {
+        Conditions {
+            label: group.regex.as_ref().map(|r| r.as_str()).unwrap_or("group"),
+            min_size: group.min_size.as_ref(),
+            max_size: group.max_size.as_ref(),
+            is_hardlinked_elsewhere: group.is_hardlinked_elsewhere,
+            hardlinked_into: group.hardlinked_into.as_deref(),
+            pdf: group.pdf.as_ref(),
+            image: group.image.as_ref(),
+            content: group.content.as_ref(),
+            header: group.header(),
+        }
+    }
+}
+
+/// What's been found out about a candidate while trying rules, so that it's only found out once
+/// however many rules look at it.
+#[derive(Default)]
+struct Probe<'r> {
+    size: Option<u64>,
+    /// Extracted text by language list, so OCR runs once per language list.
+    texts: Vec<(&'r [String], String)>,
+    pdf_info: Option<PdfInfo>,
+    image: Option<Option<DiskImage>>,
+    /// The first bytes of the file, with how many were asked for.
+    header: Option<(usize, Vec<u8>)>,
+    /// Whether the candidate met each group's conditions, by group id.
+    groups: Vec<(usize, bool)>,
+}
+
+impl<'r> Probe<'r> {
+    /// Whether `candidate` meets the conditions of `group`, checking them only the first time.
+    fn group_matches(&mut self, group: &'r RuleGroup, candidate: &Candidate) -> Result<bool> {
+        if let Some((_, met)) = self.groups.iter().find(|(id, _)| *id == group.id) {
+            return Ok(*met)
+        }
+        let conditions = &group.conditions;
+        let met = conditions.regex.as_ref().is_none_or(|r| r.is_match(candidate.name))
+            && conditions.from_archive.is_none_or(|f| f == candidate.from_archive)
+            && self.matches(&Conditions::of_group(conditions), candidate)?;
+        if !met {
+            debug!(filename=candidate.name; "file doesn't meet the group's conditions - skipping its rules");
+        }
+        self.groups.push((group.id, met));
+        Ok(met)
+    }
+
+    /// Whether `candidate` meets `conditions`, logging why it doesn't.
+    fn matches(&mut self, conditions: &Conditions<'r>, candidate: &Candidate) -> Result<bool> {
+        let (label, name) = (conditions.label, candidate.name);
+        if conditions.min_size.is_some() || conditions.max_size.is_some() {
+            let size = match (candidate.size, self.size) {
+                (Some(size), _) | (None, Some(size)) => size,
+                (None, None) => *self.size.insert(fsutil::tree_size(candidate.source)?),
+            };
+            if ![conditions.min_size, conditions.max_size].into_iter().flatten().all(|range| range.contains(size)) {
+                info!(regex=label, filename=name, size=size; "file is outside the size limits for this rule - skipping rule");
+                return Ok(false)
+            }
+        }
+        if !candidate.read_content {
+            return Ok(true)
+        }
+        if !links_match(conditions, candidate)? {
+            return Ok(false)
+        }
+        if let Some(condition) = &conditions.header {
+            let needs = condition.needs();
+            let header = match &self.header {
+                Some((read, header)) if *read >= needs => header,
+                _ => &self.header.insert((needs, header::read(candidate.source, needs)?)).1,
+            };
+            if !condition.matches(header, label, name) {
+                return Ok(false)
+            }
+        }
+        if let Some(condition) = conditions.pdf {
+            if !pdf::is_pdf(candidate.source) {
+                info!(regex=label, filename=name; "file isn't a PDF, so can't meet the PDF condition - skipping rule");
+                return Ok(false)
+            }
+            let info = match &self.pdf_info {
+                Some(info) => info,
+                None => self.pdf_info.insert(PdfInfo::read(candidate.source)?),
+            };
+            if !condition.matches(info, name) {
+                return Ok(false)
+            }
+        }
+        if let Some(condition) = conditions.image {
+            let image = match &self.image {
+                Some(image) => image,
+                None => self.image.insert(diskimage::read(candidate.source)?),
+            };
+            match image {
+                Some(image) if condition.matches(image, name) => {},
+                Some(_) => return Ok(false),
+                None => {
+                    info!(regex=label, filename=name; "file isn't a disk image, so can't meet the image condition - skipping rule");
+                    return Ok(false)
+                },
+            }
+        }
+        if let Some(content) = conditions.content {
+            if !ocr::is_supported(candidate.source) {
+                info!(regex=label, filename=name; "file isn't a PDF or image, so can't meet the content condition - skipping rule");
+                return Ok(false)
+            }
+            let text = match self.texts.iter().find(|(languages, _)| *languages == content.languages.as_slice()) {
+                Some((_, text)) => text,
+                None => {
+                    let text = ocr::text(candidate.source, &content.languages)?;
+                    self.texts.push((&content.languages, text));
+                    &self.texts.last().unwrap().1
+                },
+            };
+            if !content.regex.is_match(text) {
+                info!(regex=label, filename=name, content=content.regex.as_str(); "file content doesn't match - skipping rule");
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Whether `name` is a dot-file or dot-directory.
+pub fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Checks hard link conditions, logging why the rule is skipped if they aren't met.
+fn links_match(conditions: &Conditions, candidate: &Candidate) -> Result<bool> {
+    if let Some(expected) = conditions.is_hardlinked_elsewhere {
+        let linked = fsutil::has_other_links(candidate.source)?;
+        if linked != expected {
+            info!(regex=conditions.label, filename=candidate.name, hardlinked=linked; "file's hard links don't match isHardlinkedElsewhere - skipping rule");
+            return Ok(false)
+        }
+    }
+    if let (Some(dir), Some(base_dir)) = (conditions.hardlinked_into, candidate.base_dir) {
+        if !fsutil::is_linked_into(candidate.source, &base_dir.join(dir))? {
+            info!(regex=conditions.label, filename=candidate.name, dir=dir.to_str(); "file isn't hard linked into hardlinkedInto - skipping rule");
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+/// Checks the rule's destination conditions, logging why the rule is skipped if they aren't met.
+fn destination_has_room(rule: &Rule, dest: &Path) -> Result<bool> {
+    if let Some(range) = &rule.dest_free_space {
+        let free = fsutil::free_space(dest)?;
+        if !range.contains(free) {
+            info!(regex=rule.regex.as_str(), dest=dest.to_str(), free_space=free, required=as_display!(range); "destination is short of free space - skipping rule");
+            return Ok(false)
+        }
+    }
+    if let Some(range) = &rule.dest_file_count {
+        let count = fsutil::entry_count(dest)?;
+        if !range.contains(count) {
+            info!(regex=rule.regex.as_str(), dest=dest.to_str(), file_count=count, allowed=as_display!(range); "destination has too many entries - skipping rule");
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+/// Checks `destExists` and `destNewerThanSource` against where the rule would put the candidate.
+fn destination_state_matches(rule: &Rule, dest: &Path, candidate: &Candidate) -> Result<bool> {
+    if rule.dest_exists.is_none() && rule.dest_newer_than_source.is_none() {
+        return Ok(true)
+    }
+    let target = match rule.actions.iter().find(|a| matches!(a, Action::Move { .. } | Action::LinkIntoLibrary { .. } | Action::Unzip { .. })) {
+        Some(Action::Move { rename, .. } | Action::LinkIntoLibrary { rename, .. }) => normalise::resolve(dest, plan::moved_name(rename.as_deref(), candidate.name, candidate.source, dest).as_ref(), rule.normalise_names),
+        _ => dest.to_path_buf(),
+    };
+    let existing = fs::symlink_metadata(&target).ok();
+
+    if let Some(expected) = rule.dest_exists {
+        if existing.is_some() != expected {
+            info!(regex=rule.regex.as_str(), dest=target.to_str(), exists=existing.is_some(); "destination existence doesn't match destExists - skipping rule");
+            return Ok(false)
+        }
+    }
+    if let Some(expected) = rule.dest_newer_than_source {
+        let newer = match &existing {
+            Some(dest) => dest.modified()? > fs::symlink_metadata(candidate.source)?.modified()?,
+            None => false,
+        };
+        if newer != expected {
+            info!(regex=rule.regex.as_str(), dest=target.to_str(), newer=newer; "destination age doesn't match destNewerThanSource - skipping rule");
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
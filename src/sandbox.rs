@@ -0,0 +1,226 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Restrictions applied to an `exec` action's command.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SandboxConfig {
+    #[serde(default="default_enabled")]
+    pub enabled: bool,
+    /// Extra paths the command may read. System directories needed to run programs are always
+    /// readable.
+    #[serde(default)]
+    pub read: Vec<PathBuf>,
+    /// Extra paths the command may write, on top of the action's `dests`.
+    #[serde(default)]
+    pub write: Vec<PathBuf>,
+    /// Whether the command may open IPv4/IPv6 sockets.
+    #[serde(default)]
+    pub network: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig { enabled: true, read: Vec::new(), write: Vec::new(), network: false }
+    }
+}
+
+/// Directories programs are read and executed from.
+const SYSTEM_PATHS: &[&str] = &["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/proc"];
+/// Devices commands commonly read or write.
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/urandom"];
+
+// Landlock filesystem access rights, from linux/landlock.h.
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+/// Everything up to and including `MAKE_SYM`, the rights known to the first Landlock ABI.
+const ACCESS_ABI_1: u64 = (1 << 13) - 1;
+const ACCESS_REFER: u64 = 1 << 13;
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+/// Rights that can be granted on a file rather than a directory.
+const ACCESS_FILE: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE;
+
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+const RULE_PATH_BENEATH: u32 = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// Syscalls a file-processing command has no business making.
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_ptrace, libc::SYS_mount, libc::SYS_umount2, libc::SYS_pivot_root, libc::SYS_chroot,
+    libc::SYS_reboot, libc::SYS_kexec_load, libc::SYS_init_module, libc::SYS_finit_module,
+    libc::SYS_delete_module, libc::SYS_bpf, libc::SYS_setns, libc::SYS_unshare, libc::SYS_swapon,
+    libc::SYS_swapoff, libc::SYS_keyctl, libc::SYS_add_key, libc::SYS_request_key,
+    libc::SYS_perf_event_open, libc::SYS_process_vm_readv, libc::SYS_process_vm_writev,
+];
+
+/// Confines `command` to reading `source` and writing `dests` (plus whatever `config` adds) with
+/// Landlock, and blocks privileged syscalls with a seccomp filter. The restrictions are applied
+/// in the child just before it executes.
+pub fn confine(command: &mut Command, config: &SandboxConfig, program: &Path, source: &Path, dests: &[PathBuf]) -> Result<()> {
+    if !config.enabled {
+        return Ok(())
+    }
+
+    let ruleset = Ruleset::new()?;
+    for path in SYSTEM_PATHS {
+        ruleset.allow_if_exists(Path::new(path), ACCESS_EXECUTE | ACCESS_READ_FILE | ACCESS_READ_DIR)?;
+    }
+    for path in DEVICES {
+        ruleset.allow_if_exists(Path::new(path), ACCESS_READ_FILE | ACCESS_WRITE_FILE)?;
+    }
+    if program.is_absolute() {
+        ruleset.allow(program, ACCESS_EXECUTE | ACCESS_READ_FILE)?;
+    }
+    ruleset.allow(source, ACCESS_READ_FILE | ACCESS_READ_DIR)?;
+    for path in &config.read {
+        ruleset.allow(path, ACCESS_READ_FILE | ACCESS_READ_DIR)?;
+    }
+    for path in dests.iter().chain(&config.write) {
+        fs::create_dir_all(path)?;
+        ruleset.allow(path, ruleset.handled)?;
+    }
+    let filter = seccomp_filter(config.network);
+    debug!(source=source.to_str(), dests=format!("{dests:?}"), network=config.network; "sandboxing command");
+
+    let ruleset_fd = ruleset.fd;
+    // SAFETY: the closure only makes syscalls on data prepared before forking - no allocation or locking
+    unsafe {
+        command.pre_exec(move || {
+            let program = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_ptr() as *mut _ };
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd.as_raw_fd(), 0) != 0
+                || libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program as *const libc::sock_fprog) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+struct Ruleset {
+    fd: OwnedFd,
+    /// Rights the ruleset restricts - everything this kernel's Landlock knows about.
+    handled: u64,
+}
+
+impl Ruleset {
+    fn new() -> Result<Ruleset> {
+        // SAFETY: a null attribute with the version flag only queries the supported ABI
+        let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0, CREATE_RULESET_VERSION) };
+        if abi < 1 {
+            return Err(format!("Landlock is not available on this kernel ({}) - set `sandbox: {{ enabled: false }}` to run the command unconfined", io::Error::last_os_error()).into())
+        }
+        let mut handled = ACCESS_ABI_1;
+        if abi >= 2 {
+            handled |= ACCESS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_TRUNCATE;
+        }
+
+        let attr = RulesetAttr { handled_access_fs: handled };
+        // SAFETY: attr is a valid ruleset attribute of the size passed
+        let fd = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr as *const RulesetAttr, std::mem::size_of::<RulesetAttr>(), 0) };
+        if fd < 0 {
+            return Err(format!("unable to create Landlock ruleset: {}", io::Error::last_os_error()).into())
+        }
+        // SAFETY: fd is a freshly created descriptor nothing else owns
+        Ok(Ruleset { fd: unsafe { OwnedFd::from_raw_fd(fd as i32) }, handled })
+    }
+
+    fn allow_if_exists(&self, path: &Path, access: u64) -> Result<()> {
+        match path.exists() {
+            true => self.allow(path, access),
+            false => Ok(()),
+        }
+    }
+
+    /// Grants `access` to `path` and, if it is a directory, everything below it.
+    fn allow(&self, path: &Path, access: u64) -> Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(format!("unable to open [{}] for the sandbox: {}", path.display(), io::Error::last_os_error()).into())
+        }
+        // SAFETY: fd was just opened and is closed when this goes out of scope
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut access = access & self.handled;
+        if !path.is_dir() {
+            access &= ACCESS_FILE;
+        }
+        let attr = PathBeneathAttr { allowed_access: access, parent_fd: fd.as_raw_fd() };
+        // SAFETY: attr is a valid path-beneath rule for the ruleset descriptor
+        let added = unsafe { libc::syscall(libc::SYS_landlock_add_rule, self.fd.as_raw_fd(), RULE_PATH_BENEATH, &attr as *const PathBeneathAttr, 0) };
+        if added != 0 {
+            return Err(format!("unable to add [{}] to the sandbox: {}", path.display(), io::Error::last_os_error()).into())
+        }
+        Ok(())
+    }
+}
+
+/// A filter that fails the denied syscalls (and internet sockets, unless `network`) with `EPERM`,
+/// and kills the command if it switches to another architecture's syscall table.
+fn seccomp_filter(network: bool) -> Vec<libc::sock_filter> {
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    const ARG0: u32 = 16;
+    let load = |offset| libc::sock_filter { code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16, jt: 0, jf: 0, k: offset };
+    let jump_eq = |value, jt, jf| libc::sock_filter { code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16, jt, jf, k: value };
+    let ret = |value| libc::sock_filter { code: (libc::BPF_RET | libc::BPF_K) as u16, jt: 0, jf: 0, k: value };
+    let deny = ret(libc::SECCOMP_RET_ERRNO | libc::EPERM as u32);
+
+    let mut filter = vec![
+        load(ARCH),
+        jump_eq(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(NR),
+    ];
+    for syscall in DENIED_SYSCALLS {
+        filter.push(jump_eq(*syscall as u32, 0, 1));
+        filter.push(deny);
+    }
+    if !network {
+        filter.extend([
+            jump_eq(libc::SYS_socket as u32, 0, 4),
+            load(ARG0),
+            jump_eq(libc::AF_INET as u32, 1, 0),
+            jump_eq(libc::AF_INET6 as u32, 0, 1),
+            deny,
+        ]);
+    }
+    filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    filter
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::Result;
+
+/// Reference to a credential, so secret values don't have to be written into rules.yml.
+///
+/// ```yaml
+/// token: { env: HOOK_TOKEN }
+/// token: { secretFile: /run/secrets/hook-token }
+/// token: { keyring: { service: download-organiser, account: hook } }
+/// token: "inline value"
+/// ```
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SecretRef {
+    Env { env: String },
+    File {
+        #[serde(rename="secretFile")]
+        secret_file: PathBuf,
+    },
+    Keyring { keyring: KeyringRef },
+    Plain(String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct KeyringRef {
+    service: String,
+    account: String,
+}
+
+/// A resolved secret value. Its `Debug` output never includes the value.
+pub struct Secret(String);
+
+impl SecretRef {
+    /// Looks up the current value. This is done at use rather than at load so rotated secret
+    /// files and keyring entries are picked up without a restart.
+    pub fn resolve(&self) -> Result<Secret> {
+        let value = match self {
+            SecretRef::Env { env } => std::env::var(env)
+                .map_err(|_| format!("secret environment variable [{env}] is not set"))?,
+            SecretRef::File { secret_file } => fs::read_to_string(secret_file)
+                .map_err(|e| format!("unable to read secret file [{}]: {e}", secret_file.display()))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            SecretRef::Keyring { keyring } => {
+                let output = Command::new("secret-tool")
+                    .args(["lookup", "service", &keyring.service, "account", &keyring.account])
+                    .output()
+                    .map_err(|e| format!("unable to run secret-tool for keyring lookup: {e}"))?;
+                if !output.status.success() || output.stdout.is_empty() {
+                    return Err(format!("no keyring entry for service [{}] account [{}]", keyring.service, keyring.account).into())
+                }
+                String::from_utf8(output.stdout)?.trim_end_matches(['\r', '\n']).to_string()
+            },
+            SecretRef::Plain(value) => value.clone(),
+        };
+        Ok(Secret(value))
+    }
+}
+
+impl fmt::Debug for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Env { env } => write!(f, "env:{env}"),
+            SecretRef::File { secret_file } => write!(f, "secretFile:{}", secret_file.display()),
+            SecretRef::Keyring { keyring } => write!(f, "keyring:{}/{}", keyring.service, keyring.account),
+            SecretRef::Plain(_) => f.write_str("[redacted]"),
+        }
+    }
+}
+
+/// Serialises the reference rather than the value, with inline values redacted.
+impl Serialize for SecretRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = match self {
+            SecretRef::Plain(_) => return serializer.serialize_str("[redacted]"),
+            _ => serializer.serialize_map(Some(1))?,
+        };
+        match self {
+            SecretRef::Env { env } => map.serialize_entry("env", env)?,
+            SecretRef::File { secret_file } => map.serialize_entry("secretFile", secret_file)?,
+            SecretRef::Keyring { keyring } => map.serialize_entry("keyring", keyring)?,
+            SecretRef::Plain(_) => unreachable!(),
+        }
+        map.end()
+    }
+}
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
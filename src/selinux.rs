@@ -0,0 +1,98 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Extended attribute holding a file's SELinux security context.
+const ATTRIBUTE: &str = "security.selinux";
+
+/// How files a rule puts in place are given the SELinux label of where they end up. A rename
+/// keeps the label a file had where it was downloaded, which a confined service such as Plex
+/// usually isn't allowed to read.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Relabel {
+    /// Give them the label of the directory they're in, as a file created there would get.
+    #[serde(rename="inherit")]
+    Inherit,
+    /// Run `restorecon` on them, giving them the label the policy's file contexts say they
+    /// should have.
+    #[serde(rename="restorecon")]
+    Restorecon,
+    /// Leave them as they are, for rules that shouldn't follow the top-level setting.
+    #[serde(rename="off")]
+    Off,
+}
+
+/// Relabels `paths`, and everything inside the directories among them. Nothing happens on a
+/// filesystem without SELinux labels.
+pub fn relabel(paths: &[PathBuf], how: Relabel) -> Result<()> {
+    match how {
+        Relabel::Off => Ok(()),
+        Relabel::Inherit => {
+            for path in paths {
+                let parent = path.parent().unwrap_or(Path::new("/"));
+                if let Some(label) = get(parent).map_err(|e| format!("unable to read the SELinux label of [{}]: {e}", parent.display()))? {
+                    set_all(path, &label)?;
+                }
+            }
+            Ok(())
+        },
+        Relabel::Restorecon => {
+            if paths.is_empty() {
+                return Ok(())
+            }
+            let status = Command::new("restorecon").arg("-R").arg("--").args(paths).status()
+                .map_err(|e| format!("unable to run restorecon: {e}"))?;
+            match status.success() {
+                true => Ok(()),
+                false => Err(format!("restorecon failed with {status}").into()),
+            }
+        },
+    }
+}
+
+fn set_all(path: &Path, label: &[u8]) -> Result<()> {
+    set(path, label).map_err(|e| format!("unable to set the SELinux label of [{}]: {e}", path.display()))?;
+    if fs::symlink_metadata(path)?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            set_all(&entry?.path(), label)?;
+        }
+    }
+    Ok(())
+}
+
+/// The label of `path`, or `None` if its filesystem doesn't have them.
+fn get(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(ATTRIBUTE)?;
+    let mut value = vec![0u8; 256];
+    loop {
+        // SAFETY: both strings are NUL-terminated and value is valid for value.len() bytes
+        let size = unsafe { libc::lgetxattr(c_path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if size >= 0 {
+            value.truncate(size as usize);
+            return Ok(Some(value))
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => return Ok(None),
+            Some(libc::ERANGE) => value.resize(value.len() * 4, 0),
+            _ => return Err(err),
+        }
+    }
+}
+
+fn set(path: &Path, label: &[u8]) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(ATTRIBUTE)?;
+    // SAFETY: both strings are NUL-terminated and label is valid for label.len() bytes
+    if unsafe { libc::lsetxattr(c_path.as_ptr(), name.as_ptr(), label.as_ptr().cast(), label.len(), 0) } != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Result;
+
+/// Parses a size such as `700MB`, `1.5GiB` or `4096`.
+///
+/// `kB`/`MB`/`GB`/`TB`/`PB` are SI (powers of 1000) and `KiB`/`MiB`/`GiB`/`TiB`/`PiB` are IEC
+/// (powers of 1024). Single letters (`k`, `m`, `g`, `t`, `p`) are IEC, as in `ls -h`. Units are
+/// case-insensitive and a bare number is bytes.
+pub fn parse(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount.parse()
+        .map_err(|_| format!("size [{value}] does not start with a number"))?;
+
+    let unit = unit.trim().to_ascii_lowercase();
+    let multiplier: u64 = match unit.as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000u64.pow(2),
+        "gb" => 1000u64.pow(3),
+        "tb" => 1000u64.pow(4),
+        "pb" => 1000u64.pow(5),
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "p" | "pib" => 1 << 50,
+        _ => return Err(format!("unknown size unit [{unit}] in [{value}]").into()),
+    };
+
+    Ok((amount * multiplier as f64).round() as u64)
+}
+
+/// Formats `bytes` in the largest IEC unit it has at least one of, such as `1.5GiB`.
+pub fn format(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1 << 10 {
+        return format!("{bytes}B")
+    }
+    let mut amount = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if amount < 1024.0 {
+            break
+        }
+        amount /= 1024.0;
+        unit = next;
+    }
+    format!("{amount:.1}{unit}")
+}
+
+/// Sizes a file may have, written as a comparison (`>= 700MB`, `< 10GB`), a range (`700MB..4GB`,
+/// including the lower and excluding the upper bound) or a bare size. Also used for plain counts.
+#[derive(Debug, Clone)]
+pub struct SizeRange {
+    /// Smallest allowed size, and whether it is itself allowed.
+    min: Option<(u64, bool)>,
+    /// Largest allowed size, and whether it is itself allowed.
+    max: Option<(u64, bool)>,
+    raw: String,
+}
+
+/// What a bare size means, depending on which key it was given for.
+#[derive(Clone, Copy)]
+pub enum Bare {
+    AtLeast,
+    AtMost,
+    Exactly,
+}
+
+impl SizeRange {
+    pub fn parse(value: &str, bare: Bare) -> Result<SizeRange> {
+        SizeRange::parse_with(value, bare, parse)
+    }
+
+    /// Parses a range of whole numbers, such as `< 10000`.
+    pub fn parse_count(value: &str, bare: Bare) -> Result<SizeRange> {
+        SizeRange::parse_with(value, bare, |n| {
+            n.trim().parse().map_err(|_| format!("[{}] is not a whole number", n.trim()).into())
+        })
+    }
+
+    fn parse_with(value: &str, bare: Bare, parse: impl Fn(&str) -> Result<u64>) -> Result<SizeRange> {
+        let raw = value.trim().to_string();
+        let (min, max) = if let Some((low, high)) = raw.split_once("..") {
+            let min = if low.trim().is_empty() { None } else { Some((parse(low)?, true)) };
+            let max = if high.trim().is_empty() { None } else { Some((parse(high)?, false)) };
+            (min, max)
+        } else if let Some(size) = raw.strip_prefix(">=") {
+            (Some((parse(size)?, true)), None)
+        } else if let Some(size) = raw.strip_prefix('>') {
+            (Some((parse(size)?, false)), None)
+        } else if let Some(size) = raw.strip_prefix("<=") {
+            (None, Some((parse(size)?, true)))
+        } else if let Some(size) = raw.strip_prefix('<') {
+            (None, Some((parse(size)?, false)))
+        } else {
+            match bare {
+                Bare::AtLeast => (Some((parse(&raw)?, true)), None),
+                Bare::AtMost => (None, Some((parse(&raw)?, true))),
+                Bare::Exactly => {
+                    let size = parse(&raw)?;
+                    (Some((size, true)), Some((size, true)))
+                },
+            }
+        };
+        Ok(SizeRange { min, max, raw })
+    }
+
+    pub fn contains(&self, size: u64) -> bool {
+        let above = match self.min {
+            Some((min, inclusive)) => size > min || (inclusive && size == min),
+            None => true,
+        };
+        let below = match self.max {
+            Some((max, inclusive)) => size < max || (inclusive && size == max),
+            None => true,
+        };
+        above && below
+    }
+}
+
+impl fmt::Display for SizeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl Serialize for SizeRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+/// Serde helper for counts where a bare number has to match exactly, such as a PDF's `pages`.
+pub fn deserialize_count<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<SizeRange>, D::Error> {
+    deserialize_range(deserializer, Bare::Exactly, true)
+}
+
+/// Serde helper for a single size, such as `4GiB`, or a number of bytes.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u64, D::Error> {
+    parse(&raw(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// The size as written, whether YAML read it as a number or a string.
+fn raw<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Bytes(u64),
+        Text(String),
+    }
+    Ok(match Raw::deserialize(deserializer)? {
+        Raw::Bytes(bytes) => bytes.to_string(),
+        Raw::Text(text) => text,
+    })
+}
+
+fn deserialize_range<'de, D: Deserializer<'de>>(deserializer: D, bare: Bare, count: bool) -> std::result::Result<Option<SizeRange>, D::Error> {
+    let raw = raw(deserializer)?;
+    let range = if count { SizeRange::parse_count(&raw, bare) } else { SizeRange::parse(&raw, bare) };
+    range.map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Serde helper for `minSize`, where a bare size is a lower bound. Used with `#[serde(default)]`.
+pub fn deserialize_min<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<SizeRange>, D::Error> {
+    deserialize_range(deserializer, Bare::AtLeast, false)
+}
+
+/// Serde helper for `maxSize`, where a bare size is an upper bound. Used with `#[serde(default)]`.
+pub fn deserialize_max<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<SizeRange>, D::Error> {
+    deserialize_range(deserializer, Bare::AtMost, false)
+}
+
+/// Serde helper for `destFileCount`, where a bare number is an upper bound.
+pub fn deserialize_max_count<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<SizeRange>, D::Error> {
+    deserialize_range(deserializer, Bare::AtMost, true)
+}
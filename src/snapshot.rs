@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// `BTRFS_IOC_SNAP_CREATE_V2`, `_IOW(0x94, 23, struct btrfs_ioctl_vol_args_v2)`.
+const BTRFS_IOC_SNAP_CREATE_V2: libc::c_ulong = 0x5000_9417;
+
+/// `BTRFS_SUBVOL_RDONLY`, making the snapshot read-only.
+const BTRFS_SUBVOL_RDONLY: u64 = 1 << 1;
+
+/// Takes a filesystem snapshot before a rescan queues many files, as a rollback point should the
+/// rules do something unexpected to all of them.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SnapshotConfig {
+    /// Fewest files a rescan has to find before a snapshot is taken. Every rescan when unset.
+    #[serde(rename="minFiles", default)]
+    pub min_files: usize,
+    #[serde(flatten)]
+    pub method: Method,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub enum Method {
+    /// A read-only snapshot of a btrfs subvolume, taken directly with an ioctl.
+    #[serde(rename="btrfs")]
+    Btrfs {
+        /// The subvolume to snapshot.
+        subvolume: PathBuf,
+        /// Directory on the same filesystem the snapshots are created in.
+        dir: PathBuf,
+    },
+    /// A command that takes the snapshot, such as `zfs snapshot`. `{name}` in its arguments is
+    /// replaced with the snapshot's name.
+    #[serde(rename="command")]
+    Command(Vec<String>),
+}
+
+/// `struct btrfs_ioctl_vol_args_v2`, with the size and qgroup union left as padding.
+#[repr(C)]
+struct VolArgsV2 {
+    fd: i64,
+    transid: u64,
+    flags: u64,
+    unused: [u64; 4],
+    name: [u8; 4040],
+}
+
+const _: () = assert!(std::mem::size_of::<VolArgsV2>() == 4096);
+
+impl SnapshotConfig {
+    pub fn validate(&self) -> Result<()> {
+        match &self.method {
+            Method::Btrfs { subvolume, dir } if !subvolume.is_absolute() || !dir.is_absolute() => {
+                Err("btrfs snapshot subvolume and dir must be absolute paths".into())
+            },
+            Method::Command(command) if command.is_empty() => Err("snapshot command is empty".into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes a snapshot, returning its name.
+    pub fn take(&self) -> Result<String> {
+        let name = format!("download-organiser-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+        match &self.method {
+            Method::Btrfs { subvolume, dir } => btrfs(subvolume, dir, &name)?,
+            Method::Command(command) => {
+                let (program, args) = command.split_first().ok_or("snapshot command is empty")?;
+                let status = Command::new(program).args(args.iter().map(|a| a.replace("{name}", &name))).status()
+                    .map_err(|e| format!("unable to run [{program}]: {e}"))?;
+                if !status.success() {
+                    return Err(format!("snapshot command [{program}] failed with {status}").into())
+                }
+            },
+        }
+        Ok(name)
+    }
+}
+
+fn btrfs(subvolume: &Path, dir: &Path, name: &str) -> Result<()> {
+    let source = File::open(subvolume).map_err(|e| format!("unable to open subvolume [{}]: {e}", subvolume.display()))?;
+    let dest = File::open(dir).map_err(|e| format!("unable to open snapshot directory [{}]: {e}", dir.display()))?;
+    let mut args = VolArgsV2 { fd: source.as_raw_fd() as i64, transid: 0, flags: BTRFS_SUBVOL_RDONLY, unused: [0; 4], name: [0; 4040] };
+    args.name[..name.len()].copy_from_slice(name.as_bytes());
+    // SAFETY: dest is an open directory and args is a btrfs_ioctl_vol_args_v2 with a NUL-terminated name
+    if unsafe { libc::ioctl(dest.as_raw_fd(), BTRFS_IOC_SNAP_CREATE_V2, &mut args) } != 0 {
+        let err = std::io::Error::last_os_error();
+        return Err(format!("unable to snapshot [{}] into [{}]: {err}", subvolume.display(), dir.join(name).display()).into())
+    }
+    Ok(())
+}
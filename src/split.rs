@@ -0,0 +1,163 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+
+/// Extension of the manifest written beside a split file's chunks.
+const MANIFEST: &str = ".split.json";
+
+/// What a file was split into, so the chunks can be checked and joined back together.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Manifest {
+    /// Name of the original file.
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the original file.
+    pub sha256: String,
+    #[serde(rename="chunkSize")]
+    pub chunk_size: u64,
+    /// Chunks in the order they're joined, named after the original with `.001`, `.002` and so on.
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Chunk {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// How many chunks of `chunk_size` a file of `size` bytes is split into.
+pub fn chunk_count(size: u64, chunk_size: u64) -> u64 {
+    size.div_ceil(chunk_size).max(1)
+}
+
+/// Where the manifest goes for a file split into chunks beside `path`.
+pub fn manifest_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{name}{MANIFEST}"))
+}
+
+/// Splits `source` into chunks of `chunk_size` bytes named after `name` in the directory `into`,
+/// with a manifest beside them, and returns the manifest's path.
+pub fn split(source: &Path, into: &Path, name: &str, chunk_size: u64) -> Result<PathBuf> {
+    let mut file = File::open(source)?;
+    let size = file.metadata()?.len();
+    let count = chunk_count(size, chunk_size);
+    // at least three digits, as other splitters write them
+    let width = count.to_string().len().max(3);
+    let mut whole = Sha256::new();
+    let mut chunks = Vec::new();
+    for n in 1..=count {
+        let chunk_name = format!("{name}.{n:0width$}");
+        let mut out = BufWriter::new(File::create(into.join(&chunk_name))?);
+        let mut hasher = Sha256::new();
+        let written = copy(&mut (&mut file).take(chunk_size), &mut [&mut whole, &mut hasher], &mut out)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        chunks.push(Chunk { name: chunk_name, size: written, sha256: hex(hasher) });
+    }
+    let manifest = Manifest { name: name.to_string(), size, sha256: hex(whole), chunk_size, chunks };
+    if manifest.chunks.iter().map(|c| c.size).sum::<u64>() != size {
+        return Err(format!("[{}] changed size while it was being split", source.display()).into())
+    }
+    let path = manifest_path(&into.join(name));
+    fs::write(&path, serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(path)
+}
+
+/// Checks the chunks beside `manifest` against it, returning what's wrong, if anything.
+pub fn check(manifest: &Path) -> Result<Option<String>> {
+    read_chunks(manifest, &mut io::sink())
+}
+
+/// Joins the chunks listed in `manifest` back into `output`, which mustn't exist yet, checking
+/// them as they're read. Nothing is left at `output` if they don't match the manifest.
+pub fn join(manifest: &Path, output: &Path) -> Result<()> {
+    if output.exists() {
+        return Err(format!("[{}] already exists", output.display()).into())
+    }
+    let name = output.file_name().ok_or("output has no file name")?.to_string_lossy();
+    let partial = output.with_file_name(format!(".{name}.partial"));
+    let mut out = BufWriter::new(File::create(&partial)?);
+    let joined = read_chunks(manifest, &mut out).and_then(|problem| {
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(problem)
+    });
+    match joined {
+        Ok(None) => Ok(fs::rename(&partial, output)?),
+        Ok(Some(problem)) => {
+            let _ = fs::remove_file(&partial);
+            Err(problem.into())
+        },
+        Err(err) => {
+            let _ = fs::remove_file(&partial);
+            Err(err)
+        },
+    }
+}
+
+/// Name of the file `manifest` lists the chunks of.
+pub fn original_name(manifest: &Path) -> Result<String> {
+    Ok(load(manifest)?.name)
+}
+
+fn load(manifest: &Path) -> Result<Manifest> {
+    let contents = fs::read(manifest).map_err(|e| format!("unable to read [{}]: {e}", manifest.display()))?;
+    serde_json::from_slice(&contents).map_err(|e| format!("[{}] is not a split manifest: {e}", manifest.display()).into())
+}
+
+/// Reads the chunks listed in `manifest` into `out` in order, returning the first way they
+/// differ from it.
+fn read_chunks(manifest: &Path, out: &mut impl Write) -> Result<Option<String>> {
+    let listed = load(manifest)?;
+    let dir = manifest.parent().unwrap_or(Path::new("."));
+    let mut whole = Sha256::new();
+    for chunk in &listed.chunks {
+        let path = dir.join(&chunk.name);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some(format!("chunk [{}] is missing", chunk.name))),
+            Err(err) => return Err(err.into()),
+        };
+        let mut hasher = Sha256::new();
+        let size = copy(&mut file, &mut [&mut whole, &mut hasher], out)?;
+        if size != chunk.size {
+            return Ok(Some(format!("chunk [{}] is {size} bytes but the manifest says {}", chunk.name, chunk.size)))
+        }
+        let sha256 = hex(hasher);
+        if sha256 != chunk.sha256 {
+            return Ok(Some(format!("chunk [{}] has SHA-256 {sha256} but the manifest says {}", chunk.name, chunk.sha256)))
+        }
+    }
+    let sha256 = hex(whole);
+    if sha256 != listed.sha256 {
+        return Ok(Some(format!("[{}] joins to SHA-256 {sha256} but the manifest says {}", listed.name, listed.sha256)))
+    }
+    Ok(None)
+}
+
+/// Copies `from` into `to`, feeding everything copied to `hashers`, and returns how much there was.
+fn copy(from: &mut impl Read, hashers: &mut [&mut Sha256], to: &mut impl Write) -> Result<u64> {
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut total = 0;
+    loop {
+        let read = match from.read(&mut buffer) {
+            Ok(0) => return Ok(total),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for hasher in hashers.iter_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        to.write_all(&buffer[..read])?;
+        total += read as u64;
+    }
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher.finalize().iter().map(|b| format!("{b:02x}")).collect()
+}
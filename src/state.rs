@@ -0,0 +1,24 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{as_debug, warn};
+
+/// Makes sure the state directory exists, returning `None` when it can't be written to (e.g. a
+/// read-only container filesystem) so callers fall back to keeping state in memory.
+pub fn prepare(dir: &Path) -> Option<PathBuf> {
+    if let Err(err) = fs::create_dir_all(dir) {
+        warn!(state_dir=dir.to_str(), error=as_debug!(err); "state directory could not be created - state will be kept in memory only");
+        return None
+    }
+
+    let probe = dir.join(format!(".probe-{}", std::process::id()));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            Some(dir.to_path_buf())
+        },
+        Err(err) => {
+            warn!(state_dir=dir.to_str(), error=as_debug!(err); "state directory is not writable - state will be kept in memory only");
+            None
+        },
+    }
+}
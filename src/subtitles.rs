@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use log::{debug, info};
+use serde::Deserialize;
+
+use crate::http;
+use crate::secret::SecretRef;
+use crate::Result;
+
+const API: &str = "https://api.opensubtitles.com/api/v1";
+
+/// OpenSubtitles rejects requests without an identifying user agent.
+const USER_AGENT: &str = concat!("download-organiser v", env!("CARGO_PKG_VERSION"));
+
+/// Bytes read from each end of a video for its OpenSubtitles hash.
+const HASH_CHUNK: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct Search {
+    data: Vec<Subtitle>,
+}
+
+#[derive(Deserialize)]
+struct Subtitle {
+    attributes: Attributes,
+}
+
+#[derive(Deserialize)]
+struct Attributes {
+    language: Option<String>,
+    #[serde(default)]
+    download_count: u64,
+    /// Whether the subtitle was made for this exact release rather than just the same title.
+    #[serde(default)]
+    moviehash_match: bool,
+    files: Vec<SubtitleFile>,
+}
+
+#[derive(Deserialize)]
+struct SubtitleFile {
+    file_id: u64,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    link: String,
+}
+
+/// Account on OpenSubtitles, for a higher download quota than the API key has alone.
+pub struct Account<'a> {
+    pub username: &'a SecretRef,
+    pub password: &'a SecretRef,
+}
+
+/// Downloads the best subtitle in each of `languages` for `video` from OpenSubtitles, saving them
+/// beside it as `<name>.<language>.srt`. Subtitles matching the exact release are preferred, then
+/// the most downloaded. Returns the files written; languages with no subtitles are skipped.
+pub async fn fetch(video: &Path, languages: &[String], api_key: &SecretRef, account: Option<Account<'_>>) -> Result<Vec<PathBuf>> {
+    let stem = video.file_stem().ok_or_else(|| format!("[{}] has no file name", video.display()))?.to_string_lossy().to_string();
+    let mut headers = vec![
+        format!("Api-Key: {}", api_key.resolve()?.expose()),
+        format!("User-Agent: {USER_AGENT}"),
+        "Content-Type: application/json".to_string(),
+        "Accept: application/json".to_string(),
+    ];
+    if let Some(account) = account {
+        let body = serde_json::json!({ "username": account.username.resolve()?.expose(), "password": account.password.resolve()?.expose() });
+        let (_, response) = http::request("POST", &format!("{API}/login"), &headers, Some(&body.to_string())).await?;
+        let login: Login = serde_json::from_slice(&response).map_err(|e| format!("unexpected OpenSubtitles login response: {e}"))?;
+        headers.push(format!("Authorization: Bearer {}", login.token));
+    }
+
+    // the API asks for languages sorted, so equivalent searches share its cache
+    let mut sorted = languages.to_vec();
+    sorted.sort();
+    let url = format!("{API}/subtitles?languages={}&moviehash={}&query={}",
+        http::encode(&sorted.join(",")), hash(video)?, http::encode(&stem));
+    let (_, response) = http::request("GET", &url, &headers, None).await?;
+    let search: Search = serde_json::from_slice(&response).map_err(|e| format!("unexpected OpenSubtitles search response: {e}"))?;
+
+    let mut written = Vec::new();
+    for language in languages {
+        let best = search.data.iter()
+            .map(|s| &s.attributes)
+            .filter(|a| a.language.as_deref() == Some(language.as_str()) && !a.files.is_empty())
+            .max_by_key(|a| (a.moviehash_match, a.download_count));
+        let file_id = match best {
+            Some(attributes) => attributes.files[0].file_id,
+            None => {
+                info!(video=video.to_str(), language=language; "no subtitles found");
+                continue
+            },
+        };
+        let target = video.with_file_name(format!("{stem}.{language}.srt"));
+        if target.exists() {
+            debug!(subtitle=target.to_str(); "subtitle already exists - not downloading");
+            continue
+        }
+
+        let body = serde_json::json!({ "file_id": file_id });
+        let (_, response) = http::request("POST", &format!("{API}/download"), &headers, Some(&body.to_string())).await?;
+        let download: Download = serde_json::from_slice(&response).map_err(|e| format!("unexpected OpenSubtitles download response: {e}"))?;
+        let (_, subtitle) = http::request("GET", &download.link, &[format!("User-Agent: {USER_AGENT}")], None).await?;
+        fs::write(&target, subtitle)?;
+        info!(subtitle=target.to_str(), language=language; "downloaded subtitle");
+        written.push(target);
+    }
+    Ok(written)
+}
+
+/// The OpenSubtitles hash of a video: its size plus the sum of the 64-bit little-endian words in
+/// its first and last 64KiB, as 16 hex digits.
+pub fn hash(video: &Path) -> Result<String> {
+    let mut file = fs::File::open(video)?;
+    let size = file.metadata()?.len();
+    if size < HASH_CHUNK {
+        return Err(format!("[{}] is too small to be a video", video.display()).into())
+    }
+
+    let mut hash = size;
+    let mut chunk = vec![0u8; HASH_CHUNK as usize];
+    for offset in [0, size - HASH_CHUNK] {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk)?;
+        for word in chunk.chunks_exact(8) {
+            hash = hash.wrapping_add(u64::from_le_bytes(word.try_into().unwrap()));
+        }
+    }
+    Ok(format!("{hash:016x}"))
+}
@@ -0,0 +1,378 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::format::{Item, StrftimeItems};
+use log::{as_error, warn};
+
+use crate::clock;
+use crate::diskimage::{self, DiskImage};
+use crate::fsutil;
+use crate::normalise::{self, Normalisation};
+use crate::pdf::{self, PdfInfo};
+use crate::users;
+use crate::Result;
+
+/// Format used by a date token without one, e.g. `{mtime}`.
+const DEFAULT_FORMAT: &str = "%Y-%m-%d";
+
+/// What a PDF token expands to when the document doesn't have that field.
+const MISSING: &str = "unknown";
+
+const PDF_FIELDS: [&str; 4] = ["title", "author", "producer", "pages"];
+
+const IMAGE_FIELDS: [&str; 2] = ["label", "format"];
+
+/// Length assumed for `{user}` when estimating how long a template can get, the longest login name
+/// Linux allows.
+const LONG_USER: usize = 32;
+
+/// Length assumed for a PDF field or volume label when estimating how long a template can get.
+/// Most are shorter, but titles in particular can run on.
+const LONG_FIELD: usize = 100;
+
+/// Functions a token can call, with how many arguments each takes.
+const FUNCTIONS: [(&str, usize); 5] = [("lower", 1), ("upper", 1), ("replace", 3), ("truncate", 2), ("pad", 2)];
+
+enum Token<'a> {
+    /// `now`, `mtime` or `ctime`, with its strftime format.
+    Date(&'a str, &'a str),
+    /// A field of the PDF's document information.
+    Pdf(&'a str),
+    /// The volume label or format of a disk image.
+    Image(&'a str),
+    /// The user whose per-user watch directory the file is in.
+    User,
+    /// The file's `name`, or its `stem` or `ext` (without the dot).
+    Name(&'a str),
+    /// One more than the number of entries already in the destination directory.
+    Counter,
+    /// A function applied to its arguments, such as `lower(name)`.
+    Call(&'a str, Vec<Arg<'a>>),
+}
+
+enum Arg<'a> {
+    Token(Token<'a>),
+    /// A quoted string.
+    Text(&'a str),
+    Number(usize),
+}
+
+/// What a template is being expanded for, with what it has read of the file so far.
+struct Context<'s> {
+    source: &'s Path,
+    /// The destination directory, for `{counter}`.
+    dir: Option<&'s Path>,
+    metadata: Option<fs::Metadata>,
+    pdf_info: Option<PdfInfo>,
+    image: Option<Option<DiskImage>>,
+}
+
+/// Expands the tokens in a destination or new name for the file at `source`. Dates are in local
+/// time:
+///
+/// - `{now:FORMAT}` - when the file is processed
+/// - `{mtime:FORMAT}` - when the file was last modified
+/// - `{ctime:FORMAT}` - when the file was created, if the filesystem records it, otherwise when its
+///   inode last changed
+/// - `{pdf:FIELD}` - the PDF's `title`, `author`, `producer` or `pages`, or `unknown`
+/// - `{image:FIELD}` - a disk image's volume `label` or `format` (`iso` or `img`), or `unknown`
+/// - `{user}` - the user whose per-user watch directory the file is in, or `unknown`
+/// - `{name}`, `{stem}`, `{ext}` - the file's name, without its extension, or just its extension
+/// - `{counter}` - in a new name, one more than the number of entries in the destination
+///
+/// `FORMAT` is a strftime format such as `%Y/%m`, and defaults to `%Y-%m-%d`. A token can also
+/// call a function on the others, as in `{truncate(lower(stem), 40)}`: `lower(x)`, `upper(x)`,
+/// `replace(x, 'from', 'to')`, `truncate(x, n)` or `pad(x, n)`. Anything else in braces is left
+/// alone. A file that can't be read uses the current time.
+pub fn expand(template: &str, source: &Path) -> String {
+    expand_in(template, source, None)
+}
+
+/// Expands a new name for the file at `source` that's going into `dir`, which `{counter}` counts.
+pub fn expand_name(template: &str, source: &Path, dir: &Path) -> String {
+    expand_in(template, source, Some(dir))
+}
+
+fn expand_in(template: &str, source: &Path, dir: Option<&Path>) -> String {
+    if !template.contains('{') {
+        return template.to_string()
+    }
+    let mut context = Context { source, dir, metadata: fs::metadata(source).ok(), pdf_info: None, image: None };
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let token = rest[start + 1..].find('}').map(|end| &rest[start + 1..start + 1 + end]);
+        let (token, parsed) = match token.and_then(|t| parse(t).map(|parsed| (t, parsed))) {
+            Some(found) => found,
+            None => {
+                expanded.push('{');
+                rest = &rest[start + 1..];
+                continue
+            },
+        };
+        expanded.push_str(&context.value(&parsed));
+        rest = &rest[start + token.len() + 2..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+impl Context<'_> {
+    fn value(&mut self, token: &Token) -> String {
+        match token {
+            Token::Date(kind, format) => {
+                let time = match (*kind, &self.metadata) {
+                    ("mtime", Some(metadata)) => metadata.modified().ok(),
+                    ("ctime", Some(metadata)) => metadata.created().ok()
+                        .or_else(|| Some(UNIX_EPOCH + Duration::new(metadata.ctime().max(0) as u64, metadata.ctime_nsec() as u32))),
+                    _ => None,
+                };
+                clock::format(time.unwrap_or_else(SystemTime::now), format)
+            },
+            Token::Pdf(field) => {
+                let source = self.source;
+                let info = self.pdf_info.get_or_insert_with(|| read_pdf(source));
+                let value = info.field(field).unwrap_or_else(|| MISSING.to_string());
+                // a title is one path component, however many slashes it has
+                value.replace(['/', '\0'], "-").trim().to_string()
+            },
+            Token::Image(field) => {
+                let source = self.source;
+                let image = self.image.get_or_insert_with(|| read_image(source));
+                let value = image.as_ref().and_then(|i| i.field(field)).unwrap_or_else(|| MISSING.to_string());
+                value.replace(['/', '\0'], "-").trim().to_string()
+            },
+            Token::User => users::of(self.source).unwrap_or_else(|| MISSING.to_string()),
+            Token::Name(part) => {
+                let part = match *part {
+                    "stem" => self.source.file_stem(),
+                    "ext" => self.source.extension(),
+                    _ => self.source.file_name(),
+                };
+                part.map(|p| p.to_string_lossy().to_string()).unwrap_or_default()
+            },
+            Token::Counter => {
+                let taken = self.dir.and_then(|dir| fs::read_dir(dir).ok())
+                    .map(|entries| entries.flatten().filter(|e| !e.file_name().to_string_lossy().starts_with('.')).count())
+                    .unwrap_or(0);
+                (taken + 1).to_string()
+            },
+            Token::Call(function, args) => call(function, args, &mut |token| self.value(token)),
+        }
+    }
+}
+
+/// Applies `function` to `args`, getting each token's value from `value`.
+fn call(function: &str, args: &[Arg], value: &mut dyn FnMut(&Token) -> String) -> String {
+    let mut text = |arg: &Arg| match arg {
+        Arg::Token(token) => value(token),
+        Arg::Text(text) => text.to_string(),
+        Arg::Number(n) => n.to_string(),
+    };
+    let number = |arg: &Arg| match arg {
+        Arg::Number(n) => *n,
+        _ => 0,
+    };
+    match function {
+        "lower" => text(&args[0]).to_lowercase(),
+        "upper" => text(&args[0]).to_uppercase(),
+        "replace" => {
+            let (from, to) = (text(&args[1]), text(&args[2]));
+            text(&args[0]).replace(&from, &to)
+        },
+        "truncate" => text(&args[0]).chars().take(number(&args[1])).collect::<String>().trim_end().to_string(),
+        "pad" => format!("{:0>width$}", text(&args[0]), width = number(&args[1])),
+        _ => String::new(),
+    }
+}
+
+/// Expands the destination `template` for `source`, relative to `base_dir`, with names in `form`
+/// and anything too long for the filesystem shortened.
+pub fn dest(base_dir: &Path, template: &str, source: &Path, form: Option<Normalisation>) -> PathBuf {
+    fsutil::fit(&normalise::path(base_dir, &base_dir.join(expand(template, source)), form))
+}
+
+fn read_pdf(source: &Path) -> PdfInfo {
+    if !pdf::is_pdf(source) {
+        return PdfInfo::default()
+    }
+    PdfInfo::read(source).unwrap_or_else(|err| {
+        warn!(path=source.to_str(), error=as_error!(*err); "unable to read PDF metadata");
+        PdfInfo::default()
+    })
+}
+
+fn read_image(source: &Path) -> Option<DiskImage> {
+    diskimage::read(source).unwrap_or_else(|err| {
+        warn!(path=source.to_str(), error=as_error!(*err); "unable to read disk image");
+        None
+    })
+}
+
+/// Checks the tokens in a template, which would otherwise only fail when a file is moved.
+pub fn validate(template: &str) -> Result<()> {
+    check(template, false)
+}
+
+/// Checks the tokens in a new name for a file, which can also use `{counter}`.
+pub fn validate_name(template: &str) -> Result<()> {
+    check(template, true)
+}
+
+fn check(template: &str, name: bool) -> Result<()> {
+    for token in template.split('{').skip(1).filter_map(|t| t.split_once('}').map(|(t, _)| t)) {
+        match parse(token) {
+            Some(parsed) => check_token(&parsed, template, name)?,
+            None if is_call(token) => {
+                let err = parse_call(token).err().unwrap_or_default();
+                return Err(format!("invalid function call [{{{token}}}] in [{template}]: {err}").into())
+            },
+            None if token.starts_with("pdf:") => {
+                return Err(format!("unknown PDF field in [{{{token}}}] - expected one of {}", PDF_FIELDS.join(", ")).into())
+            },
+            None if token.starts_with("image:") => {
+                return Err(format!("unknown disk image field in [{{{token}}}] - expected one of {}", IMAGE_FIELDS.join(", ")).into())
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn check_token(token: &Token, template: &str, name: bool) -> Result<()> {
+    match token {
+        Token::Date(kind, format) if StrftimeItems::new(format).any(|item| item == Item::Error) => {
+            Err(format!("invalid {kind} format [{format}] in [{template}]").into())
+        },
+        Token::Counter if !name => Err(format!("{{counter}} in [{template}] can only be used in rename").into()),
+        Token::Call(_, args) => args.iter().try_for_each(|arg| match arg {
+            Arg::Token(token) => check_token(token, template, name),
+            _ => Ok(()),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// What a template could plausibly expand to, for judging whether it might be too long: dates as of
+/// now, PDF fields, volume labels and names as `LONG_FIELD` characters, and users as `LONG_USER`.
+pub fn estimate(template: &str) -> String {
+    let mut estimated = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        estimated.push_str(&rest[..start]);
+        let token = rest[start + 1..].find('}').map(|end| &rest[start + 1..start + 1 + end]);
+        let (token, parsed) = match token.and_then(|t| parse(t).map(|parsed| (t, parsed))) {
+            Some(found) => found,
+            None => {
+                estimated.push('{');
+                rest = &rest[start + 1..];
+                continue
+            },
+        };
+        estimated.push_str(&estimate_token(&parsed));
+        rest = &rest[start + token.len() + 2..];
+    }
+    estimated.push_str(rest);
+    estimated
+}
+
+fn estimate_token(token: &Token) -> String {
+    match token {
+        Token::Date(_, format) => clock::format(SystemTime::now(), format),
+        Token::Pdf("pages") | Token::Counter => "9999".to_string(),
+        Token::Image("format") => "iso".to_string(),
+        Token::Name("ext") => "x".repeat(8),
+        Token::Pdf(_) | Token::Image(_) | Token::Name(_) => "x".repeat(LONG_FIELD),
+        Token::User => "x".repeat(LONG_USER),
+        Token::Call(function, args) => call(function, args, &mut |token| estimate_token(token)),
+    }
+}
+
+/// Whether a template has any tokens.
+pub fn has_tokens(template: &str) -> bool {
+    template.split('{').skip(1).any(|t| t.split_once('}').and_then(|(t, _)| parse(t)).is_some())
+}
+
+/// Works out what a token is from what's between its braces, if it's one this module expands.
+fn parse(token: &str) -> Option<Token<'_>> {
+    if is_call(token) {
+        return parse_call(token).ok()
+    }
+    let (kind, argument) = match token.split_once(':') {
+        Some((kind, argument)) => (kind, Some(argument)),
+        None => (token, None),
+    };
+    match kind {
+        "now" | "mtime" | "ctime" => Some(Token::Date(kind, match argument {
+            Some("iso") => clock::ISO_FORMAT,
+            argument => argument.unwrap_or(DEFAULT_FORMAT),
+        })),
+        "pdf" => argument.filter(|field| PDF_FIELDS.contains(field)).map(Token::Pdf),
+        "image" => argument.filter(|field| IMAGE_FIELDS.contains(field)).map(Token::Image),
+        "user" if argument.is_none() => Some(Token::User),
+        "name" | "stem" | "ext" if argument.is_none() => Some(Token::Name(kind)),
+        "counter" if argument.is_none() => Some(Token::Counter),
+        _ => None,
+    }
+}
+
+/// Whether a token looks like a function call, such as `lower(name)`.
+fn is_call(token: &str) -> bool {
+    token.split_once('(').is_some_and(|(function, _)| !function.is_empty() && function.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+fn parse_call(token: &str) -> std::result::Result<Token<'_>, String> {
+    let (function, rest) = token.split_once('(').unwrap_or((token, ""));
+    let args = rest.strip_suffix(')').ok_or("missing closing parenthesis")?;
+    let arity = FUNCTIONS.iter().find(|(name, _)| *name == function).map(|(_, arity)| *arity)
+        .ok_or_else(|| format!("unknown function {function} - expected one of {}", FUNCTIONS.map(|(name, _)| name).join(", ")))?;
+    let args = split_args(args)?.into_iter().map(parse_arg).collect::<std::result::Result<Vec<_>, _>>()?;
+    if args.len() != arity {
+        return Err(format!("{function} takes {arity} argument{}", if arity == 1 { "" } else { "s" }))
+    }
+    if matches!(function, "truncate" | "pad") && !matches!(args[1], Arg::Number(_)) {
+        return Err(format!("{function} takes a number as its second argument"))
+    }
+    Ok(Token::Call(function, args))
+}
+
+/// Splits a call's arguments at the commas outside any quotes or nested call.
+fn split_args(args: &str) -> std::result::Result<Vec<&str>, String> {
+    let mut split = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (i, c) in args.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {},
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => return Err("unbalanced parentheses".to_string()),
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                split.push(&args[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    if quote.is_some() || depth != 0 {
+        return Err("unclosed quote or parenthesis".to_string())
+    }
+    split.push(&args[start..]);
+    Ok(split)
+}
+
+fn parse_arg(arg: &str) -> std::result::Result<Arg<'_>, String> {
+    let arg = arg.trim();
+    for quote in ['\'', '"'] {
+        if let Some(text) = arg.strip_prefix(quote).and_then(|a| a.strip_suffix(quote)) {
+            return Ok(Arg::Text(text))
+        }
+    }
+    if let Ok(number) = arg.parse() {
+        return Ok(Arg::Number(number))
+    }
+    parse(arg).map(Arg::Token).ok_or_else(|| format!("[{arg}] is not a token, quoted text or number"))
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature="tls")]
+pub use server::Acceptor;
+#[cfg(not(feature="tls"))]
+pub use unsupported::Acceptor;
+
+/// TLS for the control API's TCP address.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TlsConfig {
+    /// PEM file with the server's certificate, followed by any intermediates.
+    pub cert: PathBuf,
+    /// PEM file with the certificate's private key.
+    pub key: PathBuf,
+    /// PEM file with the CAs client certificates must be signed by. When set, clients without a
+    /// valid certificate are refused before they can make a request.
+    #[serde(rename="clientCa")]
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The TLS server, which needs the `tls` feature.
+#[cfg(feature="tls")]
+mod server {
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::crypto;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+    use tokio_rustls::rustls::server::WebPkiClientVerifier;
+    use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+    use tokio_rustls::server::TlsStream;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::Result;
+    use super::TlsConfig;
+
+    pub struct Acceptor(TlsAcceptor);
+
+    impl Acceptor {
+        /// Loads the certificate, key and client CAs. Read once, on start.
+        pub fn new(config: &TlsConfig) -> Result<Acceptor> {
+            let provider = Arc::new(crypto::ring::default_provider());
+            let builder = ServerConfig::builder_with_provider(Arc::clone(&provider)).with_safe_default_protocol_versions()?;
+            let builder = match &config.client_ca {
+                Some(file) => {
+                    let mut roots = RootCertStore::empty();
+                    for cert in certificates(file)? {
+                        roots.add(cert).map_err(|e| format!("invalid client CA in [{}]: {e}", file.display()))?;
+                    }
+                    builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?)
+                },
+                None => builder.with_no_client_auth(),
+            };
+            let key = PrivateKeyDer::from_pem_file(&config.key)
+                .map_err(|e| format!("unable to read TLS key [{}]: {e}", config.key.display()))?;
+            let server = builder.with_single_cert(certificates(&config.cert)?, key)
+                .map_err(|e| format!("unable to use TLS certificate [{}]: {e}", config.cert.display()))?;
+            Ok(Acceptor(TlsAcceptor::from(Arc::new(server))))
+        }
+
+        pub async fn accept(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+            self.0.accept(stream).await
+        }
+    }
+
+    fn certificates(file: &Path) -> Result<Vec<CertificateDer<'static>>> {
+        let certs = CertificateDer::pem_file_iter(file)
+            .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| format!("unable to read certificates [{}]: {e}", file.display()))?;
+        if certs.is_empty() {
+            return Err(format!("no certificates in [{}]", file.display()).into())
+        }
+        Ok(certs)
+    }
+}
+
+/// Stand-in for when TLS support isn't compiled in. A `tls` config is refused when it's loaded
+/// then, so nothing is ever accepted.
+#[cfg(not(feature="tls"))]
+mod unsupported {
+    use std::io;
+    use tokio::net::TcpStream;
+
+    use crate::version;
+    use crate::Result;
+    use super::TlsConfig;
+
+    pub enum Acceptor {}
+
+    impl Acceptor {
+        pub fn new(_config: &TlsConfig) -> Result<Acceptor> {
+            version::require("setting", "control.tls")?;
+            unreachable!("the tls feature is compiled in")
+        }
+
+        pub async fn accept(&self, _stream: TcpStream) -> io::Result<TcpStream> {
+            match *self {}
+        }
+    }
+}
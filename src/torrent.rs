@@ -0,0 +1,176 @@
+use std::fs;
+use std::path::Path;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil;
+use crate::http::{self, Body, Field};
+use crate::pattern::Pattern;
+use crate::secret::SecretRef;
+use crate::Result;
+
+/// Hands a `.torrent` or `.magnet` file to a torrent client and removes it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TorrentConfig {
+    #[serde(flatten)]
+    pub client: Client,
+    /// Category (qBittorrent) or label (Transmission) to add the torrent with, or subdirectory of
+    /// a blackhole. `$1` or `${name}` take capture groups from the rule's regex. Defaults to the
+    /// regex's first capture group, if it has one.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag="client")]
+pub enum Client {
+    /// qBittorrent's Web UI API, at e.g. `http://localhost:8080`.
+    #[serde(rename="qbittorrent")]
+    Qbittorrent {
+        url: String,
+        /// Can be left out when the Web UI skips authentication for the organiser's address.
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        username: Option<SecretRef>,
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        password: Option<SecretRef>,
+    },
+    /// Transmission's RPC interface, at e.g. `http://localhost:9091`.
+    #[serde(rename="transmission")]
+    Transmission {
+        url: String,
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        username: Option<SecretRef>,
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        password: Option<SecretRef>,
+    },
+    /// A directory a client watches for new torrent files, relative to `baseDir`.
+    #[serde(rename="blackhole")]
+    Blackhole { dir: String },
+}
+
+impl Client {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Client::Qbittorrent { .. } => "qbittorrent",
+            Client::Transmission { .. } => "transmission",
+            Client::Blackhole { .. } => "blackhole",
+        }
+    }
+}
+
+impl TorrentConfig {
+    /// The category for the file named `name`, matched by `regex`.
+    pub fn category(&self, regex: &Pattern, name: &str) -> Option<String> {
+        let category = regex.expand(name, self.category.as_deref().unwrap_or("$1"))?;
+        Some(category.trim().to_string()).filter(|c| !c.is_empty())
+    }
+}
+
+/// What a torrent file holds: the torrent itself, or a magnet link in a `.magnet` file.
+enum Torrent<'a> {
+    File(&'a Path),
+    Magnet(String),
+}
+
+fn read(path: &Path) -> Result<Torrent<'_>> {
+    if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("magnet")) {
+        return Ok(Torrent::File(path))
+    }
+    let link = fs::read_to_string(path)?.trim().to_string();
+    if !link.starts_with("magnet:") {
+        return Err(format!("[{}] doesn't hold a magnet link", path.display()).into())
+    }
+    Ok(Torrent::Magnet(link))
+}
+
+/// Submits the torrent at `path` to the client, then removes it (a blackhole gets the file
+/// itself).
+pub async fn submit(config: &TorrentConfig, path: &Path, category: Option<&str>, base_dir: &Path) -> Result<()> {
+    match &config.client {
+        Client::Qbittorrent { url, username, password } => {
+            qbittorrent(url.trim_end_matches('/'), username.as_ref().zip(password.as_ref()), &read(path)?, category).await?
+        },
+        Client::Transmission { url, username, password } => {
+            transmission(url.trim_end_matches('/'), username.as_ref().zip(password.as_ref()), &read(path)?, category).await?
+        },
+        Client::Blackhole { dir } => {
+            let mut dir = base_dir.join(dir);
+            if let Some(category) = category {
+                dir.push(category.replace('/', "-"));
+            }
+            fs::create_dir_all(&dir)?;
+            let name = path.file_name().ok_or("torrent has no file name")?;
+            fsutil::rename(path, &dir.join(name))?;
+            return Ok(())
+        },
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+async fn qbittorrent(url: &str, login: Option<(&SecretRef, &SecretRef)>, torrent: &Torrent<'_>, category: Option<&str>) -> Result<()> {
+    // the Web UI rejects requests whose referer isn't itself, as CSRF protection
+    let mut headers = vec![format!("Referer: {url}")];
+    if let Some((username, password)) = login {
+        let form = format!("username={}&password={}", http::encode(username.resolve()?.expose()), http::encode(password.resolve()?.expose()));
+        let login_headers = [headers[0].clone(), "Content-Type: application/x-www-form-urlencoded".to_string()];
+        let response = http::exchange("POST", &format!("{url}/api/v2/auth/login"), &login_headers, Body::Data(&form)).await?;
+        let cookie = response.header("Set-Cookie").and_then(|c| c.split(';').next()).filter(|_| response.status == 200);
+        match cookie {
+            Some(cookie) if response.body.starts_with(b"Ok") => headers.push(format!("Cookie: {cookie}")),
+            _ => return Err(format!("qBittorrent login failed with status {}", response.status).into()),
+        }
+    }
+
+    let mut form = vec![match torrent {
+        Torrent::File(path) => ("torrents", Field::File(path)),
+        Torrent::Magnet(link) => ("urls", Field::Text(link)),
+    }];
+    if let Some(category) = category {
+        form.push(("category", Field::Text(category)));
+    }
+    let response = http::exchange("POST", &format!("{url}/api/v2/torrents/add"), &headers, Body::Form(&form)).await?;
+    if response.status != 200 || response.body.starts_with(b"Fails") {
+        return Err(format!("qBittorrent didn't add the torrent: status {}, {}", response.status, String::from_utf8_lossy(&response.body).trim()).into())
+    }
+    debug!(category=category; "torrent added to qBittorrent");
+    Ok(())
+}
+
+async fn transmission(url: &str, login: Option<(&SecretRef, &SecretRef)>, torrent: &Torrent<'_>, category: Option<&str>) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Reply { result: String }
+
+    let rpc = if url.ends_with("/rpc") { url.to_string() } else { format!("{url}/transmission/rpc") };
+    let mut arguments = serde_json::Map::new();
+    match torrent {
+        Torrent::File(path) => arguments.insert("metainfo".to_string(), http::base64(&fs::read(path)?).into()),
+        Torrent::Magnet(link) => arguments.insert("filename".to_string(), link.clone().into()),
+    };
+    if let Some(category) = category {
+        arguments.insert("labels".to_string(), serde_json::json!([category]));
+    }
+    let body = serde_json::json!({ "method": "torrent-add", "arguments": arguments }).to_string();
+
+    let mut headers = vec!["Content-Type: application/json".to_string()];
+    if let Some((username, password)) = login {
+        let credentials = format!("{}:{}", username.resolve()?.expose(), password.resolve()?.expose());
+        headers.push(format!("Authorization: Basic {}", http::base64(credentials.as_bytes())));
+    }
+    let mut response = http::exchange("POST", &rpc, &headers, Body::Data(&body)).await?;
+    // Transmission answers the first request with the session id to send, as CSRF protection
+    if response.status == 409 {
+        let session = response.header("X-Transmission-Session-Id").ok_or("Transmission asked for a session id but didn't give one")?;
+        headers.push(format!("X-Transmission-Session-Id: {session}"));
+        response = http::exchange("POST", &rpc, &headers, Body::Data(&body)).await?;
+    }
+    if response.status != 200 {
+        return Err(format!("Transmission didn't add the torrent: status {}", response.status).into())
+    }
+    let reply: Reply = serde_json::from_slice(&response.body).map_err(|e| format!("unexpected Transmission response: {e}"))?;
+    if reply.result != "success" {
+        return Err(format!("Transmission didn't add the torrent: {}", reply.result).into())
+    }
+    debug!(category=category; "torrent added to Transmission");
+    Ok(())
+}
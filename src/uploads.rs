@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+use crate::glob::Glob;
+
+/// Names uploaders give files while they're being written: dot-files, as Samba clients, rsync and
+/// pure-ftpd use, and the suffixes of WinSCP, FileZilla and most download managers.
+const DEFAULT_MARKERS: [&str; 4] = [".*", "*.filepart", "*.part", "*.partial"];
+
+/// A watch directory that's an upload area, such as a Samba share or an FTP server's incoming
+/// directory, whose uploaders write each file under a temporary name and rename it when it's done.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UploadConfig {
+    /// Globs for the names of files still being uploaded, which are only processed once renamed
+    /// to something else.
+    #[serde(default="default_markers")]
+    pub markers: Vec<Glob>,
+}
+
+fn default_markers() -> Vec<Glob> {
+    DEFAULT_MARKERS.iter().filter_map(|marker| Glob::new(marker).ok()).collect()
+}
+
+impl UploadConfig {
+    /// Whether `name` marks a file that's still being uploaded.
+    pub fn in_progress(&self, name: &str) -> bool {
+        self.markers.iter().any(|marker| marker.is_match(name))
+    }
+}
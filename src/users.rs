@@ -0,0 +1,90 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+use log::{as_debug, info, warn};
+
+use crate::config::WatchDir;
+use crate::Result;
+
+/// The component of a watch directory's path that stands for each user's directory, as in
+/// `/home/*/Downloads`.
+const WILDCARD: &str = "*";
+
+/// Every per-user watch directory with its user, longest path first, for `{user}`. Process-wide, as
+/// the template that reads it is called from everywhere a destination is worked out.
+static USER_DIRS: RwLock<Vec<(PathBuf, String)>> = RwLock::new(Vec::new());
+
+/// Whether `path` is a per-user watch directory such as `/home/*/Downloads`.
+pub fn is_per_user(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == WILDCARD)
+}
+
+/// Checks that a per-user path has one `*`, as a whole component, and no other wildcards.
+pub fn validate(name: &str, path: &Path) -> Result<()> {
+    let wildcards = path.components().filter(|c| c.as_os_str().to_string_lossy().contains('*')).count();
+    if wildcards == 0 {
+        return Ok(())
+    }
+    if wildcards > 1 || !is_per_user(path) {
+        return Err(format!("watch directory [{name}] path can only have one `*`, as a whole component such as /home/*/Downloads").into())
+    }
+    Ok(())
+}
+
+/// Replaces each per-user watch directory with one for every user directory the `*` matches that
+/// has the rest of the path, named the same, and registers its user for `{user}`. Dot-directories
+/// are passed over. Users are found when the organiser starts.
+pub fn expand(dirs: Vec<WatchDir>) -> Vec<WatchDir> {
+    let mut expanded = Vec::new();
+    let mut registered = USER_DIRS.write().unwrap();
+    for dir in dirs {
+        if !is_per_user(&dir.path) {
+            expanded.push(dir);
+            continue
+        }
+        let (parent, rest) = split(&dir.path);
+        let entries = match fs::read_dir(&parent) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!(watch_dir=dir.name, path=parent.to_str(), error=as_debug!(err); "unable to list user directories - not watching any");
+                continue
+            },
+        };
+        let mut users: Vec<(String, PathBuf)> = entries.flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|user| !user.starts_with('.'))
+            .map(|user| {
+                let path = parent.join(&user);
+                let path = if rest.as_os_str().is_empty() { path } else { path.join(&rest) };
+                (user, path)
+            })
+            .filter(|(_, path)| path.is_dir())
+            .collect();
+        users.sort();
+        if users.is_empty() {
+            warn!(watch_dir=dir.name, path=dir.path.to_str(); "no user directories match per-user watch directory");
+        }
+        for (user, path) in users {
+            info!(watch_dir=dir.name, user=user, path=path.to_str(); "found user directory");
+            registered.push((path.clone(), user));
+            expanded.push(WatchDir { path, ..dir.clone() });
+        }
+    }
+    registered.sort_by_key(|(path, _)| std::cmp::Reverse(path.as_os_str().len()));
+    expanded
+}
+
+/// The user whose per-user watch directory `source` is in, if it's in one.
+pub fn of(source: &Path) -> Option<String> {
+    USER_DIRS.read().unwrap().iter().find(|(dir, _)| source.starts_with(dir)).map(|(_, user)| user.clone())
+}
+
+/// The part of a per-user path before the `*`, and the part after it.
+fn split(path: &Path) -> (PathBuf, PathBuf) {
+    let mut components = path.components();
+    let parent: PathBuf = components.by_ref().take_while(|c| c.as_os_str() != OsStr::new(WILDCARD)).collect();
+    let rest: PathBuf = components.filter(|c| !matches!(c, Component::CurDir)).collect();
+    (parent, rest)
+}
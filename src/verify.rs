@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::hashcache;
+use crate::Result;
+
+/// Checksum files for a single file, named after it.
+const OWN_CHECKSUMS: [&str; 2] = [".sha256", ".sha256sum"];
+
+/// Checksum lists covering a whole release directory.
+const CHECKSUM_LISTS: [&str; 3] = ["SHA256SUMS", "sha256sum.txt", "SHA256SUMS.txt"];
+
+/// Extensions of detached signatures.
+const SIGNATURES: [&str; 3] = [".sig", ".asc", ".gpg"];
+
+/// Checks a file against the checksum and signature published alongside it, before it's filed.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct VerifyConfig {
+    /// Require a matching SHA-256 from `<name>.sha256`, `SHA256SUMS` or a Fedora-style `*-CHECKSUM`
+    /// beside the file.
+    #[serde(default="default_checksum")]
+    pub checksum: bool,
+    /// Require a good OpenPGP signature over the file or over the checksum list, checked with `gpgv`.
+    #[serde(default)]
+    pub signature: bool,
+    /// Keyring of trusted keys for `gpgv`. Its default, `~/.gnupg/trustedkeys.kbx`, when unset.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub keyring: Option<PathBuf>,
+}
+
+fn default_checksum() -> bool {
+    true
+}
+
+/// Checks `path`, returning why it failed, if it did.
+pub fn check(path: &Path, config: &VerifyConfig) -> Result<Option<String>> {
+    let dir = path.parent().ok_or("file has no parent directory")?;
+    let name = path.file_name().ok_or("file has no name")?.to_string_lossy().to_string();
+
+    let mut signed_list = None;
+    if config.checksum {
+        let (list, expected) = match find_checksum(dir, &name)? {
+            Some(found) => found,
+            None => return Ok(Some(format!("no SHA-256 checksum for [{name}] found beside it"))),
+        };
+        let actual = hashcache::hash_file(path)?;
+        if !actual.eq_ignore_ascii_case(&expected) {
+            return Ok(Some(format!("SHA-256 is {actual} but [{}] says {expected}", list.display())))
+        }
+        debug!(path=path.to_str(), checksums=list.to_str(); "checksum matches");
+        signed_list = Some(list);
+    }
+
+    if config.signature {
+        // a signature over the file itself, or over the checksum list it was checked against
+        let mut candidates = vec![path.to_path_buf()];
+        candidates.extend(signed_list);
+        for data in candidates {
+            if let Some(signature) = detached_signature(&data) {
+                return gpgv(config, &[signature.as_path(), data.as_path()])
+            }
+            if data != path && is_clearsigned(&data)? {
+                return gpgv(config, &[data.as_path()])
+            }
+        }
+        return Ok(Some(format!("no signature for [{name}] or its checksums found beside it")))
+    }
+    Ok(None)
+}
+
+/// The checksum file that lists `name`, and the checksum it gives.
+fn find_checksum(dir: &Path, name: &str) -> Result<Option<(PathBuf, String)>> {
+    for suffix in OWN_CHECKSUMS {
+        let file = dir.join(format!("{name}{suffix}"));
+        if let Some(hash) = read_list(&file)?.and_then(|text| lookup(&text, name, true)) {
+            return Ok(Some((file, hash)))
+        }
+    }
+    let mut lists: Vec<PathBuf> = CHECKSUM_LISTS.iter().map(|l| dir.join(l)).collect();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().ends_with("CHECKSUM") {
+            lists.push(entry.path());
+        }
+    }
+    for file in lists {
+        if let Some(hash) = read_list(&file)?.and_then(|text| lookup(&text, name, false)) {
+            return Ok(Some((file, hash)))
+        }
+    }
+    Ok(None)
+}
+
+fn read_list(file: &Path) -> Result<Option<String>> {
+    match fs::read(file) {
+        Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("unable to read [{}]: {err}", file.display()).into()),
+    }
+}
+
+/// Finds the SHA-256 for `name` in GNU (`<hash>  <name>`) or BSD (`SHA256 (<name>) = <hash>`)
+/// format. A file's own checksum file can also be just the hash.
+fn lookup(text: &str, name: &str, own: bool) -> Option<String> {
+    let is_hash = |h: &str| h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit());
+    for line in text.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("SHA256 (") {
+            if let Some((file, hash)) = rest.split_once(") = ") {
+                if file == name && is_hash(hash.trim()) {
+                    return Some(hash.trim().to_string())
+                }
+            }
+            continue
+        }
+        let (hash, file) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let file = file.trim_start().trim_start_matches('*');
+        if is_hash(hash) && (file == name || file.strip_prefix("./") == Some(name) || (own && file.is_empty())) {
+            return Some(hash.to_string())
+        }
+    }
+    None
+}
+
+fn detached_signature(data: &Path) -> Option<PathBuf> {
+    SIGNATURES.iter().map(|ext| {
+        let mut signature = data.as_os_str().to_owned();
+        signature.push(ext);
+        PathBuf::from(signature)
+    }).find(|signature| signature.is_file())
+}
+
+fn is_clearsigned(file: &Path) -> Result<bool> {
+    Ok(read_list(file)?.is_some_and(|text| text.starts_with("-----BEGIN PGP SIGNED MESSAGE-----")))
+}
+
+fn gpgv(config: &VerifyConfig, files: &[&Path]) -> Result<Option<String>> {
+    let mut command = Command::new("gpgv");
+    if let Some(keyring) = &config.keyring {
+        command.arg("--keyring").arg(keyring);
+    }
+    let output = command.args(files).output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => "[gpgv] is not installed - it's needed to check signatures".to_string(),
+        _ => format!("unable to run [gpgv]: {e}"),
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Ok(Some(format!("bad signature [{}]: {}", files[0].display(), stderr.trim())))
+    }
+    debug!(signature=files[0].to_str(); "signature is good");
+    Ok(None)
+}
@@ -0,0 +1,90 @@
+use serde::Serialize;
+
+use crate::config::{Action, SCHEMA_VERSION};
+use crate::notify::Channel;
+use crate::outcome::Sink;
+use crate::Result;
+
+/// Cargo features this binary was built with.
+const FEATURES: &[&str] = &[
+    #[cfg(feature="archives")]
+    "archives",
+    #[cfg(feature="dbus")]
+    "dbus",
+    #[cfg(feature="tls")]
+    "tls",
+];
+
+/// Parts of the config that need a feature: what kind of thing each is, its name in the config,
+/// and the feature.
+const GATED: &[(&str, &str, &str)] = &[
+    ("action", "unzip", "archives"),
+    ("action", "recompress", "archives"),
+    ("notification channel", "desktop", "dbus"),
+    ("setting", "dbus", "dbus"),
+    ("setting", "control.tls", "tls"),
+];
+
+/// What this binary is and what it was built with, for `download-organiser version` and
+/// `GET /version`.
+#[derive(Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Commit it was built from, when built from a git checkout.
+    #[serde(rename="gitHash", skip_serializing_if="Option::is_none")]
+    pub git_hash: Option<&'static str>,
+    pub features: &'static [&'static str],
+    /// Those compiled in.
+    pub actions: Vec<&'static str>,
+    #[serde(rename="outcomeSinks")]
+    pub outcome_sinks: Vec<&'static str>,
+    #[serde(rename="notificationChannels")]
+    pub notification_channels: Vec<&'static str>,
+    /// Version of the config format it reads.
+    #[serde(rename="configSchema")]
+    pub config_schema: u32,
+}
+
+pub fn info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: Some(env!("GIT_HASH")).filter(|hash| !hash.is_empty()),
+        features: FEATURES,
+        actions: compiled_in("action", Action::NAMES),
+        outcome_sinks: compiled_in("outcome sink", Sink::NAMES),
+        notification_channels: compiled_in("notification channel", Channel::NAMES),
+        config_schema: SCHEMA_VERSION,
+    }
+}
+
+fn compiled_in(kind: &str, names: &[&'static str]) -> Vec<&'static str> {
+    names.iter().copied().filter(|name| require(kind, name).is_ok()).collect()
+}
+
+/// Fails with a "not compiled in" error if the `kind` of thing called `name` in the config needs a
+/// feature this binary was built without.
+pub fn require(kind: &str, name: &str) -> Result<()> {
+    match GATED.iter().find(|(k, n, _)| *k == kind && *n == name) {
+        Some((_, _, feature)) if !FEATURES.contains(feature) =>
+            Err(format!("{kind} [{name}] is not compiled in - rebuild with `--features {feature}`").into()),
+        _ => Ok(()),
+    }
+}
+
+/// Prints the build information.
+pub fn print() {
+    let info = info();
+    match info.git_hash {
+        Some(hash) => println!("download-organiser {} ({hash})", info.version),
+        None => println!("download-organiser {}", info.version),
+    }
+    println!("config schema: {}", info.config_schema);
+    let features = match info.features {
+        [] => "none".to_string(),
+        features => features.join(", "),
+    };
+    println!("features: {features}");
+    println!("actions: {}", info.actions.join(", "));
+    println!("outcome sinks: {}", info.outcome_sinks.join(", "));
+    println!("notification channels: {}", info.notification_channels.join(", "));
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::duration;
+use crate::Result;
+
+/// Longest the watchdog sleeps between checks, so a long timeout is still noticed soon after it
+/// passes.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Profiles whose watchdog has found them stuck, which `/healthz` reports. Shared by every profile
+/// as the control API is.
+static STALLED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct WatchdogConfig {
+    /// How long the event loop, or a worker on a single file, can go without progress before it's
+    /// reported as stuck.
+    #[serde(default="default_timeout", deserialize_with="duration::deserialize", serialize_with="duration::serialize")]
+    pub timeout: Duration,
+    /// Abandon a file that's stuck, so its worker can move on. Only work that's waiting (on the
+    /// network, or between retries) can be abandoned; a filesystem call blocked in the kernel, such
+    /// as on a dead mount, can't be interrupted.
+    #[serde(default)]
+    pub cancel: bool,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(300)
+}
+
+/// Watches a profile's event loop and workers from a thread of its own, which keeps running when
+/// they're blocked.
+pub struct Watchdog {
+    profile: String,
+    timeout: Duration,
+    cancel: bool,
+    /// When the event loop last went round.
+    heartbeat: Mutex<Instant>,
+    /// What each worker is doing.
+    busy: Mutex<HashMap<u64, Arc<Activity>>>,
+    next_id: AtomicU64,
+}
+
+/// A file being processed.
+struct Activity {
+    path: PathBuf,
+    started: Instant,
+    /// The action being performed, and when it started.
+    action: Mutex<Option<(String, Instant)>>,
+    /// Whether it has been reported as stuck, so it's only reported once.
+    reported: AtomicBool,
+    cancelled: Notify,
+}
+
+/// A worker's claim on a file, which tells the watchdog what it's doing and ends when dropped.
+pub struct Busy {
+    watchdog: Arc<Watchdog>,
+    id: u64,
+    activity: Arc<Activity>,
+}
+
+impl Watchdog {
+    /// Starts watching. The returned watchdog has to be told about the event loop's progress with
+    /// [`beat`](Self::beat) and the workers' with [`begin`](Self::begin).
+    pub fn start(profile: &str, config: &WatchdogConfig) -> Result<Arc<Watchdog>> {
+        let watchdog = Arc::new(Watchdog {
+            profile: profile.to_string(),
+            timeout: config.timeout,
+            cancel: config.cancel,
+            heartbeat: Mutex::new(Instant::now()),
+            busy: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        });
+        let watching = Arc::clone(&watchdog);
+        thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || watching.watch())
+            .map_err(|e| format!("unable to start watchdog: {e}"))?;
+        Ok(watchdog)
+    }
+
+    /// How often the event loop should beat.
+    pub fn interval(&self) -> Duration {
+        (self.timeout / 4).min(MAX_CHECK_INTERVAL)
+    }
+
+    pub fn beat(&self) {
+        *self.heartbeat.lock().unwrap() = Instant::now();
+    }
+
+    pub fn begin(self: &Arc<Self>, path: &Path) -> Busy {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let activity = Arc::new(Activity {
+            path: path.to_path_buf(),
+            started: Instant::now(),
+            action: Mutex::new(None),
+            reported: AtomicBool::new(false),
+            cancelled: Notify::new(),
+        });
+        self.busy.lock().unwrap().insert(id, Arc::clone(&activity));
+        Busy { watchdog: Arc::clone(self), id, activity }
+    }
+
+    fn watch(&self) {
+        let mut loop_stalled = false;
+        loop {
+            thread::sleep(self.interval());
+
+            let silent = self.heartbeat.lock().unwrap().elapsed();
+            if silent >= self.timeout && !loop_stalled {
+                error!(profile=self.profile, elapsed_secs=silent.as_secs(); "event loop has made no progress - it may be blocked on an unresponsive filesystem");
+            } else if silent < self.timeout && loop_stalled {
+                info!(profile=self.profile; "event loop is making progress again");
+            }
+            loop_stalled = silent >= self.timeout;
+
+            let mut stuck = false;
+            for activity in self.busy.lock().unwrap().values() {
+                let elapsed = activity.started.elapsed();
+                if elapsed < self.timeout {
+                    continue
+                }
+                stuck = true;
+                if activity.reported.swap(true, Ordering::Relaxed) {
+                    continue
+                }
+                let action = activity.action.lock().unwrap().clone();
+                let action_elapsed = action.as_ref().map(|(_, started)| started.elapsed().as_secs());
+                error!(profile=self.profile, path=activity.path.to_str(), action=action.as_ref().map(|(a, _)| a.as_str()),
+                    elapsed_secs=elapsed.as_secs(), action_elapsed_secs=action_elapsed;
+                    "file has been processing for too long - it may be stuck");
+                if self.cancel {
+                    activity.cancelled.notify_one();
+                }
+            }
+
+            let mut stalled = STALLED.lock().unwrap();
+            if loop_stalled || stuck {
+                stalled.insert(self.profile.clone());
+            } else {
+                stalled.remove(&self.profile);
+            }
+        }
+    }
+}
+
+impl Busy {
+    /// Records the action now being performed.
+    pub fn action(&self, name: &str) {
+        *self.activity.action.lock().unwrap() = Some((name.to_string(), Instant::now()));
+    }
+
+    /// Completes when the watchdog gives up on the file.
+    pub async fn cancelled(&self) {
+        self.activity.cancelled.notified().await
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.watchdog.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Profiles the watchdog has found stuck.
+pub fn stalled() -> Vec<String> {
+    STALLED.lock().unwrap().iter().cloned().collect()
+}
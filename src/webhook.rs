@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+
+use crate::http;
+use crate::secret::SecretRef;
+use crate::Result;
+
+/// Sends a JSON payload describing a processed file to an HTTP endpoint.
+pub async fn send(url: &SecretRef, method: &str, headers: &BTreeMap<String, SecretRef>, payload: &str) -> Result<u16> {
+    let mut lines = vec!["Content-Type: application/json".to_string()];
+    for (name, value) in headers {
+        lines.push(format!("{name}: {}", value.resolve()?.expose()));
+    }
+    let (status, _) = http::request(method, url.resolve()?.expose(), &lines, Some(payload)).await?;
+    Ok(status)
+}
+
+/// Renders a flat JSON object from string key/value pairs.
+pub fn json_object(fields: &[(&str, &str)]) -> String {
+    let fields: Vec<String> = fields.iter()
+        .map(|(k, v)| format!("{}:{}", json_string(k), json_string(v)))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use log::{as_debug, debug, info, warn};
+
+use crate::mode::Modes;
+use crate::normalise::{self, Normalisation};
+use crate::Result;
+
+/// Name of the work directory kept on each destination filesystem when `workDir` isn't set.
+const DEFAULT_NAME: &str = ".work";
+
+/// Numbers scratch directories, so two never share a name within the process whichever profile
+/// makes them.
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// Where intermediate files are written before they're complete, so a destination only ever sees
+/// finished files.
+pub struct WorkDirs {
+    base_dir: PathBuf,
+    /// `workDir` from the config, used for every destination.
+    configured: Option<PathBuf>,
+    /// Work directories already cleaned of stale scratch directories by this process.
+    cleaned: Mutex<HashSet<PathBuf>>,
+}
+
+/// A scratch directory of its own for one job, removed along with anything left in it when
+/// dropped.
+pub struct Scratch {
+    path: PathBuf,
+}
+
+impl WorkDirs {
+    /// `configured` is relative to `base_dir`. The work directory for `base_dir` itself is
+    /// cleaned straight away.
+    pub fn new(base_dir: &Path, configured: Option<PathBuf>) -> WorkDirs {
+        let work_dirs = WorkDirs { base_dir: base_dir.to_path_buf(), configured: configured.map(|dir| base_dir.join(dir)), cleaned: Mutex::new(HashSet::new()) };
+        work_dirs.clean(&work_dirs.root_for(base_dir));
+        work_dirs
+    }
+
+    /// The work directory for files that will end up in `dest`. Without `workDir`, it's `.work` in
+    /// `baseDir` when that's on the same filesystem as `dest`, so finished files are renamed into
+    /// place rather than copied. Otherwise it's `.work` in the highest directory above `dest` that
+    /// is on its filesystem and inside `baseDir`.
+    pub fn root_for(&self, dest: &Path) -> PathBuf {
+        if let Some(configured) = &self.configured {
+            return configured.clone()
+        }
+        let existing = dest.ancestors().find(|p| p.exists()).unwrap_or(&self.base_dir);
+        let device = match fs::metadata(existing) {
+            Ok(metadata) => metadata.dev(),
+            Err(_) => return self.base_dir.join(DEFAULT_NAME),
+        };
+        let top = existing.ancestors()
+            .take_while(|dir| dir.starts_with(&self.base_dir) && fs::metadata(dir).is_ok_and(|m| m.dev() == device))
+            .last()
+            .unwrap_or(existing);
+        top.join(DEFAULT_NAME)
+    }
+
+    /// A new scratch directory on the same filesystem as `dest`.
+    pub fn scratch(&self, dest: &Path) -> Result<Scratch> {
+        let root = self.root_for(dest);
+        self.clean(&root);
+        let path = root.join(format!("{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&path).map_err(|e| format!("unable to create scratch directory [{}]: {e}", path.display()))?;
+        Ok(Scratch { path })
+    }
+
+    /// Removes the scratch directories in `root` left by organisers that are no longer running,
+    /// the first time `root` is used.
+    fn clean(&self, root: &Path) {
+        if !self.cleaned.lock().unwrap().insert(root.to_path_buf()) {
+            return
+        }
+        let entries = match fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => {
+                warn!(work_dir=root.to_str(), error=as_debug!(err); "unable to read work directory");
+                return
+            },
+        };
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let pid = match name.split_once('-').and_then(|(pid, _)| pid.parse::<i32>().ok()) {
+                Some(pid) => pid,
+                None => {
+                    debug!(path=entry.path().to_str(); "leaving unrecognised entry in work directory");
+                    continue
+                },
+            };
+            if pid as u32 == std::process::id() || is_running(pid) {
+                continue
+            }
+            match fs::remove_dir_all(entry.path()) {
+                Ok(()) => removed += 1,
+                Err(err) => warn!(path=entry.path().to_str(), error=as_debug!(err); "unable to remove stale scratch directory"),
+            }
+        }
+        if removed > 0 {
+            info!(work_dir=root.to_str(), removed=removed; "removed stale scratch directories");
+        }
+    }
+}
+
+fn is_running(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists and can be signalled
+    unsafe { libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+impl Scratch {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves everything in the scratch directory into `dest`, replacing files already there and
+    /// merging into directories, and returns where the files ended up. Names are written in `form`,
+    /// and match existing ones that only differ in normalisation. Files moved in and directories
+    /// created get `modes`.
+    pub fn publish(&self, dest: &Path, form: Option<Normalisation>, modes: Modes) -> Result<Vec<PathBuf>> {
+        let mut published = Vec::new();
+        publish_dir(&self.path, dest, form, modes, &mut published)?;
+        Ok(published)
+    }
+}
+
+fn publish_dir(from: &Path, to: &Path, form: Option<Normalisation>, modes: Modes, published: &mut Vec<PathBuf>) -> Result<()> {
+    modes.create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = normalise::resolve(to, &entry.file_name(), form);
+        if entry.file_type()?.is_dir() {
+            // merging into a symlink would write wherever it points
+            if fs::symlink_metadata(&target).is_ok_and(|m| m.file_type().is_symlink()) {
+                return Err(format!("[{}] is a symlink - not extracting into it", target.display()).into())
+            }
+            publish_dir(&entry.path(), &target, form, modes, published)?;
+        } else {
+            modes.apply(&entry.path())?;
+            fs::rename(entry.path(), &target)
+                .map_err(|e| format!("unable to move [{}] into place: {e}", target.display()))?;
+            published.push(target);
+        }
+    }
+    Ok(())
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!(path=self.path.to_str(), error=as_debug!(err); "unable to remove scratch directory");
+            }
+        }
+    }
+}